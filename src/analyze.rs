@@ -0,0 +1,1077 @@
+use crate::dissect::{self, tcp_flags, Packet, Tcp, Transport};
+use crate::output::open_capture;
+use crate::pcap::{BlockKind, CaptureReader, StreamFormat};
+use crate::progress::human_bytes;
+use anyhow::Result;
+use clap::Parser;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use tracing::warn;
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap analyze",
+    about = "Summarize a capture file: TCP health, DNS and HTTP/1"
+)]
+/// Arguments of `kcap analyze`.
+pub struct AnalyzeArgs {
+    #[arg(help = "Capture file (pcap or pcapng, optionally .gz or .zst)")]
+    pub file: String,
+
+    #[arg(
+        long,
+        default_value_t = 10,
+        help = "Number of flows and DNS names to list"
+    )]
+    pub top: usize,
+
+    #[arg(long, help = "Print the report as JSON")]
+    pub json: bool,
+}
+
+/// Runs `kcap analyze` and prints the report to stdout.
+/// Parameters: `args` (AnalyzeArgs) parsed arguments.
+/// Returns: Result<()> error when the file cannot be read.
+pub fn run(args: AnalyzeArgs) -> Result<()> {
+    let report = analyze(open_capture(&args.file, None)?)?;
+    let mut stdout = io::stdout().lock();
+    if args.json {
+        let doc = report.to_json(args.top);
+        writeln!(stdout, "{}", serde_json::to_string_pretty(&doc)?)?;
+    } else {
+        write!(stdout, "{}", report.render(&args.file, args.top))?;
+    }
+    Ok(())
+}
+
+/// Reads a whole capture and summarizes it.
+/// Parameters: `reader` (R) plain pcap/pcapng bytes.
+/// Returns: Result<Report> summary; a damaged tail ends the analysis early instead of failing.
+pub fn analyze<R: Read>(reader: R) -> Result<Report> {
+    let mut capture = CaptureReader::new(reader)?;
+    let mut analyzer = Analyzer::default();
+    loop {
+        match capture.next_block() {
+            Ok(Some(block)) => {
+                if let (BlockKind::Packet(info), Some(data)) = (&block.kind, block.packet_data()) {
+                    analyzer.packet(info.ts_nanos, info.linktype, data, info.orig_len);
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                // A capture killed mid-write leaves a partial last record.
+                warn!("stopped at a damaged record: {err:#}");
+                analyzer.truncated = true;
+                break;
+            }
+        }
+    }
+    Ok(analyzer.finish(capture.format()))
+}
+
+type Endpoint = (IpAddr, u16);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct FlowKey {
+    protocol: &'static str,
+    a: Endpoint,
+    b: Endpoint,
+}
+
+impl FlowKey {
+    fn new(protocol: &'static str, src: Endpoint, dst: Endpoint) -> Self {
+        let (a, b) = if src <= dst { (src, dst) } else { (dst, src) };
+        FlowKey { protocol, a, b }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TcpConn {
+    client: Endpoint,
+    server: Endpoint,
+    first: u64,
+    last: u64,
+    packets: u64,
+    bytes: u64,
+    syn: Option<u64>,
+    syn_ack: Option<u64>,
+    established: Option<u64>,
+    fin: [bool; 2],
+    resets: u64,
+    retransmissions: u64,
+    zero_windows: u64,
+    // Per direction, 0 being client to server.
+    next_seq: [Option<u32>; 2],
+    zero: [bool; 2],
+}
+
+impl TcpConn {
+    fn new(src: Endpoint, dst: Endpoint, tcp: &Tcp, ts: u64) -> Self {
+        let syn = tcp.flags & tcp_flags::SYN != 0;
+        let ack = tcp.flags & tcp_flags::ACK != 0;
+        // The SYN sender is the client; mid-stream, guess the ephemeral (higher) port.
+        let src_is_client = if syn { !ack } else { src.1 > dst.1 };
+        let (client, server) = if src_is_client {
+            (src, dst)
+        } else {
+            (dst, src)
+        };
+        TcpConn {
+            client,
+            server,
+            first: ts,
+            last: ts,
+            packets: 0,
+            bytes: 0,
+            syn: None,
+            syn_ack: None,
+            established: None,
+            fin: [false; 2],
+            resets: 0,
+            retransmissions: 0,
+            zero_windows: 0,
+            next_seq: [None; 2],
+            zero: [false; 2],
+        }
+    }
+
+    /// Accounts one segment; returns whether it carried new (not retransmitted) data.
+    fn segment(&mut self, dir: usize, tcp: &Tcp, ts: u64, wire_len: u32) -> bool {
+        // Merged captures are in arrival order, so timestamps may go backwards.
+        self.first = self.first.min(ts);
+        self.last = self.last.max(ts);
+        self.packets += 1;
+        self.bytes += u64::from(wire_len);
+        let flags = tcp.flags;
+        let (syn, ack) = (flags & tcp_flags::SYN != 0, flags & tcp_flags::ACK != 0);
+
+        match (dir, syn, ack) {
+            (0, true, false) => {
+                self.syn.get_or_insert(ts);
+            }
+            (1, true, true) => {
+                self.syn_ack.get_or_insert(ts);
+            }
+            (0, false, true) if self.syn_ack.is_some() && self.established.is_none() => {
+                self.established = Some(ts);
+            }
+            _ => {}
+        }
+        if flags & tcp_flags::RST != 0 {
+            self.resets += 1;
+        }
+        if flags & tcp_flags::FIN != 0 {
+            self.fin[dir] = true;
+        }
+        let zero = tcp.window == 0 && flags & (tcp_flags::RST | tcp_flags::SYN) == 0;
+        if zero && !self.zero[dir] {
+            self.zero_windows += 1;
+        }
+        self.zero[dir] = zero;
+
+        let len = tcp.payload_len as u32 + u32::from(syn) + u32::from(flags & tcp_flags::FIN != 0);
+        if len == 0 {
+            return false;
+        }
+        let end = tcp.seq.wrapping_add(len);
+        match self.next_seq[dir] {
+            Some(next) if !seq_after(end, next) => {
+                // A one-byte probe just below the next sequence number is a keep-alive.
+                let keepalive = tcp.payload_len <= 1 && !syn && tcp.seq.wrapping_add(1) == next;
+                if !keepalive {
+                    self.retransmissions += 1;
+                }
+                false
+            }
+            _ => {
+                self.next_seq[dir] = Some(end);
+                true
+            }
+        }
+    }
+
+    fn handshake_rtt(&self) -> Option<u64> {
+        Some(self.established?.saturating_sub(self.syn?))
+    }
+
+    fn state(&self) -> &'static str {
+        if self.resets > 0 {
+            if self.syn.is_some() && self.established.is_none() {
+                "refused"
+            } else {
+                "reset"
+            }
+        } else if self.fin == [true, true] {
+            "closed"
+        } else if self.established.is_some() {
+            "open"
+        } else if self.syn.is_some() {
+            "no-handshake"
+        } else {
+            "mid-stream"
+        }
+    }
+}
+
+// Sequence number comparison modulo 2^32.
+fn seq_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[derive(Debug, Clone)]
+struct OtherFlow {
+    protocol: &'static str,
+    client: Endpoint,
+    server: Endpoint,
+    first: u64,
+    last: u64,
+    packets: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Analyzer {
+    packets: u64,
+    bytes: u64,
+    first: Option<u64>,
+    last: u64,
+    non_ip: u64,
+    truncated: bool,
+    tcp: HashMap<FlowKey, TcpConn>,
+    /// Connections whose ports were reused by a later one.
+    finished: Vec<TcpConn>,
+    other: HashMap<FlowKey, OtherFlow>,
+    dns_pending: HashMap<(Endpoint, u16), u64>,
+    dns: DnsSummary,
+    http: HttpSummary,
+}
+
+impl Analyzer {
+    fn packet(&mut self, ts: u64, linktype: u32, frame: &[u8], wire_len: u32) {
+        self.packets += 1;
+        self.bytes += u64::from(wire_len);
+        self.first = Some(self.first.map_or(ts, |first| first.min(ts)));
+        self.last = self.last.max(ts);
+        let Some(packet) = dissect::dissect(linktype, frame) else {
+            self.non_ip += 1;
+            return;
+        };
+        match &packet.transport {
+            Transport::Tcp(tcp) => self.tcp_segment(&packet, tcp, ts, wire_len),
+            Transport::Udp(udp) => {
+                let (src, dst) = ((packet.src, udp.src_port), (packet.dst, udp.dst_port));
+                self.other_packet("udp", src, dst, ts, wire_len);
+                if udp.src_port == 53 || udp.dst_port == 53 {
+                    self.dns_message(src, dst, udp.payload, ts);
+                }
+            }
+            _ => {
+                let name = packet.protocol_name();
+                self.other_packet(name, (packet.src, 0), (packet.dst, 0), ts, wire_len);
+            }
+        }
+    }
+
+    fn tcp_segment(&mut self, packet: &Packet, tcp: &Tcp, ts: u64, wire_len: u32) {
+        let (src, dst) = ((packet.src, tcp.src_port), (packet.dst, tcp.dst_port));
+        let key = FlowKey::new("tcp", src, dst);
+        // A new SYN on a finished connection is port reuse; start over.
+        let new_syn = tcp.flags & (tcp_flags::SYN | tcp_flags::ACK) == tcp_flags::SYN;
+        if new_syn
+            && self
+                .tcp
+                .get(&key)
+                .is_some_and(|c| matches!(c.state(), "closed" | "reset" | "refused"))
+        {
+            self.finished.extend(self.tcp.remove(&key));
+        }
+        let conn = self
+            .tcp
+            .entry(key)
+            .or_insert_with(|| TcpConn::new(src, dst, tcp, ts));
+        let dir = usize::from(src != conn.client);
+        let fresh = conn.segment(dir, tcp, ts, wire_len);
+        if fresh && !tcp.payload.is_empty() {
+            self.http.observe(tcp.payload);
+        }
+    }
+
+    fn other_packet(
+        &mut self,
+        protocol: &'static str,
+        src: Endpoint,
+        dst: Endpoint,
+        ts: u64,
+        len: u32,
+    ) {
+        let flow = self
+            .other
+            .entry(FlowKey::new(protocol, src, dst))
+            .or_insert(OtherFlow {
+                protocol,
+                client: src,
+                server: dst,
+                first: ts,
+                last: ts,
+                packets: 0,
+                bytes: 0,
+            });
+        flow.first = flow.first.min(ts);
+        flow.last = flow.last.max(ts);
+        flow.packets += 1;
+        flow.bytes += u64::from(len);
+    }
+
+    fn dns_message(&mut self, src: Endpoint, dst: Endpoint, payload: &[u8], ts: u64) {
+        let Some(msg) = parse_dns(payload) else {
+            return;
+        };
+        let question = format!("{} {}", msg.name, qtype_name(msg.qtype));
+        if !msg.response {
+            self.dns.queries += 1;
+            *self.dns.names.entry(question).or_default() += 1;
+            self.dns_pending.insert((src, msg.id), ts);
+            return;
+        }
+        self.dns.responses += 1;
+        let rcode = rcode_name(msg.rcode);
+        *self.dns.rcodes.entry(rcode.clone()).or_default() += 1;
+        if msg.rcode != 0 {
+            *self
+                .dns
+                .errors
+                .entry(format!("{question} {rcode}"))
+                .or_default() += 1;
+        }
+        if let Some(sent) = self.dns_pending.remove(&(dst, msg.id)) {
+            self.dns.latencies.push(ts.saturating_sub(sent));
+        }
+    }
+
+    fn finish(mut self, format: StreamFormat) -> Report {
+        let mut tcp = TcpSummary::default();
+        let mut flows = Vec::new();
+        for conn in self.tcp.values().chain(&self.finished) {
+            tcp.connections += 1;
+            *tcp.states.entry(conn.state()).or_default() += 1;
+            tcp.retransmissions += conn.retransmissions;
+            tcp.retransmitting_connections += u64::from(conn.retransmissions > 0);
+            tcp.zero_windows += conn.zero_windows;
+            tcp.zero_window_connections += u64::from(conn.zero_windows > 0);
+            tcp.resets += conn.resets;
+            if let Some(rtt) = conn.handshake_rtt() {
+                tcp.handshake_rtts.push(rtt);
+            }
+            flows.push(FlowSummary {
+                protocol: "tcp",
+                client: conn.client,
+                server: conn.server,
+                packets: conn.packets,
+                bytes: conn.bytes,
+                duration: conn.last.saturating_sub(conn.first),
+                state: Some(conn.state()),
+                retransmissions: conn.retransmissions,
+                resets: conn.resets,
+                zero_windows: conn.zero_windows,
+                handshake_rtt: conn.handshake_rtt(),
+            });
+        }
+        for flow in self.other.values() {
+            flows.push(FlowSummary {
+                protocol: flow.protocol,
+                client: flow.client,
+                server: flow.server,
+                packets: flow.packets,
+                bytes: flow.bytes,
+                duration: flow.last.saturating_sub(flow.first),
+                state: None,
+                retransmissions: 0,
+                resets: 0,
+                zero_windows: 0,
+                handshake_rtt: None,
+            });
+        }
+        flows.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.client.cmp(&b.client)));
+        tcp.handshake_rtts.sort_unstable();
+        self.dns.unanswered = self.dns_pending.len() as u64;
+        self.dns.latencies.sort_unstable();
+        Report {
+            format,
+            packets: self.packets,
+            bytes: self.bytes,
+            duration: self.first.map_or(0, |first| self.last.saturating_sub(first)),
+            non_ip: self.non_ip,
+            truncated: self.truncated,
+            flows,
+            tcp,
+            dns: self.dns,
+            http: self.http,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Totals of one flow (TCP connection, or UDP/other conversation).
+pub struct FlowSummary {
+    pub protocol: &'static str,
+    /// Side that opened the flow (or sent first).
+    pub client: (IpAddr, u16),
+    pub server: (IpAddr, u16),
+    pub packets: u64,
+    pub bytes: u64,
+    /// Nanoseconds between first and last packet.
+    pub duration: u64,
+    /// TCP only: `open`, `closed`, `reset`, `refused`, `no-handshake` or `mid-stream`.
+    pub state: Option<&'static str>,
+    pub retransmissions: u64,
+    pub resets: u64,
+    pub zero_windows: u64,
+    /// SYN to handshake ACK, in nanoseconds.
+    pub handshake_rtt: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// TCP health over all connections.
+pub struct TcpSummary {
+    pub connections: u64,
+    /// Connections per `FlowSummary::state`.
+    pub states: BTreeMap<&'static str, u64>,
+    pub retransmissions: u64,
+    pub retransmitting_connections: u64,
+    pub resets: u64,
+    /// Times a receiver advertised a zero window.
+    pub zero_windows: u64,
+    pub zero_window_connections: u64,
+    /// Sorted, in nanoseconds.
+    pub handshake_rtts: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// DNS over UDP port 53.
+pub struct DnsSummary {
+    pub queries: u64,
+    pub responses: u64,
+    pub unanswered: u64,
+    /// Responses per rcode name.
+    pub rcodes: BTreeMap<String, u64>,
+    /// Queries per `name TYPE`.
+    pub names: BTreeMap<String, u64>,
+    /// Failed responses per `name TYPE RCODE`.
+    pub errors: BTreeMap<String, u64>,
+    /// Query to response time, sorted, in nanoseconds.
+    pub latencies: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// HTTP/1 requests and responses found at the start of TCP segments.
+pub struct HttpSummary {
+    pub methods: BTreeMap<String, u64>,
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+impl HttpSummary {
+    fn observe(&mut self, payload: &[u8]) {
+        const METHODS: [&str; 9] = [
+            "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+        ];
+        if let Some(rest) = payload
+            .strip_prefix(b"HTTP/1.1 ")
+            .or_else(|| payload.strip_prefix(b"HTTP/1.0 "))
+        {
+            let status = rest
+                .get(..3)
+                .and_then(|s| std::str::from_utf8(s).ok())
+                .and_then(|s| s.parse::<u16>().ok());
+            if let Some(status) = status {
+                *self.statuses.entry(status).or_default() += 1;
+            }
+            return;
+        }
+        let is_http = |rest: &[u8]| {
+            let line = &rest[..rest.len().min(2048)];
+            line.windows(7).any(|w| w == b"HTTP/1.")
+        };
+        for method in METHODS {
+            if let Some(rest) = payload
+                .strip_prefix(method.as_bytes())
+                .and_then(|r| r.strip_prefix(b" "))
+            {
+                if is_http(rest) {
+                    *self.methods.entry(method.to_string()).or_default() += 1;
+                }
+                return;
+            }
+        }
+    }
+
+    /// Number of requests seen.
+    /// Returns: u64 sum over methods.
+    pub fn requests(&self) -> u64 {
+        self.methods.values().sum()
+    }
+
+    /// Number of responses seen.
+    /// Returns: u64 sum over statuses.
+    pub fn responses(&self) -> u64 {
+        self.statuses.values().sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Result of `analyze`.
+pub struct Report {
+    pub format: StreamFormat,
+    pub packets: u64,
+    /// Bytes on the wire (original packet lengths).
+    pub bytes: u64,
+    /// Nanoseconds between first and last packet.
+    pub duration: u64,
+    /// Packets that are not IPv4/IPv6 (ARP, LLDP, unknown link types).
+    pub non_ip: u64,
+    /// The file ended in a damaged record.
+    pub truncated: bool,
+    /// Largest first.
+    pub flows: Vec<FlowSummary>,
+    pub tcp: TcpSummary,
+    pub dns: DnsSummary,
+    pub http: HttpSummary,
+}
+
+impl Report {
+    /// Renders the report for a terminal.
+    /// Parameters: `file` (&str) analyzed file name, for the heading.
+    /// Parameters: `top` (usize) flows and DNS names to list.
+    /// Returns: String multi-line report.
+    pub fn render(&self, file: &str, top: usize) -> String {
+        let mut out = String::new();
+        let format = match self.format {
+            StreamFormat::Pcap => "pcap",
+            StreamFormat::Pcapng => "pcapng",
+        };
+        let _ = writeln!(
+            out,
+            "{file}: {format}, {} packets, {} on the wire, {:.3} s",
+            self.packets,
+            human_bytes(self.bytes as f64),
+            self.duration as f64 / 1e9
+        );
+        if self.non_ip > 0 {
+            let _ = writeln!(out, "  {} non-IP packets", self.non_ip);
+        }
+        if self.truncated {
+            let _ = writeln!(
+                out,
+                "  the file ends in a damaged record; analysis stops there"
+            );
+        }
+
+        let tcp = &self.tcp;
+        let _ = writeln!(out, "\nTCP: {} connections", tcp.connections);
+        if tcp.connections > 0 {
+            let states: Vec<String> = tcp.states.iter().map(|(s, n)| format!("{n} {s}")).collect();
+            let _ = writeln!(out, "  {}", states.join(", "));
+            let _ = writeln!(
+                out,
+                "  retransmissions: {} segments in {} connections",
+                tcp.retransmissions, tcp.retransmitting_connections
+            );
+            let _ = writeln!(out, "  resets: {}", tcp.resets);
+            let _ = writeln!(
+                out,
+                "  zero window: {} events in {} connections",
+                tcp.zero_windows, tcp.zero_window_connections
+            );
+            if let Some(line) = distribution(&tcp.handshake_rtts) {
+                let _ = writeln!(
+                    out,
+                    "  handshake RTT: {line} ({} handshakes)",
+                    tcp.handshake_rtts.len()
+                );
+            }
+        }
+
+        if !self.flows.is_empty() {
+            let _ = writeln!(out, "\nTop flows by bytes:");
+            let _ = writeln!(
+                out,
+                "  {:<5} {:<25} {:<25} {:>8} {:>11} {:>9} {:<12} {:>7} {:>4} {:>5} {:>9}",
+                "proto",
+                "client",
+                "server",
+                "packets",
+                "bytes",
+                "duration",
+                "state",
+                "retrans",
+                "rst",
+                "zwin",
+                "rtt"
+            );
+            for f in self.flows.iter().take(top) {
+                let _ = writeln!(
+                    out,
+                    "  {:<5} {:<25} {:<25} {:>8} {:>11} {:>8.3}s {:<12} {:>7} {:>4} {:>5} {:>9}",
+                    f.protocol,
+                    endpoint(f.client),
+                    endpoint(f.server),
+                    f.packets,
+                    human_bytes(f.bytes as f64),
+                    f.duration as f64 / 1e9,
+                    f.state.unwrap_or("-"),
+                    f.retransmissions,
+                    f.resets,
+                    f.zero_windows,
+                    f.handshake_rtt.map_or("-".to_string(), millis),
+                );
+            }
+        }
+
+        let dns = &self.dns;
+        if dns.queries + dns.responses > 0 {
+            let _ = writeln!(
+                out,
+                "\nDNS: {} queries, {} responses, {} unanswered",
+                dns.queries, dns.responses, dns.unanswered
+            );
+            let rcodes: Vec<String> = dns.rcodes.iter().map(|(r, n)| format!("{r} {n}")).collect();
+            if !rcodes.is_empty() {
+                let _ = writeln!(out, "  response codes: {}", rcodes.join(", "));
+            }
+            if let Some(line) = distribution(&dns.latencies) {
+                let _ = writeln!(out, "  response time: {line}");
+            }
+            if !dns.errors.is_empty() {
+                let _ = writeln!(out, "  failures:");
+                for (name, n) in ranked(&dns.errors, top) {
+                    let _ = writeln!(out, "    {n:>6}  {name}");
+                }
+            }
+            let _ = writeln!(out, "  top names:");
+            for (name, n) in ranked(&dns.names, top) {
+                let _ = writeln!(out, "    {n:>6}  {name}");
+            }
+        }
+
+        let http = &self.http;
+        if http.requests() + http.responses() > 0 {
+            let _ = writeln!(
+                out,
+                "\nHTTP/1: {} requests, {} responses",
+                http.requests(),
+                http.responses()
+            );
+            let methods: Vec<String> = http
+                .methods
+                .iter()
+                .map(|(m, n)| format!("{m} {n}"))
+                .collect();
+            if !methods.is_empty() {
+                let _ = writeln!(out, "  methods: {}", methods.join(", "));
+            }
+            let statuses: Vec<String> = http
+                .statuses
+                .iter()
+                .map(|(s, n)| format!("{s} {n}"))
+                .collect();
+            if !statuses.is_empty() {
+                let _ = writeln!(out, "  status: {}", statuses.join(", "));
+            }
+        }
+        out
+    }
+
+    /// Renders the report for automation.
+    /// Parameters: `top` (usize) flows and DNS names to list.
+    /// Returns: serde_json::Value report document.
+    pub fn to_json(&self, top: usize) -> Value {
+        let flows: Vec<Value> = self
+            .flows
+            .iter()
+            .take(top)
+            .map(|f| {
+                json!({
+                    "protocol": f.protocol,
+                    "client": endpoint(f.client),
+                    "server": endpoint(f.server),
+                    "packets": f.packets,
+                    "bytes": f.bytes,
+                    "duration_ms": f.duration as f64 / 1e6,
+                    "state": f.state,
+                    "retransmissions": f.retransmissions,
+                    "resets": f.resets,
+                    "zero_windows": f.zero_windows,
+                    "handshake_rtt_ms": f.handshake_rtt.map(|n| n as f64 / 1e6),
+                })
+            })
+            .collect();
+        let ranked_json = |map: &BTreeMap<String, u64>| -> Vec<Value> {
+            ranked(map, top)
+                .into_iter()
+                .map(|(name, n)| json!({ "name": name, "count": n }))
+                .collect()
+        };
+        json!({
+            "format": match self.format {
+                StreamFormat::Pcap => "pcap",
+                StreamFormat::Pcapng => "pcapng",
+            },
+            "packets": self.packets,
+            "bytes": self.bytes,
+            "duration_ms": self.duration as f64 / 1e6,
+            "non_ip_packets": self.non_ip,
+            "truncated": self.truncated,
+            "tcp": {
+                "connections": self.tcp.connections,
+                "states": self.tcp.states,
+                "retransmissions": self.tcp.retransmissions,
+                "retransmitting_connections": self.tcp.retransmitting_connections,
+                "resets": self.tcp.resets,
+                "zero_windows": self.tcp.zero_windows,
+                "zero_window_connections": self.tcp.zero_window_connections,
+                "handshake_rtt_ms": stats_json(&self.tcp.handshake_rtts),
+            },
+            "flows": flows,
+            "dns": {
+                "queries": self.dns.queries,
+                "responses": self.dns.responses,
+                "unanswered": self.dns.unanswered,
+                "rcodes": self.dns.rcodes,
+                "response_time_ms": stats_json(&self.dns.latencies),
+                "failures": ranked_json(&self.dns.errors),
+                "top_names": ranked_json(&self.dns.names),
+            },
+            "http": {
+                "requests": self.http.requests(),
+                "responses": self.http.responses(),
+                "methods": self.http.methods,
+                "statuses": self.http.statuses,
+            },
+        })
+    }
+}
+
+fn endpoint((ip, port): (IpAddr, u16)) -> String {
+    match (ip, port) {
+        (ip, 0) => ip.to_string(),
+        (IpAddr::V6(ip), port) => format!("[{ip}]:{port}"),
+        (ip, port) => format!("{ip}:{port}"),
+    }
+}
+
+fn millis(nanos: u64) -> String {
+    format!("{:.2} ms", nanos as f64 / 1e6)
+}
+
+// Nearest-rank percentile of sorted values.
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    sorted[(sorted.len() * p).div_ceil(100).clamp(1, sorted.len()) - 1]
+}
+
+fn distribution(sorted: &[u64]) -> Option<String> {
+    let max = *sorted.last()?;
+    Some(format!(
+        "min {}, median {}, p95 {}, max {}",
+        millis(sorted[0]),
+        millis(percentile(sorted, 50)),
+        millis(percentile(sorted, 95)),
+        millis(max)
+    ))
+}
+
+fn stats_json(sorted: &[u64]) -> Value {
+    if sorted.is_empty() {
+        return Value::Null;
+    }
+    let ms = |n: u64| n as f64 / 1e6;
+    json!({
+        "count": sorted.len(),
+        "min": ms(sorted[0]),
+        "median": ms(percentile(sorted, 50)),
+        "p95": ms(percentile(sorted, 95)),
+        "max": ms(sorted[sorted.len() - 1]),
+    })
+}
+
+fn ranked(map: &BTreeMap<String, u64>, top: usize) -> Vec<(&str, u64)> {
+    let mut rows: Vec<(&str, u64)> = map.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+    rows.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    rows.truncate(top);
+    rows
+}
+
+struct DnsMessage {
+    id: u16,
+    response: bool,
+    rcode: u8,
+    name: String,
+    qtype: u16,
+}
+
+fn parse_dns(msg: &[u8]) -> Option<DnsMessage> {
+    let be16 = |off: usize| Some(u16::from_be_bytes([*msg.get(off)?, *msg.get(off + 1)?]));
+    let id = be16(0)?;
+    let flags = be16(2)?;
+    if be16(4)? == 0 {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut off = 12;
+    loop {
+        let len = usize::from(*msg.get(off)?);
+        off += 1;
+        if len == 0 {
+            break;
+        }
+        // Questions are not compressed; a pointer here means this is not DNS.
+        if len > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(msg.get(off..off + len)?).into_owned());
+        off += len;
+    }
+    Some(DnsMessage {
+        id,
+        response: flags & 0x8000 != 0,
+        rcode: (flags & 0x000f) as u8,
+        name: if labels.is_empty() {
+            ".".to_string()
+        } else {
+            labels.join(".")
+        },
+        qtype: be16(off)?,
+    })
+}
+
+fn rcode_name(rcode: u8) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        n => format!("RCODE{n}"),
+    }
+}
+
+fn qtype_name(qtype: u16) -> String {
+    match qtype {
+        1 => "A".to_string(),
+        2 => "NS".to_string(),
+        5 => "CNAME".to_string(),
+        6 => "SOA".to_string(),
+        12 => "PTR".to_string(),
+        15 => "MX".to_string(),
+        16 => "TXT".to_string(),
+        28 => "AAAA".to_string(),
+        33 => "SRV".to_string(),
+        65 => "HTTPS".to_string(),
+        n => format!("TYPE{n}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissect::testdata::{ipv4_tcp, ipv4_udp, sll};
+    use crate::dissect::LINKTYPE_LINUX_SLL;
+    use tcp_flags::{ACK, FIN, PSH, RST, SYN};
+
+    const CLIENT: [u8; 4] = [10, 0, 0, 1];
+    const SERVER: [u8; 4] = [10, 0, 0, 9];
+    const MS: u64 = 1_000_000;
+
+    struct Trace(Analyzer);
+
+    impl Trace {
+        fn new() -> Self {
+            Trace(Analyzer::default())
+        }
+
+        fn tcp(
+            &mut self,
+            ms: u64,
+            to_server: bool,
+            seq_ack: (u32, u32),
+            flags: u8,
+            win: u16,
+            data: &[u8],
+        ) {
+            let ip = if to_server {
+                ipv4_tcp(CLIENT, SERVER, (40000, 80), seq_ack, flags, win, data)
+            } else {
+                ipv4_tcp(SERVER, CLIENT, (80, 40000), seq_ack, flags, win, data)
+            };
+            let frame = sll(&ip);
+            self.0
+                .packet(ms * MS, LINKTYPE_LINUX_SLL, &frame, frame.len() as u32);
+        }
+
+        fn udp(&mut self, ms: u64, src: [u8; 4], dst: [u8; 4], ports: (u16, u16), data: &[u8]) {
+            let frame = sll(&ipv4_udp(src, dst, ports, data));
+            self.0
+                .packet(ms * MS, LINKTYPE_LINUX_SLL, &frame, frame.len() as u32);
+        }
+    }
+
+    fn dns(id: u16, response: bool, rcode: u8, name: &str) -> Vec<u8> {
+        let mut m = id.to_be_bytes().to_vec();
+        let flags: u16 = if response {
+            0x8180 | u16::from(rcode)
+        } else {
+            0x0100
+        };
+        m.extend_from_slice(&flags.to_be_bytes());
+        m.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+        for label in name.split('.') {
+            m.push(label.len() as u8);
+            m.extend_from_slice(label.as_bytes());
+        }
+        m.extend_from_slice(&[0, 0, 1, 0, 1]);
+        m
+    }
+
+    #[test]
+    fn follows_tcp_lifecycle() {
+        let mut t = Trace::new();
+        t.tcp(0, true, (100, 0), SYN, 64240, b"");
+        t.tcp(2, false, (500, 101), SYN | ACK, 65160, b"");
+        t.tcp(4, true, (101, 501), ACK, 502, b"");
+        let request = b"GET /health HTTP/1.1\r\nHost: x\r\n\r\n";
+        t.tcp(5, true, (101, 501), PSH | ACK, 502, request);
+        // The same request again: a retransmission, not a second request.
+        t.tcp(205, true, (101, 501), PSH | ACK, 502, request);
+        t.tcp(
+            206,
+            false,
+            (501, 134),
+            PSH | ACK,
+            0,
+            b"HTTP/1.1 503 Service Unavailable\r\n\r\n",
+        );
+        t.tcp(207, false, (537, 134), ACK, 0, b"");
+        t.tcp(300, false, (537, 134), ACK, 1000, b"");
+        t.tcp(301, true, (134, 537), FIN | ACK, 502, b"");
+        t.tcp(302, false, (537, 135), FIN | ACK, 1000, b"");
+
+        let report = t.0.finish(StreamFormat::Pcap);
+        assert_eq!(report.tcp.connections, 1);
+        assert_eq!(report.tcp.states.get("closed"), Some(&1));
+        assert_eq!(report.tcp.retransmissions, 1);
+        assert_eq!(report.tcp.zero_windows, 1);
+        assert_eq!(report.tcp.handshake_rtts, vec![4 * MS]);
+        assert_eq!(report.http.methods.get("GET"), Some(&1));
+        assert_eq!(report.http.statuses.get(&503), Some(&1));
+        let flow = &report.flows[0];
+        assert_eq!(flow.client.1, 40000);
+        assert_eq!(flow.packets, 10);
+    }
+
+    #[test]
+    fn reset_before_handshake_is_refused() {
+        let mut t = Trace::new();
+        t.tcp(0, true, (100, 0), SYN, 64240, b"");
+        t.tcp(1, false, (0, 101), RST | ACK, 0, b"");
+        let report = t.0.finish(StreamFormat::Pcap);
+        assert_eq!(report.tcp.states.get("refused"), Some(&1));
+        assert_eq!(report.tcp.resets, 1);
+        // A RST carries a zero window without meaning it.
+        assert_eq!(report.tcp.zero_windows, 0);
+    }
+
+    #[test]
+    fn keepalives_are_not_retransmissions() {
+        let mut t = Trace::new();
+        t.tcp(0, true, (100, 0), PSH | ACK, 502, b"abc");
+        t.tcp(10, true, (102, 0), ACK, 502, b"\0");
+        let report = t.0.finish(StreamFormat::Pcap);
+        assert_eq!(report.tcp.retransmissions, 0);
+        assert_eq!(report.tcp.states.get("mid-stream"), Some(&1));
+    }
+
+    #[test]
+    fn out_of_order_timestamps_do_not_underflow() {
+        let mut t = Trace::new();
+        t.tcp(10_000, true, (100, 0), PSH | ACK, 502, b"abc");
+        t.tcp(5_000, true, (103, 0), PSH | ACK, 502, b"def");
+        t.udp(10_000, CLIENT, SERVER, (5000, 6000), b"x");
+        t.udp(5_000, CLIENT, SERVER, (5000, 6000), b"y");
+        let report = t.0.finish(StreamFormat::Pcap);
+        assert_eq!(report.duration, 5_000 * MS);
+        assert!(report.flows.iter().all(|f| f.duration == 5_000 * MS));
+    }
+
+    #[test]
+    fn summarizes_dns() {
+        let mut t = Trace::new();
+        let resolver = [10, 96, 0, 10];
+        t.udp(
+            0,
+            CLIENT,
+            resolver,
+            (5353, 53),
+            &dns(7, false, 0, "orders.prod.svc"),
+        );
+        t.udp(
+            3,
+            resolver,
+            CLIENT,
+            (53, 5353),
+            &dns(7, true, 0, "orders.prod.svc"),
+        );
+        t.udp(
+            4,
+            CLIENT,
+            resolver,
+            (5354, 53),
+            &dns(8, false, 0, "nope.prod.svc"),
+        );
+        t.udp(
+            5,
+            resolver,
+            CLIENT,
+            (53, 5354),
+            &dns(8, true, 3, "nope.prod.svc"),
+        );
+        t.udp(
+            6,
+            CLIENT,
+            resolver,
+            (5355, 53),
+            &dns(9, false, 0, "slow.example"),
+        );
+
+        let report = t.0.finish(StreamFormat::Pcap);
+        assert_eq!((report.dns.queries, report.dns.responses), (3, 2));
+        assert_eq!(report.dns.unanswered, 1);
+        assert_eq!(report.dns.rcodes.get("NXDOMAIN"), Some(&1));
+        assert_eq!(report.dns.errors.get("nope.prod.svc A NXDOMAIN"), Some(&1));
+        assert_eq!(report.dns.latencies, vec![MS, 3 * MS]);
+
+        let text = report.render("cap.pcap", 10);
+        assert!(text.contains("DNS: 3 queries, 2 responses, 1 unanswered"));
+        assert_eq!(report.to_json(10)["dns"]["rcodes"]["NOERROR"], 1);
+    }
+
+    #[test]
+    fn reads_capture_files() {
+        let ip = ipv4_tcp(CLIENT, SERVER, (40000, 80), (1, 0), SYN, 64240, b"");
+        let frame = sll(&ip);
+        let capture = crate::pcap::testdata::pcap_stream(&[(1, &frame), (2, b"junk")]);
+        let report = analyze(io::Cursor::new(capture)).unwrap();
+        assert_eq!(report.packets, 2);
+        assert_eq!(report.non_ip, 1);
+        assert_eq!(report.tcp.states.get("no-handshake"), Some(&1));
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let values: Vec<u64> = (1..=20).collect();
+        assert_eq!(percentile(&values, 50), 10);
+        assert_eq!(percentile(&values, 95), 19);
+        assert_eq!(percentile(&[5], 95), 5);
+    }
+}
@@ -0,0 +1,301 @@
+use crate::k8s::IpOwners;
+use crate::keylog::Secrets;
+use crate::pcap::{self, BlockKind, CaptureReader, StreamFormat};
+use anyhow::Result;
+use std::io::{self, Read};
+use std::net::IpAddr;
+use tracing::debug;
+
+/// Capture stream rewritten as pcapng with cluster names after every section header and
+/// TLS secrets ahead of the packets that follow their arrival.
+pub struct Annotated<R> {
+    capture: CaptureReader<R>,
+    names: Vec<(IpAddr, String)>,
+    secrets: Option<Secrets>,
+    /// Classic pcap input, whose records are converted to Enhanced Packet Blocks.
+    convert: bool,
+    big_endian: bool,
+    pending: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+/// Embeds the names of cluster addresses into a capture stream as pcapng Name Resolution
+/// Blocks, converting classic pcap to pcapng on the way.
+/// Parameters: `stream` (R) pcap or pcapng bytes; blocks until its header arrives.
+/// Parameters: `owners` (&IpOwners) addresses to name.
+/// Returns: Result<Box<dyn Read + Send>> pcapng stream, empty when `stream` was empty.
+pub fn with_names<R: Read + Send + 'static>(
+    stream: R,
+    owners: &IpOwners,
+) -> Result<Box<dyn Read + Send>> {
+    annotate(stream, owners, None)
+}
+
+/// Like `with_names`, additionally embedding TLS key log lines as Decryption Secrets Blocks
+/// as they are collected, so Wireshark can decrypt the capture.
+/// Parameters: `stream` (R) pcap or pcapng bytes; blocks until its header arrives.
+/// Parameters: `owners` (&IpOwners) addresses to name, possibly none.
+/// Parameters: `secrets` (Option<Secrets>) key log lines collected while the capture runs.
+/// Returns: Result<Box<dyn Read + Send>> pcapng stream, empty when `stream` was empty.
+pub fn annotate<R: Read + Send + 'static>(
+    stream: R,
+    owners: &IpOwners,
+    secrets: Option<Secrets>,
+) -> Result<Box<dyn Read + Send>> {
+    // A capture that never started has nothing to annotate; let the exit status explain it.
+    let Some(capture) = pcap::read_started(stream)? else {
+        return Ok(Box::new(io::empty()));
+    };
+    let names = owners
+        .iter()
+        .map(|(ip, owner)| (*ip, owner.label()))
+        .collect();
+    Ok(Box::new(Annotated::new(capture, names, secrets)))
+}
+
+impl<R: Read> Annotated<R> {
+    fn new(
+        capture: CaptureReader<R>,
+        names: Vec<(IpAddr, String)>,
+        secrets: Option<Secrets>,
+    ) -> Self {
+        let big_endian = capture.big_endian();
+        let convert = capture.format() == StreamFormat::Pcap;
+        let mut this = Annotated {
+            convert,
+            big_endian: if convert { false } else { big_endian },
+            pending: Vec::new(),
+            pos: 0,
+            done: false,
+            capture,
+            names,
+            secrets,
+        };
+        if convert {
+            let header = this.capture.header();
+            let snaplen = pcap::read_u32(header, 16, big_endian);
+            let linktype = this.capture.linktype();
+            this.pending = pcap::section_header_block(false);
+            this.pending
+                .extend(pcap::interface_description_block(linktype, snaplen, false));
+        } else {
+            this.pending = this.capture.header().to_vec();
+        }
+        this.push_names();
+        this
+    }
+
+    fn push_names(&mut self) {
+        if !self.names.is_empty() {
+            let block = pcap::name_resolution_block(&self.names, self.big_endian);
+            self.pending.extend(block);
+        }
+    }
+
+    /// Appends the secrets that arrived since the last call as one block.
+    fn push_secrets(&mut self) {
+        let Some(secrets) = &self.secrets else {
+            return;
+        };
+        let lines = secrets.take();
+        if !lines.is_empty() {
+            let kind = pcap::SECRETS_TLS_KEY_LOG;
+            let block = pcap::decryption_secrets_block(kind, &lines, self.big_endian);
+            self.pending.extend(block);
+        }
+    }
+
+    fn refill(&mut self) {
+        self.pending.clear();
+        self.pos = 0;
+        let block = match self.capture.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                // Keys logged late still help readers that look at the whole file.
+                self.push_secrets();
+                self.done = true;
+                return;
+            }
+            Err(err) => {
+                // Typically a record cut short when the capture was stopped.
+                debug!("stopped annotating capture stream: {err:#}");
+                self.push_secrets();
+                self.done = true;
+                return;
+            }
+        };
+        match (&block.kind, self.convert) {
+            (BlockKind::Packet(info), true) => {
+                self.push_secrets();
+                let data = block.packet_data().unwrap_or(&[]);
+                let epb = pcap::enhanced_packet_block(info, data, false);
+                self.pending.extend(epb);
+            }
+            (BlockKind::Packet(_), false) => {
+                self.push_secrets();
+                self.pending.extend(block.raw);
+            }
+            (BlockKind::SectionHeader, _) => {
+                // Names belong to a section, so a new section needs them again.
+                self.big_endian = self.capture.big_endian();
+                self.pending = block.raw;
+                self.push_names();
+            }
+            _ => self.pending = block.raw,
+        }
+    }
+}
+
+impl<R: Read> Read for Annotated<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.pending.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.refill();
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::k8s::{IpOwner, OwnerKind};
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+
+    fn owners() -> IpOwners {
+        let mut owners = IpOwners::new();
+        owners.insert(
+            "10.244.3.17".parse().unwrap(),
+            IpOwner {
+                kind: OwnerKind::Pod,
+                namespace: "prod".into(),
+                name: "orders-7f9".into(),
+                node: Some("node-a".into()),
+            },
+        );
+        owners.insert(
+            "fd00::a".parse().unwrap(),
+            IpOwner {
+                kind: OwnerKind::Service,
+                namespace: "prod".into(),
+                name: "orders".into(),
+                node: None,
+            },
+        );
+        owners
+    }
+
+    fn blocks(bytes: Vec<u8>) -> Vec<pcap::Block> {
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.format(), StreamFormat::Pcapng);
+        let mut out = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            out.push(block);
+        }
+        out
+    }
+
+    #[test]
+    fn converts_pcap_and_embeds_names() {
+        let input = testdata::pcap_stream(&[(1, b"abc"), (2, b"defg")]);
+        let mut out = Vec::new();
+        with_names(Cursor::new(input), &owners())
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        let blocks = blocks(out);
+        assert_eq!(blocks[0].kind, BlockKind::InterfaceDescription);
+        assert_eq!(
+            blocks[1].kind,
+            BlockKind::Other(pcap::BLOCK_NAME_RESOLUTION)
+        );
+        let nrb = &blocks[1].raw;
+        // First record: IPv4, 4 address bytes plus "orders-7f9.prod\0".
+        assert_eq!(&nrb[8..12], &[1, 0, 20, 0]);
+        assert_eq!(&nrb[12..16], &[10, 244, 3, 17]);
+        assert_eq!(&nrb[16..32], b"orders-7f9.prod\0");
+        assert!(nrb.windows(16).any(|w| w == b"orders.prod.svc\0"));
+
+        let BlockKind::Packet(info) = &blocks[3].kind else {
+            panic!("expected a packet");
+        };
+        assert_eq!(info.ts_nanos, 2_000_000_000);
+        assert_eq!(info.linktype, 113);
+        assert_eq!(blocks[3].packet_data().unwrap(), b"defg");
+    }
+
+    #[test]
+    fn inserts_names_after_each_pcapng_section() {
+        let section = testdata::pcapng_stream(&[(1, b"one")]);
+        let mut input = section.clone();
+        input.extend_from_slice(&section);
+        let mut out = Vec::new();
+        with_names(Cursor::new(input), &owners())
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        let kinds: Vec<BlockKind> = blocks(out)
+            .into_iter()
+            .map(|b| b.kind)
+            .filter(|k| !matches!(k, BlockKind::Packet(_)))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                BlockKind::Other(pcap::BLOCK_NAME_RESOLUTION),
+                BlockKind::InterfaceDescription,
+                BlockKind::SectionHeader,
+                BlockKind::Other(pcap::BLOCK_NAME_RESOLUTION),
+                BlockKind::InterfaceDescription,
+            ]
+        );
+    }
+
+    #[test]
+    fn embeds_secrets_before_later_packets() {
+        let input = testdata::pcap_stream(&[(1, b"hello"), (2, b"data")]);
+        let secrets = Secrets::default();
+        let line = format!("CLIENT_RANDOM {} {}", "ab".repeat(32), "cd".repeat(48));
+        let mut stream =
+            annotate(Cursor::new(input), &IpOwners::new(), Some(secrets.clone())).unwrap();
+        // The header and first packet are read before the key arrives.
+        let mut head = [0u8; 64];
+        stream.read_exact(&mut head).unwrap();
+        secrets.push_line(&line);
+        let mut out = head.to_vec();
+        stream.read_to_end(&mut out).unwrap();
+
+        let blocks = blocks(out);
+        let kinds: Vec<&BlockKind> = blocks.iter().map(|b| &b.kind).collect();
+        assert!(matches!(
+            kinds[..],
+            [
+                BlockKind::InterfaceDescription,
+                BlockKind::Packet(_),
+                BlockKind::Other(pcap::BLOCK_DECRYPTION_SECRETS),
+                BlockKind::Packet(_),
+            ]
+        ));
+        let dsb = &blocks[2].raw;
+        assert_eq!(pcap::read_u32(dsb, 8, false), pcap::SECRETS_TLS_KEY_LOG);
+        assert_eq!(pcap::read_u32(dsb, 12, false) as usize, line.len() + 1);
+        assert_eq!(&dsb[16..16 + line.len()], line.as_bytes());
+    }
+
+    #[test]
+    fn empty_stream_stays_empty() {
+        let mut out = Vec::new();
+        with_names(io::empty(), &owners())
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        assert!(out.is_empty());
+    }
+}
@@ -1,7 +1,38 @@
 ﻿use crate::cli::CaptureFormat;
-use std::process::Child;
-use std::thread;
-use std::time::Duration;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+/// Starts a command on a capture target (again), independent of the caller's borrows.
+pub type Spawner = Box<dyn Fn() -> Result<Child> + Send + Sync>;
+
+struct Tracked {
+    id: u32,
+    /// Stops the remote tool so it can print its counters before the transport closes.
+    remote_stop: Option<Arc<Spawner>>,
+}
+
+// Capture processes to stop when the user interrupts kcap.
+static RUNNING: Mutex<Vec<Tracked>> = Mutex::new(Vec::new());
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static HANDLES_INTERRUPT: AtomicBool = AtomicBool::new(false);
+// How long a remotely stopped capture gets to exit before its transport is terminated.
+const STOP_GRACE: Duration = Duration::from_secs(5);
+// Processes kcap terminated itself (duration, Ctrl-C); their exit is not a failure.
+static TERMINATED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+// Remote stderr lines kept for the session manifest.
+const STDERR_TAIL: usize = 20;
+// Prefix of the stderr line in which `stoppable` reports the remote pid.
+const PID_MARKER: &str = "kcap-remote-pid: ";
+
+/// Pid of a `stoppable` remote command, set once it has been reported.
+pub type RemotePid = Arc<OnceLock<u32>>;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// Capture tool choices supported by the remote host.
@@ -10,6 +41,17 @@ pub enum CaptureTool {
     Tshark,
 }
 
+impl CaptureTool {
+    /// Remote executable name.
+    /// Returns: &'static str program run on the target.
+    pub fn program(self) -> &'static str {
+        match self {
+            CaptureTool::Tcpdump => "tcpdump",
+            CaptureTool::Tshark => "tshark",
+        }
+    }
+}
+
 /// Selects the capture tool for a requested format.
 /// Parameters: `format` (CaptureFormat) requested output format.
 /// Returns: CaptureTool chosen to satisfy the format.
@@ -26,19 +68,21 @@ pub fn select_tool(format: CaptureFormat) -> CaptureTool {
 /// Parameters: `iface` (&str) interface name (e.g. "eth0", "any").
 /// Parameters: `format` (CaptureFormat) requested output format.
 /// Parameters: `filter` (Option<&str>) optional capture filter expression.
+/// Parameters: `snaplen` (u32) bytes to keep per packet, 0 for whole packets.
 /// Returns: String containing a shell command for remote execution.
 pub fn build_capture_command(
     tool: CaptureTool,
     iface: &str,
     format: CaptureFormat,
     filter: Option<&str>,
+    snaplen: u32,
 ) -> String {
     // Build a shell-safe command that streams capture bytes to stdout.
     let filter_escaped = filter.map(shell_escape_single_quotes);
 
     match tool {
         CaptureTool::Tcpdump => {
-            let mut cmd = format!("tcpdump -i {iface} -U -s 0");
+            let mut cmd = format!("tcpdump -i {iface} -U -s {snaplen}");
             if let Some(f) = filter_escaped {
                 cmd.push(' ');
                 cmd.push_str(&f);
@@ -52,6 +96,10 @@ pub fn build_capture_command(
                 CaptureFormat::Pcapng => "pcapng",
             };
             let mut cmd = format!("tshark -i {iface} -q -w - -F {fmt}");
+            // tshark's default is already the whole packet.
+            if snaplen > 0 {
+                cmd.push_str(&format!(" -s {snaplen}"));
+            }
             if let Some(f) = filter_escaped {
                 cmd.push_str(" -f ");
                 cmd.push_str(&f);
@@ -61,8 +109,25 @@ pub fn build_capture_command(
     }
 }
 
-/// Terminates a child process after a timeout.
-/// Parameters: `child` (&mut Child) spawned process handle.
+/// Runs a remote command in the background and reports its pid on stderr, so
+/// `stop_command` can signal it; the shell exits with the command's status.
+/// Parameters: `cmd` (&str) remote command, e.g. from `build_capture_command`.
+/// Returns: String shell command list for `sh -c`.
+pub fn stoppable(cmd: &str) -> String {
+    // No file on the target: anything under a shared /tmp could be planted by other users.
+    format!("{cmd} & p=$!; echo \"{PID_MARKER}$p\" >&2; wait $p")
+}
+
+/// Builds the remote command that stops a `stoppable` command. tcpdump and tshark print
+/// their packet counters on SIGTERM, which they do not get when only ssh is killed.
+/// Parameters: `pid` (u32) pid the command reported, see `watch_stderr`.
+/// Returns: String shell command for `sh -c`.
+pub fn stop_command(pid: u32) -> String {
+    format!("kill -TERM {pid}")
+}
+
+/// Stops a tracked capture after a timeout, remotely when it was tracked with a remote stop.
+/// Parameters: `child` (&mut Child) spawned process handle, registered with `track`.
 /// Parameters: `seconds` (u64) timeout in seconds; 0 disables termination.
 pub fn kill_after(child: &mut Child, seconds: u64) {
     if seconds == 0 {
@@ -73,23 +138,334 @@ pub fn kill_after(child: &mut Child, seconds: u64) {
     let id = child.id();
     thread::spawn(move || {
         thread::sleep(Duration::from_secs(seconds));
-        #[cfg(unix)]
-        {
-            let _ = std::process::Command::new("kill")
-                .arg("-TERM")
-                .arg(id.to_string())
-                .status();
+        if !stop_tracked(id) {
+            terminate(id);
+        }
+    });
+}
+
+#[derive(Debug, Clone, Default)]
+/// Stops the captures of one run on request, where `stop_all` would stop every run; an
+/// rpcap client ending its capture must not depend on a packet arriving first.
+pub struct StopHandle(Arc<Mutex<StopGroup>>);
+
+#[derive(Debug, Default)]
+struct StopGroup {
+    stopped: bool,
+    ids: Vec<u32>,
+}
+
+impl StopHandle {
+    /// Adds a tracked capture process to the run, stopping it at once if the run was
+    /// stopped while it was being spawned.
+    /// Parameters: `child` (&Child) process registered with `track` or `track_remote`.
+    pub fn add(&self, child: &Child) {
+        let id = child.id();
+        let stopped = {
+            let mut group = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            group.ids.push(id);
+            group.stopped
+        };
+        if stopped {
+            stop_tracked(id);
         }
-        #[cfg(windows)]
-        {
-            let _ = std::process::Command::new("taskkill")
-                .args(["/PID", &id.to_string(), "/T", "/F"])
-                .status();
+    }
+
+    /// Stops every capture of the run that is still running.
+    pub fn stop(&self) {
+        let ids = {
+            let mut group = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            group.stopped = true;
+            group.ids.clone()
+        };
+        for id in ids {
+            stop_tracked(id);
+        }
+    }
+}
+
+/// Registers a capture process to be stopped by `stop_all`.
+/// Parameters: `child` (&Child) spawned capture process.
+pub fn track(child: &Child) {
+    register(child, None);
+}
+
+/// Registers a capture process whose remote tool `remote_stop` can stop cleanly.
+/// Parameters: `child` (&Child) spawned transport (ssh or exec) process.
+/// Parameters: `remote_stop` (Spawner) starts `stop_command` on the same target.
+pub fn track_remote(child: &Child, remote_stop: Spawner) {
+    register(child, Some(Arc::new(remote_stop)));
+}
+
+fn register(child: &Child, remote_stop: Option<Arc<Spawner>>) {
+    let tracked = Tracked {
+        id: child.id(),
+        remote_stop,
+    };
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(tracked);
+}
+
+/// Forgets a tracked process once it has been waited for.
+/// Parameters: `id` (u32) process id.
+pub fn untrack(id: u32) {
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|t| t.id != id);
+}
+
+/// Stops every tracked capture so their streams end and outputs are finalized.
+pub fn stop_all() {
+    STOPPING.store(true, Ordering::SeqCst);
+    let tracked: Vec<(u32, Option<Arc<Spawner>>)> = RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|t| (t.id, t.remote_stop.clone()))
+        .collect();
+    for (id, remote_stop) in tracked {
+        stop(id, remote_stop);
+    }
+}
+
+/// Terminates every tracked process at once, for a second Ctrl-C.
+pub fn abort_all() {
+    let tracked = std::mem::take(&mut *RUNNING.lock().unwrap_or_else(|e| e.into_inner()));
+    for t in tracked {
+        terminate(t.id);
+    }
+}
+
+/// Records that Ctrl-C is handled by `stop_all`, so `shield` keeps capture processes away
+/// from the terminal's SIGINT.
+pub fn handle_interrupts() {
+    HANDLES_INTERRUPT.store(true, Ordering::SeqCst);
+}
+
+/// Starts a capture transport in its own process group once kcap handles Ctrl-C itself;
+/// otherwise the terminal's SIGINT would kill ssh before the remote tool is stopped.
+/// Parameters: `cmd` (&mut Command) ssh or exec command about to be spawned.
+pub fn shield(cmd: &mut Command) {
+    #[cfg(unix)]
+    if HANDLES_INTERRUPT.load(Ordering::SeqCst) {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+// Stops a process that is still tracked; one already waited for is left alone.
+fn stop_tracked(id: u32) -> bool {
+    let tracked = RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|t| t.id == id)
+        .map(|t| t.remote_stop.clone());
+    match tracked {
+        Some(remote_stop) => {
+            stop(id, remote_stop);
+            true
+        }
+        None => false,
+    }
+}
+
+fn stop(id: u32, remote_stop: Option<Arc<Spawner>>) {
+    let Some(remote_stop) = remote_stop else {
+        terminate(id);
+        return;
+    };
+    mark_terminated(id);
+    thread::spawn(move || {
+        let deadline = Instant::now() + STOP_GRACE;
+        let stopped = match remote_stop() {
+            Ok(mut child) => wait_until(&mut child, deadline).is_some_and(|s| s.success()),
+            Err(err) => {
+                debug!("could not stop the remote capture tool: {err:#}");
+                false
+            }
+        };
+        // Once the tool has exited, the transport ends by itself.
+        while stopped && Instant::now() < deadline && is_tracked(id) {
+            thread::sleep(Duration::from_millis(50));
+        }
+        if is_tracked(id) {
+            terminate(id);
         }
     });
 }
 
-fn shell_escape_single_quotes(input: &str) -> String {
+fn wait_until(child: &mut Child, deadline: Instant) -> Option<ExitStatus> {
+    while Instant::now() < deadline {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Some(status);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    None
+}
+
+fn is_tracked(id: u32) -> bool {
+    RUNNING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .any(|t| t.id == id)
+}
+
+/// Reports whether kcap itself terminated a process, e.g. when the duration expired.
+/// Parameters: `id` (u32) process id.
+/// Returns: bool true when the process was stopped on purpose.
+pub fn stopped_by_kcap(id: u32) -> bool {
+    TERMINATED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .contains(&id)
+}
+
+/// Reports whether `stop_all` was called, so supervisors stop re-attaching.
+/// Returns: bool true once a stop was requested.
+pub fn stop_requested() -> bool {
+    STOPPING.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Packet counters tcpdump and tshark print to stderr when they exit.
+pub struct CaptureStats {
+    pub captured: Option<u64>,
+    /// tcpdump's "received by filter".
+    pub received: Option<u64>,
+    /// tcpdump's "dropped by kernel", or the sum of tshark's "dropped from IFACE".
+    pub dropped_by_kernel: Option<u64>,
+    pub dropped_by_interface: Option<u64>,
+}
+
+impl CaptureStats {
+    /// Parses the exit summary from the capture tool's stderr.
+    /// Parameters: `lines` (&[String]) stderr lines, e.g. the tail kept by `watch_stderr`.
+    /// Returns: Option<CaptureStats> None when no counter was printed.
+    pub fn parse(lines: &[String]) -> Option<CaptureStats> {
+        let mut stats = CaptureStats::default();
+        let mut found = false;
+        for line in lines {
+            let mut words = line.trim().splitn(2, ' ');
+            let Some(Ok(n)) = words.next().map(str::parse::<u64>) else {
+                continue;
+            };
+            let rest = words.next().unwrap_or("");
+            let rest = rest
+                .strip_prefix("packets ")
+                .or_else(|| rest.strip_prefix("packet "))
+                .unwrap_or(rest);
+            let slot = match rest {
+                "captured" => &mut stats.captured,
+                "received by filter" => &mut stats.received,
+                "dropped by kernel" => &mut stats.dropped_by_kernel,
+                "dropped by interface" => &mut stats.dropped_by_interface,
+                r if r == "dropped" || r.starts_with("dropped from ") => {
+                    // tshark prints one line per interface.
+                    &mut stats.dropped_by_kernel
+                }
+                _ => continue,
+            };
+            *slot = Some(slot.unwrap_or(0) + n);
+            found = true;
+        }
+        found.then_some(stats)
+    }
+
+    /// Kernel drops relative to the packets the capture saw.
+    /// Returns: Option<f64> percentage, None when nothing was dropped or counted.
+    pub fn drop_percent(&self) -> Option<f64> {
+        let dropped = self.dropped_by_kernel.filter(|d| *d > 0)?;
+        let seen = self.received.or(self.captured).unwrap_or(0).max(dropped);
+        Some(dropped as f64 * 100.0 / seen as f64)
+    }
+}
+
+/// Remote stderr being echoed to ours while the capture runs.
+pub struct StderrWatch(Option<JoinHandle<Vec<String>>>);
+
+impl StderrWatch {
+    /// Waits for the stream to close.
+    /// Returns: Vec<String> the last lines the remote side printed.
+    pub fn finish(mut self) -> Vec<String> {
+        self.0
+            .take()
+            .and_then(|t| t.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Echoes a capture's piped stderr and keeps its last lines (tool banners, drop counts).
+/// Parameters: `child` (&mut Child) capture process spawned with a piped stderr.
+/// Parameters: `remote_pid` (Option<RemotePid>) receives the pid a `stoppable` command reports.
+/// Returns: StderrWatch handle; dropping it leaves the echo running.
+pub fn watch_stderr(child: &mut Child, remote_pid: Option<RemotePid>) -> StderrWatch {
+    let Some(stderr) = child.stderr.take() else {
+        return StderrWatch(None);
+    };
+    StderrWatch(Some(thread::spawn(move || {
+        let mut tail = VecDeque::with_capacity(STDERR_TAIL);
+        for line in BufReader::new(stderr).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if let (Some(slot), Some(pid)) = (&remote_pid, line.strip_prefix(PID_MARKER)) {
+                if let Ok(pid) = pid.trim().parse() {
+                    let _ = slot.set(pid);
+                    continue;
+                }
+            }
+            if crate::events::human_stderr() {
+                crate::progress::eprint_line(&line);
+            } else {
+                info!(remote_stderr = %line, "capture tool output");
+            }
+            if tail.len() == STDERR_TAIL {
+                tail.pop_front();
+            }
+            tail.push_back(line);
+        }
+        tail.into()
+    })))
+}
+
+fn mark_terminated(id: u32) {
+    TERMINATED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(id);
+}
+
+fn terminate(id: u32) {
+    mark_terminated(id);
+    #[cfg(unix)]
+    {
+        let _ = std::process::Command::new("kill")
+            .arg("-TERM")
+            .arg(id.to_string())
+            .status();
+    }
+    #[cfg(windows)]
+    {
+        let _ = std::process::Command::new("taskkill")
+            .args(["/PID", &id.to_string(), "/T", "/F"])
+            .status();
+    }
+}
+
+/// Quotes a value for the remote `sh -c` command line.
+/// Parameters: `input` (&str) raw value such as a filter or path.
+/// Returns: String single-quoted shell word.
+pub fn shell_escape_single_quotes(input: &str) -> String {
     // Remote command runs via `sh -c`, so quotes must be safe.
     if input.is_empty() {
         return "''".to_string();
@@ -102,6 +478,41 @@ fn shell_escape_single_quotes(input: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn parses_tcpdump_stats() {
+        let lines: Vec<String> = [
+            "tcpdump: listening on any, link-type LINUX_SLL2",
+            "1200 packets captured",
+            "1500 packets received by filter",
+            "300 packets dropped by kernel",
+            "1 packet dropped by interface",
+        ]
+        .map(String::from)
+        .to_vec();
+        let stats = CaptureStats::parse(&lines).unwrap();
+        assert_eq!(stats.captured, Some(1200));
+        assert_eq!(stats.received, Some(1500));
+        assert_eq!(stats.dropped_by_kernel, Some(300));
+        assert_eq!(stats.dropped_by_interface, Some(1));
+        assert_eq!(stats.drop_percent(), Some(20.0));
+    }
+
+    #[test]
+    fn parses_tshark_stats() {
+        let lines: Vec<String> = [
+            "Capturing on 'eth0' and 'eth1'",
+            "98 packets captured",
+            "1 packet dropped from eth0",
+            "1 packet dropped from eth1",
+        ]
+        .map(String::from)
+        .to_vec();
+        let stats = CaptureStats::parse(&lines).unwrap();
+        assert_eq!(stats.captured, Some(98));
+        assert_eq!(stats.dropped_by_kernel, Some(2));
+        assert!(CaptureStats::parse(&["ssh: connect failed".to_string()]).is_none());
+    }
+
     #[test]
     fn escape_single_quotes() {
         let s = "host 10.0.0.1 and tcp port 443";
@@ -117,8 +528,9 @@ mod tests {
             "any",
             CaptureFormat::Pcap,
             Some("tcp port 443"),
+            0,
         );
-        assert!(cmd.contains("tcpdump -i any"));
+        assert!(cmd.contains("tcpdump -i any -U -s 0"));
         assert!(cmd.contains("'tcp port 443'"));
         assert!(cmd.ends_with("-w -"));
     }
@@ -130,9 +542,10 @@ mod tests {
             "eth0",
             CaptureFormat::Pcapng,
             Some("port 53"),
+            96,
         );
         assert!(cmd.contains("tshark -i eth0"));
-        assert!(cmd.contains("-F pcapng"));
+        assert!(cmd.contains("-F pcapng -s 96"));
         assert!(cmd.contains("-f 'port 53'"));
     }
 }
@@ -1,4 +1,31 @@
-﻿use clap::{Parser, ValueEnum};
+﻿use clap::{Parser, Subcommand, ValueEnum};
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap",
+    version,
+    about = "Remote packet capture helper",
+    long_about = "Capture packets remotely over SSH, or inside a Kubernetes pod via kubectl exec.",
+    args_conflicts_with_subcommands = true
+)]
+/// The kcap command line: a subcommand, or a capture run when none is given.
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub args: Args,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+/// Subcommands of `kcap`; each one's help comes from its own arguments.
+pub enum Command {
+    Serve(crate::serve::ServeArgs),
+    Rpcap(crate::rpcap::RpcapArgs),
+    Analyze(crate::analyze::AnalyzeArgs),
+    Sanitize(crate::sanitize::SanitizeArgs),
+    Config(crate::config::ConfigArgs),
+}
 
 #[derive(Parser, Debug, Clone)]
 #[command(
@@ -9,6 +36,10 @@
 )]
 /// CLI arguments for a capture run.
 pub struct Args {
+    // Unset options are filled from ~/.config/kcap/config.toml and kcap.toml (see config).
+    #[arg(short = 'P', long, help = "Named profile from the kcap configuration files")]
+    pub profile: Option<String>,
+
     // SSH identity for connecting to the target.
     #[arg(long, help = "SSH username (optional)")]
     pub ssh_user: Option<String>,
@@ -23,7 +54,25 @@ pub struct Args {
     #[arg(long, help = "SSH jump host (bastion)")]
     pub jump_host: Option<String>,
 
-    #[arg(long, help = "Kubernetes namespace")]
+    // Pin kubectl to an explicit cluster so scripted runs never follow a stray current-context.
+    #[arg(long, help = "kubeconfig file used for all kubectl calls")]
+    pub kubeconfig: Option<String>,
+
+    #[arg(long, help = "kubeconfig context used for all kubectl calls")]
+    pub context: Option<String>,
+
+    #[arg(long, help = "kubeconfig cluster used for all kubectl calls")]
+    pub cluster: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = KubeBackend::Auto,
+        help = "How to reach Kubernetes: kubectl, native API client, or auto"
+    )]
+    pub kube_backend: KubeBackend,
+
+    #[arg(short = 'n', long, help = "Kubernetes namespace")]
     pub namespace: Option<String>,
 
     #[arg(long, help = "Kubernetes pod name (resolved to node)")]
@@ -32,6 +81,38 @@ pub struct Args {
     #[arg(long, help = "Container name (kubectl exec target)")]
     pub container: Option<String>,
 
+    // A service fans out to one capture per ready endpoint, merged into one output.
+    #[arg(
+        long,
+        conflicts_with_all = ["ssh_host", "pod"],
+        help = "Kubernetes service name (captures every ready endpoint)"
+    )]
+    pub service: Option<String>,
+
+    #[arg(
+        short = 'l',
+        long,
+        conflicts_with_all = ["ssh_host", "pod", "service"],
+        help = "Kubernetes label selector (captures every matching pod)"
+    )]
+    pub selector: Option<String>,
+
+    // Keeps exec streams attached across CrashLoopBackOff and rollouts.
+    #[arg(
+        long,
+        conflicts_with_all = ["ssh_host", "service"],
+        help = "Re-attach when the pod restarts or is replaced (with --pod or --selector)"
+    )]
+    pub follow: bool,
+
+    // Node names become SSH targets through the [nodes] inventory or their InternalIP.
+    #[arg(
+        long,
+        conflicts_with_all = ["ssh_host", "selector", "follow"],
+        help = "Capture --service endpoints or the --pod on their nodes over SSH instead of inside pods"
+    )]
+    pub via_node: bool,
+
     #[arg(long, help = "Port filter")]
     pub port: Option<u16>,
 
@@ -41,18 +122,154 @@ pub struct Args {
     #[arg(long, default_value = "any", help = "Capture interface (e.g. eth0/any)")]
     pub iface: String,
 
-    #[arg(long, default_value = "capture.pcap", help = "Output file, use - for stdout")]
+    // File names are templates, so repeated runs never clobber earlier captures.
+    #[arg(
+        long,
+        default_value = crate::naming::DEFAULT_TEMPLATE,
+        help = "Output file, - for stdout, fifo:PATH, tcp:HOST:PORT or rotate:SIZE:PATH; file names may use {target}, {ns}, {pod}, {node}, {ts}, {port} and {seq}"
+    )]
     pub output: String,
 
+    #[arg(long, help = "Directory for relative output file names (created if missing)")]
+    pub output_dir: Option<String>,
+
+    #[arg(long, help = "Overwrite existing output files")]
+    pub force: bool,
+
+    // Compressed while writing, so long captures never sit uncompressed on disk.
+    #[arg(
+        long,
+        value_enum,
+        help = "Compress the output (default: inferred from a .gz or .zst file name)"
+    )]
+    pub compress: Option<Compression>,
+
+    // Trades remote CPU for bandwidth on slow bastion links; decompressed before writing.
+    #[arg(
+        long,
+        value_enum,
+        help = "Compress the stream on the remote side with gzip, zstd or lz4 when available"
+    )]
+    pub remote_compress: Option<RemoteCompression>,
+
+    // Secondary outputs are best effort; only --output can fail the run.
+    #[arg(
+        long,
+        value_name = "SINK",
+        help = "Also copy the capture to SINK: path, -, fifo:PATH, tcp:HOST:PORT or rotate:SIZE:PATH (repeatable)"
+    )]
+    pub tee: Vec<String>,
+
+    // The recording keeps going if the viewer is closed.
+    #[arg(long, help = "Open a live Wireshark (or tshark) view while writing --output")]
+    pub wireshark: bool,
+
     // Format controls which capture tool is selected remotely.
     #[arg(long, value_enum, default_value_t = CaptureFormat::Pcap, help = "Output format")]
     pub format: CaptureFormat,
 
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Bytes to capture per packet on the target, 0 for whole packets"
+    )]
+    pub snaplen: u32,
+
     #[arg(long, help = "Capture duration (seconds), empty means run until stopped")]
     pub duration: Option<u64>,
 
     #[arg(long, help = "Additional capture filter expression (combined with port)")]
     pub filter: Option<String>,
+
+    // Kernel drops silently invalidate latency and retransmission analyses.
+    #[arg(
+        long,
+        default_value_t = 1.0,
+        help = "Warn when the kernel dropped more than this percentage of packets"
+    )]
+    pub drop_warn_percent: f64,
+
+    // The SSH session carrying the capture is excluded unless asked otherwise. Its source
+    // port is only known inside that session, so the filter covers every connection from
+    // kcap's address to the SSH port.
+    #[arg(
+        long,
+        help = "Keep kcap's own SSH session traffic in the capture; without it, all traffic \
+                between kcap's address (or the jump host) and the SSH port is left out, \
+                including other SSH sessions from that address"
+    )]
+    pub no_exclude_self: bool,
+
+    // Drawn only when stderr is a terminal and stdout is not the capture stream.
+    #[arg(long, help = "Do not show the live progress line on stderr")]
+    pub no_progress: bool,
+
+    #[arg(long, help = "Show the top talkers and ports under the progress line")]
+    pub stats: bool,
+
+    // Read by main once the configuration is applied, before logging starts; JSON also
+    // turns on --events to stderr.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, help = "Log format on stderr")]
+    pub log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "-",
+        help = "Write JSON Lines events (progress, rotation, drops, errors) to PATH, - for stderr"
+    )]
+    pub events: Option<String>,
+
+    // Decoding happens locally on the received stream; the remote command is unchanged.
+    #[arg(long, help = "Print one summary line per packet to stdout instead of writing a file")]
+    pub decode: bool,
+
+    #[arg(long, requires = "decode", help = "With --decode, also write the capture to --output")]
+    pub save: bool,
+
+    // Names are looked up once when the capture starts.
+    #[arg(
+        long,
+        help = "Embed pod, service and node names of cluster IPs in the capture (writes pcapng)"
+    )]
+    pub embed_names: bool,
+
+    // Tailed on every target in parallel with the capture.
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "SSLKEYLOGFILE on the target to embed as TLS decryption secrets (writes pcapng)"
+    )]
+    pub keylog: Option<String>,
+
+    // Filled from the [nodes] sections of the configuration files.
+    #[arg(skip)]
+    pub inventory: crate::inventory::Inventory,
+
+    // Lets the embedding command (rpcap) stop this run's captures.
+    #[arg(skip)]
+    pub stop: crate::capture::StopHandle,
+
+    // Sanitizing runs locally before anything is written, decoded or served.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PayloadPolicy::Keep,
+        help = "What to do with packet payloads before writing"
+    )]
+    pub payload: PayloadPolicy,
+
+    #[arg(long, help = "Replace IP addresses with prefix-preserving pseudonyms")]
+    pub anonymize_ips: bool,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        requires = "anonymize_ips",
+        help = "Key for --anonymize-ips, so pseudonyms match across captures (or KCAP_ANONYMIZE_KEY)"
+    )]
+    pub anonymize_key: Option<String>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
@@ -69,3 +286,48 @@ pub enum CaptureFormat {
     Pcap,
     Pcapng,
 }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Treatment of packet payloads when sanitizing a capture.
+pub enum PayloadPolicy {
+    Keep,
+    /// Cut packets after the TCP/UDP (or ICMP, or IP) header.
+    Truncate,
+    /// Overwrite TCP/UDP payloads with zeros, keeping packet lengths.
+    Zero,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Compression applied to written capture files.
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Compressor to run on the capture target.
+pub enum RemoteCompression {
+    /// The best one found on the target (zstd, lz4, then gzip).
+    Auto,
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Format of kcap's own diagnostics on stderr.
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log shippers and wrappers.
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// Backend used for Kubernetes queries and exec.
+pub enum KubeBackend {
+    /// kubectl when it is on PATH, otherwise the native client if built in.
+    Auto,
+    Kubectl,
+    /// Direct API server access (requires the `native-k8s` feature).
+    Native,
+}
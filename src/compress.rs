@@ -0,0 +1,324 @@
+use crate::cli::RemoteCompression;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// Remote shell snippet listing the compressors available on the target, best first.
+pub const PROBE_COMMAND: &str =
+    "for c in zstd lz4 gzip; do command -v $c >/dev/null 2>&1 && echo $c; done; true";
+
+/// How a compressed remote stream went, kept so the run can report it once the capture
+/// has ended.
+pub type StreamReport = Arc<Mutex<StreamOutcome>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Outcome of decoding one compressed remote stream.
+pub struct StreamOutcome {
+    /// Decode error when the stream is corrupt or truncated.
+    pub failure: Option<String>,
+    /// Bytes moved, set once the stream has ended.
+    pub stats: Option<CompressionStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Bytes a compressed remote stream carried.
+pub struct CompressionStats {
+    pub codec: Codec,
+    /// Compressed bytes read from the remote command.
+    pub wire_bytes: u64,
+    /// Capture bytes they decoded to.
+    pub capture_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Capture bytes per transferred byte.
+    /// Returns: Option<f64> None when nothing was transferred.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.wire_bytes > 0).then(|| self.capture_bytes as f64 / self.wire_bytes as f64)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Compressor run on the remote side of the capture pipe.
+pub enum Codec {
+    Gzip,
+    Zstd,
+    Lz4,
+}
+
+impl Codec {
+    /// Remote command name, as printed by `PROBE_COMMAND`.
+    /// Returns: &'static str executable name.
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gzip",
+            Codec::Zstd => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    /// Appends the compressor to a remote capture command.
+    /// Parameters: `capture_cmd` (&str) command writing capture bytes to stdout.
+    /// Returns: String pipeline writing compressed bytes to stdout and exiting with the
+    /// capture command's status, so a missing or failing tool is not reported as success.
+    pub fn wrap_command(self, capture_cmd: &str) -> String {
+        // Fast levels: the point is to relieve the link, not to burn the node's CPU.
+        let compressor = match self {
+            Codec::Gzip => "gzip -1 -c",
+            Codec::Zstd => "zstd -1 -c -q",
+            Codec::Lz4 => "lz4 -1 -c -q",
+        };
+        // POSIX sh has no pipefail: the status travels on fd 3 while the data uses fd 4.
+        format!(
+            "exec 4>&1; s=$({{ {{ ({capture_cmd}); echo $? >&3; }} | {compressor} >&4; }} 3>&1); \
+             exit ${{s:-1}}"
+        )
+    }
+
+    /// Decompresses a remote stream, recording the bytes moved when it ends.
+    /// Parameters: `label` (&str) capture source, used in log messages.
+    /// Parameters: `reader` (R) compressed bytes from the remote command.
+    /// Parameters: `report` (StreamReport) receives the byte counts and any decode error.
+    /// Returns: Box<dyn Read + Send> plain capture bytes, ending after the last good byte.
+    pub fn decoder<R: Read + Send + 'static>(
+        self,
+        label: &str,
+        reader: R,
+        report: StreamReport,
+    ) -> Box<dyn Read + Send> {
+        let wire = Arc::new(AtomicU64::new(0));
+        let counted = Counted {
+            inner: reader,
+            count: wire.clone(),
+        };
+        let inner: Box<dyn Read + Send> = match self {
+            Codec::Gzip => Box::new(flate2::read::MultiGzDecoder::new(counted)),
+            Codec::Zstd => match zstd::stream::read::Decoder::new(counted) {
+                Ok(d) => Box::new(d),
+                Err(err) => Box::new(Failed(Some(err))),
+            },
+            Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(counted)),
+        };
+        Box::new(Decompressed {
+            label: label.to_string(),
+            codec: self,
+            inner,
+            report,
+            wire,
+            plain: 0,
+            done: false,
+        })
+    }
+}
+
+/// Parses the output of `PROBE_COMMAND`.
+/// Parameters: `output` (&str) one compressor name per line.
+/// Returns: Vec<Codec> available compressors in the order they were listed.
+pub fn parse_probe(output: &str) -> Vec<Codec> {
+    output
+        .lines()
+        .filter_map(|line| match line.trim() {
+            "gzip" => Some(Codec::Gzip),
+            "zstd" => Some(Codec::Zstd),
+            "lz4" => Some(Codec::Lz4),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Picks the compressor to use for a target.
+/// Parameters: `requested` (RemoteCompression) user choice; `Auto` takes the best available.
+/// Parameters: `available` (&[Codec]) compressors found on the target, best first.
+/// Returns: Option<Codec> None when the requested compressor is missing.
+pub fn choose(requested: RemoteCompression, available: &[Codec]) -> Option<Codec> {
+    let wanted = match requested {
+        RemoteCompression::Auto => return available.first().copied(),
+        RemoteCompression::Gzip => Codec::Gzip,
+        RemoteCompression::Zstd => Codec::Zstd,
+        RemoteCompression::Lz4 => Codec::Lz4,
+    };
+    available.contains(&wanted).then_some(wanted)
+}
+
+struct Counted<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Counted<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+struct Failed(Option<io::Error>);
+
+impl Read for Failed {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(self.0.take().unwrap_or_else(|| io::ErrorKind::Other.into()))
+    }
+}
+
+struct Decompressed {
+    label: String,
+    codec: Codec,
+    inner: Box<dyn Read + Send>,
+    report: StreamReport,
+    wire: Arc<AtomicU64>,
+    plain: u64,
+    done: bool,
+}
+
+impl Decompressed {
+    fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        // Reported with the capture statistics once the remote command has exited.
+        let stats = CompressionStats {
+            codec: self.codec,
+            wire_bytes: self.wire.load(Ordering::Relaxed),
+            capture_bytes: self.plain,
+        };
+        self.report.lock().unwrap_or_else(|e| e.into_inner()).stats = Some(stats);
+    }
+}
+
+impl Read for Decompressed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done {
+            return Ok(0);
+        }
+        match self.inner.read(buf) {
+            Ok(0) => {
+                self.finish();
+                Ok(0)
+            }
+            Ok(n) => {
+                self.plain += n as u64;
+                Ok(n)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Err(err),
+            Err(err) => {
+                // Everything decoded so far is still good, so the file is finished cleanly;
+                // whether the early end is an error depends on how the capture stopped.
+                warn!(source = %self.label, "compressed stream ended early: {err}");
+                let detail = format!("corrupt or truncated {} stream: {err}", self.codec.name());
+                self.report.lock().unwrap_or_else(|e| e.into_inner()).failure = Some(detail);
+                self.finish();
+                Ok(0)
+            }
+        }
+    }
+}
+
+impl Drop for Decompressed {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn sample() -> Vec<u8> {
+        b"kcap capture bytes ".repeat(200)
+    }
+
+    fn compress(codec: Codec, data: &[u8]) -> Vec<u8> {
+        match codec {
+            Codec::Gzip => {
+                let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            }
+            Codec::Zstd => zstd::encode_all(data, 1).unwrap(),
+            Codec::Lz4 => {
+                let mut e = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                e.write_all(data).unwrap();
+                e.finish().unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_every_codec() {
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Lz4] {
+            let wire = compress(codec, &sample());
+            let wire_bytes = wire.len() as u64;
+            let report = StreamReport::default();
+            let mut out = Vec::new();
+            codec
+                .decoder("test", Cursor::new(wire), report.clone())
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, sample(), "{codec:?}");
+            let outcome = report.lock().unwrap().clone();
+            assert_eq!(outcome.failure, None);
+            let stats = outcome.stats.unwrap();
+            assert_eq!(stats.wire_bytes, wire_bytes);
+            assert_eq!(stats.capture_bytes, sample().len() as u64);
+            assert!(stats.ratio().unwrap() > 1.0, "{codec:?}");
+        }
+    }
+
+    #[test]
+    fn truncated_stream_keeps_decoded_prefix_and_reports_failure() {
+        for codec in [Codec::Gzip, Codec::Zstd, Codec::Lz4] {
+            let wire = compress(codec, &sample());
+            let report = StreamReport::default();
+            let mut out = Vec::new();
+            codec
+                .decoder("test", Cursor::new(wire[..wire.len() / 2].to_vec()), report.clone())
+                .read_to_end(&mut out)
+                .unwrap();
+            assert!(sample().starts_with(&out), "{codec:?}");
+            let detail = report.lock().unwrap().failure.clone().unwrap();
+            assert!(detail.contains(codec.name()), "{detail}");
+        }
+    }
+
+    #[test]
+    fn chooses_from_probe() {
+        let found = parse_probe("zstd\ngzip\n");
+        assert_eq!(found, vec![Codec::Zstd, Codec::Gzip]);
+        assert_eq!(choose(RemoteCompression::Auto, &found), Some(Codec::Zstd));
+        assert_eq!(choose(RemoteCompression::Gzip, &found), Some(Codec::Gzip));
+        assert_eq!(choose(RemoteCompression::Lz4, &found), None);
+        assert_eq!(choose(RemoteCompression::Auto, &[]), None);
+    }
+
+    #[test]
+    fn wraps_remote_command() {
+        assert_eq!(
+            Codec::Lz4.wrap_command("tcpdump -i any -U -s 0 -w -"),
+            "exec 4>&1; s=$({ { (tcpdump -i any -U -s 0 -w -); echo $? >&3; } \
+             | lz4 -1 -c -q >&4; } 3>&1); exit ${s:-1}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn wrapped_command_keeps_the_capture_status() {
+        let run = |cmd: &str| {
+            let out = std::process::Command::new("sh")
+                .args(["-c", &Codec::Gzip.wrap_command(cmd)])
+                .output()
+                .unwrap();
+            let mut plain = Vec::new();
+            Codec::Gzip
+                .decoder("test", io::Cursor::new(out.stdout), StreamReport::default())
+                .read_to_end(&mut plain)
+                .unwrap();
+            (out.status.code(), plain)
+        };
+        assert_eq!(run("printf pcap"), (Some(0), b"pcap".to_vec()));
+        assert_eq!(run("printf pc; exit 127"), (Some(127), b"pc".to_vec()));
+    }
+}
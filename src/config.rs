@@ -0,0 +1,676 @@
+use crate::cli::{Args, Cli, Command};
+use crate::inventory::{Inventory, NodeEntry};
+use anyhow::{bail, Context, Result};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, CommandFactory, Parser, ValueEnum};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+/// Project-local configuration file, looked up from the working directory upwards.
+pub const PROJECT_FILE: &str = "kcap.toml";
+
+// Options choosing what to capture; a layer that sets one of them replaces all of them,
+// so a profile's `pod` never ends up next to a default `ssh-host`.
+const TARGET_KEYS: [&str; 4] = ["ssh-host", "pod", "service", "selector"];
+
+// Options that only make sense on the command line.
+const CLI_ONLY: [&str; 3] = ["profile", "help", "version"];
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap config",
+    about = "Inspect kcap configuration files and profiles"
+)]
+/// Arguments of `kcap config`.
+pub struct ConfigArgs {
+    #[arg(value_enum, help = "What to do")]
+    pub action: ConfigAction,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "CAPTURE FLAGS",
+        help = "Capture flags (e.g. -P NAME) to merge into the shown configuration"
+    )]
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// `kcap config` actions.
+pub enum ConfigAction {
+    /// Print the effective capture settings and where each one came from.
+    Show,
+}
+
+#[derive(Debug, Clone, Default)]
+/// One configuration file: `[defaults]` plus `[profiles.NAME]` tables of capture options
+/// and `[nodes.NAME]` SSH settings of cluster nodes.
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub defaults: Table,
+    pub profiles: BTreeMap<String, Table>,
+    pub nodes: BTreeMap<String, NodeEntry>,
+}
+
+impl ConfigFile {
+    /// Parses the text of a configuration file.
+    /// Parameters: `path` (&Path) file the text was read from, used in messages.
+    /// Parameters: `text` (&str) TOML document.
+    /// Returns: Result<ConfigFile> error on invalid TOML or unknown sections.
+    pub fn parse(path: &Path, text: &str) -> Result<ConfigFile> {
+        let table: Table = text
+            .parse()
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let mut file = ConfigFile {
+            path: path.to_path_buf(),
+            ..Default::default()
+        };
+        for (section, value) in table {
+            match (section.as_str(), value) {
+                ("defaults", Value::Table(defaults)) => file.defaults = defaults,
+                ("profiles", Value::Table(profiles)) => {
+                    for (name, profile) in profiles {
+                        let Value::Table(profile) = profile else {
+                            bail!("{}: profile `{name}` must be a table", path.display());
+                        };
+                        file.profiles.insert(name, profile);
+                    }
+                }
+                ("nodes", Value::Table(nodes)) => {
+                    for (name, node) in nodes {
+                        let Value::Table(node) = node else {
+                            bail!("{}: node `{name}` must be a table", path.display());
+                        };
+                        let source = format!("nodes.{name} in {}", path.display());
+                        file.nodes.insert(name, NodeEntry::parse(&node, &source)?);
+                    }
+                }
+                _ => bail!(
+                    "{}: unknown section `{section}` \
+                     (expected [defaults], [profiles.NAME] or [nodes.NAME])",
+                    path.display()
+                ),
+            }
+        }
+        Ok(file)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A configured option value and the file section it came from.
+pub struct Setting {
+    pub value: Value,
+    pub source: String,
+}
+
+/// Configured options by long flag name.
+pub type Settings = BTreeMap<String, Setting>;
+
+#[derive(Debug, Clone, Default)]
+/// Loaded configuration files, lowest precedence first.
+pub struct Config {
+    pub files: Vec<ConfigFile>,
+}
+
+impl Config {
+    /// Reads the user file (`$XDG_CONFIG_HOME/kcap/config.toml`, else
+    /// `~/.config/kcap/config.toml`) and the nearest `kcap.toml`; missing files are skipped.
+    /// Returns: Result<Config> error when a file exists but cannot be read or parsed.
+    pub fn load() -> Result<Config> {
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| project_config_path(&dir));
+        let mut files = Vec::new();
+        for path in user_config_path().into_iter().chain(project) {
+            if !path.is_file() {
+                continue;
+            }
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            files.push(ConfigFile::parse(&path, &text)?);
+        }
+        Ok(Config { files })
+    }
+
+    /// Merges the defaults and, if given, one profile of every file.
+    /// Precedence, highest first: the profile in `kcap.toml`, the profile in the user file,
+    /// defaults in `kcap.toml`, defaults in the user file.
+    /// Parameters: `profile` (Option<&str>) profile selected with `--profile`.
+    /// Returns: Result<Settings> error when the profile is defined nowhere.
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings> {
+        let mut settings = Settings::new();
+        for file in &self.files {
+            let source = format!("defaults in {}", file.path.display());
+            merge(&mut settings, &file.defaults, &source);
+        }
+        let Some(name) = profile else {
+            return Ok(settings);
+        };
+        let mut found = false;
+        for file in &self.files {
+            if let Some(table) = file.profiles.get(name) {
+                let source = format!("profile {name} in {}", file.path.display());
+                merge(&mut settings, table, &source);
+                found = true;
+            }
+        }
+        if !found {
+            let known: BTreeSet<&str> = self
+                .files
+                .iter()
+                .flat_map(|f| f.profiles.keys().map(String::as_str))
+                .collect();
+            if known.is_empty() {
+                bail!("unknown profile `{name}`: no profiles are configured");
+            }
+            bail!(
+                "unknown profile `{name}` (configured: {})",
+                Vec::from_iter(known).join(", ")
+            );
+        }
+        Ok(settings)
+    }
+
+    /// Node entries of all files; `kcap.toml` replaces user entries of the same name.
+    /// Returns: Inventory without discovered addresses.
+    pub fn inventory(&self) -> Inventory {
+        let mut inventory = Inventory::default();
+        for file in &self.files {
+            for (name, entry) in &file.nodes {
+                inventory.insert(name.clone(), entry.clone());
+            }
+        }
+        inventory
+    }
+}
+
+fn merge(settings: &mut Settings, table: &Table, source: &str) {
+    if TARGET_KEYS.iter().any(|key| table.contains_key(*key)) {
+        settings.retain(|key, _| !TARGET_KEYS.contains(&key.as_str()));
+    }
+    for (key, value) in table {
+        let setting = Setting {
+            value: value.clone(),
+            source: source.to_string(),
+        };
+        settings.insert(key.clone(), setting);
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        return Some(PathBuf::from(dir).join("kcap").join("config.toml"));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(
+        PathBuf::from(home)
+            .join(".config")
+            .join("kcap")
+            .join("config.toml"),
+    )
+}
+
+fn project_config_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|d| d.join(PROJECT_FILE))
+        .find(|p| p.is_file())
+}
+
+/// Command lines that embed the capture options (`kcap`, `kcap serve`, `kcap rpcap`, extcap).
+pub trait CaptureCommand: Parser {
+    /// The embedded capture options.
+    fn capture_args(&mut self) -> &mut Args;
+}
+
+impl CaptureCommand for Args {
+    fn capture_args(&mut self) -> &mut Args {
+        self
+    }
+}
+
+/// Parses a command line carrying capture options, filling options it leaves unset from
+/// the configuration files and the profile chosen with `--profile`; exits on usage errors
+/// like `T::parse`.
+/// Parameters: `argv` (&[String]) arguments without the program and subcommand name.
+/// Returns: Result<T> error when a configuration file or profile is invalid.
+pub fn parse_args<T: CaptureCommand>(argv: &[String]) -> Result<T> {
+    configured(&Config::load()?, argv)
+}
+
+/// Parses a kcap command line: a subcommand, or a capture run when none is given. The
+/// capture options of `kcap`, `kcap serve` and `kcap rpcap` are completed from the
+/// configuration files as in `parse_args`; exits on usage errors like `Cli::parse`.
+/// Parameters: `argv` (&[String]) arguments without the program name.
+/// Returns: Result<Cli> error when a configuration file or profile is invalid.
+pub fn parse_cli(argv: &[String]) -> Result<Cli> {
+    configured_cli(&Config::load()?, argv)
+}
+
+fn configured<T: CaptureCommand>(config: &Config, argv: &[String]) -> Result<T> {
+    let (matches, _) = resolve(T::command(), config, argv)?;
+    let mut parsed = T::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    parsed.capture_args().inventory = config.inventory();
+    Ok(parsed)
+}
+
+fn configured_cli(config: &Config, argv: &[String]) -> Result<Cli> {
+    let mut cli = Cli::parse_from(std::iter::once("kcap").chain(argv.iter().map(String::as_str)));
+    // Capture options cannot come before a subcommand, so clap only finds one in argv[0].
+    match &mut cli.command {
+        None => cli.args = configured(config, argv)?,
+        Some(Command::Serve(serve)) => *serve = configured(config, &argv[1..])?,
+        Some(Command::Rpcap(rpcap)) => *rpcap = configured(config, &argv[1..])?,
+        Some(_) => {}
+    }
+    Ok(cli)
+}
+
+/// Applies `config` to a command line of `command`, which embeds the capture options.
+/// Returns: Result<(ArgMatches, Settings)> final matches and the settings that were used.
+fn resolve(
+    command: clap::Command,
+    config: &Config,
+    argv: &[String],
+) -> Result<(ArgMatches, Settings)> {
+    let bin = command.get_name().to_string();
+    let cli = command
+        .clone()
+        .try_get_matches_from(std::iter::once(bin.as_str()).chain(argv.iter().map(String::as_str)))
+        .unwrap_or_else(|err| err.exit());
+    let mut settings = config.settings(cli.get_one::<String>("profile").map(String::as_str))?;
+
+    let on_cli = |key: &str| {
+        find_arg(&command, key).is_some_and(|arg| {
+            cli.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        })
+    };
+    // The command line is the last layer: its target replaces the configured one.
+    if TARGET_KEYS.iter().any(|key| on_cli(key)) {
+        settings.retain(|key, _| !TARGET_KEYS.contains(&key.as_str()));
+    }
+    settings.retain(|key, _| !on_cli(key));
+    // A configured flag must not make an option typed on the command line unusable.
+    let typed: Vec<&Arg> = command
+        .get_arguments()
+        .filter(|arg| on_cli(arg.get_long().unwrap_or_default()))
+        .collect();
+    settings.retain(|key, _| {
+        find_arg(&command, key)
+            .is_none_or(|arg| !typed.iter().any(|other| conflicting(&command, arg, other)))
+    });
+
+    // Only capture options are configurable, whichever command embeds them.
+    let capture = Args::command();
+    let mut full = vec![bin.clone()];
+    let mut applied: Vec<(&str, &Setting)> = Vec::new();
+    for (key, setting) in &settings {
+        let args = setting_args(&capture, key, setting)?;
+        // Settings left over from different layers can still exclude each other; name them
+        // rather than the flags clap would report.
+        if let (false, Some(arg)) = (args.is_empty(), find_arg(&command, key)) {
+            let clash = applied.iter().find(|(other, _)| {
+                find_arg(&command, other).is_some_and(|other| conflicting(&command, arg, other))
+            });
+            if let Some((other, first)) = clash {
+                bail!(
+                    "{}: `{key}` cannot be used with `{other}` from {}",
+                    setting.source,
+                    first.source
+                );
+            }
+            applied.push((key, setting));
+        }
+        // Check each value on its own so a bad one is blamed on its file.
+        if let Err(err) = command
+            .clone()
+            .try_get_matches_from(std::iter::once(bin.clone()).chain(args.clone()))
+        {
+            if matches!(
+                err.kind(),
+                ErrorKind::InvalidValue | ErrorKind::ValueValidation
+            ) {
+                let detail = err.to_string();
+                let detail = detail.lines().next().unwrap_or_default();
+                bail!("{}: invalid `{key}`: {detail}", setting.source);
+            }
+        }
+        full.extend(args);
+    }
+    full.extend(argv.iter().cloned());
+    let matches = command
+        .try_get_matches_from(full)
+        .unwrap_or_else(|err| err.exit());
+    Ok((matches, settings))
+}
+
+fn find_arg<'a>(command: &'a clap::Command, key: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|a| a.get_long() == Some(key))
+}
+
+fn conflicting(command: &clap::Command, a: &Arg, b: &Arg) -> bool {
+    let excludes = |x: &Arg, y: &Arg| {
+        command
+            .get_arg_conflicts_with(x)
+            .iter()
+            .any(|c| c.get_id() == y.get_id())
+    };
+    excludes(a, b) || excludes(b, a)
+}
+
+/// Command-line words equivalent to one configured option.
+fn setting_args(command: &clap::Command, key: &str, setting: &Setting) -> Result<Vec<String>> {
+    let source = &setting.source;
+    let arg = match find_arg(command, key) {
+        Some(_) if CLI_ONLY.contains(&key) => {
+            bail!("{source}: `{key}` can only be given on the command line")
+        }
+        Some(arg) => arg,
+        None => bail!("{source}: unknown option `{key}`"),
+    };
+    let flag = !arg.get_action().takes_values();
+    let words = |value: &Value| -> Result<Vec<String>> {
+        Ok(match value {
+            Value::Boolean(true) if flag => vec![format!("--{key}")],
+            Value::Boolean(false) if flag => Vec::new(),
+            Value::String(s) if !flag => vec![format!("--{key}={s}")],
+            Value::Integer(n) if !flag => vec![format!("--{key}={n}")],
+            Value::Float(f) if !flag => vec![format!("--{key}={f}")],
+            _ if flag => bail!("{source}: `{key}` must be true or false"),
+            _ => bail!("{source}: `{key}` must be a string or number"),
+        })
+    };
+    match &setting.value {
+        Value::Array(items) if matches!(arg.get_action(), ArgAction::Append) => {
+            let mut out = Vec::new();
+            for item in items {
+                out.extend(words(item)?);
+            }
+            Ok(out)
+        }
+        value => words(value),
+    }
+}
+
+/// Runs `kcap config`.
+/// Parameters: `args` (ConfigArgs) parsed arguments.
+/// Returns: Result<()> error when a configuration file or profile is invalid.
+pub fn run(args: ConfigArgs) -> Result<()> {
+    match args.action {
+        ConfigAction::Show => {
+            let config = Config::load()?;
+            print!("{}", show(&config, &args.args)?);
+            Ok(())
+        }
+    }
+}
+
+/// Renders the effective capture settings as TOML, each commented with its source.
+/// Parameters: `config` (&Config) loaded configuration files.
+/// Parameters: `argv` (&[String]) capture flags, possibly selecting a profile.
+/// Returns: Result<String> text for `kcap config show`.
+fn show(config: &Config, argv: &[String]) -> Result<String> {
+    let (matches, settings) = resolve(Args::command(), config, argv)?;
+    let mut out = String::new();
+    if config.files.is_empty() {
+        out.push_str("# no configuration files found\n");
+    }
+    for file in &config.files {
+        out.push_str(&format!("# file: {}\n", file.path.display()));
+    }
+    if let Some(profile) = matches.get_one::<String>("profile") {
+        out.push_str(&format!("# profile: {profile}\n"));
+    }
+    let command = Args::command();
+    for arg in command.get_arguments() {
+        let Some(key) = arg.get_long().filter(|k| !CLI_ONLY.contains(k)) else {
+            continue;
+        };
+        let id = arg.get_id().as_str();
+        let Some(raw) = matches.get_raw(id) else {
+            continue;
+        };
+        let values: Vec<String> = raw.map(|v| v.to_string_lossy().into_owned()).collect();
+        let source = match (matches.value_source(id), settings.get(key)) {
+            (Some(ValueSource::CommandLine), Some(setting)) => setting.source.as_str(),
+            (Some(ValueSource::CommandLine), None) => "command line",
+            _ => "built-in default",
+        };
+        let rendered: Vec<String> = values.iter().map(|v| render(v, arg)).collect();
+        let value = if matches!(arg.get_action(), ArgAction::Append) {
+            format!("[{}]", rendered.join(", "))
+        } else {
+            rendered.concat()
+        };
+        out.push_str(&format!("{key} = {value}  # {source}\n"));
+    }
+    for (name, entry) in config.inventory().entries() {
+        let name = Value::String(name.clone());
+        out.push_str(&format!("\n[nodes.{name}]  # {}\n", entry.source));
+        let fields = [
+            ("host", entry.host.clone().map(Value::String)),
+            ("port", entry.port.map(|p| Value::Integer(p.into()))),
+            ("user", entry.user.clone().map(Value::String)),
+            ("jump-host", entry.jump_host.clone().map(Value::String)),
+        ];
+        for (key, value) in fields {
+            if let Some(value) = value {
+                out.push_str(&format!("{key} = {value}\n"));
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn render(raw: &str, arg: &Arg) -> String {
+    let bare = !arg.get_action().takes_values()
+        || raw.parse::<i64>().is_ok()
+        || raw.parse::<f64>().is_ok_and(f64::is_finite);
+    if bare {
+        raw.to_string()
+    } else {
+        Value::String(raw.to_string()).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::LogFormat;
+    use clap::FromArgMatches;
+
+    fn config() -> Config {
+        let user = ConfigFile::parse(
+            Path::new("/home/u/.config/kcap/config.toml"),
+            r#"
+            [defaults]
+            ssh-user = "ops"
+            jump-host = "bastion.example.com"
+            ssh-host = "10.0.0.5"
+            tee = ["copy.pcap"]
+
+            [profiles.prod-orders]
+            namespace = "prod"
+            pod = "orders-7f9c"
+            port = 8080
+            "#,
+        )
+        .unwrap();
+        let project = ConfigFile::parse(
+            Path::new("/work/kcap.toml"),
+            r#"
+            [defaults]
+            ssh-user = "deploy"
+
+            [nodes."prod-*"]
+            jump-host = "bastion-prod"
+
+            [profiles.prod-orders]
+            duration = 30
+            force = true
+            "#,
+        )
+        .unwrap();
+        Config {
+            files: vec![user, project],
+        }
+    }
+
+    fn args(argv: &[&str]) -> Args {
+        let argv: Vec<String> = argv.iter().map(|s| s.to_string()).collect();
+        let (matches, _) = resolve(Args::command(), &config(), &argv).unwrap();
+        Args::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn profile_overrides_defaults_and_cli_overrides_both() {
+        let plain = args(&[]);
+        assert_eq!(plain.ssh_user.as_deref(), Some("deploy"));
+        assert_eq!(plain.ssh_host.as_deref(), Some("10.0.0.5"));
+        assert_eq!(plain.tee, ["copy.pcap"]);
+        assert_eq!(plain.port, None);
+
+        let profiled = args(&["-P", "prod-orders", "--port", "9090"]);
+        assert_eq!(profiled.namespace.as_deref(), Some("prod"));
+        assert_eq!(profiled.pod.as_deref(), Some("orders-7f9c"));
+        // The profile's pod replaces the default SSH host instead of competing with it.
+        assert_eq!(profiled.ssh_host, None);
+        assert_eq!(profiled.jump_host.as_deref(), Some("bastion.example.com"));
+        assert_eq!(profiled.port, Some(9090));
+        assert_eq!(profiled.duration, Some(30));
+        assert!(profiled.force);
+
+        let selected = args(&["--profile", "prod-orders", "--selector", "app=orders"]);
+        assert_eq!(selected.pod, None);
+        assert_eq!(selected.selector.as_deref(), Some("app=orders"));
+    }
+
+    #[test]
+    fn subcommands_embedding_capture_options_use_the_config() {
+        let argv: Vec<String> = ["-P", "prod-orders", "--listen", "127.0.0.1:9000"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let (matches, _) = resolve(crate::serve::ServeArgs::command(), &config(), &argv).unwrap();
+        let serve = crate::serve::ServeArgs::from_arg_matches(&matches).unwrap();
+        assert_eq!(serve.listen, "127.0.0.1:9000");
+        assert_eq!(serve.args.pod.as_deref(), Some("orders-7f9c"));
+        assert_eq!(serve.args.ssh_user.as_deref(), Some("deploy"));
+
+        // Options of the embedding command are not capture options.
+        let file = ConfigFile::parse(Path::new("kcap.toml"), "[defaults]\nlisten = \"x\"").unwrap();
+        let config = Config { files: vec![file] };
+        let err = resolve(crate::serve::ServeArgs::command(), &config, &[]).unwrap_err();
+        assert!(err.to_string().contains("unknown option `listen`"));
+    }
+
+    #[test]
+    fn command_line_dispatches_subcommands_and_defaults_to_a_capture() {
+        let cli = |argv: &[&str]| {
+            let argv: Vec<String> = argv.iter().map(|s| s.to_string()).collect();
+            configured_cli(&config(), &argv).unwrap()
+        };
+        let capture = cli(&["--pod", "serve"]);
+        assert!(capture.command.is_none());
+        assert_eq!(capture.args.pod.as_deref(), Some("serve"));
+        assert_eq!(capture.args.ssh_user.as_deref(), Some("deploy"));
+
+        let Some(Command::Serve(serve)) = cli(&["serve", "-P", "prod-orders"]).command else {
+            panic!("expected kcap serve");
+        };
+        assert_eq!(serve.args.pod.as_deref(), Some("orders-7f9c"));
+        assert!(matches!(
+            cli(&["config", "show", "-P", "prod-orders"]).command,
+            Some(Command::Config(_))
+        ));
+    }
+
+    #[test]
+    fn configured_log_format_reaches_main() {
+        let file = ConfigFile::parse(
+            Path::new("kcap.toml"),
+            "[defaults]\nlog-format = \"json\"\n[profiles.quiet]\nlog-format = \"text\"",
+        )
+        .unwrap();
+        let config = Config { files: vec![file] };
+        let cli = |argv: &[&str]| {
+            let argv: Vec<String> = argv.iter().map(|s| s.to_string()).collect();
+            configured_cli(&config, &argv).unwrap()
+        };
+        assert_eq!(cli(&["--pod", "web-1"]).args.log_format, LogFormat::Json);
+        assert_eq!(cli(&["-P", "quiet"]).args.log_format, LogFormat::Text);
+        let Some(Command::Serve(serve)) = cli(&["serve"]).command else {
+            panic!("expected kcap serve");
+        };
+        assert_eq!(serve.args.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn configured_flags_yield_to_conflicting_cli_options() {
+        let file = ConfigFile::parse(
+            Path::new("kcap.toml"),
+            "[defaults]\nfollow = true\nvia-node = false\n\n[profiles.nodes]\nvia-node = true\n",
+        )
+        .unwrap();
+        let config = Config { files: vec![file] };
+        let resolve_args = |argv: &[&str]| {
+            let argv: Vec<String> = argv.iter().map(|s| s.to_string()).collect();
+            resolve(Args::command(), &config, &argv)
+                .map(|(matches, _)| Args::from_arg_matches(&matches).unwrap())
+        };
+
+        let ssh = resolve_args(&["--ssh-host", "10.0.0.9"]).unwrap();
+        assert_eq!(ssh.ssh_host.as_deref(), Some("10.0.0.9"));
+        assert!(!ssh.follow);
+        let pod = resolve_args(&["--pod", "p1"]).unwrap();
+        assert!(pod.follow);
+
+        let err = resolve_args(&["-P", "nodes", "--pod", "p1"]).unwrap_err().to_string();
+        assert!(
+            err.contains("profile nodes in kcap.toml: `via-node` cannot be used with `follow`"),
+            "{err}"
+        );
+        let nodes = resolve_args(&["-P", "nodes", "--pod", "p1", "--follow"]);
+        assert!(nodes.is_ok_and(|a| a.follow && !a.via_node));
+    }
+
+    #[test]
+    fn rejects_unknown_profiles_options_and_values() {
+        let argv = ["-P".to_string(), "staging".to_string()];
+        let err = resolve(Args::command(), &config(), &argv)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("unknown profile `staging` (configured: prod-orders)"));
+
+        let bad = |text: &str| {
+            let file = ConfigFile::parse(Path::new("kcap.toml"), text).unwrap();
+            let config = Config { files: vec![file] };
+            resolve(Args::command(), &config, &[])
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(bad("[defaults]\nssh-usr = \"x\"").contains("unknown option `ssh-usr`"));
+        assert!(bad("[defaults]\nforce = \"yes\"").contains("`force` must be true or false"));
+        assert!(
+            bad("[defaults]\nport = \"http\"").contains("defaults in kcap.toml: invalid `port`")
+        );
+        assert!(bad("[defaults]\nprofile = \"x\"").contains("only be given on the command line"));
+        assert!(ConfigFile::parse(Path::new("kcap.toml"), "[target]\npod = \"x\"").is_err());
+    }
+
+    #[test]
+    fn show_names_the_source_of_each_value() {
+        let argv = ["-P".to_string(), "prod-orders".to_string()];
+        let text = show(&config(), &argv).unwrap();
+        assert!(text.contains("# profile: prod-orders\n"));
+        assert!(text.contains("ssh-user = \"deploy\"  # defaults in /work/kcap.toml\n"));
+        assert!(text
+            .contains("port = 8080  # profile prod-orders in /home/u/.config/kcap/config.toml\n"));
+        assert!(text.contains("ssh-port = 22  # built-in default\n"));
+        assert!(text.contains("tee = [\"copy.pcap\"]  # defaults in"));
+        assert!(!text.contains("ssh-host ="));
+        assert!(text.contains("\n[nodes.\"prod-*\"]  # nodes.prod-* in /work/kcap.toml\n"));
+        assert!(text.ends_with("jump-host = \"bastion-prod\"\n"));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::dissect::{self, tcp_flags, Packet, Tcp, Transport};
+use crate::k8s::IpOwners;
+use crate::meter::Observer;
+use crate::pcap::{Block, BlockKind, PacketInfo};
+use std::io::Write;
+use std::net::IpAddr;
+
+/// Formats one packet as a tcpdump-style summary line, naming cluster addresses.
+/// Parameters: `info` (&PacketInfo) packet metadata from the capture stream.
+/// Parameters: `frame` (&[u8]) captured bytes.
+/// Parameters: `names` (&IpOwners) cluster objects to show next to addresses.
+/// Returns: String line without a trailing newline.
+pub fn format_packet(info: &PacketInfo, frame: &[u8], names: &IpOwners) -> String {
+    let time = time_of_day(info.ts_nanos);
+    let Some(packet) = dissect::dissect(info.linktype, frame) else {
+        return format!(
+            "{time} non-IP frame (linktype {}), length {}",
+            info.linktype, info.orig_len
+        );
+    };
+    let family = if packet.src.is_ipv6() { "IP6" } else { "IP" };
+    let (src_port, dst_port) = packet.ports().unzip();
+    format!(
+        "{time} {family} {} > {}: {}",
+        endpoint(packet.src, src_port, names),
+        endpoint(packet.dst, dst_port, names),
+        summary(&packet)
+    )
+}
+
+/// Observer printing `format_packet` lines for every packet of a metered stream.
+/// Parameters: `names` (IpOwners) cluster objects to show next to addresses.
+/// Parameters: `out` (Box<dyn Write + Send>) destination, normally stdout.
+/// Returns: Observer for `Meter::wrap`.
+pub fn observer(names: IpOwners, mut out: Box<dyn Write + Send>) -> Observer {
+    let mut closed = false;
+    Box::new(move |block: &Block| {
+        let (BlockKind::Packet(info), Some(frame)) = (&block.kind, block.packet_data()) else {
+            return;
+        };
+        // A reader that went away (e.g. `| head`) must not fail the capture itself.
+        if !closed {
+            let line = format_packet(info, frame, &names);
+            closed = writeln!(out, "{line}").and_then(|_| out.flush()).is_err();
+        }
+    })
+}
+
+fn time_of_day(ts_nanos: u64) -> String {
+    let secs = ts_nanos / 1_000_000_000;
+    let micros = ts_nanos % 1_000_000_000 / 1_000;
+    let day = secs % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{micros:06}",
+        day / 3600,
+        day % 3600 / 60,
+        day % 60
+    )
+}
+
+fn endpoint(ip: IpAddr, port: Option<u16>, names: &IpOwners) -> String {
+    let mut text = match port {
+        Some(port) => format!("{ip}.{port}"),
+        None => ip.to_string(),
+    };
+    if let Some(owner) = names.get(&ip) {
+        text.push_str(&format!(" ({})", owner.label()));
+    }
+    text
+}
+
+fn summary(packet: &Packet) -> String {
+    match &packet.transport {
+        Transport::Tcp(tcp) => tcp_summary(tcp),
+        Transport::Udp(udp) => format!("UDP, length {}", udp.payload_len),
+        Transport::Icmp { kind, code } => icmp_summary(*kind, *code),
+        Transport::Icmpv6 { kind, code } => icmpv6_summary(*kind, *code),
+        Transport::Fragment { protocol } => format!("ip-proto-{protocol} fragment"),
+        Transport::Other { protocol } => {
+            format!("ip-proto-{protocol}, length {}", packet.ip_len)
+        }
+    }
+}
+
+fn tcp_summary(tcp: &Tcp) -> String {
+    let mut line = format!("Flags [{}], seq {}", flag_letters(tcp.flags), tcp.seq);
+    if tcp.payload_len > 0 {
+        let end = tcp.seq.wrapping_add(tcp.payload_len as u32);
+        line.push_str(&format!(":{end}"));
+    }
+    if tcp.flags & tcp_flags::ACK != 0 {
+        line.push_str(&format!(", ack {}", tcp.ack));
+    }
+    line.push_str(&format!(", win {}", tcp.window));
+    let options: Vec<String> = tcp
+        .mss
+        .map(|mss| format!("mss {mss}"))
+        .into_iter()
+        .chain(tcp.window_scale.map(|shift| format!("wscale {shift}")))
+        .collect();
+    if !options.is_empty() {
+        line.push_str(&format!(", options [{}]", options.join(",")));
+    }
+    line.push_str(&format!(", length {}", tcp.payload_len));
+    line
+}
+
+fn flag_letters(flags: u8) -> String {
+    // Same letters and order as tcpdump; `.` is ACK.
+    const LETTERS: [(u8, char); 8] = [
+        (tcp_flags::FIN, 'F'),
+        (tcp_flags::SYN, 'S'),
+        (tcp_flags::RST, 'R'),
+        (tcp_flags::PSH, 'P'),
+        (tcp_flags::ACK, '.'),
+        (tcp_flags::URG, 'U'),
+        (tcp_flags::ECE, 'E'),
+        (tcp_flags::CWR, 'W'),
+    ];
+    let letters: String = LETTERS
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, letter)| letter)
+        .collect();
+    if letters.is_empty() {
+        "none".to_string()
+    } else {
+        letters
+    }
+}
+
+fn icmp_summary(kind: u8, code: u8) -> String {
+    match (kind, code) {
+        (0, _) => "ICMP echo reply".to_string(),
+        (8, _) => "ICMP echo request".to_string(),
+        (3, 3) => "ICMP port unreachable".to_string(),
+        (3, 4) => "ICMP fragmentation needed".to_string(),
+        (3, _) => format!("ICMP unreachable, code {code}"),
+        (11, _) => "ICMP time exceeded".to_string(),
+        _ => format!("ICMP type {kind}, code {code}"),
+    }
+}
+
+fn icmpv6_summary(kind: u8, code: u8) -> String {
+    match (kind, code) {
+        (128, _) => "ICMP6 echo request".to_string(),
+        (129, _) => "ICMP6 echo reply".to_string(),
+        (1, _) => format!("ICMP6 destination unreachable, code {code}"),
+        (2, _) => "ICMP6 packet too big".to_string(),
+        (3, _) => "ICMP6 time exceeded".to_string(),
+        (135, _) => "ICMP6 neighbor solicitation".to_string(),
+        (136, _) => "ICMP6 neighbor advertisement".to_string(),
+        _ => format!("ICMP6 type {kind}, code {code}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissect::testdata;
+    use crate::dissect::LINKTYPE_LINUX_SLL;
+    use crate::k8s::{IpOwner, OwnerKind};
+    use std::sync::{Arc, Mutex};
+
+    fn info(frame: &[u8], ts_nanos: u64) -> PacketInfo {
+        PacketInfo {
+            interface: 0,
+            linktype: LINKTYPE_LINUX_SLL,
+            ts_nanos,
+            orig_len: frame.len() as u32,
+            data: 0..frame.len(),
+        }
+    }
+
+    #[test]
+    fn formats_tcp_like_tcpdump_with_names() {
+        let frame = testdata::sll(&testdata::ipv4_tcp(
+            [10, 244, 1, 5],
+            [10, 96, 0, 20],
+            (40000, 8080),
+            (1000, 77),
+            tcp_flags::PSH | tcp_flags::ACK,
+            502,
+            b"GET / HTTP/1.1\r\n",
+        ));
+        let mut names = IpOwners::new();
+        names.insert(
+            "10.244.1.5".parse().unwrap(),
+            IpOwner {
+                kind: OwnerKind::Pod,
+                namespace: "prod".into(),
+                name: "orders-7f9c".into(),
+                node: Some("node-a".into()),
+            },
+        );
+        // 2024-03-01T12:00:01.000250Z
+        let ts = 1_709_294_401_000_250_000;
+        assert_eq!(
+            format_packet(&info(&frame, ts), &frame, &names),
+            "12:00:01.000250 IP 10.244.1.5.40000 (orders-7f9c.prod) > 10.96.0.20.8080: \
+             Flags [P.], seq 1000:1016, ack 77, win 502, length 16"
+        );
+    }
+
+    #[test]
+    fn formats_udp_icmp_and_non_ip() {
+        let names = IpOwners::new();
+        let udp = testdata::sll(&testdata::ipv4_udp(
+            [10, 0, 0, 1],
+            [10, 0, 0, 2],
+            (5353, 53),
+            b"q",
+        ));
+        assert!(format_packet(&info(&udp, 0), &udp, &names)
+            .ends_with("IP 10.0.0.1.5353 > 10.0.0.2.53: UDP, length 1"));
+
+        let mut icmp = testdata::ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (0, 0), b"");
+        icmp[9] = 1;
+        icmp[20] = 8;
+        let icmp = testdata::sll(&icmp);
+        assert!(format_packet(&info(&icmp, 0), &icmp, &names)
+            .ends_with("IP 10.0.0.1 > 10.0.0.2: ICMP echo request"));
+
+        let arp = vec![0u8; 42];
+        assert_eq!(
+            format_packet(&info(&arp, 0), &arp, &names),
+            "00:00:00.000000 non-IP frame (linktype 113), length 42"
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn observer_prints_one_line_per_packet() {
+        use crate::meter::Meter;
+        use crate::pcap::testdata as pcapdata;
+        use std::io::{Cursor, Read};
+
+        let frame = testdata::ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), b"x");
+        let capture = pcapdata::pcap_stream(&[(1, &frame), (2, &frame)]);
+        let out = Shared::default();
+        let observer = observer(IpOwners::new(), Box::new(out.clone()));
+        let mut copy = Vec::new();
+        Meter::new()
+            .wrap(Cursor::new(capture), vec![observer])
+            .read_to_end(&mut copy)
+            .unwrap();
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), 2);
+    }
+}
@@ -0,0 +1,497 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// BSD loopback encapsulation: a 4-byte address family in host byte order.
+pub const LINKTYPE_NULL: u32 = 0;
+/// Ethernet II, possibly VLAN tagged.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Raw IPv4 or IPv6, told apart by the version nibble.
+pub const LINKTYPE_RAW: u32 = 101;
+/// OpenBSD loopback: like `LINKTYPE_NULL` in network byte order.
+pub const LINKTYPE_LOOP: u32 = 108;
+/// Linux "cooked" capture, what `tcpdump -i any` writes.
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+/// Raw IPv4.
+pub const LINKTYPE_IPV4: u32 = 228;
+/// Raw IPv6.
+pub const LINKTYPE_IPV6: u32 = 229;
+/// Linux "cooked" capture v2, written by newer tcpdump for `-i any`.
+pub const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+/// TCP header flag bits.
+pub mod tcp_flags {
+    pub const FIN: u8 = 0x01;
+    pub const SYN: u8 = 0x02;
+    pub const RST: u8 = 0x04;
+    pub const PSH: u8 = 0x08;
+    pub const ACK: u8 = 0x10;
+    pub const URG: u8 = 0x20;
+    pub const ECE: u8 = 0x40;
+    pub const CWR: u8 = 0x80;
+}
+
+/// EtherType of IPv4.
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+/// EtherType of IPv6.
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+/// EtherType of ARP.
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// IP packet decoded from a captured frame.
+pub struct Packet<'a> {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    /// TTL or hop limit.
+    pub ttl: u8,
+    /// Length of the IP packet according to its header.
+    pub ip_len: usize,
+    /// Offset of the IP header in the frame.
+    pub ip_offset: usize,
+    /// Offset of the transport header (or fragment data) in the frame.
+    pub transport_offset: usize,
+    pub transport: Transport<'a>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Transport layer of a decoded packet.
+pub enum Transport<'a> {
+    Tcp(Tcp<'a>),
+    Udp(Udp<'a>),
+    Icmp {
+        kind: u8,
+        code: u8,
+    },
+    Icmpv6 {
+        kind: u8,
+        code: u8,
+    },
+    /// Non-first IP fragment, whose transport header is in an earlier packet.
+    Fragment {
+        protocol: u8,
+    },
+    /// Other IP protocol, or a transport header cut off by the snap length.
+    Other {
+        protocol: u8,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// TCP segment header and captured payload.
+pub struct Tcp<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    /// See `tcp_flags`.
+    pub flags: u8,
+    /// Unscaled receive window.
+    pub window: u16,
+    /// Window scale option, only present on SYN segments.
+    pub window_scale: Option<u8>,
+    pub mss: Option<u16>,
+    /// Payload length according to the IP header, even when the capture was truncated.
+    pub payload_len: usize,
+    /// Captured payload bytes, at most `payload_len`.
+    pub payload: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// UDP datagram header and captured payload.
+pub struct Udp<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    /// Payload length according to the UDP header.
+    pub payload_len: usize,
+    pub payload: &'a [u8],
+}
+
+impl Packet<'_> {
+    /// Transport protocol name as used in filters and summaries.
+    /// Returns: &'static str e.g. `tcp`, `udp`, `icmp`.
+    pub fn protocol_name(&self) -> &'static str {
+        match self.transport {
+            Transport::Tcp(_) => "tcp",
+            Transport::Udp(_) => "udp",
+            Transport::Icmp { .. } => "icmp",
+            Transport::Icmpv6 { .. } => "icmp6",
+            Transport::Fragment { .. } => "frag",
+            Transport::Other { .. } => "ip",
+        }
+    }
+
+    /// Source and destination ports for TCP and UDP.
+    /// Returns: Option<(u16, u16)> None for portless protocols.
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        match &self.transport {
+            Transport::Tcp(t) => Some((t.src_port, t.dst_port)),
+            Transport::Udp(u) => Some((u.src_port, u.dst_port)),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the IP and transport headers of a captured frame.
+/// Parameters: `linktype` (u32) link-layer header type of the interface.
+/// Parameters: `frame` (&[u8]) captured bytes.
+/// Returns: Option<Packet> None for non-IP frames, unknown link types or truncated headers.
+pub fn dissect(linktype: u32, frame: &[u8]) -> Option<Packet<'_>> {
+    let (ethertype, offset) = network_layer(linktype, frame)?;
+    let data = frame.get(offset..)?;
+    let mut packet = match ethertype {
+        ETHERTYPE_IPV4 => ipv4(data)?,
+        ETHERTYPE_IPV6 => ipv6(data)?,
+        _ => return None,
+    };
+    packet.ip_offset = offset;
+    packet.transport_offset += offset;
+    Some(packet)
+}
+
+/// Finds the network layer of a captured frame.
+/// Parameters: `linktype` (u32) link-layer header type of the interface.
+/// Parameters: `frame` (&[u8]) captured bytes.
+/// Returns: Option<(u16, usize)> EtherType of the network protocol and its offset in `frame`.
+pub fn network_layer(linktype: u32, frame: &[u8]) -> Option<(u16, usize)> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = be16(frame, 12)?;
+            let mut offset = 14;
+            // 802.1Q and 802.1ad tags, possibly stacked.
+            while matches!(ethertype, 0x8100 | 0x88a8 | 0x9100) {
+                ethertype = be16(frame, offset + 2)?;
+                offset += 4;
+            }
+            Some((ethertype, offset))
+        }
+        LINKTYPE_LINUX_SLL => Some((be16(frame, 14)?, 16)),
+        LINKTYPE_LINUX_SLL2 => Some((be16(frame, 0)?, 20)),
+        LINKTYPE_NULL | LINKTYPE_LOOP => {
+            let family = frame.get(..4)?;
+            let family = if linktype == LINKTYPE_LOOP {
+                u32::from_be_bytes(family.try_into().ok()?)
+            } else {
+                // Host byte order of the capturing machine; families are small numbers.
+                let le = u32::from_le_bytes(family.try_into().ok()?);
+                if le > 0xffff {
+                    le.swap_bytes()
+                } else {
+                    le
+                }
+            };
+            match family {
+                2 => Some((ETHERTYPE_IPV4, 4)),
+                // AF_INET6 differs between BSDs.
+                10 | 24 | 28 | 30 => Some((ETHERTYPE_IPV6, 4)),
+                _ => None,
+            }
+        }
+        LINKTYPE_RAW => match frame.first()? >> 4 {
+            4 => Some((ETHERTYPE_IPV4, 0)),
+            6 => Some((ETHERTYPE_IPV6, 0)),
+            _ => None,
+        },
+        LINKTYPE_IPV4 => Some((ETHERTYPE_IPV4, 0)),
+        LINKTYPE_IPV6 => Some((ETHERTYPE_IPV6, 0)),
+        _ => None,
+    }
+}
+
+fn ipv4(data: &[u8]) -> Option<Packet<'_>> {
+    if data.first()? >> 4 != 4 {
+        return None;
+    }
+    let ihl = usize::from(data[0] & 0x0f) * 4;
+    let total = usize::from(be16(data, 2)?);
+    let protocol = *data.get(9)?;
+    let src = Ipv4Addr::from(<[u8; 4]>::try_from(data.get(12..16)?).ok()?);
+    let dst = Ipv4Addr::from(<[u8; 4]>::try_from(data.get(16..20)?).ok()?);
+    if ihl < 20 {
+        return None;
+    }
+    // Segmentation offload hands the capture packets with a zero total length.
+    let total = if total == 0 { data.len() } else { total };
+    let fragment_offset = be16(data, 6)? & 0x1fff;
+    let transport = if fragment_offset != 0 {
+        Transport::Fragment { protocol }
+    } else {
+        let captured = data.get(ihl..total.min(data.len())).unwrap_or(&[]);
+        transport(protocol, captured, total.saturating_sub(ihl), false)
+    };
+    Some(Packet {
+        src: IpAddr::V4(src),
+        dst: IpAddr::V4(dst),
+        ttl: data[8],
+        ip_len: total,
+        ip_offset: 0,
+        transport_offset: ihl,
+        transport,
+    })
+}
+
+fn ipv6(data: &[u8]) -> Option<Packet<'_>> {
+    if data.first()? >> 4 != 6 {
+        return None;
+    }
+    let payload_len = usize::from(be16(data, 4)?);
+    let mut next = *data.get(6)?;
+    let ttl = *data.get(7)?;
+    let src = Ipv6Addr::from(<[u8; 16]>::try_from(data.get(8..24)?).ok()?);
+    let dst = Ipv6Addr::from(<[u8; 16]>::try_from(data.get(24..40)?).ok()?);
+    let payload_len = if payload_len == 0 {
+        data.len().saturating_sub(40)
+    } else {
+        payload_len
+    };
+    let end = (40 + payload_len).min(data.len());
+    let mut offset = 40;
+    let mut fragment = false;
+    loop {
+        match next {
+            // Hop-by-hop, routing and destination options.
+            0 | 43 | 60 => {
+                let Some(len) = data.get(offset + 1) else {
+                    break;
+                };
+                next = data[offset];
+                offset += (usize::from(*len) + 1) * 8;
+            }
+            44 => {
+                let Some(frag) = be16(data, offset + 2) else {
+                    break;
+                };
+                next = data[offset];
+                fragment = frag & 0xfff8 != 0;
+                offset += 8;
+            }
+            _ => break,
+        }
+    }
+    let transport = if fragment {
+        Transport::Fragment { protocol: next }
+    } else {
+        let captured = data.get(offset..end).unwrap_or(&[]);
+        transport(
+            next,
+            captured,
+            (40 + payload_len).saturating_sub(offset),
+            true,
+        )
+    };
+    Some(Packet {
+        src: IpAddr::V6(src),
+        dst: IpAddr::V6(dst),
+        ttl,
+        ip_len: 40 + payload_len,
+        ip_offset: 0,
+        transport_offset: offset,
+        transport,
+    })
+}
+
+fn transport(protocol: u8, data: &[u8], declared: usize, v6: bool) -> Transport<'_> {
+    let other = Transport::Other { protocol };
+    match protocol {
+        6 => tcp(data, declared).map_or(other, Transport::Tcp),
+        17 => match (be16(data, 0), be16(data, 2)) {
+            (Some(src_port), Some(dst_port)) => Transport::Udp(Udp {
+                src_port,
+                dst_port,
+                payload_len: be16(data, 4).map_or(0, |len| usize::from(len).saturating_sub(8)),
+                payload: data.get(8..).unwrap_or(&[]),
+            }),
+            _ => other,
+        },
+        1 if !v6 && data.len() >= 2 => Transport::Icmp {
+            kind: data[0],
+            code: data[1],
+        },
+        58 if v6 && data.len() >= 2 => Transport::Icmpv6 {
+            kind: data[0],
+            code: data[1],
+        },
+        _ => other,
+    }
+}
+
+fn tcp(data: &[u8], declared: usize) -> Option<Tcp<'_>> {
+    let header_len = usize::from(*data.get(12)? >> 4) * 4;
+    if header_len < 20 || data.len() < 20 {
+        return None;
+    }
+    let mut window_scale = None;
+    let mut mss = None;
+    let options = data.get(20..header_len).unwrap_or(&[]);
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => break,
+            1 => i += 1,
+            kind => {
+                let Some(&len) = options.get(i + 1) else {
+                    break;
+                };
+                let len = usize::from(len);
+                if len < 2 || i + len > options.len() {
+                    break;
+                }
+                match (kind, len) {
+                    (2, 4) => mss = be16(options, i + 2),
+                    (3, 3) => window_scale = Some(options[i + 2]),
+                    _ => {}
+                }
+                i += len;
+            }
+        }
+    }
+    Some(Tcp {
+        src_port: be16(data, 0)?,
+        dst_port: be16(data, 2)?,
+        seq: be32(data, 4)?,
+        ack: be32(data, 8)?,
+        flags: data[13],
+        window: be16(data, 14)?,
+        window_scale,
+        mss,
+        payload_len: declared.saturating_sub(header_len),
+        payload: data.get(header_len..).unwrap_or(&[]),
+    })
+}
+
+/// Reads a big-endian u16.
+/// Parameters: `data` (&[u8]) bytes to read from.
+/// Parameters: `off` (usize) offset of the field.
+/// Returns: Option<u16> None when `data` is too short.
+pub(crate) fn be16(data: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(off..off + 2)?.try_into().ok()?))
+}
+
+fn be32(data: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(off..off + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+pub(crate) mod testdata {
+    /// Builds an IPv4 TCP packet without link-layer header.
+    pub fn ipv4_tcp(
+        src: [u8; 4],
+        dst: [u8; 4],
+        ports: (u16, u16),
+        (seq, ack): (u32, u32),
+        flags: u8,
+        window: u16,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let total = 20 + 20 + payload.len();
+        let mut p = vec![0x45, 0];
+        p.extend_from_slice(&(total as u16).to_be_bytes());
+        p.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&dst);
+        p.extend_from_slice(&ports.0.to_be_bytes());
+        p.extend_from_slice(&ports.1.to_be_bytes());
+        p.extend_from_slice(&seq.to_be_bytes());
+        p.extend_from_slice(&ack.to_be_bytes());
+        p.extend_from_slice(&[0x50, flags]);
+        p.extend_from_slice(&window.to_be_bytes());
+        p.extend_from_slice(&[0, 0, 0, 0]);
+        p.extend_from_slice(payload);
+        p
+    }
+
+    /// Builds an IPv4 UDP packet without link-layer header.
+    pub fn ipv4_udp(src: [u8; 4], dst: [u8; 4], ports: (u16, u16), payload: &[u8]) -> Vec<u8> {
+        let total = 20 + 8 + payload.len();
+        let mut p = vec![0x45, 0];
+        p.extend_from_slice(&(total as u16).to_be_bytes());
+        p.extend_from_slice(&[0, 0, 0x40, 0, 64, 17, 0, 0]);
+        p.extend_from_slice(&src);
+        p.extend_from_slice(&dst);
+        p.extend_from_slice(&ports.0.to_be_bytes());
+        p.extend_from_slice(&ports.1.to_be_bytes());
+        p.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+        p.extend_from_slice(&[0, 0]);
+        p.extend_from_slice(payload);
+        p
+    }
+
+    /// Prefixes an IP packet with a Linux cooked (SLL) header.
+    pub fn sll(ip: &[u8]) -> Vec<u8> {
+        let mut f = vec![0u8; 14];
+        let ethertype: u16 = if ip[0] >> 4 == 6 { 0x86dd } else { 0x0800 };
+        f.extend_from_slice(&ethertype.to_be_bytes());
+        f.extend_from_slice(ip);
+        f
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dissects_tcp_over_cooked_capture() {
+        let ip = testdata::ipv4_tcp(
+            [10, 0, 0, 1],
+            [10, 0, 0, 2],
+            (40000, 443),
+            (7, 9),
+            tcp_flags::PSH | tcp_flags::ACK,
+            512,
+            b"hello",
+        );
+        let frame = testdata::sll(&ip);
+        let p = dissect(LINKTYPE_LINUX_SLL, &frame).unwrap();
+        assert_eq!(p.src, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(p.ports(), Some((40000, 443)));
+        let Transport::Tcp(tcp) = p.transport else {
+            panic!("not tcp");
+        };
+        assert_eq!((tcp.seq, tcp.ack, tcp.window), (7, 9, 512));
+        assert_eq!(tcp.payload, b"hello");
+        assert_eq!(tcp.payload_len, 5);
+    }
+
+    #[test]
+    fn truncated_payload_keeps_declared_length() {
+        let ip = testdata::ipv4_tcp([1, 1, 1, 1], [2, 2, 2, 2], (1, 2), (0, 0), 0, 0, &[7; 100]);
+        let p = dissect(LINKTYPE_RAW, &ip[..60]).unwrap();
+        let Transport::Tcp(tcp) = p.transport else {
+            panic!("not tcp");
+        };
+        assert_eq!(tcp.payload.len(), 20);
+        assert_eq!(tcp.payload_len, 100);
+    }
+
+    #[test]
+    fn dissects_vlan_tagged_udp() {
+        let ip = testdata::ipv4_udp([10, 0, 0, 1], [10, 0, 0, 53], (5353, 53), b"q");
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x0a, 0x08, 0x00]);
+        frame.extend_from_slice(&ip);
+        let p = dissect(LINKTYPE_ETHERNET, &frame).unwrap();
+        assert_eq!(p.protocol_name(), "udp");
+        assert_eq!(p.ports(), Some((5353, 53)));
+    }
+
+    #[test]
+    fn dissects_ipv6_with_extension_header() {
+        let mut ip = vec![0x60, 0, 0, 0];
+        ip.extend_from_slice(&(8u16 + 8).to_be_bytes());
+        ip.extend_from_slice(&[0, 64]); // hop-by-hop options, hop limit
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ip.extend_from_slice(&[17, 0, 0, 0, 0, 0, 0, 0]);
+        ip.extend_from_slice(&[0, 53, 0x9c, 0x40, 0, 8, 0, 0]);
+        let p = dissect(LINKTYPE_RAW, &ip).unwrap();
+        assert_eq!(p.ports(), Some((53, 40000)));
+        assert_eq!(p.ttl, 64);
+    }
+
+    #[test]
+    fn ignores_non_ip_frames() {
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06, 0, 1]);
+        assert_eq!(dissect(LINKTYPE_ETHERNET, &arp), None);
+        assert_eq!(dissect(9999, &[0x45; 40]), None);
+    }
+}
@@ -0,0 +1,280 @@
+use thiserror::Error;
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+/// Failures automation can act on, each with a stable exit code.
+pub enum KcapError {
+    #[error("cannot connect to {host} over ssh: {detail}")]
+    SshConnect { host: String, detail: String },
+
+    #[error("ssh authentication to {host} failed: {detail}")]
+    SshAuth { host: String, detail: String },
+
+    #[error("kubectl not found on PATH")]
+    KubectlNotFound,
+
+    #[error("pod {pod} not found")]
+    PodNotFound { pod: String },
+
+    #[error("{tool} is not installed on {target}")]
+    ToolMissing { tool: String, target: String },
+
+    #[error("permission denied capturing on {target}: {detail}")]
+    PermissionDenied { target: String, detail: String },
+
+    #[error("capture filter rejected on {target}: {detail}")]
+    InvalidFilter { target: String, detail: String },
+
+    #[error("failed to write output {output}: {detail}")]
+    OutputWrite { output: String, detail: String },
+
+    #[error("remote command failed for {target} ({status}){}", detail_suffix(.detail))]
+    RemoteExit {
+        target: String,
+        status: String,
+        detail: String,
+    },
+}
+
+fn detail_suffix(detail: &str) -> String {
+    if detail.is_empty() {
+        String::new()
+    } else {
+        format!(": {detail}")
+    }
+}
+
+impl KcapError {
+    /// Process exit code: 20-29 are worth retrying, 30-39 need a configuration change.
+    /// Returns: i32 stable exit code.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            KcapError::SshConnect { .. } => 20,
+            KcapError::RemoteExit { .. } => 21,
+            KcapError::SshAuth { .. } => 30,
+            KcapError::KubectlNotFound => 31,
+            KcapError::PodNotFound { .. } => 32,
+            KcapError::ToolMissing { .. } => 33,
+            KcapError::PermissionDenied { .. } => 34,
+            KcapError::InvalidFilter { .. } => 35,
+            KcapError::OutputWrite { .. } => 36,
+        }
+    }
+
+    /// Whether running the same command again may succeed.
+    /// Returns: bool true for transient failures.
+    pub fn retryable(&self) -> bool {
+        self.exit_code() < 30
+    }
+
+    /// Suggested fix shown under the error message.
+    /// Returns: &'static str remediation hint.
+    pub fn hint(&self) -> &'static str {
+        match self {
+            KcapError::SshConnect { .. } => {
+                "check the host name, --ssh-port and --jump-host, then retry"
+            }
+            KcapError::SshAuth { .. } => {
+                "check --ssh-user and that your key is loaded (ssh-add -l) or in ~/.ssh/config"
+            }
+            KcapError::KubectlNotFound => {
+                "install kubectl or use --kube-backend native (needs the native-k8s build)"
+            }
+            KcapError::PodNotFound { .. } => {
+                "check the pod name and -n/--namespace (kubectl get pods -n NAMESPACE)"
+            }
+            KcapError::ToolMissing { .. } => {
+                "install tcpdump (or tshark for --format pcapng) on the target or in the container"
+            }
+            KcapError::PermissionDenied { .. } => {
+                "run as root or grant NET_ADMIN/NET_RAW to the container (or cap_net_raw to tcpdump)"
+            }
+            KcapError::InvalidFilter { .. } => {
+                "fix --filter; it must be valid pcap-filter(7) syntax"
+            }
+            KcapError::OutputWrite { .. } => {
+                "check that the output directory exists, is writable and has free space"
+            }
+            KcapError::RemoteExit { .. } => {
+                "see the remote error output above; retry if it was transient"
+            }
+        }
+    }
+}
+
+/// Finds the typed error behind an `anyhow` chain.
+/// Parameters: `err` (&anyhow::Error) error returned by a run.
+/// Returns: Option<&KcapError> the first typed cause.
+pub fn find(err: &anyhow::Error) -> Option<&KcapError> {
+    err.chain().find_map(|e| e.downcast_ref::<KcapError>())
+}
+
+/// Exit code for a failed run; 1 when the failure is not classified.
+/// Parameters: `err` (&anyhow::Error) error returned by a run.
+/// Returns: i32 process exit code.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    find(err).map_or(1, KcapError::exit_code)
+}
+
+/// Classifies a failed remote capture from its exit code and stderr.
+/// Parameters: `target` (&str) capture target label.
+/// Parameters: `host` (Option<&str>) SSH host when the capture ran over SSH.
+/// Parameters: `tool` (&str) capture program.
+/// Parameters: `status` (&str) printable exit status.
+/// Parameters: `code` (Option<i32>) exit code, None when killed by a signal.
+/// Parameters: `stderr` (&[String]) last stderr lines.
+/// Returns: KcapError most specific match.
+pub fn classify_remote(
+    target: &str,
+    host: Option<&str>,
+    tool: &str,
+    status: &str,
+    code: Option<i32>,
+    stderr: &[String],
+) -> KcapError {
+    let has = |needle: &str| stderr.iter().rev().find(|l| l.contains(needle)).cloned();
+    let target = target.to_string();
+
+    // ssh reports its own failures with exit code 255.
+    if let (Some(host), Some(255)) = (host, code) {
+        let host = host.to_string();
+        if let Some(detail) = has("Permission denied (").or_else(|| has("Too many authentication"))
+        {
+            return KcapError::SshAuth { host, detail };
+        }
+        for needle in [
+            "Could not resolve hostname",
+            "Connection refused",
+            "Connection timed out",
+            "No route to host",
+            "Connection closed",
+            "Network is unreachable",
+        ] {
+            if let Some(detail) = has(needle) {
+                return KcapError::SshConnect { host, detail };
+            }
+        }
+    }
+    if let Some(detail) = has("(NotFound)") {
+        let pod = detail.split('"').nth(1).unwrap_or(&target).to_string();
+        return KcapError::PodNotFound { pod };
+    }
+    let missing = code == Some(127)
+        || [
+            format!("{tool}: not found"),
+            format!("{tool}: command not found"),
+        ]
+        .iter()
+        .any(|n| has(n).is_some())
+        || has("executable file not found").is_some();
+    if missing {
+        return KcapError::ToolMissing {
+            tool: tool.to_string(),
+            target,
+        };
+    }
+    for needle in [
+        "syntax error in filter expression",
+        "can't parse filter expression",
+        "Invalid capture filter",
+    ] {
+        if let Some(detail) = has(needle) {
+            return KcapError::InvalidFilter { target, detail };
+        }
+    }
+    for needle in [
+        "You don't have permission",
+        "Operation not permitted",
+        "Permission denied",
+    ] {
+        if let Some(detail) = has(needle) {
+            return KcapError::PermissionDenied { target, detail };
+        }
+    }
+    KcapError::RemoteExit {
+        target,
+        status: status.to_string(),
+        detail: stderr.last().cloned().unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn lines(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn classify(host: Option<&str>, code: i32, stderr: &[&str]) -> KcapError {
+        classify_remote(
+            "ssh/node1",
+            host,
+            "tcpdump",
+            "exit",
+            Some(code),
+            &lines(stderr),
+        )
+    }
+
+    #[test]
+    fn classifies_ssh_failures() {
+        let auth = classify(
+            Some("node1"),
+            255,
+            &["node1: Permission denied (publickey)."],
+        );
+        assert!(matches!(auth, KcapError::SshAuth { .. }));
+        assert!(!auth.retryable());
+        let conn = classify(
+            Some("node1"),
+            255,
+            &["ssh: connect to host node1 port 22: Connection refused"],
+        );
+        assert!(matches!(conn, KcapError::SshConnect { .. }));
+        assert!(conn.retryable());
+    }
+
+    #[test]
+    fn classifies_capture_failures() {
+        assert!(matches!(
+            classify(None, 127, &["sh: 1: tcpdump: not found"]),
+            KcapError::ToolMissing { .. }
+        ));
+        assert!(matches!(
+            classify(
+                None,
+                1,
+                &["tcpdump: any: You don't have permission to capture on that device"]
+            ),
+            KcapError::PermissionDenied { .. }
+        ));
+        assert!(matches!(
+            classify(None, 1, &["tcpdump: syntax error in filter expression"]),
+            KcapError::InvalidFilter { .. }
+        ));
+        assert_eq!(
+            classify(
+                None,
+                1,
+                &["Error from server (NotFound): pods \"web-1\" not found"]
+            ),
+            KcapError::PodNotFound {
+                pod: "web-1".into()
+            }
+        );
+        assert!(matches!(
+            classify(None, 2, &["something else"]),
+            KcapError::RemoteExit { .. }
+        ));
+    }
+
+    #[test]
+    fn exit_code_survives_context() {
+        let err = Err::<(), _>(KcapError::KubectlNotFound)
+            .context("failed to start capture")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 31);
+        assert_eq!(exit_code(&anyhow::anyhow!("plain")), 1);
+    }
+}
@@ -0,0 +1,272 @@
+use crate::capture::CaptureStats;
+use crate::compress::CompressionStats;
+use crate::meter::Snapshot;
+use crate::naming::rfc3339;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+/// Version of the event schema; bumped only for incompatible changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Time between `progress` events.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+static SINK: OnceLock<Mutex<Box<dyn Write + Send>>> = OnceLock::new();
+static JSON_STDERR: AtomicBool = AtomicBool::new(false);
+static ON_STDERR: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq)]
+/// Something automation may want to react to during a run.
+pub enum Event<'a> {
+    /// A capture target was picked, with the node it runs on when known.
+    TargetResolved {
+        target: &'a str,
+        node: Option<&'a str>,
+    },
+    /// The command to run on the target is final.
+    CommandBuilt { target: &'a str, command: &'a str },
+    /// The remote capture process was started.
+    CaptureStarted { target: &'a str },
+    /// Periodic counts of the capture stream.
+    Progress(Snapshot),
+    /// `rotate:` output moved on to a new file.
+    Rotated { file: &'a str },
+    /// Exit counters of one capture, with the kernel drop percentage.
+    Drops {
+        target: &'a str,
+        stats: CaptureStats,
+        warn: bool,
+    },
+    /// Bytes a compressed remote stream carried.
+    Compression {
+        target: &'a str,
+        stats: CompressionStats,
+    },
+    /// All captures ended.
+    Stopped { totals: Snapshot, ok: bool },
+    /// The run failed; `code` is the process exit code.
+    Error {
+        message: &'a str,
+        code: i32,
+        retryable: Option<bool>,
+        hint: Option<&'a str>,
+    },
+}
+
+impl Event<'_> {
+    /// Renders the event as one schema object.
+    /// Parameters: `time` (SystemTime) when the event happened.
+    /// Returns: serde_json::Value with `schema`, `ts`, `event` and the event's fields.
+    pub fn to_json(&self, time: SystemTime) -> Value {
+        let (name, fields) = match self {
+            Event::TargetResolved { target, node } => {
+                ("target_resolved", json!({ "target": target, "node": node }))
+            }
+            Event::CommandBuilt { target, command } => (
+                "command_built",
+                json!({ "target": target, "command": command }),
+            ),
+            Event::CaptureStarted { target } => ("capture_started", json!({ "target": target })),
+            Event::Progress(snap) => ("progress", counts(snap)),
+            Event::Rotated { file } => ("rotated", json!({ "file": file })),
+            Event::Drops {
+                target,
+                stats,
+                warn,
+            } => (
+                "drops",
+                json!({
+                    "target": target,
+                    "captured": stats.captured,
+                    "received_by_filter": stats.received,
+                    "dropped_by_kernel": stats.dropped_by_kernel,
+                    "dropped_by_interface": stats.dropped_by_interface,
+                    "drop_percent": stats.drop_percent(),
+                    "warn": warn,
+                }),
+            ),
+            Event::Compression { target, stats } => (
+                "compression",
+                json!({
+                    "target": target,
+                    "codec": stats.codec.name(),
+                    "wire_bytes": stats.wire_bytes,
+                    "capture_bytes": stats.capture_bytes,
+                    "ratio": stats.ratio().map(round),
+                }),
+            ),
+            Event::Stopped { totals, ok } => {
+                let mut fields = counts(totals);
+                fields["status"] = json!(if *ok { "ok" } else { "failed" });
+                ("stopped", fields)
+            }
+            Event::Error {
+                message,
+                code,
+                retryable,
+                hint,
+            } => (
+                "error",
+                json!({
+                    "message": message,
+                    "exit_code": code,
+                    "retryable": retryable,
+                    "hint": hint,
+                }),
+            ),
+        };
+        let mut doc = json!({
+            "schema": SCHEMA_VERSION,
+            "ts": rfc3339(time),
+            "event": name,
+        });
+        if let (Some(doc), Value::Object(fields)) = (doc.as_object_mut(), fields) {
+            doc.extend(fields);
+        }
+        doc
+    }
+}
+
+fn counts(snap: &Snapshot) -> Value {
+    json!({
+        "packets": snap.packets,
+        "bytes": snap.bytes,
+        "elapsed_secs": round(snap.elapsed.as_secs_f64()),
+        "packets_per_sec": round(snap.packets_per_sec()),
+        "bytes_per_sec": round(snap.bytes_per_sec()),
+    })
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
+
+/// Sends events to a destination for the rest of the process; later calls are ignored.
+/// Parameters: `dest` (&str) `-` for stderr, otherwise a file to create.
+/// Returns: Result<()> error when the file cannot be created.
+pub fn init(dest: &str) -> Result<()> {
+    if SINK.get().is_some() {
+        return Ok(());
+    }
+    let writer: Box<dyn Write + Send> = if dest == "-" {
+        ON_STDERR.store(true, Ordering::Relaxed);
+        Box::new(io::stderr())
+    } else {
+        Box::new(File::create(dest).with_context(|| format!("failed to create {dest}"))?)
+    };
+    let _ = SINK.set(Mutex::new(writer));
+    Ok(())
+}
+
+/// Whether `init` was called, i.e. events are being written.
+/// Returns: bool true when `emit` writes.
+pub fn enabled() -> bool {
+    SINK.get().is_some()
+}
+
+/// Whether events go to stderr, so errors need no separate log line.
+/// Returns: bool true after `init("-")`.
+pub fn on_stderr() -> bool {
+    ON_STDERR.load(Ordering::Relaxed)
+}
+
+/// Writes one event as a JSON line; does nothing until `init` was called.
+/// Parameters: `event` (&Event) event to write.
+pub fn emit(event: &Event) {
+    let Some(sink) = SINK.get() else {
+        return;
+    };
+    let line = event.to_json(SystemTime::now()).to_string();
+    let mut sink = sink.lock().unwrap_or_else(|e| e.into_inner());
+    // Events are advisory; a closed pipe must not fail the capture.
+    let _ = writeln!(sink, "{line}").and_then(|_| sink.flush());
+}
+
+/// Reserves stderr for JSON (`--log-format json`), turning off kcap's plain-text notices.
+/// Parameters: `json` (bool) whether stderr carries JSON only.
+pub fn set_json_stderr(json: bool) {
+    JSON_STDERR.store(json, Ordering::Relaxed);
+}
+
+/// Whether kcap may print plain-text notices to stderr.
+/// Returns: bool false under `--log-format json`.
+pub fn human_stderr() -> bool {
+    !JSON_STDERR.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn renders_schema_fields() {
+        let t = UNIX_EPOCH + Duration::from_secs(1_709_294_400);
+        let doc = Event::CommandBuilt {
+            target: "ssh/node1",
+            command: "tcpdump -i any -U -s 0 -w -",
+        }
+        .to_json(t);
+        assert_eq!(
+            doc,
+            json!({
+                "schema": 1,
+                "ts": "2024-03-01T12:00:00Z",
+                "event": "command_built",
+                "target": "ssh/node1",
+                "command": "tcpdump -i any -U -s 0 -w -",
+            })
+        );
+    }
+
+    #[test]
+    fn renders_counts_and_rates() {
+        let snap = Snapshot {
+            packets: 10,
+            bytes: 4000,
+            elapsed: Duration::from_secs(4),
+        };
+        let doc = Event::Stopped {
+            totals: snap,
+            ok: true,
+        }
+        .to_json(UNIX_EPOCH);
+        assert_eq!(doc["event"], "stopped");
+        assert_eq!(doc["packets_per_sec"], 2.5);
+        assert_eq!(doc["bytes_per_sec"], 1000.0);
+        assert_eq!(doc["status"], "ok");
+
+        let drops = Event::Drops {
+            target: "pod/default/web",
+            stats: CaptureStats {
+                captured: Some(90),
+                received: Some(100),
+                dropped_by_kernel: Some(10),
+                dropped_by_interface: None,
+            },
+            warn: true,
+        }
+        .to_json(UNIX_EPOCH);
+        assert_eq!(drops["dropped_by_kernel"], 10);
+        assert!(drops["dropped_by_interface"].is_null());
+        assert_eq!(drops["warn"], true);
+
+        let compression = Event::Compression {
+            target: "pod/default/web",
+            stats: CompressionStats {
+                codec: crate::compress::Codec::Gzip,
+                wire_bytes: 3,
+                capture_bytes: 10,
+            },
+        }
+        .to_json(UNIX_EPOCH);
+        assert_eq!(compression["event"], "compression");
+        assert_eq!(compression["codec"], "gzip");
+        assert_eq!(compression["ratio"], 3.333);
+    }
+}
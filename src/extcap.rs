@@ -0,0 +1,255 @@
+use crate::cli::Args;
+use anyhow::{bail, Result};
+use clap::Parser;
+
+// Wireshark extcap protocol: Wireshark runs kcap to list interfaces, link types
+// and config fields, then runs it again with `--capture --fifo <path>`.
+// https://www.wireshark.org/docs/wsdg_html_chunked/ChCaptureExtcap.html
+
+/// Interface name kcap advertises to Wireshark.
+pub const INTERFACE: &str = "kcap";
+
+#[derive(Parser, Debug, Clone)]
+#[command(name = "kcap", about = "Wireshark extcap entry point for kcap")]
+/// Extcap arguments; the capture fields map straight onto `Args`.
+pub struct ExtcapArgs {
+    #[command(flatten)]
+    pub args: Args,
+
+    #[arg(long, help = "List extcap interfaces")]
+    pub extcap_interfaces: bool,
+
+    #[arg(long, help = "Wireshark version calling the plugin")]
+    pub extcap_version: Option<String>,
+
+    #[arg(long, help = "Extcap interface to operate on")]
+    pub extcap_interface: Option<String>,
+
+    #[arg(long, help = "List link types of the interface")]
+    pub extcap_dlts: bool,
+
+    #[arg(long, help = "List configuration fields of the interface")]
+    pub extcap_config: bool,
+
+    #[arg(long, help = "Start capturing into --fifo")]
+    pub capture: bool,
+
+    #[arg(long, help = "FIFO Wireshark reads the capture from")]
+    pub fifo: Option<String>,
+
+    #[arg(long, help = "Capture filter entered in Wireshark")]
+    pub extcap_capture_filter: Option<String>,
+}
+
+impl crate::config::CaptureCommand for ExtcapArgs {
+    fn capture_args(&mut self) -> &mut Args {
+        &mut self.args
+    }
+}
+
+/// Checks whether the command line comes from Wireshark's extcap machinery.
+/// Parameters: `argv` (&[String]) process arguments without the program name.
+/// Returns: bool true when an extcap flag is present.
+pub fn is_extcap_invocation(argv: &[String]) -> bool {
+    argv.iter().any(|a| {
+        let flag = a.split('=').next().unwrap_or(a);
+        matches!(
+            flag,
+            "--extcap-interfaces" | "--extcap-interface" | "--extcap-dlts" | "--extcap-config"
+        )
+    })
+}
+
+/// Handles one extcap invocation, printing discovery output or running a capture.
+/// Parameters: `ext` (ExtcapArgs) parsed extcap arguments.
+/// Returns: Result<()> indicating success or failure.
+pub fn run(ext: ExtcapArgs) -> Result<()> {
+    if ext.extcap_interfaces {
+        print!("{}", interfaces());
+        return Ok(());
+    }
+    if let Some(iface) = ext.extcap_interface.as_deref() {
+        if iface != INTERFACE {
+            bail!("unknown extcap interface {iface}");
+        }
+    }
+    if ext.extcap_dlts {
+        print!("{}", dlts());
+        return Ok(());
+    }
+    if ext.extcap_config {
+        print!("{}", config());
+        return Ok(());
+    }
+    if ext.capture {
+        return crate::run(capture_args(ext)?);
+    }
+    bail!(
+        "nothing to do: expected --extcap-interfaces, --extcap-dlts, --extcap-config or --capture"
+    )
+}
+
+/// Maps an extcap capture request onto regular capture arguments.
+/// Parameters: `ext` (ExtcapArgs) parsed extcap arguments with `--capture`.
+/// Returns: Result<Args> arguments writing to the Wireshark FIFO.
+pub fn capture_args(ext: ExtcapArgs) -> Result<Args> {
+    let Some(fifo) = ext.fifo else {
+        bail!("--capture requires --fifo");
+    };
+    let mut args = ext.args;
+    args.output = fifo;
+    // Wireshark creates the FIFO before starting kcap.
+    args.force = true;
+    // Wireshark's own capture filter narrows whatever was configured in the dialog.
+    args.filter = match (args.filter.take(), ext.extcap_capture_filter) {
+        (Some(a), Some(b)) if !b.trim().is_empty() => Some(format!("({a}) and ({b})")),
+        (a, Some(b)) if !b.trim().is_empty() => a.or(Some(b)),
+        (a, _) => a,
+    };
+    Ok(args)
+}
+
+/// Returns the `--extcap-interfaces` listing.
+pub fn interfaces() -> String {
+    format!(
+        "extcap {{version={}}}{{help=https://www.wireshark.org/docs/man-pages/extcap.html}}\n\
+         interface {{value={INTERFACE}}}{{display=Remote capture over SSH or Kubernetes (kcap)}}\n",
+        env!("CARGO_PKG_VERSION")
+    )
+}
+
+/// Returns the `--extcap-dlts` listing.
+pub fn dlts() -> String {
+    // The default interface is "any", which tcpdump captures as Linux cooked frames.
+    // Wireshark still honors whatever link type the stream header carries.
+    "dlt {number=113}{name=LINUX_SLL}{display=Linux cooked capture}\n".to_string()
+}
+
+/// Returns the `--extcap-config` listing; each `call` is a regular kcap flag.
+pub fn config() -> String {
+    let fields = [
+        (
+            "--ssh-host",
+            "SSH host",
+            "{type=string}{tooltip=Capture on this host over SSH instead of Kubernetes}{group=Target}",
+        ),
+        (
+            "--namespace",
+            "Namespace",
+            "{type=string}{default=default}{tooltip=Kubernetes namespace of the pod}{group=Target}",
+        ),
+        (
+            "--pod",
+            "Pod",
+            "{type=string}{tooltip=Capture inside this pod via kubectl exec}{group=Target}",
+        ),
+        (
+            "--container",
+            "Container",
+            "{type=string}{tooltip=Container of the pod to exec into}{group=Target}",
+        ),
+        (
+            "--port",
+            "Port",
+            "{type=unsigned}{range=1,65535}{tooltip=Only capture traffic on this port}{group=Filter}",
+        ),
+        (
+            "--filter",
+            "Filter",
+            "{type=string}{tooltip=Additional tcpdump filter expression}{group=Filter}",
+        ),
+    ];
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, (call, display, rest))| {
+            format!("arg {{number={i}}}{{call={call}}}{{display={display}}}{rest}\n")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(argv: &[&str]) -> ExtcapArgs {
+        ExtcapArgs::try_parse_from(std::iter::once("kcap").chain(argv.iter().copied())).unwrap()
+    }
+
+    #[test]
+    fn detects_extcap_invocations() {
+        let argv = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(is_extcap_invocation(&argv(&["--extcap-interfaces"])));
+        assert!(is_extcap_invocation(&argv(&[
+            "--extcap-interface=kcap",
+            "--capture"
+        ])));
+        assert!(!is_extcap_invocation(&argv(&[
+            "--pod", "web", "--output", "x.pcap"
+        ])));
+    }
+
+    #[test]
+    fn config_calls_are_capture_flags() {
+        for line in config().lines() {
+            let call = line
+                .split("{call=")
+                .nth(1)
+                .and_then(|s| s.split('}').next())
+                .unwrap();
+            let value = if call == "--port" { "80" } else { "x" };
+            assert!(
+                Args::try_parse_from(["kcap", call, value]).is_ok(),
+                "{call} is not a kcap flag"
+            );
+        }
+    }
+
+    #[test]
+    fn capture_writes_to_fifo_with_combined_filter() {
+        let ext = parse(&[
+            "--capture",
+            "--extcap-interface=kcap",
+            "--fifo",
+            "/tmp/ws-fifo",
+            "--extcap-capture-filter",
+            "icmp",
+            "--ssh-host",
+            "10.0.0.10",
+            "--port",
+            "443",
+            "--filter",
+            "tcp",
+        ]);
+        let args = capture_args(ext).unwrap();
+        assert_eq!(args.output, "/tmp/ws-fifo");
+        assert_eq!(args.ssh_host.as_deref(), Some("10.0.0.10"));
+        assert_eq!(args.port, Some(443));
+        assert_eq!(args.filter.as_deref(), Some("(tcp) and (icmp)"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn capture_accepts_the_existing_wireshark_fifo() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("wireshark_extcap_kcap");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let fifo = fifo.to_string_lossy().to_string();
+
+        let args = capture_args(parse(&["--capture", "--fifo", &fifo])).unwrap();
+        let vars = crate::naming::NameVars::default();
+        let output = crate::naming::resolve_sink(&args.output, &vars, None, false).unwrap();
+        assert_eq!(output, fifo);
+        assert!(args.force);
+    }
+
+    #[test]
+    fn capture_without_fifo_fails() {
+        let ext = parse(&["--capture", "--extcap-interface", "kcap"]);
+        assert!(capture_args(ext).is_err());
+    }
+}
@@ -22,6 +22,35 @@ pub fn build_filter(port: Option<u16>, protocol: Protocol, extra: Option<&str>)
     }
 }
 
+/// Builds a filter clause that drops kcap's own SSH transport packets.
+/// Parameters: `peer_ip` (&str) address the remote host sees for kcap's session.
+/// Parameters: `ssh_port` (u16) remote SSH server port.
+/// Returns: String filter clause excluding the session.
+pub fn build_self_exclusion(peer_ip: &str, ssh_port: u16) -> String {
+    format!("not (host {peer_ip} and port {ssh_port})")
+}
+
+/// Builds a filter clause matching any of the given host addresses.
+/// Parameters: `ips` (&[String]) addresses to match.
+/// Returns: String filter clause.
+pub fn build_host_filter(ips: &[String]) -> String {
+    ips.iter()
+        .map(|ip| format!("host {ip}"))
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+/// Appends a mandatory clause to an optional filter expression.
+/// Parameters: `filter` (Option<String>) existing filter, if any.
+/// Parameters: `clause` (&str) clause that must also match.
+/// Returns: String with both expressions combined.
+pub fn and_clause(filter: Option<String>, clause: &str) -> String {
+    match filter {
+        Some(f) => format!("({f}) and ({clause})"),
+        None => clause.to_string(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -44,4 +73,15 @@ mod tests {
         let f = build_filter(None, Protocol::All, Some("host 10.0.0.1")).unwrap();
         assert_eq!(f, "host 10.0.0.1");
     }
+
+    #[test]
+    fn self_exclusion_combines_with_filter() {
+        let excl = build_self_exclusion("10.0.0.5", 22);
+        assert_eq!(excl, "not (host 10.0.0.5 and port 22)");
+        assert_eq!(
+            and_clause(Some("tcp port 443".to_string()), &excl),
+            "(tcp port 443) and (not (host 10.0.0.5 and port 22))"
+        );
+        assert_eq!(and_clause(None, &excl), excl);
+    }
 }
@@ -0,0 +1,311 @@
+use crate::error::{self, KcapError};
+use crate::k8s::{self, Runner};
+use crate::merge::MergeHandle;
+use anyhow::Result;
+use std::process::Child;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// What to keep capturing while pods restart or get replaced.
+pub struct FollowSpec {
+    pub namespace: String,
+    /// Pod to stay on while it exists; None captures every pod matching `selector`.
+    pub pod: Option<String>,
+    /// Selector used to find replacement pods; None stays on `pod` by name, which is
+    /// enough for restarts and for StatefulSet pods that come back under the same name.
+    pub selector: Option<String>,
+    pub container: Option<String>,
+    pub duration: Option<Duration>,
+    pub poll: Duration,
+}
+
+struct Active {
+    pod: String,
+    child: Child,
+}
+
+/// Keeps captures attached to pods until the duration expires, feeding them into a merge.
+/// Parameters: `runner` (&impl Runner) command runner for pod lookups.
+/// Parameters: `spec` (&FollowSpec) pods to follow.
+/// Parameters: `handle` (MergeHandle) merge receiving every capture stream.
+/// Parameters: `spawn` (FnMut(&str) -> Result<Child>) starts a capture in the named pod.
+/// Returns: Result<()> once the duration has expired.
+pub fn supervise(
+    runner: &impl Runner,
+    spec: &FollowSpec,
+    handle: MergeHandle,
+    mut spawn: impl FnMut(&str) -> Result<Child>,
+) -> Result<()> {
+    let deadline = spec.duration.map(|d| Instant::now() + d);
+    let mut pinned = spec.pod.clone();
+    let mut active: Vec<Active> = Vec::new();
+    let mut last_end: Option<Instant> = None;
+    let mut seen: Vec<String> = Vec::new();
+
+    loop {
+        // Reap captures whose exec stream died (container restart, pod deletion, ...).
+        active.retain_mut(|a| match a.child.try_wait() {
+            Ok(Some(status)) => {
+                info!(pod = %a.pod, %status, "capture stream ended; waiting to re-attach");
+                last_end = Some(Instant::now());
+                false
+            }
+            Ok(None) => true,
+            Err(err) => {
+                warn!(pod = %a.pod, "failed to poll capture process: {err}");
+                true
+            }
+        });
+
+        if deadline.is_some_and(|d| Instant::now() >= d) || crate::capture::stop_requested() {
+            for a in &mut active {
+                let _ = a.child.kill();
+                let _ = a.child.wait();
+            }
+            return Ok(());
+        }
+
+        let desired = match desired_pods(runner, spec, &mut pinned, active.is_empty()) {
+            Ok(pods) => pods,
+            // Waiting does not install kubectl.
+            Err(err) if matches!(error::find(&err), Some(KcapError::KubectlNotFound)) => {
+                for a in &mut active {
+                    let _ = a.child.kill();
+                    let _ = a.child.wait();
+                }
+                return Err(err);
+            }
+            Err(err) => {
+                // API hiccups are expected during rollouts; try again next tick.
+                warn!("failed to look up pods to follow: {err:#}");
+                Vec::new()
+            }
+        };
+
+        for pod in desired {
+            if active.iter().any(|a| a.pod == pod) {
+                continue;
+            }
+            let mut child = match spawn(&pod) {
+                Ok(c) => c,
+                Err(err) => {
+                    warn!(%pod, "failed to start capture: {err:#}");
+                    continue;
+                }
+            };
+            let Some(stdout) = child.stdout.take() else {
+                let _ = child.kill();
+                continue;
+            };
+
+            let reason = if seen.contains(&pod) {
+                "container restarted"
+            } else {
+                "new pod"
+            };
+            let comment = last_end.map(|t| {
+                format!(
+                    "kcap: re-attached to pod/{}/{pod} ({reason}) after a {:.1}s gap",
+                    spec.namespace,
+                    t.elapsed().as_secs_f64()
+                )
+            });
+            info!(%pod, reason, "attaching capture");
+            handle.add_source(format!("pod/{}/{pod}", spec.namespace), stdout, comment);
+            if !seen.contains(&pod) {
+                seen.push(pod.clone());
+            }
+            active.push(Active { pod, child });
+        }
+
+        let mut sleep = spec.poll;
+        if let Some(d) = deadline {
+            sleep = sleep.min(d.saturating_duration_since(Instant::now()));
+        }
+        thread::sleep(sleep);
+    }
+}
+
+fn desired_pods(
+    runner: &impl Runner,
+    spec: &FollowSpec,
+    pinned: &mut Option<String>,
+    idle: bool,
+) -> Result<Vec<String>> {
+    let ns = &spec.namespace;
+    let container = spec.container.as_deref();
+    let Some(pod) = pinned.clone() else {
+        let Some(selector) = &spec.selector else {
+            return Ok(Vec::new());
+        };
+        let pods = k8s::list_pod_states(runner, ns, selector, container)?;
+        return Ok(pods
+            .into_iter()
+            .filter(|p| p.ready_for_exec())
+            .map(|p| p.name)
+            .collect());
+    };
+
+    match k8s::get_pod_state(runner, ns, &pod, container)? {
+        Some(state) if !state.deleting => {
+            // Same pod: wait out CrashLoopBackOff until the container runs again.
+            Ok(if state.ready_for_exec() {
+                vec![pod]
+            } else {
+                Vec::new()
+            })
+        }
+        _ if idle => {
+            let Some(selector) = &spec.selector else {
+                // Nothing to find a replacement with; wait for the pod to come back.
+                return Ok(Vec::new());
+            };
+            // The pinned pod is gone; move to a replacement from the same workload.
+            let replacement = k8s::list_pod_states(runner, ns, selector, container)?
+                .into_iter()
+                .find(|p| p.ready_for_exec() && p.name != pod);
+            Ok(match replacement {
+                Some(p) => {
+                    info!(old = %pod, new = %p.name, "pod replaced; following replacement");
+                    *pinned = Some(p.name.clone());
+                    vec![p.name]
+                }
+                None => Vec::new(),
+            })
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Runner that replays scripted kubectl outputs in order.
+    struct ScriptedRunner {
+        outputs: Mutex<VecDeque<Result<String>>>,
+    }
+
+    impl Runner for ScriptedRunner {
+        fn run_capture(&self, _program: &str, _args: &[&str]) -> Result<String> {
+            self.outputs
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or_else(|| Ok(String::new()))
+        }
+    }
+
+    /// kubectl JSON for a pod; `started` is None while its container is not running.
+    fn pod(name: &str, phase: &str, started: Option<&str>) -> String {
+        let state = match started {
+            Some(t) => serde_json::json!({"running": {"startedAt": t}}),
+            None => serde_json::json!({"waiting": {}}),
+        };
+        serde_json::json!({
+            "metadata": {"name": name},
+            "status": {"phase": phase, "containerStatuses": [{"name": "app", "state": state}]},
+        })
+        .to_string()
+    }
+
+    fn list(pods: &[String]) -> String {
+        format!("{{\"items\":[{}]}}", pods.join(","))
+    }
+
+    fn spec(pod: Option<&str>) -> FollowSpec {
+        FollowSpec {
+            namespace: "prod".to_string(),
+            pod: pod.map(str::to_string),
+            selector: Some("app=orders".to_string()),
+            container: None,
+            duration: None,
+            poll: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn pinned_pod_waits_for_restart() {
+        let runner = ScriptedRunner {
+            outputs: Mutex::new(VecDeque::from([Ok(pod("orders-1", "Running", None))])),
+        };
+        let mut pinned = Some("orders-1".to_string());
+        let pods = desired_pods(&runner, &spec(Some("orders-1")), &mut pinned, true).unwrap();
+        assert!(pods.is_empty());
+    }
+
+    #[test]
+    fn pinned_pod_moves_to_replacement() {
+        let runner = ScriptedRunner {
+            outputs: Mutex::new(VecDeque::from([
+                // `--ignore-not-found` prints nothing for a deleted pod.
+                Ok(String::new()),
+                Ok(list(&[pod("orders-2", "Running", Some("2024-01-01T00:00:00Z"))])),
+            ])),
+        };
+        let mut pinned = Some("orders-1".to_string());
+        let pods = desired_pods(&runner, &spec(Some("orders-1")), &mut pinned, true).unwrap();
+        assert_eq!(pods, vec!["orders-2"]);
+        assert_eq!(pinned.as_deref(), Some("orders-2"));
+    }
+
+    #[test]
+    fn pod_without_selector_is_followed_by_name() {
+        let runner = ScriptedRunner {
+            outputs: Mutex::new(VecDeque::from([
+                Ok(String::new()),
+                Ok(pod("db-0", "Running", Some("2024-01-01T00:00:00Z"))),
+            ])),
+        };
+        let mut s = spec(Some("db-0"));
+        s.selector = None;
+        let mut pinned = Some("db-0".to_string());
+        assert!(desired_pods(&runner, &s, &mut pinned, true)
+            .unwrap()
+            .is_empty());
+        let pods = desired_pods(&runner, &s, &mut pinned, true).unwrap();
+        assert_eq!(pods, vec!["db-0"]);
+        assert_eq!(pinned.as_deref(), Some("db-0"));
+    }
+
+    #[test]
+    fn selector_follows_every_ready_pod() {
+        let runner = ScriptedRunner {
+            outputs: Mutex::new(VecDeque::from([Ok(list(&[
+                pod("a", "Running", Some("t1")),
+                pod("b", "Pending", None),
+                pod("c", "Running", Some("t2")),
+            ]))])),
+        };
+        let mut pinned = None;
+        let pods = desired_pods(&runner, &spec(None), &mut pinned, true).unwrap();
+        assert_eq!(pods, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn supervise_fails_without_kubectl() {
+        struct NoKubectl;
+        impl Runner for NoKubectl {
+            fn run_capture(&self, _program: &str, _args: &[&str]) -> Result<String> {
+                Err(KcapError::KubectlNotFound.into())
+            }
+        }
+        let (_stream, handle) = crate::merge::merged();
+        let err = supervise(&NoKubectl, &spec(Some("orders-1")), handle, |_| unreachable!());
+        assert_eq!(error::exit_code(&err.unwrap_err()), 31);
+    }
+
+    #[test]
+    fn supervise_stops_at_deadline() {
+        let runner = ScriptedRunner {
+            outputs: Mutex::new(VecDeque::new()),
+        };
+        let mut s = spec(None);
+        s.duration = Some(Duration::from_millis(20));
+        let (_stream, handle) = crate::merge::merged();
+        supervise(&runner, &s, handle, |_| unreachable!()).unwrap();
+    }
+}
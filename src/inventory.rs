@@ -0,0 +1,240 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use toml::{Table, Value};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// How to reach one node (or every node matching a `*` pattern) over SSH.
+pub struct NodeEntry {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub jump_host: Option<String>,
+    /// Configuration file section the entry came from.
+    pub source: String,
+}
+
+impl NodeEntry {
+    /// Parses one `[nodes.NAME]` table.
+    /// Parameters: `table` (&Table) entry keys `host`, `port`, `user` and `jump-host`.
+    /// Parameters: `source` (&str) where the entry was defined, used in messages.
+    /// Returns: Result<NodeEntry> error on unknown keys or mistyped values.
+    pub fn parse(table: &Table, source: &str) -> Result<NodeEntry> {
+        let mut entry = NodeEntry {
+            source: source.to_string(),
+            ..Default::default()
+        };
+        for (key, value) in table {
+            let text = || match value {
+                Value::String(s) if !s.is_empty() => Ok(s.clone()),
+                _ => bail!("{source}: `{key}` must be a non-empty string"),
+            };
+            match key.as_str() {
+                "host" => entry.host = Some(text()?),
+                "user" => entry.user = Some(text()?),
+                "jump-host" => entry.jump_host = Some(text()?),
+                "port" => {
+                    let port = value.as_integer().and_then(|p| u16::try_from(p).ok());
+                    let port = port.with_context(|| format!("{source}: invalid `port`"))?;
+                    entry.port = Some(port);
+                }
+                _ => {
+                    bail!("{source}: unknown key `{key}` (expected host, port, user or jump-host)")
+                }
+            }
+        }
+        Ok(entry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// SSH connection settings for one capture target.
+pub struct Route {
+    pub host: String,
+    pub port: u16,
+    pub user: Option<String>,
+    pub jump_host: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Node names mapped to SSH settings: configured entries plus InternalIPs found in the
+/// cluster for nodes the entries give no host for.
+pub struct Inventory {
+    entries: BTreeMap<String, NodeEntry>,
+    discovered: BTreeMap<String, IpAddr>,
+}
+
+impl Inventory {
+    /// Adds or replaces the entry for a node name or `*` pattern.
+    /// Parameters: `name` (String) node name, e.g. `worker-3` or `prod-*`.
+    /// Parameters: `entry` (NodeEntry) connection settings.
+    pub fn insert(&mut self, name: String, entry: NodeEntry) {
+        self.entries.insert(name, entry);
+    }
+
+    /// Configured entries by name or pattern.
+    /// Returns: &BTreeMap<String, NodeEntry> entries in name order.
+    pub fn entries(&self) -> &BTreeMap<String, NodeEntry> {
+        &self.entries
+    }
+
+    /// Settings for `name`: fields of an exact entry, completed by the matching patterns
+    /// from the most to the least specific.
+    /// Parameters: `name` (&str) node name or `--ssh-host` value.
+    /// Returns: Option<NodeEntry> merged entry, None when nothing matches.
+    pub fn entry(&self, name: &str) -> Option<NodeEntry> {
+        let mut patterns: Vec<(&String, &NodeEntry)> = self
+            .entries
+            .iter()
+            .filter(|(pattern, _)| pattern.contains('*') && glob_match(pattern, name))
+            .collect();
+        patterns.sort_by_key(|(pattern, _)| {
+            std::cmp::Reverse(pattern.len() - pattern.matches('*').count())
+        });
+        let mut matched = self
+            .entries
+            .get(name)
+            .into_iter()
+            .chain(patterns.into_iter().map(|p| p.1));
+        let mut entry = matched.next()?.clone();
+        for more in matched {
+            entry.host = entry.host.or_else(|| more.host.clone());
+            entry.port = entry.port.or(more.port);
+            entry.user = entry.user.or_else(|| more.user.clone());
+            entry.jump_host = entry.jump_host.or_else(|| more.jump_host.clone());
+        }
+        Some(entry)
+    }
+
+    /// Whether `node` still needs an address from the cluster.
+    /// Parameters: `node` (&str) Kubernetes node name.
+    /// Returns: bool true when no entry names a host for it.
+    pub fn needs_address(&self, node: &str) -> bool {
+        self.entry(node).and_then(|e| e.host).is_none() && !self.discovered.contains_key(node)
+    }
+
+    /// Records cluster addresses for nodes without a configured host.
+    /// Parameters: `addresses` (BTreeMap<String, IpAddr>) InternalIP by node name.
+    pub fn add_addresses(&mut self, addresses: BTreeMap<String, IpAddr>) {
+        self.discovered.extend(addresses);
+    }
+
+    /// Connection settings for `name`; entry fields win over the `--ssh-*` flags.
+    /// Parameters: `name` (&str) node name or `--ssh-host` value.
+    /// Parameters: `user` (Option<&str>) `--ssh-user`.
+    /// Parameters: `port` (u16) `--ssh-port`.
+    /// Parameters: `jump_host` (Option<&str>) `--jump-host`.
+    /// Returns: Route to connect with; the name itself is the host when nothing else is known.
+    pub fn route(
+        &self,
+        name: &str,
+        user: Option<&str>,
+        port: u16,
+        jump_host: Option<&str>,
+    ) -> Route {
+        let entry = self.entry(name).unwrap_or_default();
+        let host = entry
+            .host
+            .or_else(|| self.discovered.get(name).map(IpAddr::to_string))
+            .unwrap_or_else(|| name.to_string());
+        Route {
+            host,
+            port: entry.port.unwrap_or(port),
+            user: entry.user.or(user.map(str::to_string)),
+            jump_host: entry.jump_host.or(jump_host.map(str::to_string)),
+        }
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inventory() -> Inventory {
+        let mut inventory = Inventory::default();
+        let mut add = |name: &str, text: &str| {
+            let table: Table = text.parse().unwrap();
+            let entry = NodeEntry::parse(&table, name).unwrap();
+            inventory.insert(name.to_string(), entry);
+        };
+        add("prod-*", "jump-host = \"bastion-prod\"\nuser = \"core\"");
+        add("prod-db-*", "jump-host = \"bastion-db\"");
+        add("prod-web-1", "host = \"10.1.0.7\"\nport = 2222");
+        inventory
+    }
+
+    #[test]
+    fn entries_inherit_from_patterns_and_beat_flags() {
+        let inventory = inventory();
+        let route = inventory.route("prod-web-1", Some("ops"), 22, Some("cli-jump"));
+        assert_eq!(
+            route,
+            Route {
+                host: "10.1.0.7".into(),
+                port: 2222,
+                user: Some("core".into()),
+                jump_host: Some("bastion-prod".into()),
+            }
+        );
+        let db = inventory.route("prod-db-2", None, 22, None);
+        assert_eq!(db.jump_host.as_deref(), Some("bastion-db"));
+        assert_eq!(db.user.as_deref(), Some("core"));
+        assert_eq!(db.host, "prod-db-2");
+        let other = inventory.route("staging-1", Some("ops"), 22, None);
+        assert_eq!(
+            (other.host.as_str(), other.user.as_deref()),
+            ("staging-1", Some("ops"))
+        );
+    }
+
+    #[test]
+    fn discovered_addresses_fill_missing_hosts() {
+        let mut inventory = inventory();
+        assert!(inventory.needs_address("prod-db-2"));
+        assert!(!inventory.needs_address("prod-web-1"));
+        let ip: IpAddr = "10.2.0.9".parse().unwrap();
+        inventory.add_addresses(BTreeMap::from([("prod-db-2".to_string(), ip)]));
+        let route = inventory.route("prod-db-2", None, 22, None);
+        assert_eq!(route.host, "10.2.0.9");
+        assert_eq!(route.jump_host.as_deref(), Some("bastion-db"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_bad_ports() {
+        let parse = |text: &str| {
+            let table: Table = text.parse().unwrap();
+            NodeEntry::parse(&table, "nodes.x").unwrap_err().to_string()
+        };
+        assert!(parse("hostname = \"a\"").contains("unknown key `hostname`"));
+        assert!(parse("port = 70000").contains("invalid `port`"));
+        assert!(parse("user = 3").contains("`user` must be a non-empty string"));
+    }
+
+    #[test]
+    fn globs_match_whole_names() {
+        assert!(glob_match("prod-*", "prod-web-1"));
+        assert!(glob_match("*-web-*", "prod-web-1"));
+        assert!(glob_match("a*a", "aa"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(!glob_match("prod-*", "staging-prod-1"));
+    }
+}
@@ -1,8 +1,15 @@
-﻿use anyhow::{bail, Context, Result};
+﻿use crate::capture::Spawner;
+use crate::cli::KubeBackend;
+use crate::error::KcapError;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
 use std::process::{Child, Command, Stdio};
 use std::sync::Mutex;
+use tracing::debug;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 /// Resolved target for running remote capture.
 pub enum Target {
     Ssh { host: String },
@@ -13,7 +20,13 @@ pub enum Target {
     },
 }
 
-/// Executes external commands for capture-related queries.
+/// Label linking EndpointSlices to the Service they belong to.
+pub(crate) const SERVICE_NAME_LABEL: &str = "kubernetes.io/service-name";
+
+/// Executes external commands and the Kubernetes queries kcap needs.
+///
+/// The Kubernetes operations default to `kubectl ... -o json` through `run_capture`;
+/// backends that talk to the API server directly override them.
 pub trait Runner {
     /// Runs a command and returns trimmed stdout on success.
     /// Parameters: `program` (&str) executable name.
@@ -21,8 +34,325 @@ pub trait Runner {
     /// Returns: Result<String> with trimmed stdout or an error.
     // Abstract external command execution for testability.
     fn run_capture(&self, program: &str, args: &[&str]) -> Result<String>;
+
+    /// Reports the kubeconfig context in use.
+    /// Returns: Result<String> context name, empty when none is set.
+    fn current_context(&self) -> Result<String> {
+        self.run_capture("kubectl", &["config", "current-context"])
+    }
+
+    /// Fetches one pod.
+    /// Parameters: `namespace` (&str) pod namespace.
+    /// Parameters: `name` (&str) pod name.
+    /// Returns: Result<Option<Pod>> the pod, or None when it does not exist.
+    fn get_pod(&self, namespace: &str, name: &str) -> Result<Option<Pod>> {
+        let doc = kubectl_get(self, &["pod", name, "-n", namespace, "--ignore-not-found"])?;
+        Ok(doc.as_ref().map(Pod::from_json))
+    }
+
+    /// Lists pods.
+    /// Parameters: `namespace` (Option<&str>) namespace to search; None for all namespaces.
+    /// Parameters: `selector` (Option<&str>) label selector, e.g. `app=orders`.
+    /// Returns: Result<Vec<Pod>> matching pods.
+    fn list_pods(&self, namespace: Option<&str>, selector: Option<&str>) -> Result<Vec<Pod>> {
+        let doc = kubectl_list(self, "pods", namespace, selector)?;
+        Ok(items(&doc).iter().map(Pod::from_json).collect())
+    }
+
+    /// Fetches one Service.
+    /// Parameters: `namespace` (&str) service namespace.
+    /// Parameters: `name` (&str) service name.
+    /// Returns: Result<Option<Service>> the service, or None when it does not exist.
+    fn get_service(&self, namespace: &str, name: &str) -> Result<Option<Service>> {
+        let doc = kubectl_get(self, &["service", name, "-n", namespace, "--ignore-not-found"])?;
+        Ok(doc.as_ref().map(Service::from_json))
+    }
+
+    /// Lists Services.
+    /// Parameters: `namespace` (Option<&str>) namespace to search; None for all namespaces.
+    /// Returns: Result<Vec<Service>> services.
+    fn list_services(&self, namespace: Option<&str>) -> Result<Vec<Service>> {
+        let doc = kubectl_list(self, "services", namespace, None)?;
+        Ok(items(&doc).iter().map(Service::from_json).collect())
+    }
+
+    /// Lists the EndpointSlices of a Service.
+    /// Parameters: `namespace` (&str) service namespace.
+    /// Parameters: `service` (&str) service name.
+    /// Returns: Result<Vec<EndpointSlice>> slices, empty when the service has none.
+    fn list_endpoints(&self, namespace: &str, service: &str) -> Result<Vec<EndpointSlice>> {
+        let selector = format!("{SERVICE_NAME_LABEL}={service}");
+        let doc = kubectl_list(self, "endpointslices", Some(namespace), Some(&selector))?;
+        Ok(items(&doc).iter().map(EndpointSlice::from_json).collect())
+    }
+
+    /// Lists cluster nodes.
+    /// Returns: Result<Vec<Node>> nodes.
+    fn list_nodes(&self) -> Result<Vec<Node>> {
+        let doc = kubectl_get(self, &["nodes"])?.unwrap_or_default();
+        Ok(items(&doc).iter().map(Node::from_json).collect())
+    }
+}
+
+/// Runs `kubectl get ... -o json`.
+/// Parameters: `runner` (&R) command runner.
+/// Parameters: `args` (&[&str]) arguments after `get`.
+/// Returns: Result<Option<Value>> document, or None when kubectl printed nothing
+/// (`--ignore-not-found` on a missing object).
+fn kubectl_get<R: Runner + ?Sized>(runner: &R, args: &[&str]) -> Result<Option<Value>> {
+    let mut full = vec!["get"];
+    full.extend_from_slice(args);
+    full.extend(["-o", "json"]);
+    let out = runner.run_capture("kubectl", &full)?;
+    if out.is_empty() {
+        return Ok(None);
+    }
+    let doc = serde_json::from_str(&out).context("unexpected kubectl output")?;
+    Ok(Some(doc))
+}
+
+fn kubectl_list<R: Runner + ?Sized>(
+    runner: &R,
+    resource: &str,
+    namespace: Option<&str>,
+    selector: Option<&str>,
+) -> Result<Value> {
+    let mut args = vec![resource];
+    match namespace {
+        Some(ns) => args.extend(["-n", ns]),
+        None => args.push("-A"),
+    }
+    if let Some(s) = selector {
+        args.extend(["-l", s]);
+    }
+    Ok(kubectl_get(runner, &args)?.unwrap_or_default())
+}
+
+/// Returns the `items` of a Kubernetes list document.
+/// Parameters: `doc` (&Value) list document.
+/// Returns: &[Value] items, empty when the document is not a list.
+pub fn items(doc: &Value) -> &[Value] {
+    doc["items"].as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+fn string(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+fn strings<'a>(values: impl IntoIterator<Item = &'a Value>) -> Vec<String> {
+    values
+        .into_iter()
+        .filter_map(Value::as_str)
+        .map(str::to_string)
+        .collect()
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Reads `name`/`port` pairs; unnamed ports get an empty name.
+fn named_ports(value: &Value) -> Vec<(String, u16)> {
+    array(value)
+        .iter()
+        .filter_map(|p| {
+            let port = u16::try_from(p["port"].as_u64()?).ok()?;
+            Some((string(&p["name"]), port))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The parts of a pod kcap looks at.
+pub struct Pod {
+    pub namespace: String,
+    pub name: String,
+    pub labels: BTreeMap<String, String>,
+    pub deleting: bool,
+    pub node: Option<String>,
+    pub host_network: bool,
+    pub phase: String,
+    /// Assigned addresses, primary first; empty until the pod is scheduled.
+    pub ips: Vec<String>,
+    /// Start time of every running container, by container name.
+    pub running: Vec<(String, String)>,
+}
+
+impl Pod {
+    /// Reads a pod from its API representation.
+    /// Parameters: `doc` (&Value) pod object.
+    /// Returns: Pod with missing fields left empty.
+    pub fn from_json(doc: &Value) -> Self {
+        let (meta, spec, status) = (&doc["metadata"], &doc["spec"], &doc["status"]);
+        let mut ips = strings(array(&status["podIPs"]).iter().map(|ip| &ip["ip"]));
+        if ips.is_empty() {
+            ips = strings([&status["podIP"]]);
+        }
+        let labels = meta["labels"].as_object().into_iter().flatten();
+        let running = array(&status["containerStatuses"]).iter().filter_map(|c| {
+            let since = c["state"]["running"]["startedAt"].as_str()?;
+            Some((string(&c["name"]), since.to_string()))
+        });
+        Pod {
+            namespace: string(&meta["namespace"]),
+            name: string(&meta["name"]),
+            labels: labels
+                .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                .collect(),
+            deleting: !meta["deletionTimestamp"].is_null(),
+            node: Some(string(&spec["nodeName"])).filter(|n| !n.is_empty()),
+            host_network: spec["hostNetwork"].as_bool().unwrap_or(false),
+            phase: string(&status["phase"]),
+            ips,
+            running: running.collect(),
+        }
+    }
+
+    /// Summarizes whether the pod can be exec'd into.
+    /// Parameters: `container` (Option<&str>) container to check, if pinned; otherwise any
+    /// running container counts.
+    /// Returns: PodState of the pod.
+    pub fn state(&self, container: Option<&str>) -> PodState {
+        let running_since: Vec<&str> = self
+            .running
+            .iter()
+            .filter(|(name, _)| container.is_none_or(|c| c == name))
+            .map(|(_, since)| since.as_str())
+            .collect();
+        PodState {
+            name: self.name.clone(),
+            phase: self.phase.clone(),
+            deleting: self.deleting,
+            running_since: running_since.join(" "),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The parts of a Service kcap looks at.
+pub struct Service {
+    pub namespace: String,
+    pub name: String,
+    /// Service ports by name; a single unnamed port has an empty name.
+    pub ports: Vec<(String, u16)>,
+    /// Cluster addresses; headless services have none that parse.
+    pub cluster_ips: Vec<String>,
+}
+
+impl Service {
+    /// Reads a Service from its API representation.
+    /// Parameters: `doc` (&Value) service object.
+    /// Returns: Service with missing fields left empty.
+    pub fn from_json(doc: &Value) -> Self {
+        let spec = &doc["spec"];
+        let mut cluster_ips = strings(array(&spec["clusterIPs"]));
+        if cluster_ips.is_empty() {
+            cluster_ips = strings([&spec["clusterIP"]]);
+        }
+        Service {
+            namespace: string(&doc["metadata"]["namespace"]),
+            name: string(&doc["metadata"]["name"]),
+            ports: named_ports(&spec["ports"]),
+            cluster_ips,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// One EndpointSlice of a Service.
+pub struct EndpointSlice {
+    /// Pod ports by Service port name.
+    pub ports: Vec<(String, u16)>,
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// One endpoint of an EndpointSlice.
+pub struct Endpoint {
+    /// First address of the endpoint.
+    pub address: Option<String>,
+    /// Backing pod; None for endpoints that are not pods (e.g. manually managed IPs).
+    pub pod: Option<String>,
+    pub node: Option<String>,
+    pub ready: bool,
 }
 
+impl EndpointSlice {
+    /// Reads an EndpointSlice from its API representation.
+    /// Parameters: `doc` (&Value) slice object.
+    /// Returns: EndpointSlice with missing fields left empty.
+    pub fn from_json(doc: &Value) -> Self {
+        let endpoints = array(&doc["endpoints"]).iter().map(|ep| {
+            let target = &ep["targetRef"];
+            Endpoint {
+                address: ep["addresses"][0].as_str().map(str::to_string),
+                pod: (target["kind"] == "Pod")
+                    .then(|| string(&target["name"]))
+                    .filter(|p| !p.is_empty()),
+                node: Some(string(&ep["nodeName"])).filter(|n| !n.is_empty()),
+                // An unknown condition is to be read as ready.
+                ready: ep["conditions"]["ready"].as_bool().unwrap_or(true),
+            }
+        });
+        EndpointSlice {
+            ports: named_ports(&doc["ports"]),
+            endpoints: endpoints.collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// The parts of a node kcap looks at.
+pub struct Node {
+    pub name: String,
+    /// InternalIP addresses, the ones SSH usually reaches nodes on.
+    pub internal_ips: Vec<String>,
+}
+
+impl Node {
+    /// Reads a node from its API representation.
+    /// Parameters: `doc` (&Value) node object.
+    /// Returns: Node with missing fields left empty.
+    pub fn from_json(doc: &Value) -> Self {
+        let internal = array(&doc["status"]["addresses"])
+            .iter()
+            .filter(|a| a["type"] == "InternalIP")
+            .map(|a| &a["address"]);
+        Node {
+            name: string(&doc["metadata"]["name"]),
+            internal_ips: strings(internal),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A shell command to run in a pod's container.
+pub struct PodExec {
+    pub namespace: String,
+    pub pod: String,
+    pub container: Option<String>,
+    /// Run through `sh -c` to preserve the capture command quoting.
+    pub command: String,
+}
+
+/// Turns a pod exec into a Spawner.
+pub type ExecFactory = Box<dyn Fn(PodExec) -> Spawner + Send + Sync>;
+
+/// Starts commands inside pods.
+pub trait Exec {
+    /// Prepares pod execs whose command is only known later, e.g. the one stopping a capture.
+    /// Returns: ExecFactory independent of the runner's borrows.
+    fn exec_factory(&self) -> ExecFactory;
+
+    /// Prepares a pod exec that can be started again later.
+    /// Parameters: `exec` (PodExec) command and the pod to run it in.
+    /// Returns: Spawner starting the exec with piped stdout and stderr.
+    fn exec_spawner(&self, exec: PodExec) -> Spawner {
+        self.exec_factory()(exec)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 /// Runner implementation that invokes system binaries.
 pub struct SystemRunner;
 
@@ -34,7 +364,7 @@ impl Runner for SystemRunner {
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
             .output()
-            .with_context(|| format!("failed to run {program}"))?;
+            .map_err(|err| spawn_error(program, err))?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
@@ -45,6 +375,215 @@ impl Runner for SystemRunner {
     }
 }
 
+#[derive(Debug, Clone)]
+/// Kubernetes backend chosen for this run, pinned to a kubeconfig selection.
+pub enum KubeRunner {
+    Kubectl(ContextRunner<SystemRunner>),
+    #[cfg(feature = "native-k8s")]
+    Native(crate::kube_native::NativeRunner),
+}
+
+impl KubeRunner {
+    /// Resolves the requested backend against what is installed and compiled in.
+    /// Parameters: `backend` (KubeBackend) requested backend.
+    /// Parameters: `kube` (&KubeContext) kubeconfig selection every call uses.
+    /// Returns: Result<KubeRunner> usable backend or an error if unavailable.
+    pub fn select(backend: KubeBackend, kube: &KubeContext) -> Result<Self> {
+        let kubectl = || KubeRunner::Kubectl(ContextRunner::new(SystemRunner, kube.clone()));
+        #[cfg(feature = "native-k8s")]
+        let native = || KubeRunner::Native(crate::kube_native::NativeRunner::new(kube));
+        match backend {
+            KubeBackend::Kubectl => Ok(kubectl()),
+            #[cfg(feature = "native-k8s")]
+            KubeBackend::Native => Ok(native()),
+            #[cfg(not(feature = "native-k8s"))]
+            KubeBackend::Native => bail!("kcap was built without the native-k8s feature"),
+            // Prefer kubectl when present so behavior matches what users debug by hand.
+            #[cfg(feature = "native-k8s")]
+            KubeBackend::Auto if !on_path("kubectl") => Ok(native()),
+            KubeBackend::Auto => Ok(kubectl()),
+        }
+    }
+
+    /// Returns a short backend name for logs.
+    pub fn name(&self) -> &'static str {
+        match self {
+            KubeRunner::Kubectl(_) => "kubectl",
+            #[cfg(feature = "native-k8s")]
+            KubeRunner::Native(_) => "native",
+        }
+    }
+
+    /// Spawns a pod exec with piped stdout and stderr using this backend.
+    /// Parameters: `exec` (&PodExec) command and the pod to run it in.
+    /// Returns: Result<Child> handle to the spawned process.
+    pub fn spawn_exec(&self, exec: &PodExec) -> Result<Child> {
+        match self {
+            KubeRunner::Kubectl(r) => spawn_kubectl_exec(&r.kube.apply(build_kubectl_exec_args(
+                &exec.namespace,
+                &exec.pod,
+                exec.container.as_deref(),
+                &exec.command,
+            ))),
+            #[cfg(feature = "native-k8s")]
+            KubeRunner::Native(r) => r.spawn_exec(exec),
+        }
+    }
+
+    fn backend(&self) -> &dyn Runner {
+        match self {
+            KubeRunner::Kubectl(r) => r,
+            #[cfg(feature = "native-k8s")]
+            KubeRunner::Native(r) => r,
+        }
+    }
+}
+
+impl Runner for KubeRunner {
+    fn run_capture(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.backend().run_capture(program, args)
+    }
+
+    fn current_context(&self) -> Result<String> {
+        self.backend().current_context()
+    }
+
+    fn get_pod(&self, namespace: &str, name: &str) -> Result<Option<Pod>> {
+        self.backend().get_pod(namespace, name)
+    }
+
+    fn list_pods(&self, namespace: Option<&str>, selector: Option<&str>) -> Result<Vec<Pod>> {
+        self.backend().list_pods(namespace, selector)
+    }
+
+    fn get_service(&self, namespace: &str, name: &str) -> Result<Option<Service>> {
+        self.backend().get_service(namespace, name)
+    }
+
+    fn list_services(&self, namespace: Option<&str>) -> Result<Vec<Service>> {
+        self.backend().list_services(namespace)
+    }
+
+    fn list_endpoints(&self, namespace: &str, service: &str) -> Result<Vec<EndpointSlice>> {
+        self.backend().list_endpoints(namespace, service)
+    }
+
+    fn list_nodes(&self) -> Result<Vec<Node>> {
+        self.backend().list_nodes()
+    }
+}
+
+impl Exec for KubeRunner {
+    fn exec_factory(&self) -> ExecFactory {
+        let backend = self.clone();
+        Box::new(move |exec| {
+            let backend = backend.clone();
+            Box::new(move || backend.spawn_exec(&exec))
+        })
+    }
+}
+
+#[cfg(feature = "native-k8s")]
+fn on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        dir.join(program).is_file() || dir.join(format!("{program}.exe")).is_file()
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Kubeconfig selection applied to every kubectl invocation.
+pub struct KubeContext {
+    pub kubeconfig: Option<String>,
+    pub context: Option<String>,
+    pub cluster: Option<String>,
+}
+
+impl KubeContext {
+    /// Builds kubectl global flags for this selection.
+    /// Returns: Vec<String> flags to place before the kubectl subcommand.
+    pub fn global_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (flag, value) in [
+            ("--kubeconfig", &self.kubeconfig),
+            ("--context", &self.context),
+            ("--cluster", &self.cluster),
+        ] {
+            if let Some(v) = value {
+                args.push(flag.to_string());
+                args.push(v.clone());
+            }
+        }
+        args
+    }
+
+    /// Prefixes a kubectl argument list with the global selection flags.
+    /// Parameters: `args` (Vec<String>) kubectl subcommand and its arguments.
+    /// Returns: Vec<String> full argument list for kubectl.
+    pub fn apply(&self, args: Vec<String>) -> Vec<String> {
+        let mut full = self.global_args();
+        full.extend(args);
+        full
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Runner wrapper that pins every kubectl call to a kubeconfig selection.
+pub struct ContextRunner<R> {
+    inner: R,
+    kube: KubeContext,
+}
+
+impl<R: Runner> ContextRunner<R> {
+    /// Wraps a runner so kubectl calls carry the selection flags.
+    /// Parameters: `inner` (R) runner that executes commands.
+    /// Parameters: `kube` (KubeContext) selection to apply.
+    /// Returns: ContextRunner owning both.
+    pub fn new(inner: R, kube: KubeContext) -> Self {
+        Self { inner, kube }
+    }
+}
+
+impl<R: Runner> Runner for ContextRunner<R> {
+    fn run_capture(&self, program: &str, args: &[&str]) -> Result<String> {
+        if program != "kubectl" {
+            return self.inner.run_capture(program, args);
+        }
+        // Keep global flags ahead of the subcommand so kubectl parses them first.
+        let globals = self.kube.global_args();
+        let mut full: Vec<&str> = globals.iter().map(String::as_str).collect();
+        full.extend_from_slice(args);
+        self.inner.run_capture(program, &full)
+    }
+}
+
+/// Reports the kubeconfig context kubectl will use.
+/// Parameters: `runner` (&impl Runner) command runner, usually a KubeRunner.
+/// Parameters: `kube` (&KubeContext) explicit selection, if any.
+/// Returns: Result<String> context name.
+pub fn current_context(runner: &impl Runner, kube: &KubeContext) -> Result<String> {
+    if let Some(ctx) = &kube.context {
+        return Ok(ctx.clone());
+    }
+    let ctx = runner.current_context()?;
+    if ctx.is_empty() {
+        bail!("kubectl has no current context; pass --context");
+    }
+    Ok(ctx)
+}
+
+/// Fetches a pod that has to exist.
+/// Returns: Result<Pod> the pod, or KcapError::PodNotFound.
+fn existing_pod(runner: &impl Runner, namespace: &str, pod: &str) -> Result<Pod> {
+    let found = runner.get_pod(namespace, pod)?;
+    let missing = || KcapError::PodNotFound {
+        pod: format!("{namespace}/{pod}"),
+    };
+    found.ok_or_else(|| missing().into())
+}
+
 /// Resolves a Kubernetes pod to its node name.
 /// Parameters: `runner` (&impl Runner) command runner.
 /// Parameters: `namespace` (&str) pod namespace.
@@ -52,20 +591,343 @@ impl Runner for SystemRunner {
 /// Returns: Result<String> node name or an error if missing.
 pub fn resolve_pod_node(runner: &impl Runner, namespace: &str, pod: &str) -> Result<String> {
     // Map pod to node to determine where capture should run.
-    let args = [
-        "get",
-        "pod",
-        pod,
-        "-n",
-        namespace,
-        "-o",
-        "jsonpath={.spec.nodeName}",
+    match existing_pod(runner, namespace, pod)?.node {
+        Some(node) => Ok(node),
+        None => bail!("pod {pod} has no nodeName"),
+    }
+}
+
+/// Resolves a Kubernetes pod to its primary IP address.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (&str) pod namespace.
+/// Parameters: `pod` (&str) pod name.
+/// Returns: Result<String> pod IP or an error if none is assigned yet.
+pub fn resolve_pod_ip(runner: &impl Runner, namespace: &str, pod: &str) -> Result<String> {
+    match existing_pod(runner, namespace, pod)?.ips.into_iter().next() {
+        Some(ip) => Ok(ip),
+        None => bail!("pod {pod} has no IP address yet"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A ready endpoint backing a Kubernetes Service.
+pub struct ServiceEndpoint {
+    pub ip: String,
+    pub pod: String,
+    pub node: String,
+    /// Port inside the pod (the `targetPort`) the requested Service port maps to.
+    pub port: Option<u16>,
+}
+
+/// Resolves a Service to the pods and nodes behind its EndpointSlices.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (&str) service namespace.
+/// Parameters: `service` (&str) service name.
+/// Parameters: `port` (Option<u16>) Service port to translate into each endpoint's port.
+/// Returns: Result<Vec<ServiceEndpoint>> ready endpoints or an error if none exist.
+pub fn resolve_service_endpoints(
+    runner: &impl Runner,
+    namespace: &str,
+    service: &str,
+    port: Option<u16>,
+) -> Result<Vec<ServiceEndpoint>> {
+    // Slices name their ports after the Service ports; a port that is not a Service port
+    // is taken to be a pod port already.
+    let port_name = match port {
+        Some(port) => match runner.get_service(namespace, service)? {
+            Some(svc) => service_port_name(&svc, port),
+            None => bail!("service {service} not found in namespace {namespace}"),
+        },
+        None => None,
+    };
+
+    let slices = runner.list_endpoints(namespace, service)?;
+    let mut endpoints: Vec<ServiceEndpoint> = Vec::new();
+    for (ip, pod, node, port) in endpoint_rows(&slices, port_name.as_deref()) {
+        if endpoints.iter().any(|e| e.pod == pod) {
+            // Dual-stack services list each pod once per address family.
+            continue;
+        }
+        let node = match node {
+            Some(n) => n,
+            None => resolve_pod_node(runner, namespace, &pod)?,
+        };
+        endpoints.push(ServiceEndpoint {
+            ip,
+            pod,
+            node,
+            port,
+        });
+    }
+
+    if endpoints.is_empty() {
+        bail!("service {service} in namespace {namespace} has no ready pod endpoints");
+    }
+    Ok(endpoints)
+}
+
+/// Finds the name of a Service port (empty for a single unnamed port).
+/// Parameters: `svc` (&Service) the Service.
+/// Parameters: `port` (u16) Service port.
+/// Returns: Option<String> None when `port` is not one of the Service's ports.
+fn service_port_name(svc: &Service, port: u16) -> Option<String> {
+    svc.ports
+        .iter()
+        .find(|(_, p)| *p == port)
+        .map(|(name, _)| name.clone())
+}
+
+type EndpointRow = (String, String, Option<String>, Option<u16>);
+
+fn endpoint_rows(slices: &[EndpointSlice], port_name: Option<&str>) -> Vec<EndpointRow> {
+    let mut rows = Vec::new();
+    for slice in slices {
+        let port = port_name.and_then(|wanted| {
+            slice
+                .ports
+                .iter()
+                .find(|(name, _)| name == wanted)
+                .map(|(_, number)| *number)
+        });
+        for ep in slice.endpoints.iter().filter(|ep| ep.ready) {
+            // Endpoints that are not pods (e.g. manually managed IPs) cannot be exec'd into.
+            let (Some(ip), Some(pod)) = (&ep.address, &ep.pod) else {
+                continue;
+            };
+            rows.push((ip.clone(), pod.clone(), ep.node.clone(), port));
+        }
+    }
+    rows
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Point-in-time state of a pod relevant to exec'ing into it.
+pub struct PodState {
+    pub name: String,
+    pub phase: String,
+    pub deleting: bool,
+    /// Start time of the running container(s); empty while crash-looping.
+    pub running_since: String,
+}
+
+impl PodState {
+    /// Returns whether an exec into the pod can currently succeed.
+    pub fn ready_for_exec(&self) -> bool {
+        self.phase == "Running" && !self.deleting && !self.running_since.is_empty()
+    }
+}
+
+/// Fetches a pod's exec-relevant state.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (&str) pod namespace.
+/// Parameters: `pod` (&str) pod name.
+/// Parameters: `container` (Option<&str>) container to check, if pinned.
+/// Returns: Result<Option<PodState>> state, or None when the pod no longer exists.
+pub fn get_pod_state(
+    runner: &impl Runner,
+    namespace: &str,
+    pod: &str,
+    container: Option<&str>,
+) -> Result<Option<PodState>> {
+    let found = runner.get_pod(namespace, pod)?;
+    Ok(found.map(|p| p.state(container)))
+}
+
+/// Lists pods matching a label selector with their exec-relevant state.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (&str) namespace to search.
+/// Parameters: `selector` (&str) label selector, e.g. `app=orders`.
+/// Parameters: `container` (Option<&str>) container to check, if pinned.
+/// Returns: Result<Vec<PodState>> matching pods.
+pub fn list_pod_states(
+    runner: &impl Runner,
+    namespace: &str,
+    selector: &str,
+    container: Option<&str>,
+) -> Result<Vec<PodState>> {
+    let pods = runner.list_pods(Some(namespace), Some(selector))?;
+    Ok(pods.iter().map(|p| p.state(container)).collect())
+}
+
+/// Derives a label selector that matches a pod and its future replacements.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (&str) pod namespace.
+/// Parameters: `pod` (&str) pod name.
+/// Returns: Result<Option<String>> selector such as `app=orders,tier=api`; None when the
+/// pod has no stable labels (e.g. a bare pod).
+pub fn replacement_selector(
+    runner: &impl Runner,
+    namespace: &str,
+    pod: &str,
+) -> Result<Option<String>> {
+    let labels = existing_pod(runner, namespace, pod)?.labels;
+    // Controllers stamp these per revision or per pod, so replacements would not match.
+    const UNSTABLE: [&str; 4] = [
+        "pod-template-hash",
+        "controller-revision-hash",
+        "statefulset.kubernetes.io/pod-name",
+        "apps.kubernetes.io/pod-index",
     ];
-    let node = runner.run_capture("kubectl", &args)?;
-    if node.is_empty() {
-        bail!("pod {pod} has no nodeName");
+    let selector = labels
+        .iter()
+        .filter(|(k, _)| !UNSTABLE.contains(&k.as_str()))
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(Some(selector).filter(|s| !s.is_empty()))
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Kind of cluster object an address belongs to.
+pub enum OwnerKind {
+    Pod,
+    Service,
+    Node,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Cluster object that owned an IP address when it was looked up.
+pub struct IpOwner {
+    pub kind: OwnerKind,
+    /// Empty for nodes.
+    pub namespace: String,
+    pub name: String,
+    /// Node a pod was scheduled on; None for services and nodes.
+    pub node: Option<String>,
+}
+
+impl IpOwner {
+    /// Short label shown next to an address.
+    /// Returns: String e.g. `orders-7f9c.prod`, `kube-dns.kube-system.svc` or a node name.
+    pub fn label(&self) -> String {
+        match self.kind {
+            OwnerKind::Pod => format!("{}.{}", self.name, self.namespace),
+            OwnerKind::Service => format!("{}.{}.svc", self.name, self.namespace),
+            OwnerKind::Node => self.name.clone(),
+        }
+    }
+}
+
+/// Address-to-owner mapping of a cluster at one point in time.
+pub type IpOwners = BTreeMap<IpAddr, IpOwner>;
+
+/// Looks up which pods, services and nodes own which IP addresses.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `namespace` (Option<&str>) namespace to fall back to when listing all
+/// namespaces is forbidden.
+/// Returns: Result<IpOwners> mapping; errors only when pods cannot be listed at all.
+pub fn lookup_ip_owners(runner: &impl Runner, namespace: Option<&str>) -> Result<IpOwners> {
+    let mut owners = IpOwners::new();
+    let pods = list_scoped("pods", namespace, |ns| runner.list_pods(ns, None))?;
+    for owner in pod_owners(&pods) {
+        owners.entry(owner.0).or_insert(owner.1);
+    }
+    // Services and nodes only add names; RBAC commonly hides nodes from namespace users.
+    match list_scoped("services", namespace, |ns| runner.list_services(ns)) {
+        Ok(services) => service_owners(&services).into_iter().for_each(|(ip, owner)| {
+            owners.entry(ip).or_insert(owner);
+        }),
+        Err(err) => debug!("not naming service addresses: {err:#}"),
+    }
+    match runner.list_nodes() {
+        Ok(nodes) => node_owners(&nodes).into_iter().for_each(|(ip, owner)| {
+            owners.entry(ip).or_insert(owner);
+        }),
+        Err(err) => debug!("not naming node addresses: {err:#}"),
+    }
+    Ok(owners)
+}
+
+/// Looks up the InternalIP of every node, the address SSH usually reaches nodes on.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Returns: Result<BTreeMap<String, IpAddr>> first InternalIP by node name.
+pub fn node_addresses(runner: &impl Runner) -> Result<BTreeMap<String, IpAddr>> {
+    let nodes = runner.list_nodes()?;
+    let mut addresses = BTreeMap::new();
+    for (ip, owner) in node_owners(&nodes) {
+        addresses.entry(owner.name).or_insert(ip);
+    }
+    Ok(addresses)
+}
+
+fn list_scoped<T>(
+    resource: &str,
+    namespace: Option<&str>,
+    list: impl Fn(Option<&str>) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    match list(None) {
+        Ok(items) => Ok(items),
+        Err(err) => match namespace {
+            Some(ns) => {
+                debug!("listing {resource} in all namespaces failed, using {ns}: {err:#}");
+                list(Some(ns))
+            }
+            None => Err(err),
+        },
+    }
+}
+
+fn parse_ips(ips: &[String]) -> impl Iterator<Item = IpAddr> + '_ {
+    ips.iter().filter_map(|ip| ip.parse().ok())
+}
+
+fn pod_owners(pods: &[Pod]) -> Vec<(IpAddr, IpOwner)> {
+    let mut owners = Vec::new();
+    for pod in pods {
+        // Host-network pods share the node's address, and finished pods have given theirs up.
+        if pod.host_network || !matches!(pod.phase.as_str(), "Running" | "Pending") {
+            continue;
+        }
+        for ip in parse_ips(&pod.ips) {
+            owners.push((
+                ip,
+                IpOwner {
+                    kind: OwnerKind::Pod,
+                    namespace: pod.namespace.clone(),
+                    name: pod.name.clone(),
+                    node: pod.node.clone(),
+                },
+            ));
+        }
+    }
+    owners
+}
+
+fn service_owners(services: &[Service]) -> Vec<(IpAddr, IpOwner)> {
+    let mut owners = Vec::new();
+    for svc in services {
+        // Headless services report `None`, which does not parse.
+        for ip in parse_ips(&svc.cluster_ips) {
+            owners.push((
+                ip,
+                IpOwner {
+                    kind: OwnerKind::Service,
+                    namespace: svc.namespace.clone(),
+                    name: svc.name.clone(),
+                    node: None,
+                },
+            ));
+        }
+    }
+    owners
+}
+
+fn node_owners(nodes: &[Node]) -> Vec<(IpAddr, IpOwner)> {
+    let mut owners = Vec::new();
+    for node in nodes {
+        for ip in parse_ips(&node.internal_ips) {
+            owners.push((
+                ip,
+                IpOwner {
+                    kind: OwnerKind::Node,
+                    namespace: String::new(),
+                    name: node.name.clone(),
+                    node: None,
+                },
+            ));
+        }
     }
-    Ok(node)
+    owners
 }
 
 /// Builds kubectl exec arguments for running a remote command inside a pod.
@@ -74,6 +936,7 @@ pub fn resolve_pod_node(runner: &impl Runner, namespace: &str, pod: &str) -> Res
 /// Parameters: `container` (Option<&str>) container name.
 /// Parameters: `remote_cmd` (&str) command executed inside the container.
 /// Returns: Vec<String> argument list for kubectl.
+#[allow(clippy::vec_init_then_push)]
 pub fn build_kubectl_exec_args(
     namespace: &str,
     pod: &str,
@@ -99,23 +962,31 @@ pub fn build_kubectl_exec_args(
     args
 }
 
-/// Spawns a kubectl exec process with piped stdout.
+/// Spawns a kubectl exec process with piped stdout and stderr.
 /// Parameters: `args` (&[String]) argument list for kubectl exec.
 /// Returns: Result<Child> handle to the spawned process.
 pub fn spawn_kubectl_exec(args: &[String]) -> Result<Child> {
-    Command::new("kubectl")
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to spawn kubectl exec")
+    let mut cmd = Command::new("kubectl");
+    cmd.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+    crate::capture::shield(&mut cmd);
+    cmd.spawn().map_err(|err| spawn_error("kubectl", err))
+}
+
+fn spawn_error(program: &str, err: std::io::Error) -> anyhow::Error {
+    if program == "kubectl" && err.kind() == std::io::ErrorKind::NotFound {
+        return KcapError::KubectlNotFound.into();
+    }
+    anyhow::Error::new(err).context(format!("failed to run {program}"))
 }
 
 #[derive(Debug, Default)]
-/// Test runner that returns a fixed node and records calls.
+/// Test runner that returns fixed output and records calls.
 pub struct FakeRunner {
-    pub node_name: String,
+    /// Printed by every command; JSON for Kubernetes queries.
+    pub output: String,
     pub last_command: Mutex<CommandRecord>,
+    /// Directory searched first for the programs a pod exec runs; execs run locally.
+    pub exec_path: Option<std::path::PathBuf>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -126,13 +997,14 @@ pub struct CommandRecord {
 }
 
 impl FakeRunner {
-    /// Creates a FakeRunner that returns the provided node name.
-    /// Parameters: `node_name` (&str) node name to return.
+    /// Creates a FakeRunner that prints the provided output.
+    /// Parameters: `output` (&str) stdout of every command.
     /// Returns: FakeRunner instance for tests.
-    pub fn new(node_name: &str) -> Self {
+    pub fn new(output: &str) -> Self {
         Self {
-            node_name: node_name.to_string(),
+            output: output.to_string(),
             last_command: Mutex::new(CommandRecord::default()),
+            exec_path: None,
         }
     }
 }
@@ -143,25 +1015,75 @@ impl Runner for FakeRunner {
         let mut rec = self.last_command.lock().unwrap();
         rec.program = program.to_string();
         rec.args = args.iter().map(|s| s.to_string()).collect();
-        Ok(self.node_name.clone())
+        Ok(self.output.clone())
+    }
+}
+
+impl Exec for FakeRunner {
+    fn exec_factory(&self) -> ExecFactory {
+        let mut path = std::env::var_os("PATH").unwrap_or_default();
+        if let Some(dir) = &self.exec_path {
+            let dirs = std::iter::once(dir.clone()).chain(std::env::split_paths(&path));
+            path = std::env::join_paths(dirs).unwrap_or(path);
+        }
+        Box::new(move |exec| {
+            let path = path.clone();
+            Box::new(move || {
+                let mut cmd = Command::new("sh");
+                cmd.args(["-c", &exec.command])
+                    .env("PATH", &path)
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped());
+                Ok(cmd.spawn()?)
+            })
+        })
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json::json;
 
     #[test]
     fn resolve_pod_node_uses_kubectl() {
-        let runner = FakeRunner::new("node-x");
+        let runner = FakeRunner::new(r#"{"spec":{"nodeName":"node-x"}}"#);
         let node = resolve_pod_node(&runner, "prod", "orders").unwrap();
         assert_eq!(node, "node-x");
 
         let rec = runner.last_command.lock().unwrap().clone();
         assert_eq!(rec.program, "kubectl");
-        assert!(rec.args.contains(&"get".to_string()));
-        assert!(rec.args.contains(&"pod".to_string()));
-        assert!(rec.args.contains(&"orders".to_string()));
+        assert_eq!(
+            rec.args,
+            ["get", "pod", "orders", "-n", "prod", "--ignore-not-found", "-o", "json"]
+        );
+    }
+
+    #[test]
+    fn missing_pod_is_pod_not_found() {
+        let runner = FakeRunner::new("");
+        for err in [
+            resolve_pod_node(&runner, "prod", "orders").unwrap_err(),
+            resolve_pod_ip(&runner, "prod", "orders").unwrap_err(),
+            replacement_selector(&runner, "prod", "orders").unwrap_err(),
+        ] {
+            assert_eq!(err.to_string(), "pod prod/orders not found");
+            assert_eq!(crate::error::exit_code(&err), 32);
+        }
+        assert_eq!(get_pod_state(&runner, "prod", "orders", None).unwrap(), None);
+    }
+
+    #[test]
+    fn pod_state_lookup_failures_are_not_a_deleted_pod() {
+        struct NoKubectl;
+        impl Runner for NoKubectl {
+            fn run_capture(&self, program: &str, _args: &[&str]) -> Result<String> {
+                let err = std::io::Error::from(std::io::ErrorKind::NotFound);
+                Err(spawn_error(program, err))
+            }
+        }
+        let err = get_pod_state(&NoKubectl, "prod", "orders", None).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 31);
     }
 
     #[test]
@@ -184,4 +1106,246 @@ mod tests {
         assert!(args.iter().any(|a| a == "-c"));
         assert!(args.iter().any(|a| a == "api"));
     }
+
+    fn slice(port: Option<(&str, u16)>, endpoints: &[(&str, &str, &str, bool)]) -> Value {
+        let endpoints: Vec<Value> = endpoints
+            .iter()
+            .map(|(ip, target, node, ready)| {
+                let (kind, name) = target.split_once('/').unwrap_or_default();
+                json!({
+                    "addresses": [ip],
+                    "conditions": {"ready": ready},
+                    "nodeName": node,
+                    "targetRef": {"kind": kind, "name": name},
+                })
+            })
+            .collect();
+        let ports: Vec<Value> = port
+            .into_iter()
+            .map(|(name, port)| json!({"name": name, "port": port}))
+            .collect();
+        json!({"ports": ports, "endpoints": endpoints})
+    }
+
+    #[test]
+    fn resolve_service_endpoints_uses_slices() {
+        let list = json!({"items": [slice(
+            None,
+            &[
+                ("10.244.1.5", "Pod/pay-1", "node-a", true),
+                ("10.244.2.7", "Pod/pay-2", "node-b", true),
+            ],
+        )]});
+        let runner = FakeRunner::new(&list.to_string());
+        let eps = resolve_service_endpoints(&runner, "prod", "payments", None).unwrap();
+        assert_eq!(eps.len(), 2);
+        assert_eq!(eps[0].pod, "pay-1");
+        assert_eq!(eps[1].node, "node-b");
+
+        let rec = runner.last_command.lock().unwrap().clone();
+        assert!(rec.args.contains(&"endpointslices".to_string()));
+        assert!(rec
+            .args
+            .contains(&"kubernetes.io/service-name=payments".to_string()));
+    }
+
+    #[test]
+    fn endpoint_rows_skip_unready_and_non_pods() {
+        let doc = slice(
+            None,
+            &[
+                ("10.0.0.1", "Pod/a", "", false),
+                ("10.0.0.2", "/", "node", true),
+                ("10.0.0.3", "Pod/c", "", true),
+            ],
+        );
+        let rows = endpoint_rows(&[EndpointSlice::from_json(&doc)], None);
+        assert_eq!(
+            rows,
+            vec![("10.0.0.3".to_string(), "c".to_string(), None, None)]
+        );
+    }
+
+    #[test]
+    fn endpoint_rows_map_the_service_port_to_the_pod_port() {
+        let svc = Service::from_json(&json!({"spec": {"ports": [
+            {"name": "http", "port": 80},
+            {"name": "https", "port": 443},
+        ]}}));
+        assert_eq!(service_port_name(&svc, 443).as_deref(), Some("https"));
+        assert_eq!(service_port_name(&svc, 8443), None);
+        let unnamed = Service::from_json(&json!({"spec": {"ports": [{"port": 443}]}}));
+        assert_eq!(service_port_name(&unnamed, 443).as_deref(), Some(""));
+
+        let slices = [
+            slice(Some(("https", 8443)), &[("10.0.0.1", "Pod/a", "node-a", true)]),
+            slice(Some(("https", 9443)), &[("10.0.0.2", "Pod/b", "node-b", true)]),
+        ]
+        .map(|doc| EndpointSlice::from_json(&doc));
+        let rows = endpoint_rows(&slices, Some("https"));
+        assert_eq!(rows[0].3, Some(8443));
+        assert_eq!(rows[1].3, Some(9443));
+        assert_eq!(endpoint_rows(&slices, None)[0].3, None);
+        let unnamed = slice(Some(("", 8443)), &[("10.0.0.1", "Pod/a", "", true)]);
+        let rows = endpoint_rows(&[EndpointSlice::from_json(&unnamed)], Some(""));
+        assert_eq!(rows[0].3, Some(8443));
+    }
+
+    #[test]
+    fn resolve_service_endpoints_requires_endpoints() {
+        let runner = FakeRunner::new("");
+        assert!(resolve_service_endpoints(&runner, "prod", "payments", None).is_err());
+    }
+
+    #[test]
+    fn context_runner_prefixes_kubectl_calls() {
+        let kube = KubeContext {
+            kubeconfig: Some("/tmp/kc".to_string()),
+            context: Some("prod".to_string()),
+            cluster: None,
+        };
+        let runner = ContextRunner::new(FakeRunner::new(r#"{"spec":{"nodeName":"n"}}"#), kube);
+        resolve_pod_node(&runner, "prod", "orders").unwrap();
+
+        let rec = runner.inner.last_command.lock().unwrap().clone();
+        assert_eq!(rec.args[..4], ["--kubeconfig", "/tmp/kc", "--context", "prod"]);
+        assert_eq!(rec.args[4], "get");
+
+        runner.run_capture("ssh", &["host"]).unwrap();
+        let rec = runner.inner.last_command.lock().unwrap().clone();
+        assert_eq!(rec.args, vec!["host".to_string()]);
+    }
+
+    #[test]
+    fn kube_context_applies_to_exec_args() {
+        let kube = KubeContext {
+            cluster: Some("east".to_string()),
+            ..Default::default()
+        };
+        let args = kube.apply(build_kubectl_exec_args("prod", "orders", None, "cmd"));
+        assert_eq!(args[..3], ["--cluster", "east", "exec"]);
+    }
+
+    #[test]
+    fn current_context_prefers_explicit_selection() {
+        let runner = FakeRunner::new("from-kubectl");
+        let kube = KubeContext {
+            context: Some("staging".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(current_context(&runner, &kube).unwrap(), "staging");
+        assert_eq!(
+            current_context(&runner, &KubeContext::default()).unwrap(),
+            "from-kubectl"
+        );
+    }
+
+    fn pod(name: &str, deleting: bool, containers: &[(&str, Option<&str>)]) -> Pod {
+        let statuses: Vec<Value> = containers
+            .iter()
+            .map(|(name, started)| match started {
+                Some(t) => json!({"name": name, "state": {"running": {"startedAt": t}}}),
+                None => json!({"name": name, "state": {"waiting": {"reason": "CrashLoopBackOff"}}}),
+            })
+            .collect();
+        let deletion = deleting.then_some("2024-01-01T00:01:00Z");
+        Pod::from_json(&json!({
+            "metadata": {"name": name, "deletionTimestamp": deletion},
+            "status": {"phase": "Running", "containerStatuses": statuses},
+        }))
+    }
+
+    #[test]
+    fn pod_states_track_readiness() {
+        let t = Some("2024-01-01T00:00:00Z");
+        assert!(pod("a", false, &[("api", t)]).state(None).ready_for_exec());
+        let crash_looping = pod("b", false, &[("api", None)]);
+        assert!(!crash_looping.state(None).ready_for_exec());
+        assert!(!pod("c", true, &[("api", t)]).state(None).ready_for_exec(), "terminating pod");
+    }
+
+    #[test]
+    fn pod_state_pins_container() {
+        let p = pod("a", false, &[("api", None), ("sidecar", Some("t1"))]);
+        assert!(p.state(None).ready_for_exec());
+        assert!(p.state(Some("sidecar")).ready_for_exec());
+        assert!(!p.state(Some("api")).ready_for_exec());
+    }
+
+    #[test]
+    fn replacement_selector_drops_revision_labels() {
+        let runner = FakeRunner::new(
+            r#"{"metadata":{"labels":{"app":"orders","pod-template-hash":"7f9c","tier":"api"}}}"#,
+        );
+        let sel = replacement_selector(&runner, "prod", "orders-7f9c-abcde").unwrap();
+        assert_eq!(sel.as_deref(), Some("app=orders,tier=api"));
+
+        let runner = FakeRunner::new(
+            r#"{"metadata":{"labels":{"controller-revision-hash":"db-5d8","statefulset.kubernetes.io/pod-name":"db-0"}}}"#,
+        );
+        assert_eq!(replacement_selector(&runner, "prod", "db-0").unwrap(), None);
+    }
+
+    #[test]
+    fn ip_owners_skip_host_network_and_finished_pods() {
+        let pods = [
+            json!({"metadata": {"namespace": "prod", "name": "orders-7f9c"},
+                   "spec": {"nodeName": "node-a"},
+                   "status": {"phase": "Running",
+                              "podIPs": [{"ip": "10.244.1.5"}, {"ip": "fd00::5"}]}}),
+            json!({"metadata": {"namespace": "kube-system", "name": "kube-proxy-x"},
+                   "spec": {"nodeName": "node-a", "hostNetwork": true},
+                   "status": {"phase": "Running", "podIP": "10.0.0.11"}}),
+            json!({"metadata": {"namespace": "prod", "name": "migrate-1"},
+                   "spec": {"nodeName": "node-b"},
+                   "status": {"phase": "Succeeded", "podIP": "10.244.2.9"}}),
+        ]
+        .map(|doc| Pod::from_json(&doc));
+        let pods = pod_owners(&pods);
+        assert_eq!(pods.len(), 2);
+        assert_eq!(pods[1].0, "fd00::5".parse::<IpAddr>().unwrap());
+        assert_eq!(pods[0].1.label(), "orders-7f9c.prod");
+        assert_eq!(pods[0].1.node.as_deref(), Some("node-a"));
+
+        let svcs = [
+            json!({"metadata": {"namespace": "kube-system", "name": "kube-dns"},
+                   "spec": {"clusterIPs": ["10.96.0.10"]}}),
+            json!({"metadata": {"namespace": "prod", "name": "db"}, "spec": {"clusterIP": "None"}}),
+        ]
+        .map(|doc| Service::from_json(&doc));
+        let svcs = service_owners(&svcs);
+        assert_eq!(svcs.len(), 1);
+        assert_eq!(svcs[0].1.label(), "kube-dns.kube-system.svc");
+
+        let node = Node::from_json(&json!({"metadata": {"name": "node-a"},
+            "status": {"addresses": [{"type": "Hostname", "address": "node-a"},
+                                     {"type": "InternalIP", "address": "10.0.0.11"}]}}));
+        let nodes = node_owners(&[node]);
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].1.label(), "node-a");
+    }
+
+    #[test]
+    fn lookup_ip_owners_lists_all_namespaces() {
+        let runner = FakeRunner::new("");
+        let owners = lookup_ip_owners(&runner, Some("prod")).unwrap();
+        assert!(owners.is_empty());
+        let rec = runner.last_command.lock().unwrap().clone();
+        assert_eq!(rec.args[..2], ["get", "nodes"]);
+    }
+
+    #[test]
+    fn node_addresses_keep_the_first_internal_ip() {
+        let list = json!({"items": [
+            {"metadata": {"name": "node-a"}, "status": {"addresses": [
+                {"type": "InternalIP", "address": "10.0.0.11"},
+                {"type": "InternalIP", "address": "fd00::11"},
+            ]}},
+            {"metadata": {"name": "node-b"}, "status": {"addresses": []}},
+        ]});
+        let runner = FakeRunner::new(&list.to_string());
+        let addresses = node_addresses(&runner).unwrap();
+        assert_eq!(addresses.len(), 1);
+        assert_eq!(addresses["node-a"].to_string(), "10.0.0.11");
+    }
 }
@@ -0,0 +1,136 @@
+use crate::capture::shell_escape_single_quotes;
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::process::Child;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::debug;
+
+/// Builds the remote command that streams a TLS key log, including lines written later.
+/// Parameters: `path` (&str) SSLKEYLOGFILE path on the target.
+/// Returns: String shell command for `sh -c`.
+pub fn tail_command(path: &str) -> String {
+    // -F keeps waiting when the application has not created the file yet.
+    format!("tail -n +1 -F {}", shell_escape_single_quotes(path))
+}
+
+#[derive(Debug, Default)]
+struct Collected {
+    seen: HashSet<String>,
+    pending: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+/// TLS key log lines gathered from all targets, waiting to be written into the capture.
+pub struct Secrets(Arc<Mutex<Collected>>);
+
+impl Secrets {
+    /// Queues one key log line unless it is malformed or was seen before.
+    /// Parameters: `line` (&str) line without its newline.
+    /// Returns: bool true when the line was queued.
+    pub fn push_line(&self, line: &str) -> bool {
+        let line = line.trim_end();
+        if !is_key_line(line) {
+            return false;
+        }
+        let mut collected = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        // A rotated or rewritten file is read again from the start.
+        if !collected.seen.insert(line.to_string()) {
+            return false;
+        }
+        collected.pending.extend_from_slice(line.as_bytes());
+        collected.pending.push(b'\n');
+        true
+    }
+
+    /// Takes the lines queued since the last call.
+    /// Returns: Vec<u8> complete key log lines, empty when nothing new arrived.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().unwrap_or_else(|e| e.into_inner()).pending)
+    }
+}
+
+/// Whether `line` is an NSS key log entry (`LABEL <client random> <secret>`).
+fn is_key_line(line: &str) -> bool {
+    let fields: Vec<&str> = line.split(' ').collect();
+    let label = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit() || b == b'_')
+    };
+    let hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    fields.len() == 3 && label(fields[0]) && hex(fields[1]) && hex(fields[2])
+}
+
+/// A remote `tail` of a key log file feeding `Secrets`.
+pub struct Tail {
+    child: Child,
+    delivered: Arc<AtomicUsize>,
+}
+
+impl Tail {
+    /// Starts reading the key log lines a spawned `tail_command` prints.
+    /// Parameters: `child` (Child) ssh or kubectl exec process with piped stdout/stderr.
+    /// Parameters: `secrets` (&Secrets) collection the lines are added to.
+    /// Returns: Tail handle to stop with `finish`.
+    pub fn start(mut child: Child, secrets: &Secrets) -> Tail {
+        if let Some(stderr) = child.stderr.take() {
+            thread::spawn(move || {
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    debug!(remote_stderr = %line, "key log tail output");
+                }
+            });
+        }
+        let delivered = Arc::new(AtomicUsize::new(0));
+        if let Some(stdout) = child.stdout.take() {
+            let secrets = secrets.clone();
+            let delivered = delivered.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if secrets.push_line(&line) {
+                        delivered.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+        Tail { child, delivered }
+    }
+
+    /// Stops the tail once the capture has ended.
+    /// Returns: usize number of new key log lines it delivered.
+    pub fn finish(mut self) -> usize {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        // The reader is not joined: a remote process may keep the pipe open a while.
+        self.delivered.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RANDOM: &str = "52340c85e3a6b1b0c0f2a1d5e6c7d8e9f0a1b2c3d4e5f60718293a4b5c6d7e8f";
+
+    #[test]
+    fn queues_each_key_line_once() {
+        let secrets = Secrets::default();
+        let line = format!("CLIENT_TRAFFIC_SECRET_0 {RANDOM} 0a1b2c");
+        assert!(secrets.push_line(&line));
+        assert!(!secrets.push_line(&line));
+        assert!(!secrets.push_line("# SSL/TLS secrets log file"));
+        assert!(!secrets.push_line(""));
+        assert!(!secrets.push_line(&format!("CLIENT_RANDOM {RANDOM}")));
+        assert_eq!(secrets.take(), format!("{line}\n").into_bytes());
+        assert!(secrets.take().is_empty());
+    }
+
+    #[test]
+    fn tail_command_quotes_the_path() {
+        assert_eq!(
+            tail_command("/tmp/it's.log"),
+            "tail -n +1 -F '/tmp/it'\\''s.log'"
+        );
+    }
+}
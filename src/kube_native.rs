@@ -0,0 +1,374 @@
+//! Native Kubernetes API backend used when the `native-k8s` feature is enabled.
+//!
+//! `NativeRunner` answers kcap's Kubernetes queries from the API server, and
+//! `shim_main` lets kcap stand in for `kubectl exec` as a child process so the
+//! capture pipeline keeps its process semantics.
+
+mod client;
+pub mod kubeconfig;
+
+use crate::k8s::{EndpointSlice, KubeContext, Node, Pod, PodExec, Runner, Service, SystemRunner};
+use anyhow::{Context, Result};
+use client::ApiClient;
+use kubeconfig::Selection;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::process::{Child, Command, Stdio};
+
+/// First argument that makes kcap act as a `kubectl exec` replacement.
+pub const SHIM_ARG: &str = "__kube-exec";
+
+#[derive(Debug, Clone)]
+/// Runner that serves Kubernetes queries from the API server without kubectl.
+pub struct NativeRunner {
+    selection: Selection,
+}
+
+impl NativeRunner {
+    /// Creates a runner for a kubeconfig selection.
+    /// Parameters: `kube` (&KubeContext) kubeconfig, context and cluster overrides.
+    /// Returns: NativeRunner that loads the kubeconfig on every query.
+    pub fn new(kube: &KubeContext) -> Self {
+        Self {
+            selection: Selection {
+                kubeconfig: kube.kubeconfig.clone(),
+                context: kube.context.clone(),
+                cluster: kube.cluster.clone(),
+            },
+        }
+    }
+
+    fn client(&self) -> Result<ApiClient> {
+        ApiClient::new(kubeconfig::load(&self.selection)?)
+    }
+
+    /// Spawns kcap itself as a `kubectl exec` stand-in with piped stdout and stderr.
+    /// Parameters: `exec` (&PodExec) command and the pod to run it in.
+    /// Returns: Result<Child> handle to the spawned process.
+    pub fn spawn_exec(&self, exec: &PodExec) -> Result<Child> {
+        let request = self.shim_request(exec);
+        let exe = std::env::current_exe().context("failed to locate the kcap executable")?;
+        let mut cmd = Command::new(exe);
+        cmd.arg(SHIM_ARG)
+            .arg(serde_json::to_string(&request)?)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        crate::capture::shield(&mut cmd);
+        cmd.spawn().context("failed to spawn native kubernetes exec")
+    }
+
+    fn shim_request(&self, exec: &PodExec) -> ShimRequest {
+        ShimRequest {
+            selection: self.selection.clone(),
+            namespace: exec.namespace.clone(),
+            pod: exec.pod.clone(),
+            container: exec.container.clone(),
+            command: exec.command.clone(),
+        }
+    }
+}
+
+impl Runner for NativeRunner {
+    fn run_capture(&self, program: &str, args: &[&str]) -> Result<String> {
+        // Only the Kubernetes queries are native; ssh probes and friends still run as processes.
+        SystemRunner.run_capture(program, args)
+    }
+
+    fn current_context(&self) -> Result<String> {
+        Ok(kubeconfig::load(&self.selection)?.context)
+    }
+
+    fn get_pod(&self, namespace: &str, name: &str) -> Result<Option<Pod>> {
+        self.client()?.get_pod(namespace, name)
+    }
+
+    fn list_pods(&self, namespace: Option<&str>, selector: Option<&str>) -> Result<Vec<Pod>> {
+        self.client()?.list_pods(namespace, selector)
+    }
+
+    fn get_service(&self, namespace: &str, name: &str) -> Result<Option<Service>> {
+        self.client()?.get_service(namespace, name)
+    }
+
+    fn list_services(&self, namespace: Option<&str>) -> Result<Vec<Service>> {
+        self.client()?.list_services(namespace)
+    }
+
+    fn list_endpoints(&self, namespace: &str, service: &str) -> Result<Vec<EndpointSlice>> {
+        self.client()?.list_endpoints(namespace, service)
+    }
+
+    fn list_nodes(&self) -> Result<Vec<Node>> {
+        self.client()?.list_nodes()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+/// What the exec stand-in runs, passed as a single JSON argument.
+struct ShimRequest {
+    #[serde(flatten)]
+    selection: Selection,
+    namespace: String,
+    pod: String,
+    container: Option<String>,
+    command: String,
+}
+
+/// Runs the `kubectl exec` stand-in when kcap was invoked with `SHIM_ARG`.
+/// Returns: Option<i32> exit code for the shim, or None for a normal kcap run.
+pub fn shim_main() -> Option<i32> {
+    let mut argv = std::env::args().skip(1);
+    if argv.next().as_deref() != Some(SHIM_ARG) {
+        return None;
+    }
+    let request = argv.next().unwrap_or_default();
+    let code = match run_exec_shim(&request) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            1
+        }
+    };
+    Some(code)
+}
+
+fn run_exec_shim(request: &str) -> Result<i32> {
+    let req: ShimRequest =
+        serde_json::from_str(request).context("malformed native exec request")?;
+    let client = ApiClient::new(kubeconfig::load(&req.selection)?)?;
+    // Execute through a shell to preserve the capture command quoting.
+    let command = ["sh", "-c", &req.command].map(str::to_string);
+    let mut stdout = io::stdout().lock();
+    let mut stderr = io::stderr().lock();
+    client.exec(
+        &req.namespace,
+        &req.pod,
+        req.container.as_deref(),
+        &command,
+        &mut stdout,
+        &mut stderr,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::k8s;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Serves one canned JSON response per accepted connection and reports request heads.
+    fn fake_api(responses: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (mut sock, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(sock.try_clone().unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                tx.send(head).unwrap();
+                write!(
+                    sock,
+                    "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (format!("http://{addr}"), rx)
+    }
+
+    fn kubeconfig_for(server: &str) -> tempfile::NamedTempFile {
+        let mut f = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            f,
+            "current-context: fake\nclusters:\n- name: fake\n  cluster:\n    server: {server}\ncontexts:\n- name: fake\n  context:\n    cluster: fake\n    user: fake\n    namespace: prod\nusers:\n- name: fake\n  user:\n    token: secret\n"
+        )
+        .unwrap();
+        f
+    }
+
+    fn kube(file: &tempfile::NamedTempFile) -> KubeContext {
+        KubeContext {
+            kubeconfig: Some(file.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resolves_pod_node_from_fake_api_server() {
+        let (server, requests) = fake_api(vec![(200, r#"{"spec":{"nodeName":"node-7"}}"#)]);
+        let file = kubeconfig_for(&server);
+        let runner = NativeRunner::new(&kube(&file));
+
+        let node = k8s::resolve_pod_node(&runner, "prod", "orders").unwrap();
+        assert_eq!(node, "node-7");
+
+        let head = requests.recv().unwrap();
+        assert!(head.starts_with("GET /api/v1/namespaces/prod/pods/orders HTTP/1.1"));
+        assert!(head.contains("Authorization: Bearer secret"));
+    }
+
+    #[test]
+    fn resolves_service_endpoints_from_fake_api_server() {
+        let (server, requests) = fake_api(vec![
+            (200, r#"{"spec":{"ports":[{"name":"https","port":443,"targetPort":"web"}]}}"#),
+            (200, r#"{"items":[{"ports":[{"name":"https","port":8443}],"endpoints":[{"addresses":["10.244.0.9"],"conditions":{"ready":true},"nodeName":"node-1","targetRef":{"kind":"Pod","name":"pay-0"}}]}]}"#),
+        ]);
+        let file = kubeconfig_for(&server);
+        let runner = NativeRunner::new(&kube(&file));
+
+        let eps = k8s::resolve_service_endpoints(&runner, "prod", "payments", Some(443)).unwrap();
+        assert_eq!(eps.len(), 1);
+        assert_eq!(eps[0].pod, "pay-0");
+        assert_eq!(eps[0].node, "node-1");
+        assert_eq!(eps[0].port, Some(8443));
+
+        let head = requests.recv().unwrap();
+        assert!(head.starts_with("GET /api/v1/namespaces/prod/services/payments "));
+        let head = requests.recv().unwrap();
+        assert!(head.starts_with(
+            "GET /apis/discovery.k8s.io/v1/namespaces/prod/endpointslices?labelSelector=kubernetes.io%2Fservice-name%3Dpayments "
+        ));
+    }
+
+    #[test]
+    fn reports_current_context_without_kubectl() {
+        let file = kubeconfig_for("http://127.0.0.1:1");
+        let runner = NativeRunner::new(&kube(&file));
+        let ctx = k8s::current_context(&runner, &KubeContext::default());
+        assert_eq!(ctx.unwrap(), "fake");
+    }
+
+    #[test]
+    fn missing_pod_is_none_but_other_errors_are_not() {
+        let (server, _requests) = fake_api(vec![
+            (404, r#"{"kind":"Status","reason":"NotFound","message":"pods \"gone\" not found"}"#),
+            (403, r#"{"kind":"Status","reason":"Forbidden","message":"pods is forbidden"}"#),
+        ]);
+        let file = kubeconfig_for(&server);
+        let runner = NativeRunner::new(&kube(&file));
+        assert_eq!(runner.get_pod("prod", "gone").unwrap(), None);
+        let err = runner.get_pod("prod", "orders").unwrap_err();
+        assert!(format!("{err:#}").contains("403"));
+    }
+
+    // The handshake callback signature is fixed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    fn fake_exec(frames: Vec<Vec<u8>>) -> (ApiClient, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (sock, _) = listener.accept().unwrap();
+            let mut path = String::new();
+            let mut ws = tungstenite::accept_hdr(
+                sock,
+                |req: &tungstenite::handshake::server::Request,
+                 mut resp: tungstenite::handshake::server::Response| {
+                    path = req.uri().to_string();
+                    resp.headers_mut().insert(
+                        "Sec-WebSocket-Protocol",
+                        "v4.channel.k8s.io".parse().unwrap(),
+                    );
+                    Ok(resp)
+                },
+            )
+            .unwrap();
+            for frame in frames {
+                if ws.send(tungstenite::Message::Binary(frame.into())).is_err() {
+                    break;
+                }
+            }
+            ws.close(None).ok();
+            while ws.read().is_ok() {}
+            path
+        });
+
+        let file = kubeconfig_for(&server);
+        let conn = kubeconfig::load(&Selection {
+            kubeconfig: Some(file.path().to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        (ApiClient::new(conn).unwrap(), handle)
+    }
+
+    #[test]
+    fn exec_streams_stdout_over_websocket() {
+        let status = br#"{"status":"Failure","details":{"causes":[{"reason":"ExitCode","message":"3"}]}}"#;
+        let mut frame = vec![3u8];
+        frame.extend_from_slice(status);
+        let (client, handle) = fake_exec(vec![
+            b"\x01pcap-bytes".to_vec(),
+            b"\x02warning".to_vec(),
+            frame,
+        ]);
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let cmd = vec![
+            "sh".to_string(),
+            "-c".to_string(),
+            "tcpdump -w -".to_string(),
+        ];
+        let code = client
+            .exec("prod", "orders", Some("api"), &cmd, &mut out, &mut err)
+            .unwrap();
+
+        assert_eq!(code, 3);
+        assert_eq!(out, b"pcap-bytes");
+        assert_eq!(err, b"warning");
+        let path = handle.join().unwrap();
+        assert!(path.starts_with("/api/v1/namespaces/prod/pods/orders/exec?"));
+        assert!(path.contains("container=api"));
+        assert!(path.contains("command=tcpdump%20-w%20-"));
+    }
+
+    #[test]
+    fn exec_fails_when_stdout_is_gone() {
+        struct Gone;
+        impl Write for Gone {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::BrokenPipe.into())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let (client, handle) = fake_exec(vec![b"\x01pcap-bytes".to_vec()]);
+        let cmd = vec!["tcpdump".to_string()];
+        let result = client.exec("prod", "orders", None, &cmd, &mut Gone, &mut Vec::new());
+        assert!(result.is_err());
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn shim_request_round_trips() {
+        let kube = KubeContext {
+            context: Some("prod".to_string()),
+            ..Default::default()
+        };
+        let exec = k8s::PodExec {
+            namespace: "ns".to_string(),
+            pod: "p".to_string(),
+            container: Some("c".to_string()),
+            command: "tcpdump -w - 'port 53'".to_string(),
+        };
+        let request = NativeRunner::new(&kube).shim_request(&exec);
+        let json = serde_json::to_string(&request).unwrap();
+        assert!(json.contains(r#""context":"prod""#));
+        let parsed: ShimRequest = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, request);
+    }
+}
@@ -0,0 +1,705 @@
+use super::kubeconfig::ClusterConnection;
+use crate::k8s::{self, EndpointSlice, Node, Pod, Service};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::client::IntoClientRequest;
+use tungstenite::Message;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// A stalled API server must not hang kcap; exec streams are exempt, captures may be quiet.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Byte stream to the API server, optionally wrapped in TLS.
+pub enum ApiStream {
+    Plain(TcpStream),
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl Read for ApiStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ApiStream::Plain(s) => s.read(buf),
+            ApiStream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ApiStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ApiStream::Plain(s) => s.write(buf),
+            ApiStream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ApiStream::Plain(s) => s.flush(),
+            ApiStream::Tls(s) => s.flush(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ServerUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    prefix: String,
+}
+
+fn parse_server(server: &str) -> Result<ServerUrl> {
+    let (tls, rest) = if let Some(r) = server.strip_prefix("https://") {
+        (true, r)
+    } else if let Some(r) = server.strip_prefix("http://") {
+        (false, r)
+    } else {
+        bail!("unsupported API server URL {server}");
+    };
+    let (authority, prefix) = match rest.find('/') {
+        Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+        None => (rest, ""),
+    };
+    let default_port = if tls { 443 } else { 80 };
+    let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+        let (h, after) = v6
+            .split_once(']')
+            .ok_or_else(|| anyhow!("invalid IPv6 address in {server}"))?;
+        let port = after.strip_prefix(':').map(str::parse).transpose()?;
+        (h.to_string(), port.unwrap_or(default_port))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((h, p)) => (h.to_string(), p.parse().context("invalid API server port")?),
+            None => (authority.to_string(), default_port),
+        }
+    };
+    Ok(ServerUrl {
+        tls,
+        host,
+        port,
+        prefix: prefix.to_string(),
+    })
+}
+
+/// Minimal HTTP/1.1 client for the Kubernetes API server.
+pub struct ApiClient {
+    conn: ClusterConnection,
+    url: ServerUrl,
+    tls: Option<Arc<ClientConfig>>,
+    request_timeout: Duration,
+}
+
+impl ApiClient {
+    /// Prepares TLS and authentication for a cluster connection.
+    /// Parameters: `conn` (ClusterConnection) resolved kubeconfig context.
+    /// Returns: Result<ApiClient> ready to issue requests.
+    pub fn new(conn: ClusterConnection) -> Result<Self> {
+        let url = parse_server(&conn.server)?;
+        let tls = if url.tls {
+            Some(Arc::new(tls_config(&conn)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            conn,
+            url,
+            tls,
+            request_timeout: REQUEST_TIMEOUT,
+        })
+    }
+
+    fn connect(&self, read_timeout: Option<Duration>) -> Result<ApiStream> {
+        let addr = (self.url.host.as_str(), self.url.port);
+        let sock = std::net::ToSocketAddrs::to_socket_addrs(&addr)
+            .with_context(|| format!("failed to resolve {}", self.url.host))?
+            .next()
+            .ok_or_else(|| anyhow!("no address for {}", self.url.host))?;
+        let tcp = TcpStream::connect_timeout(&sock, CONNECT_TIMEOUT)
+            .with_context(|| format!("failed to connect to {}", self.conn.server))?;
+        tcp.set_nodelay(true).ok();
+        tcp.set_read_timeout(read_timeout)
+            .context("failed to set the API read timeout")?;
+
+        let Some(config) = &self.tls else {
+            return Ok(ApiStream::Plain(tcp));
+        };
+        let name = self
+            .conn
+            .tls_server_name
+            .clone()
+            .unwrap_or_else(|| self.url.host.clone());
+        let server_name = ServerName::try_from(name.clone())
+            .with_context(|| format!("invalid TLS server name {name}"))?;
+        let session = rustls::ClientConnection::new(config.clone(), server_name)
+            .context("failed to start TLS session")?;
+        Ok(ApiStream::Tls(Box::new(rustls::StreamOwned::new(
+            session, tcp,
+        ))))
+    }
+
+    fn authorization(&self) -> Option<String> {
+        let creds = &self.conn.credentials;
+        if let Some(token) = &creds.token {
+            return Some(format!("Bearer {token}"));
+        }
+        creds.basic.as_ref().map(|(u, p)| {
+            let raw = format!("{u}:{p}");
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(raw)
+            )
+        })
+    }
+
+    fn host_header(&self) -> String {
+        if self.url.host.contains(':') {
+            format!("[{}]:{}", self.url.host, self.url.port)
+        } else {
+            format!("{}:{}", self.url.host, self.url.port)
+        }
+    }
+
+    /// Issues a GET request and parses the JSON body.
+    /// Parameters: `path` (&str) API path including any query string.
+    /// Returns: Result<Option<serde_json::Value>> response document, None when the object
+    /// does not exist, or the API error message.
+    fn get_json(&self, path: &str) -> Result<Option<serde_json::Value>> {
+        let mut stream = self.connect(Some(self.request_timeout))?;
+        let mut req = format!(
+            "GET {}{} HTTP/1.1\r\nHost: {}\r\nAccept: application/json\r\nUser-Agent: kcap/{}\r\nConnection: close\r\n",
+            self.url.prefix,
+            path,
+            self.host_header(),
+            env!("CARGO_PKG_VERSION"),
+        );
+        if let Some(auth) = self.authorization() {
+            req.push_str(&format!("Authorization: {auth}\r\n"));
+        }
+        req.push_str("\r\n");
+        stream
+            .write_all(req.as_bytes())
+            .context("failed to send API request")?;
+        stream.flush().ok();
+
+        let (status, body) = read_response(BufReader::new(stream)).with_context(|| {
+            let secs = self.request_timeout.as_secs();
+            format!("no complete answer to GET {path} (read timeout {secs}s)")
+        })?;
+        let value: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        if status == 404 && value["reason"] == "NotFound" {
+            return Ok(None);
+        }
+        if !(200..300).contains(&status) {
+            let message = value["message"]
+                .as_str()
+                .map(str::to_string)
+                .unwrap_or_else(|| String::from_utf8_lossy(&body).trim().to_string());
+            bail!("kubernetes API returned {status}: {message}");
+        }
+        if value.is_null() {
+            bail!("kubernetes API returned a non-JSON body for {path}");
+        }
+        Ok(Some(value))
+    }
+
+    /// Fetches one pod.
+    /// Parameters: `namespace` (&str) pod namespace.
+    /// Parameters: `name` (&str) pod name.
+    /// Returns: Result<Option<Pod>> the pod, or None when it does not exist.
+    pub fn get_pod(&self, namespace: &str, name: &str) -> Result<Option<Pod>> {
+        let path = object_path("/api/v1", "pods", namespace, name);
+        Ok(self.get_json(&path)?.as_ref().map(Pod::from_json))
+    }
+
+    /// Lists pods.
+    /// Parameters: `namespace` (Option<&str>) namespace to search; None for all namespaces.
+    /// Parameters: `selector` (Option<&str>) label selector.
+    /// Returns: Result<Vec<Pod>> matching pods.
+    pub fn list_pods(&self, namespace: Option<&str>, selector: Option<&str>) -> Result<Vec<Pod>> {
+        let doc = self.get_list("/api/v1", "pods", namespace, selector)?;
+        Ok(k8s::items(&doc).iter().map(Pod::from_json).collect())
+    }
+
+    /// Fetches one Service.
+    /// Parameters: `namespace` (&str) service namespace.
+    /// Parameters: `name` (&str) service name.
+    /// Returns: Result<Option<Service>> the service, or None when it does not exist.
+    pub fn get_service(&self, namespace: &str, name: &str) -> Result<Option<Service>> {
+        let path = object_path("/api/v1", "services", namespace, name);
+        Ok(self.get_json(&path)?.as_ref().map(Service::from_json))
+    }
+
+    /// Lists Services.
+    /// Parameters: `namespace` (Option<&str>) namespace to search; None for all namespaces.
+    /// Returns: Result<Vec<Service>> services.
+    pub fn list_services(&self, namespace: Option<&str>) -> Result<Vec<Service>> {
+        let doc = self.get_list("/api/v1", "services", namespace, None)?;
+        Ok(k8s::items(&doc).iter().map(Service::from_json).collect())
+    }
+
+    /// Lists the EndpointSlices of a Service.
+    /// Parameters: `namespace` (&str) service namespace.
+    /// Parameters: `service` (&str) service name.
+    /// Returns: Result<Vec<EndpointSlice>> slices.
+    pub fn list_endpoints(&self, namespace: &str, service: &str) -> Result<Vec<EndpointSlice>> {
+        let selector = format!("{}={service}", k8s::SERVICE_NAME_LABEL);
+        let doc = self.get_list(
+            "/apis/discovery.k8s.io/v1",
+            "endpointslices",
+            Some(namespace),
+            Some(&selector),
+        )?;
+        Ok(k8s::items(&doc).iter().map(EndpointSlice::from_json).collect())
+    }
+
+    /// Lists cluster nodes.
+    /// Returns: Result<Vec<Node>> nodes.
+    pub fn list_nodes(&self) -> Result<Vec<Node>> {
+        let doc = self.get_list("/api/v1", "nodes", None, None)?;
+        Ok(k8s::items(&doc).iter().map(Node::from_json).collect())
+    }
+
+    fn get_list(
+        &self,
+        group: &str,
+        plural: &str,
+        namespace: Option<&str>,
+        selector: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let mut path = group.to_string();
+        if let Some(ns) = namespace {
+            path.push_str(&format!("/namespaces/{}", percent_encode(ns)));
+        }
+        path.push_str(&format!("/{plural}"));
+        if let Some(s) = selector {
+            path.push_str(&format!("?labelSelector={}", percent_encode(s)));
+        }
+        // Lists only go missing with their API group, which counts as empty.
+        Ok(self.get_json(&path)?.unwrap_or_default())
+    }
+
+    /// Runs a command in a container over the exec WebSocket and relays its output.
+    /// Parameters: `namespace` (&str) pod namespace.
+    /// Parameters: `pod` (&str) pod name.
+    /// Parameters: `container` (Option<&str>) container name.
+    /// Parameters: `command` (&[String]) argv executed in the container.
+    /// Parameters: `stdout` (&mut impl Write) sink for channel 1.
+    /// Parameters: `stderr` (&mut impl Write) sink for channel 2.
+    /// Returns: Result<i32> remote exit code; an error when the stream ended without one.
+    pub fn exec(
+        &self,
+        namespace: &str,
+        pod: &str,
+        container: Option<&str>,
+        command: &[String],
+        stdout: &mut impl Write,
+        stderr: &mut impl Write,
+    ) -> Result<i32> {
+        let mut query = vec!["stdout=true".to_string(), "stderr=true".to_string()];
+        if let Some(c) = container {
+            query.push(format!("container={}", percent_encode(c)));
+        }
+        for arg in command {
+            query.push(format!("command={}", percent_encode(arg)));
+        }
+        let scheme = if self.url.tls { "wss" } else { "ws" };
+        let uri = format!(
+            "{scheme}://{}{}/api/v1/namespaces/{}/pods/{}/exec?{}",
+            self.host_header(),
+            self.url.prefix,
+            percent_encode(namespace),
+            percent_encode(pod),
+            query.join("&")
+        );
+        let mut request = uri.into_client_request().context("invalid exec request")?;
+        let headers = request.headers_mut();
+        headers.insert(
+            "Sec-WebSocket-Protocol",
+            EXEC_PROTOCOL.parse().expect("static header value"),
+        );
+        if let Some(auth) = self.authorization() {
+            headers.insert(
+                "Authorization",
+                auth.parse().context("invalid credentials")?,
+            );
+        }
+
+        let stream = self.connect(None)?;
+        let (mut ws, _) = tungstenite::client::client(request, stream)
+            .map_err(|e| anyhow!("exec websocket handshake failed: {e}"))?;
+
+        let mut exit_code = None;
+        loop {
+            let msg = match ws.read() {
+                Ok(m) => m,
+                Err(tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed) => {
+                    break
+                }
+                Err(tungstenite::Error::Protocol(
+                    tungstenite::error::ProtocolError::ResetWithoutClosingHandshake,
+                )) => break,
+                Err(e) => return Err(anyhow!("exec stream failed: {e}")),
+            };
+            let data = match msg {
+                Message::Binary(d) => d,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            let Some((&channel, payload)) = data.split_first() else {
+                continue;
+            };
+            match channel {
+                1 => {
+                    // The reader went away (e.g. capture stopped); that is not a clean exit.
+                    stdout
+                        .write_all(payload)
+                        .and_then(|_| stdout.flush())
+                        .context("failed to write exec output")?;
+                }
+                2 => {
+                    stderr.write_all(payload).ok();
+                }
+                3 => exit_code = Some(exit_code_from_status(payload, stderr)),
+                _ => {}
+            }
+        }
+        // A reset or a closed connection without a status frame is not a clean exit.
+        exit_code.ok_or_else(|| anyhow!("exec stream ended without an exit status"))
+    }
+}
+
+// Channel-multiplexed exec protocol: each binary frame starts with its stream id.
+const EXEC_PROTOCOL: &str = "v4.channel.k8s.io";
+
+fn exit_code_from_status(payload: &[u8], stderr: &mut impl Write) -> i32 {
+    let status: serde_json::Value = serde_json::from_slice(payload).unwrap_or_default();
+    if status["status"] == "Success" {
+        return 0;
+    }
+    let code = status["details"]["causes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["reason"] == "ExitCode")
+        .and_then(|c| c["message"].as_str())
+        .and_then(|m| m.parse().ok());
+    if code.is_none() {
+        if let Some(msg) = status["message"].as_str() {
+            writeln!(stderr, "{msg}").ok();
+        }
+    }
+    code.unwrap_or(1)
+}
+
+fn read_response<R: BufRead>(mut reader: R) -> Result<(u16, Vec<u8>)> {
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .context("failed to read API response")?;
+    let status: u16 = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("malformed API status line {line:?}"))?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        line.clear();
+        reader
+            .read_line(&mut line)
+            .context("failed to read API headers")?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = value.eq_ignore_ascii_case("chunked");
+            }
+        }
+    }
+
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader
+                .read_line(&mut line)
+                .context("failed to read chunk")?;
+            let size_text = line.trim().split(';').next().unwrap_or("");
+            let size = usize::from_str_radix(size_text, 16)
+                .with_context(|| format!("invalid chunk size {size_text:?}"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader
+                .read_exact(&mut body[start..])
+                .context("truncated chunk")?;
+            line.clear();
+            reader.read_line(&mut line).ok();
+        }
+    } else if let Some(len) = content_length {
+        body.resize(len, 0);
+        reader
+            .read_exact(&mut body)
+            .context("truncated API response")?;
+    } else {
+        // Without a length the server delimits the body by closing the connection.
+        match reader.read_to_end(&mut body) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(e) => return Err(e).context("failed to read API response"),
+        }
+    }
+    Ok((status, body))
+}
+
+fn object_path(group: &str, plural: &str, namespace: &str, name: &str) -> String {
+    format!(
+        "{group}/namespaces/{}/{plural}/{}",
+        percent_encode(namespace),
+        percent_encode(name)
+    )
+}
+
+/// Percent-encodes a query or path component.
+pub fn percent_encode(value: &str) -> String {
+    let mut out = String::new();
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+fn tls_config(conn: &ClusterConnection) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("failed to configure TLS")?;
+
+    let builder = if conn.insecure {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+    } else {
+        let mut roots = rustls::RootCertStore::empty();
+        match &conn.ca_pem {
+            Some(pem) => {
+                for cert in rustls_pemfile::certs(&mut pem.as_slice()) {
+                    roots
+                        .add(cert.context("invalid certificate authority")?)
+                        .context("invalid certificate authority")?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots)
+    };
+
+    match &conn.credentials.client_cert {
+        Some((cert_pem, key_pem)) => {
+            let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+                .collect::<Result<Vec<_>, _>>()
+                .context("invalid client certificate")?;
+            let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+                .context("invalid client key")?
+                .ok_or_else(|| anyhow!("client key PEM contains no private key"))?;
+            builder
+                .with_client_auth_cert(certs, key)
+                .context("invalid client certificate")
+        }
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+#[derive(Debug)]
+// Honors insecure-skip-tls-verify: signatures are still checked, the chain is not.
+struct NoVerification(Arc<rustls::crypto::CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn parses_server_urls() {
+        let u = parse_server("https://10.0.0.1:6443").unwrap();
+        assert_eq!((u.tls, u.host.as_str(), u.port), (true, "10.0.0.1", 6443));
+        let u = parse_server("http://[::1]:8080/k8s/").unwrap();
+        assert_eq!(
+            (u.host.as_str(), u.port, u.prefix.as_str()),
+            ("::1", 8080, "/k8s")
+        );
+        let u = parse_server("https://api.example").unwrap();
+        assert_eq!(u.port, 443);
+        assert!(parse_server("ftp://x").is_err());
+    }
+
+    #[test]
+    fn reads_chunked_response() {
+        let raw = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\n{\"a\"\r\n3\r\n:1}\r\n0\r\n\r\n";
+        let (status, body) = read_response(Cursor::new(raw.as_bytes())).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(body, b"{\"a\":1}");
+    }
+
+    #[test]
+    fn exit_code_from_failure_status() {
+        let payload = br#"{"status":"Failure","reason":"NonZeroExitCode","details":{"causes":[{"reason":"ExitCode","message":"2"}]}}"#;
+        let mut err = Vec::new();
+        assert_eq!(exit_code_from_status(payload, &mut err), 2);
+        assert_eq!(
+            exit_code_from_status(br#"{"status":"Success"}"#, &mut err),
+            0
+        );
+    }
+
+    fn plain_client(listener: &std::net::TcpListener) -> ApiClient {
+        let conn = ClusterConnection {
+            context: "test".into(),
+            server: format!("http://{}", listener.local_addr().unwrap()),
+            ca_pem: None,
+            insecure: false,
+            tls_server_name: None,
+            credentials: Default::default(),
+            namespace: "default".into(),
+        };
+        ApiClient::new(conn).unwrap()
+    }
+
+    // Accepts one exec upgrade, sends `frames`, then drops the connection.
+    fn serve_exec(listener: std::net::TcpListener, frames: Vec<Vec<u8>>) {
+        std::thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let mut ws = tungstenite::accept_hdr(tcp, offer_exec_protocol).unwrap();
+            for frame in frames {
+                ws.send(Message::Binary(frame.into())).unwrap();
+            }
+        });
+    }
+
+    // The callback signature is fixed by tungstenite.
+    #[allow(clippy::result_large_err)]
+    fn offer_exec_protocol(
+        _: &tungstenite::handshake::server::Request,
+        mut resp: tungstenite::handshake::server::Response,
+    ) -> Result<
+        tungstenite::handshake::server::Response,
+        tungstenite::handshake::server::ErrorResponse,
+    > {
+        let protocol = EXEC_PROTOCOL.parse().unwrap();
+        resp.headers_mut()
+            .insert("Sec-WebSocket-Protocol", protocol);
+        Ok(resp)
+    }
+
+    fn run_exec(client: &ApiClient) -> (Result<i32>, Vec<u8>) {
+        let mut out = Vec::new();
+        let code = client.exec(
+            "ns",
+            "pod",
+            None,
+            &["true".into()],
+            &mut out,
+            &mut Vec::new(),
+        );
+        (code, out)
+    }
+
+    #[test]
+    fn exec_without_status_frame_fails() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = plain_client(&listener);
+        serve_exec(listener, vec![b"\x01hi".to_vec()]);
+        let (code, out) = run_exec(&client);
+        assert_eq!(out, b"hi");
+        let err = code.unwrap_err().to_string();
+        assert!(err.contains("without an exit status"), "{err}");
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = plain_client(&listener);
+        let status = br#"{"status":"Failure","reason":"NonZeroExitCode","details":{"causes":[{"reason":"ExitCode","message":"3"}]}}"#;
+        serve_exec(listener, vec![[&[3u8][..], status].concat()]);
+        assert_eq!(run_exec(&client).0.unwrap(), 3);
+    }
+
+    #[test]
+    fn get_json_times_out_on_a_silent_server() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = plain_client(&listener);
+        client.request_timeout = Duration::from_millis(200);
+        let held = std::thread::spawn(move || listener.accept().map(|(tcp, _)| tcp));
+        let err = client.get_json("/api/v1/pods").unwrap_err();
+        assert!(format!("{err:#}").contains("no complete answer"), "{err:#}");
+        drop(held.join());
+    }
+
+    #[test]
+    fn percent_encodes_commands() {
+        assert_eq!(percent_encode("tcpdump -i any"), "tcpdump%20-i%20any");
+        assert_eq!(percent_encode("a'b"), "a%27b");
+    }
+}
@@ -0,0 +1,498 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const SERVICE_ACCOUNT_DIR: &str = "/var/run/secrets/kubernetes.io/serviceaccount";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct KubeconfigFile {
+    #[serde(default)]
+    clusters: Vec<Named<ClusterEntry>>,
+    #[serde(default)]
+    contexts: Vec<Named<ContextEntry>>,
+    #[serde(default)]
+    users: Vec<Named<UserEntry>>,
+    current_context: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named<T> {
+    name: String,
+    #[serde(alias = "cluster", alias = "context", alias = "user")]
+    value: T,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ClusterEntry {
+    server: String,
+    certificate_authority: Option<String>,
+    certificate_authority_data: Option<String>,
+    #[serde(default)]
+    insecure_skip_tls_verify: bool,
+    tls_server_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ContextEntry {
+    cluster: String,
+    #[serde(default)]
+    user: String,
+    namespace: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct UserEntry {
+    token: Option<String>,
+    #[serde(rename = "tokenFile")]
+    token_file: Option<String>,
+    client_certificate: Option<String>,
+    client_certificate_data: Option<String>,
+    client_key: Option<String>,
+    client_key_data: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    exec: Option<ExecConfig>,
+    auth_provider: Option<AuthProvider>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExecConfig {
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<ExecEnv>,
+    api_version: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ExecEnv {
+    name: String,
+    value: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AuthProvider {
+    #[serde(default)]
+    config: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Credentials presented to the API server.
+pub struct Credentials {
+    pub token: Option<String>,
+    pub basic: Option<(String, String)>,
+    /// PEM client certificate chain and private key.
+    pub client_cert: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Everything needed to reach one cluster's API server.
+pub struct ClusterConnection {
+    pub context: String,
+    pub server: String,
+    pub ca_pem: Option<Vec<u8>>,
+    pub insecure: bool,
+    pub tls_server_name: Option<String>,
+    pub credentials: Credentials,
+    pub namespace: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// Selection flags mirrored from kubectl's global options.
+pub struct Selection {
+    pub kubeconfig: Option<String>,
+    pub context: Option<String>,
+    pub cluster: Option<String>,
+}
+
+/// Loads kubeconfig (or in-cluster service account) and resolves the selected context.
+/// Parameters: `selection` (&Selection) explicit kubeconfig/context/cluster overrides.
+/// Returns: Result<ClusterConnection> connection details with credentials resolved.
+pub fn load(selection: &Selection) -> Result<ClusterConnection> {
+    let paths = kubeconfig_paths(selection);
+    let existing: Vec<&PathBuf> = paths.iter().filter(|p| p.exists()).collect();
+    if existing.is_empty() {
+        if selection.kubeconfig.is_none() {
+            if let Some(conn) = in_cluster()? {
+                return Ok(conn);
+            }
+        }
+        bail!("no kubeconfig found (tried {})", display_paths(&paths));
+    }
+
+    let mut merged = KubeconfigFile::default();
+    for path in existing {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let mut file: KubeconfigFile = serde_yaml::from_str(&text)
+            .with_context(|| format!("invalid kubeconfig {}", path.display()))?;
+        let base = path.parent().unwrap_or(Path::new("."));
+        // Relative file references are relative to the kubeconfig that names them.
+        for c in &mut file.clusters {
+            resolve_relative(&mut c.value.certificate_authority, base);
+        }
+        for u in &mut file.users {
+            resolve_relative(&mut u.value.token_file, base);
+            resolve_relative(&mut u.value.client_certificate, base);
+            resolve_relative(&mut u.value.client_key, base);
+        }
+        merge_into(&mut merged, file);
+    }
+    resolve(&merged, selection)
+}
+
+fn kubeconfig_paths(selection: &Selection) -> Vec<PathBuf> {
+    if let Some(p) = &selection.kubeconfig {
+        return vec![PathBuf::from(p)];
+    }
+    if let Some(list) = std::env::var_os("KUBECONFIG").filter(|v| !v.is_empty()) {
+        return std::env::split_paths(&list).collect();
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    home.map(|h| vec![PathBuf::from(h).join(".kube").join("config")])
+        .unwrap_or_default()
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "no candidate paths".to_string();
+    }
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn resolve_relative(field: &mut Option<String>, base: &Path) {
+    if let Some(p) = field {
+        if Path::new(p).is_relative() {
+            *p = base.join(&*p).to_string_lossy().into_owned();
+        }
+    }
+}
+
+fn merge_into(merged: &mut KubeconfigFile, file: KubeconfigFile) {
+    // Like kubectl, the first file to define a name or current-context wins.
+    if merged.current_context.is_none() {
+        merged.current_context = file.current_context;
+    }
+    for c in file.clusters {
+        if !merged.clusters.iter().any(|m| m.name == c.name) {
+            merged.clusters.push(c);
+        }
+    }
+    for c in file.contexts {
+        if !merged.contexts.iter().any(|m| m.name == c.name) {
+            merged.contexts.push(c);
+        }
+    }
+    for u in file.users {
+        if !merged.users.iter().any(|m| m.name == u.name) {
+            merged.users.push(u);
+        }
+    }
+}
+
+fn resolve(file: &KubeconfigFile, selection: &Selection) -> Result<ClusterConnection> {
+    let context_name = selection
+        .context
+        .clone()
+        .or_else(|| file.current_context.clone())
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| anyhow!("kubeconfig has no current-context; pass --context"))?;
+    let context = find(&file.contexts, &context_name)
+        .with_context(|| format!("context {context_name} not found in kubeconfig"))?;
+    let cluster_name = selection.cluster.as_deref().unwrap_or(&context.cluster);
+    let cluster = find(&file.clusters, cluster_name)
+        .with_context(|| format!("cluster {cluster_name} not found in kubeconfig"))?;
+    let user = if context.user.is_empty() {
+        UserEntry::default()
+    } else {
+        find(&file.users, &context.user)
+            .with_context(|| format!("user {} not found in kubeconfig", context.user))?
+    };
+
+    let ca_pem = match (
+        &cluster.certificate_authority_data,
+        &cluster.certificate_authority,
+    ) {
+        (Some(data), _) => Some(decode_b64(data, "certificate-authority-data")?),
+        (None, Some(path)) => Some(read_file(path)?),
+        (None, None) => None,
+    };
+
+    Ok(ClusterConnection {
+        context: context_name,
+        server: cluster.server.trim_end_matches('/').to_string(),
+        ca_pem,
+        insecure: cluster.insecure_skip_tls_verify,
+        tls_server_name: cluster.tls_server_name.clone(),
+        credentials: credentials(&user)?,
+        namespace: context
+            .namespace
+            .clone()
+            .unwrap_or_else(|| "default".to_string()),
+    })
+}
+
+fn find<T: Clone>(entries: &[Named<T>], name: &str) -> Option<T> {
+    entries
+        .iter()
+        .find(|e| e.name == name)
+        .map(|e| e.value.clone())
+}
+
+fn credentials(user: &UserEntry) -> Result<Credentials> {
+    let mut creds = Credentials::default();
+
+    let cert = match (&user.client_certificate_data, &user.client_certificate) {
+        (Some(data), _) => Some(decode_b64(data, "client-certificate-data")?),
+        (None, Some(path)) => Some(read_file(path)?),
+        (None, None) => None,
+    };
+    let key = match (&user.client_key_data, &user.client_key) {
+        (Some(data), _) => Some(decode_b64(data, "client-key-data")?),
+        (None, Some(path)) => Some(read_file(path)?),
+        (None, None) => None,
+    };
+    if let (Some(cert), Some(key)) = (cert, key) {
+        creds.client_cert = Some((cert, key));
+    }
+
+    creds.token = match (&user.token, &user.token_file) {
+        (Some(t), _) => Some(t.clone()),
+        (None, Some(path)) => Some(
+            String::from_utf8_lossy(&read_file(path)?)
+                .trim()
+                .to_string(),
+        ),
+        (None, None) => None,
+    };
+    if let (Some(u), Some(p)) = (&user.username, &user.password) {
+        creds.basic = Some((u.clone(), p.clone()));
+    }
+    if creds.token.is_none() {
+        // Legacy auth providers (gcp, oidc) cache a usable token in their config.
+        if let Some(provider) = &user.auth_provider {
+            creds.token = provider
+                .config
+                .get("access-token")
+                .or_else(|| provider.config.get("id-token"))
+                .cloned();
+        }
+    }
+    if let Some(exec) = &user.exec {
+        apply_exec_credential(exec, &mut creds)?;
+    }
+    Ok(creds)
+}
+
+fn apply_exec_credential(exec: &ExecConfig, creds: &mut Credentials) -> Result<()> {
+    // Credential plugins print an ExecCredential object on stdout.
+    let api_version = exec
+        .api_version
+        .clone()
+        .unwrap_or_else(|| "client.authentication.k8s.io/v1".to_string());
+    let info = serde_json::json!({
+        "apiVersion": api_version,
+        "kind": "ExecCredential",
+        "spec": {"interactive": false},
+    });
+    let mut cmd = Command::new(&exec.command);
+    cmd.args(&exec.args)
+        .env("KUBERNETES_EXEC_INFO", info.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    for e in &exec.env {
+        cmd.env(&e.name, &e.value);
+    }
+    let output = cmd
+        .output()
+        .with_context(|| format!("failed to run credential plugin {}", exec.command))?;
+    if !output.status.success() {
+        bail!(
+            "credential plugin {} failed with status {}",
+            exec.command,
+            output.status
+        );
+    }
+    let value: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("credential plugin {} printed invalid JSON", exec.command))?;
+    let status = &value["status"];
+    if let Some(token) = status["token"].as_str() {
+        creds.token = Some(token.to_string());
+    }
+    if let (Some(cert), Some(key)) = (
+        status["clientCertificateData"].as_str(),
+        status["clientKeyData"].as_str(),
+    ) {
+        creds.client_cert = Some((cert.as_bytes().to_vec(), key.as_bytes().to_vec()));
+    }
+    Ok(())
+}
+
+fn in_cluster() -> Result<Option<ClusterConnection>> {
+    // Pods get a service account token and the API address from the environment.
+    let (Ok(host), Ok(port)) = (
+        std::env::var("KUBERNETES_SERVICE_HOST"),
+        std::env::var("KUBERNETES_SERVICE_PORT"),
+    ) else {
+        return Ok(None);
+    };
+    let dir = Path::new(SERVICE_ACCOUNT_DIR);
+    let token = read_file(&dir.join("token").to_string_lossy())?;
+    let host = if host.contains(':') {
+        format!("[{host}]")
+    } else {
+        host
+    };
+    let namespace = std::fs::read_to_string(dir.join("namespace"))
+        .map(|n| n.trim().to_string())
+        .unwrap_or_else(|_| "default".to_string());
+    Ok(Some(ClusterConnection {
+        context: "in-cluster".to_string(),
+        server: format!("https://{host}:{port}"),
+        ca_pem: std::fs::read(dir.join("ca.crt")).ok(),
+        insecure: false,
+        tls_server_name: None,
+        credentials: Credentials {
+            token: Some(String::from_utf8_lossy(&token).trim().to_string()),
+            ..Default::default()
+        },
+        namespace,
+    }))
+}
+
+fn decode_b64(data: &str, field: &str) -> Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .with_context(|| format!("invalid base64 in {field}"))
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read {path}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const CONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+current-context: dev
+clusters:
+- name: dev-cluster
+  cluster:
+    server: https://10.0.0.1:6443/
+    insecure-skip-tls-verify: true
+- name: prod-cluster
+  cluster:
+    server: https://prod.example:6443
+    certificate-authority-data: Y2EtcGVt
+contexts:
+- name: dev
+  context:
+    cluster: dev-cluster
+    user: dev-user
+- name: prod
+  context:
+    cluster: prod-cluster
+    user: prod-user
+    namespace: payments
+users:
+- name: dev-user
+  user:
+    token: dev-token
+- name: prod-user
+  user:
+    tokenFile: token.txt
+"#;
+
+    fn write_config() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let mut f = std::fs::File::create(dir.path().join("config")).unwrap();
+        f.write_all(CONFIG.as_bytes()).unwrap();
+        std::fs::write(dir.path().join("token.txt"), "prod-token\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn loads_current_context() {
+        let dir = write_config();
+        let conn = load(&Selection {
+            kubeconfig: Some(dir.path().join("config").to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(conn.context, "dev");
+        assert_eq!(conn.server, "https://10.0.0.1:6443");
+        assert!(conn.insecure);
+        assert_eq!(conn.credentials.token.as_deref(), Some("dev-token"));
+        assert_eq!(conn.namespace, "default");
+    }
+
+    #[test]
+    fn explicit_context_resolves_relative_token_file() {
+        let dir = write_config();
+        let conn = load(&Selection {
+            kubeconfig: Some(dir.path().join("config").to_string_lossy().into_owned()),
+            context: Some("prod".to_string()),
+            cluster: None,
+        })
+        .unwrap();
+        assert_eq!(conn.context, "prod");
+        assert_eq!(conn.ca_pem.as_deref(), Some(&b"ca-pem"[..]));
+        assert_eq!(conn.credentials.token.as_deref(), Some("prod-token"));
+        assert_eq!(conn.namespace, "payments");
+    }
+
+    #[test]
+    fn cluster_override_and_unknown_context() {
+        let dir = write_config();
+        let path = dir.path().join("config").to_string_lossy().into_owned();
+        let conn = load(&Selection {
+            kubeconfig: Some(path.clone()),
+            context: Some("dev".to_string()),
+            cluster: Some("prod-cluster".to_string()),
+        })
+        .unwrap();
+        assert_eq!(conn.server, "https://prod.example:6443");
+
+        let err = load(&Selection {
+            kubeconfig: Some(path),
+            context: Some("missing".to_string()),
+            cluster: None,
+        })
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("context missing not found"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exec_plugin_supplies_token() {
+        let exec = ExecConfig {
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                r#"echo '{"status":{"token":"from-plugin"}}'"#.to_string(),
+            ],
+            env: Vec::new(),
+            api_version: None,
+        };
+        let mut creds = Credentials::default();
+        apply_exec_credential(&exec, &mut creds).unwrap();
+        assert_eq!(creds.token.as_deref(), Some("from-plugin"));
+    }
+}
@@ -1,23 +1,271 @@
-﻿pub mod capture;
+﻿pub mod analyze;
+pub mod annotate;
+pub mod capture;
 pub mod cli;
+pub mod compress;
+pub mod config;
+pub mod decode;
+pub mod dissect;
+pub mod error;
+pub mod events;
+pub mod extcap;
 pub mod filter;
+pub mod follow;
+pub mod inventory;
 pub mod k8s;
+pub mod keylog;
+#[cfg(feature = "native-k8s")]
+pub mod kube_native;
+pub mod manifest;
+pub mod merge;
+pub mod meter;
+pub mod naming;
 pub mod output;
+pub mod pcap;
+pub mod progress;
+pub mod rpcap;
+pub mod sanitize;
+pub mod serve;
 pub mod ssh;
 
 use anyhow::{bail, Context, Result};
 use cli::{Args, CaptureFormat};
 use k8s::Target;
+use std::io::{IsTerminal, Read};
+use std::process::Child;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 use tracing::{info, warn};
 
 /// Runs a single capture workflow from parsed CLI arguments.
 /// Parameters: `args` (Args) parsed CLI arguments.
 /// Returns: Result<()> indicating success or failure.
 pub fn run(args: Args) -> Result<()> {
-    // Orchestrates a single capture run.
-    // Resolve a concrete target early to avoid partial work.
-    let target = resolve_target(&args)?;
-    let filter = filter::build_filter(args.port, args.protocol, args.filter.as_deref());
+    if args.decode && !args.save {
+        // Only the decoded lines are wanted; the stream is read and dropped.
+        return run_recorded(args, &mut manifest::Manifest::new(false), false, |mut stream| {
+            std::io::copy(&mut stream, &mut std::io::sink())?;
+            Ok(())
+        });
+    }
+    if args.decode && (args.output == "-" || args.tee.iter().any(|t| t == "-")) {
+        bail!("--decode prints to stdout; write the capture to a file with --save --output PATH");
+    }
+    let wireshark = args.wireshark;
+    let compress = args.compress;
+    // Reject malformed or existing outputs before any remote command starts.
+    let vars = name_vars(&args);
+    let resolve = |spec: &str| {
+        naming::resolve_sink(spec, &vars, args.output_dir.as_deref(), args.force)
+    };
+    let mut claimed = Vec::new();
+    for spec in std::iter::once(&args.output).chain(&args.tee) {
+        match resolve(spec) {
+            Ok(sink) => claimed.push(sink),
+            Err(err) => {
+                claimed.iter().for_each(|sink| naming::release(sink));
+                return Err(err);
+            }
+        }
+    }
+    let output = claimed[0].clone();
+    info!(%output, "writing capture");
+    let tee: Result<Vec<_>> = claimed[1..].iter().map(|s| output::Secondary::parse(s)).collect();
+    let mut tee = match tee {
+        Ok(tee) => tee,
+        Err(err) => {
+            claimed.iter().for_each(|sink| naming::release(sink));
+            return Err(err);
+        }
+    };
+
+    let recorded_file = sidecar_file(&output)?;
+    let digest = manifest::FileDigest::default();
+    let file_digest = recorded_file.as_ref().map(|_| digest.clone());
+    let mut manifest = manifest::Manifest::new(recorded_file.is_some());
+    let progress = show_progress(&args);
+
+    let writer_output = output.clone();
+    let result = run_recorded(args, &mut manifest, progress, move |stream| {
+        if wireshark {
+            let mut viewer = output::spawn_viewer()?;
+            let stdin = viewer.stdin.take().context("failed to open viewer stdin")?;
+            tee.push(output::Secondary::writer("wireshark", stdin));
+        }
+        output::write_recorded(stream, &writer_output, compress, tee, file_digest.as_ref())
+            .map_err(|err| {
+                error::KcapError::OutputWrite {
+                    output: writer_output.clone(),
+                    detail: format!("{err:#}"),
+                }
+                .into()
+            })
+    });
+
+    if result.is_err() {
+        // Names were claimed up front; do not leave empty files behind for the next run.
+        claimed.iter().for_each(|sink| naming::release(sink));
+    }
+    if let Some(path) = recorded_file.filter(|p| std::path::Path::new(p).is_file()) {
+        let file = digest.summary();
+        match manifest::write_sidecar(&manifest, &path, &result, file.as_ref()) {
+            Ok(sidecar) => info!(manifest = %sidecar, "wrote capture manifest"),
+            Err(err) => warn!("failed to write capture manifest: {err:#}"),
+        }
+    }
+    result
+}
+
+/// Picks the output that gets a provenance sidecar: only a regular file (or one about to be
+/// created) has a natural place for it; FIFOs and devices given as plain paths get none.
+/// Parameters: `output` (&str) resolved `--output` sink.
+/// Returns: Result<Option<String>> path of the capture file to describe.
+fn sidecar_file(output: &str) -> Result<Option<String>> {
+    Ok(match output::SinkSpec::parse(output)? {
+        output::SinkSpec::File(path)
+            if std::fs::metadata(&path).map_or(true, |meta| meta.is_file()) =>
+        {
+            Some(path)
+        }
+        _ => None,
+    })
+}
+
+/// Runs a capture workflow and hands the resulting capture stream to `sink`.
+/// Parameters: `args` (Args) parsed CLI arguments; `output` is left to the sink.
+/// Parameters: `sink` (F) consumer of the (possibly merged) pcap/pcapng stream.
+/// Returns: Result<()> indicating success or failure.
+pub fn run_with_sink<F>(args: Args, sink: F) -> Result<()>
+where
+    F: FnOnce(Box<dyn Read + Send>) -> Result<()> + Send,
+{
+    run_recorded(args, &mut manifest::Manifest::new(false), false, sink)
+}
+
+/// Whether to draw the live progress display: only on an interactive stderr, and never
+/// while stdout carries the capture stream.
+/// Parameters: `args` (&Args) parsed CLI arguments.
+/// Returns: bool true when `run_recorded` should draw progress.
+fn show_progress(args: &Args) -> bool {
+    let to_stdout = args.output == "-" || args.tee.iter().any(|t| t == "-");
+    let shown = !args.no_progress
+        && !to_stdout
+        && !args.decode
+        && events::human_stderr()
+        && std::io::stderr().is_terminal();
+    if args.stats && !shown && !to_stdout && !args.decode {
+        warn!("--stats needs stderr to be a terminal; not showing statistics");
+    }
+    shown
+}
+
+/// Runs a capture workflow like `run_with_sink`, recording what ran in `manifest`.
+/// Parameters: `args` (Args) parsed CLI arguments; `output` is left to the sink.
+/// Parameters: `manifest` (&mut manifest::Manifest) receives one record per remote capture.
+/// Parameters: `progress` (bool) draw the live progress display (and `--stats`) on stderr.
+/// Parameters: `sink` (F) consumer of the (possibly merged) pcap/pcapng stream.
+/// Returns: Result<()> indicating success or failure.
+pub fn run_recorded<F>(
+    args: Args,
+    manifest: &mut manifest::Manifest,
+    progress: bool,
+    sink: F,
+) -> Result<()>
+where
+    F: FnOnce(Box<dyn Read + Send>) -> Result<()> + Send,
+{
+    match (&args.events, args.log_format) {
+        (Some(dest), _) => events::init(dest)?,
+        (None, cli::LogFormat::Json) => events::init("-")?,
+        (None, cli::LogFormat::Text) => {}
+    }
+    let sanitizer = sanitizer(&args)?;
+    // Counting costs a parse of the stream, so only do it when someone listens.
+    let meter = meter::Meter::new();
+    let metered = events::enabled() || progress || args.decode;
+    let ticker = events::enabled().then(|| {
+        let meter = meter.clone();
+        meter::Ticker::every(events::PROGRESS_INTERVAL, move || {
+            events::emit(&events::Event::Progress(meter.snapshot()));
+        })
+    });
+    let traffic = (progress && args.stats)
+        .then(|| Arc::new(Mutex::new(progress::Traffic::default())));
+    // Real names would not match anonymized addresses.
+    let named = (args.decode && !args.anonymize_ips) || args.embed_names;
+    let owners = named.then(|| ip_owners(&args));
+    let decoder = args.decode.then(|| {
+        let names = owners.clone().unwrap_or_default();
+        decode::observer(names, Box::new(std::io::stdout()))
+    });
+    let embedded = owners.filter(|_| args.embed_names);
+    let secrets = args.keylog.is_some().then(keylog::Secrets::default);
+    if secrets.is_some() && args.payload != cli::PayloadPolicy::Keep {
+        warn!("--keylog with --payload {:?} leaves no payload to decrypt", args.payload);
+    }
+    let duration = args.duration;
+    let counted = meter.clone();
+    let tailed = secrets.clone();
+    let result = run_jobs(args, manifest, tailed, move |stream| {
+        let stream = match sanitizer {
+            Some(sanitizer) => sanitize::sanitize_stream(stream, sanitizer)?,
+            None => stream,
+        };
+        let stream = match (&embedded, secrets) {
+            (None, None) => stream,
+            (owners, secrets) => {
+                let owners = owners.clone().unwrap_or_default();
+                annotate::annotate(stream, &owners, secrets)?
+            }
+        };
+        if !metered {
+            return sink(stream);
+        }
+        let observers = traffic
+            .iter()
+            .map(|t| progress::Traffic::observer(t.clone()))
+            .chain(decoder)
+            .collect();
+        let live = progress.then(|| progress::Live::start(&counted, duration, traffic.clone()));
+        let written = sink(Box::new(counted.wrap(stream, observers)));
+        // The stream has ended; draw the final counts before the exit summaries.
+        drop(live);
+        written
+    });
+    drop(ticker);
+    events::emit(&events::Event::Stopped {
+        totals: meter.snapshot(),
+        ok: result.is_ok(),
+    });
+    result
+}
+
+fn run_jobs<F>(
+    mut args: Args,
+    manifest: &mut manifest::Manifest,
+    secrets: Option<keylog::Secrets>,
+    sink: F,
+) -> Result<()>
+where
+    F: FnOnce(Box<dyn Read + Send>) -> Result<()> + Send,
+{
+    // Orchestrates a single capture run, possibly fanned out over several targets.
+    let base_filter = filter::build_filter(args.port, args.protocol, args.filter.as_deref());
+    let kube = kube_context(&args);
+    let runner = k8s::KubeRunner::select(args.kube_backend, &kube)?;
+    if args.ssh_host.is_none() {
+        // Record which cluster kubectl is about to talk to before touching it.
+        match k8s::current_context(&runner, &kube) {
+            Ok(ctx) => info!(
+                context = %ctx,
+                cluster = ?kube.cluster,
+                backend = runner.name(),
+                "using kubeconfig context"
+            ),
+            Err(err) => warn!("could not determine kubeconfig context: {err:#}"),
+        }
+    }
 
     let tool = capture::select_tool(args.format);
     // Warn when the selected tool cannot honor the requested format.
@@ -25,49 +273,848 @@ pub fn run(args: Args) -> Result<()> {
         warn!("pcapng requested but tcpdump selected; output will be pcap");
     }
 
+    if args.follow {
+        if secrets.is_some() {
+            warn!("--keylog is not supported with --follow; not collecting TLS secrets");
+        }
+        return run_follow(&args, &runner, tool, base_filter, manifest, sink);
+    }
+
+    // Resolve concrete targets early to avoid partial work.
+    let jobs = match (&args.service, &args.selector) {
+        (Some(service), _) => plan_service_jobs(&args, &runner, service, base_filter)?,
+        (None, Some(selector)) => plan_selector_jobs(&args, &runner, selector, base_filter)?,
+        (None, None) if args.via_node => vec![plan_pod_node_job(&args, &runner, base_filter)?],
+        (None, None) => {
+            let target = resolve_target(&args)?;
+            vec![CaptureJob {
+                label: target_label(&target),
+                target,
+                filter: base_filter,
+            }]
+        }
+    };
+    if args.via_node {
+        resolve_node_addresses(&mut args, &runner, &jobs);
+    }
+    capture_jobs(&args, &runner, tool, jobs, manifest, secrets, sink)
+}
+
+/// Starts the planned captures, feeds their merged stream to `sink` and records how each
+/// one ended.
+/// Parameters: `args` (&Args) CLI arguments.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `tool` (CaptureTool) remote capture tool.
+/// Parameters: `jobs` (Vec<CaptureJob>) resolved targets.
+/// Parameters: `manifest` (&mut Manifest) receives one record per job.
+/// Parameters: `secrets` (Option<Secrets>) collects `--keylog` lines, if requested.
+/// Parameters: `sink` (F) consumer of the capture stream.
+/// Returns: Result<()> the sink's error, else the first failed capture.
+fn capture_jobs<R, F>(
+    args: &Args,
+    runner: &R,
+    tool: capture::CaptureTool,
+    jobs: Vec<CaptureJob>,
+    manifest: &mut manifest::Manifest,
+    secrets: Option<keylog::Secrets>,
+    sink: F,
+) -> Result<()>
+where
+    R: k8s::Runner + k8s::Exec,
+    F: FnOnce(Box<dyn Read + Send>) -> Result<()> + Send,
+{
+    let mut started = Started::default();
+    let mut streams = Vec::new();
+    for job in jobs {
+        match start_job(args, runner, tool, job, manifest.detailed, &secrets, &mut started) {
+            Ok(stream) => streams.push(stream),
+            Err(err) => {
+                // The captures already running would otherwise outlive the failed run.
+                started.abandon();
+                return Err(err);
+            }
+        }
+    }
+    let Started { children, tails } = started;
+    let stream: Box<dyn Read + Send> = if streams.len() == 1 {
+        Box::new(streams.remove(0).1)
+    } else {
+        Box::new(merge::merge_streams(streams))
+    };
+    let written = sink(stream);
+    for (label, tail) in tails {
+        if tail.finish() == 0 && events::human_stderr() {
+            let path = args.keylog.as_deref().unwrap_or_default();
+            eprintln!("kcap: WARNING: {label}: no TLS secrets found in {path}");
+        }
+    }
+
+    let mut failed = Vec::new();
+    for (label, mut spawned, stderr) in children {
+        if written.is_err() {
+            // Nobody reads the stream any more; do not wait for the duration to expire.
+            let _ = spawned.child.kill();
+        }
+        let id = spawned.child.id();
+        let status = spawned.child.wait()?;
+        capture::untrack(id);
+        spawned.record.exit_status = Some(status.to_string());
+        spawned.record.exit_code = status.code();
+        spawned.record.stderr = stderr.finish();
+        spawned.record.stats = capture::CaptureStats::parse(&spawned.record.stderr);
+        if let Some(stats) = &spawned.record.stats {
+            report_stats(&label, stats, args.drop_warn_percent);
+        }
+        let stream = std::mem::take(&mut *spawned.stream.lock().unwrap_or_else(|e| e.into_inner()));
+        spawned.record.compression = stream.stats;
+        if let Some(stats) = &stream.stats {
+            report_compression(&label, stats);
+        }
+        // Captures stopped by --duration or Ctrl-C end with a signal; that is success.
+        let stopped = capture::stopped_by_kcap(id) || capture::stop_requested();
+        if !status.success() && !capture::stopped_by_kcap(id) {
+            let record = &spawned.record;
+            failed.push(error::classify_remote(
+                &label,
+                spawned.ssh_host.as_deref(),
+                &record.tool,
+                &status.to_string(),
+                status.code(),
+                &record.stderr,
+            ));
+        } else if let Some(detail) = stream.failure.filter(|_| !stopped) {
+            // The tool exited cleanly, but what reached kcap is not the whole capture.
+            failed.push(error::KcapError::RemoteExit {
+                target: label.clone(),
+                status: status.to_string(),
+                detail,
+            });
+        }
+        manifest.jobs.push(spawned.record);
+    }
+    written?;
+    if let Some(first) = failed.first() {
+        for other in &failed[1..] {
+            warn!("{other}");
+        }
+        return Err(first.clone().into());
+    }
+
+    Ok(())
+}
+
+/// Reports a capture's exit counters, warning when kernel drops make it unreliable.
+/// Parameters: `label` (&str) capture target.
+/// Parameters: `stats` (&CaptureStats) parsed counters.
+/// Parameters: `warn_percent` (f64) drop percentage that triggers a warning.
+fn report_stats(label: &str, stats: &capture::CaptureStats, warn_percent: f64) {
+    info!(
+        target = label,
+        captured = ?stats.captured,
+        received = ?stats.received,
+        dropped_by_kernel = ?stats.dropped_by_kernel,
+        dropped_by_interface = ?stats.dropped_by_interface,
+        "capture statistics"
+    );
+    let count = |n: Option<u64>| n.map_or("?".to_string(), |n| n.to_string());
+    let percent = stats.drop_percent().filter(|p| *p > warn_percent);
+    events::emit(&events::Event::Drops {
+        target: label,
+        stats: *stats,
+        warn: percent.is_some(),
+    });
+    if !events::human_stderr() {
+        if let Some(percent) = percent {
+            warn!(target = label, percent, "kernel dropped packets");
+        }
+        return;
+    }
+    // The summary is printed even without RUST_LOG, like the tools' own counters.
+    eprintln!(
+        "kcap: {label}: {} captured, {} received by filter, {} dropped by kernel",
+        count(stats.captured),
+        count(stats.received),
+        count(stats.dropped_by_kernel),
+    );
+    if let Some(percent) = percent {
+        eprintln!(
+            "kcap: WARNING: {label}: kernel dropped {percent:.1}% of packets; \
+             the capture is incomplete (narrow the filter to reduce load)"
+        );
+    }
+}
+
+/// Reports how much a remote compressor saved on the link.
+/// Parameters: `label` (&str) capture target.
+/// Parameters: `stats` (&CompressionStats) bytes moved by the compressed stream.
+fn report_compression(label: &str, stats: &compress::CompressionStats) {
+    info!(
+        target = label,
+        codec = stats.codec.name(),
+        wire_bytes = stats.wire_bytes,
+        capture_bytes = stats.capture_bytes,
+        "remote compression"
+    );
+    events::emit(&events::Event::Compression {
+        target: label,
+        stats: *stats,
+    });
+    if !events::human_stderr() {
+        return;
+    }
+    let ratio = stats.ratio().map_or("?".to_string(), |r| format!("{r:.1}x"));
+    eprintln!(
+        "kcap: {label}: {} sent {} bytes for {} capture bytes ({ratio})",
+        stats.codec.name(),
+        stats.wire_bytes,
+        stats.capture_bytes,
+    );
+}
+
+/// One remote capture to start, with the filter it should apply.
+struct CaptureJob {
+    label: String,
+    target: Target,
+    filter: Option<String>,
+}
+
+/// A started remote capture.
+/// Captures started so far, with the key log tails that belong to them.
+#[derive(Default)]
+struct Started {
+    children: Vec<(String, SpawnedJob, capture::StderrWatch)>,
+    tails: Vec<(String, keylog::Tail)>,
+}
+
+impl Started {
+    /// Ends every capture and tail, for a run that fails before its stream is read.
+    fn abandon(self) {
+        for (_, tail) in self.tails {
+            tail.finish();
+        }
+        for (_, mut spawned, _) in self.children {
+            let _ = spawned.child.kill();
+            let _ = spawned.child.wait();
+            capture::untrack(spawned.child.id());
+        }
+    }
+}
+
+/// Starts one planned capture, and its key log tail when `--keylog` is set.
+/// Parameters: `job` (CaptureJob) resolved target; `detailed` (bool) record extra details.
+/// Parameters: `started` (&mut Started) receives the processes, even when a later step fails.
+/// Returns: Result<(String, Box<dyn Read + Send>)> label and (decoded) capture stream.
+fn start_job<R: k8s::Runner + k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    tool: capture::CaptureTool,
+    job: CaptureJob,
+    detailed: bool,
+    secrets: &Option<keylog::Secrets>,
+    started: &mut Started,
+) -> Result<(String, Box<dyn Read + Send>)> {
+    if let (Some(path), Some(secrets)) = (&args.keylog, secrets) {
+        let cmd = keylog::tail_command(path);
+        let child = spawn_on_target(args, runner, &job.target, &cmd)
+            .with_context(|| format!("failed to tail {path} on {}", job.label))?;
+        started.tails.push((job.label.clone(), keylog::Tail::start(child, secrets)));
+    }
+    let mut spawned = spawn_job(args, runner, tool, job.target, job.filter, detailed)?;
+    // Bound the capture duration to avoid runaway sessions.
+    if let Some(d) = args.duration {
+        capture::kill_after(&mut spawned.child, d);
+    }
+    let stderr = capture::watch_stderr(&mut spawned.child, Some(spawned.remote_pid.clone()));
+    let label = job.label;
+    let stream = spawned.child.stdout.take().map(|stdout| match spawned.codec {
+        Some(codec) => codec.decoder(&label, stdout, spawned.stream.clone()),
+        None => Box::new(stdout) as Box<dyn Read + Send>,
+    });
+    started.children.push((label.clone(), spawned, stderr));
+    let stream = stream.with_context(|| format!("failed to capture stdout of {label}"))?;
+    Ok((label, stream))
+}
+
+struct SpawnedJob {
+    child: Child,
+    /// Filled in from the remote stderr; needed to stop the tool itself.
+    remote_pid: capture::RemotePid,
+    /// Remote compressor the stream has to be decoded with.
+    codec: Option<compress::Codec>,
+    /// Byte counts of the compressed stream, and whether it could be decoded to the end.
+    stream: compress::StreamReport,
+    /// Set for captures running over SSH, whose failures may be ssh's own.
+    ssh_host: Option<String>,
+    record: manifest::JobRecord,
+}
+
+fn spawn_job<R: k8s::Runner + k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    tool: capture::CaptureTool,
+    target: Target,
+    mut filter: Option<String>,
+    detailed: bool,
+) -> Result<SpawnedJob> {
+    if let Target::Ssh { host } = &target {
+        if !args.no_exclude_self {
+            filter = exclude_own_session(args, host, filter);
+        }
+    }
+
     // Build a single remote command that streams capture bytes to stdout.
-    let remote_cmd = capture::build_capture_command(tool, &args.iface, args.format, filter.as_deref());
+    let capture_cmd = capture::build_capture_command(
+        tool,
+        &args.iface,
+        args.format,
+        filter.as_deref(),
+        args.snaplen,
+    );
+    // Stopping the tool itself, not just ssh, makes it print its drop counters.
+    let mut remote_cmd = capture::stoppable(&capture_cmd);
+    let codec = remote_codec(args, runner, &target);
+    if let Some(codec) = codec {
+        remote_cmd = codec.wrap_command(&remote_cmd);
+    }
     info!(%remote_cmd, "remote capture command");
 
-    let mut child = match target {
+    let record = manifest::JobRecord {
+        target: target_label(&target),
+        node: match &target {
+            Target::Ssh { host } => Some(host.clone()),
+            Target::KubernetesExec { namespace, pod, .. } if detailed => {
+                k8s::resolve_pod_node(runner, namespace, pod).ok()
+            }
+            Target::KubernetesExec { .. } => None,
+        },
+        filter: filter.clone(),
+        remote_command: remote_cmd.clone(),
+        tool: tool.program().to_string(),
+        tool_version: detailed
+            .then(|| tool_version(args, runner, &target, tool))
+            .flatten(),
+        ..Default::default()
+    };
+    events::emit(&events::Event::TargetResolved {
+        target: &record.target,
+        node: record.node.as_deref(),
+    });
+    events::emit(&events::Event::CommandBuilt {
+        target: &record.target,
+        command: &remote_cmd,
+    });
+
+    let child = spawn_on_target(args, runner, &target, &remote_cmd)?;
+    // The tool's pid arrives on stderr (see `capture::watch_stderr`).
+    let remote_pid = capture::RemotePid::default();
+    let reported = remote_pid.clone();
+    let on_target = target_commands(args, runner, &target);
+    let stop: capture::Spawner = Box::new(move || {
+        let pid = reported
+            .get()
+            .context("the remote capture tool has not reported its pid")?;
+        on_target(&capture::stop_command(*pid))()
+    });
+    capture::track_remote(&child, stop);
+    args.stop.add(&child);
+    events::emit(&events::Event::CaptureStarted {
+        target: &record.target,
+    });
+    Ok(SpawnedJob {
+        child,
+        remote_pid,
+        codec,
+        stream: compress::StreamReport::default(),
+        ssh_host: record.node.clone().filter(|_| record.target.starts_with("ssh/")),
+        record,
+    })
+}
+
+fn tool_version<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+    tool: capture::CaptureTool,
+) -> Option<String> {
+    let cmd = format!("{} --version 2>&1 | head -n 1", tool.program());
+    match probe_target(args, runner, target, &cmd) {
+        Ok(out) => Some(out.trim().to_string()).filter(|v| !v.is_empty()),
+        Err(err) => {
+            let label = target_label(target);
+            warn!(target = %label, "could not read capture tool version: {err:#}");
+            None
+        }
+    }
+}
+
+/// Runs a short command on the capture target and returns its stdout.
+/// Parameters: `args` (&Args) CLI arguments carrying SSH settings.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `target` (&Target) where to run the command.
+/// Parameters: `cmd` (&str) shell command.
+/// Returns: Result<String> command stdout.
+fn probe_target<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+    cmd: &str,
+) -> Result<String> {
+    let child = spawn_on_target(args, runner, target, cmd)?;
+    let out = child.wait_with_output()?;
+    if !out.status.success() {
+        bail!(
+            "probe exited with {}: {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Starts a command on the capture target with piped stdout and stderr.
+/// Parameters: `args` (&Args) CLI arguments carrying SSH settings.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `target` (&Target) where to run the command.
+/// Parameters: `cmd` (&str) shell command.
+/// Returns: Result<Child> spawned ssh or exec process.
+fn spawn_on_target<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+    cmd: &str,
+) -> Result<Child> {
+    target_spawner(args, runner, target, cmd)()
+}
+
+/// Prepares a command on the capture target that can be started later, without borrows.
+/// Parameters: `args` (&Args) CLI arguments carrying SSH settings.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `target` (&Target) where to run the command.
+/// Parameters: `cmd` (&str) shell command.
+/// Returns: capture::Spawner starting ssh or exec with piped stdout and stderr.
+fn target_spawner<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+    cmd: &str,
+) -> capture::Spawner {
+    target_commands(args, runner, target)(cmd)
+}
+
+/// Prepares commands on the capture target that are only known later, without borrows.
+/// Parameters: `args` (&Args) CLI arguments carrying SSH settings.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `target` (&Target) where to run the commands.
+/// Returns: Box<dyn Fn(&str) -> capture::Spawner> spawner for any shell command.
+fn target_commands<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+) -> Box<dyn Fn(&str) -> capture::Spawner + Send + Sync> {
+    match target {
         Target::Ssh { host } => {
-            let ssh_args = ssh::build_ssh_args(
-                args.ssh_user.as_deref(),
-                &host,
-                args.ssh_port,
-                args.jump_host.as_deref(),
-                &remote_cmd,
-            );
-            ssh::spawn_ssh(&ssh_args)?
+            let route = ssh_route(args, host);
+            Box::new(move |cmd| {
+                let ssh_args = ssh::build_ssh_args(
+                    route.user.as_deref(),
+                    &route.host,
+                    route.port,
+                    route.jump_host.as_deref(),
+                    cmd,
+                );
+                Box::new(move || ssh::spawn_ssh(&ssh_args))
+            })
         }
         Target::KubernetesExec {
             namespace,
             pod,
             container,
         } => {
-            // Run capture inside the pod via kubectl exec.
-            let kubectl_args =
-                k8s::build_kubectl_exec_args(&namespace, &pod, container.as_deref(), &remote_cmd);
-            k8s::spawn_kubectl_exec(&kubectl_args)?
+            let exec = runner.exec_factory();
+            let (namespace, pod, container) = (namespace.clone(), pod.clone(), container.clone());
+            Box::new(move |cmd| {
+                exec(k8s::PodExec {
+                    namespace: namespace.clone(),
+                    pod: pod.clone(),
+                    container: container.clone(),
+                    command: cmd.to_string(),
+                })
+            })
         }
+    }
+}
+
+/// Probes the target for the compressor requested with `--remote-compress`.
+/// Parameters: `args` (&Args) CLI arguments.
+/// Parameters: `runner` (&R) backend used for pod exec.
+/// Parameters: `target` (&Target) capture target.
+/// Returns: Option<compress::Codec> None when not requested or not available.
+fn remote_codec<R: k8s::Exec>(
+    args: &Args,
+    runner: &R,
+    target: &Target,
+) -> Option<compress::Codec> {
+    let requested = args.remote_compress?;
+    let label = target_label(target);
+    let found = match probe_target(args, runner, target, compress::PROBE_COMMAND) {
+        Ok(out) => compress::parse_probe(&out),
+        Err(err) => {
+            warn!(target = %label, "could not probe for remote compressors: {err:#}");
+            Vec::new()
+        }
+    };
+    let codec = compress::choose(requested, &found);
+    match codec {
+        Some(codec) => {
+            info!(target = %label, codec = codec.name(), "compressing on the remote side");
+        }
+        None => {
+            warn!(target = %label, ?requested, "no remote compressor available; capturing uncompressed");
+        }
+    }
+    codec
+}
+
+fn run_follow<F>(
+    args: &Args,
+    runner: &k8s::KubeRunner,
+    tool: capture::CaptureTool,
+    filter: Option<String>,
+    manifest: &mut manifest::Manifest,
+    sink: F,
+) -> Result<()>
+where
+    F: FnOnce(Box<dyn Read + Send>) -> Result<()> + Send,
+{
+    let ns = args.namespace.as_deref().unwrap_or("default");
+    let (selector, followed) = match (&args.selector, &args.pod) {
+        (Some(s), _) => (Some(s.clone()), format!("selector/{ns}/{s}")),
+        (None, Some(pod)) => match k8s::replacement_selector(runner, ns, pod)? {
+            Some(s) => {
+                let followed = format!("selector/{ns}/{s}");
+                (Some(s), followed)
+            }
+            None => {
+                warn!(%pod, "pod has no stable labels; following restarts, not replacements");
+                (None, format!("pod/{ns}/{pod}"))
+            }
+        },
+        (None, None) => bail!("--follow needs --pod or --selector"),
     };
+    info!(namespace = ns, target = %followed, "following pods");
+    if args.remote_compress.is_some() {
+        warn!("--remote-compress is not supported with --follow; capturing uncompressed");
+    }
 
-    let duration = args.duration;
-    // Bound the capture duration to avoid runaway sessions.
-    if let Some(d) = duration {
-        capture::kill_after(&mut child, d);
+    let remote_cmd = capture::build_capture_command(
+        tool,
+        &args.iface,
+        args.format,
+        filter.as_deref(),
+        args.snaplen,
+    );
+    info!(%remote_cmd, "remote capture command");
+    // Individual pods come and go; the manifest records what was followed.
+    events::emit(&events::Event::CommandBuilt {
+        target: &followed,
+        command: &remote_cmd,
+    });
+    manifest.jobs.push(manifest::JobRecord {
+        target: followed,
+        filter: filter.clone(),
+        remote_command: remote_cmd.clone(),
+        tool: tool.program().to_string(),
+        ..Default::default()
+    });
+
+    // Captures come and go while the output keeps growing, so write from a separate thread.
+    let (stream, handle) = merge::merged();
+
+    let spec = follow::FollowSpec {
+        namespace: ns.to_string(),
+        pod: args.pod.clone(),
+        selector,
+        container: args.container.clone(),
+        duration: args.duration.filter(|d| *d > 0).map(Duration::from_secs),
+        poll: Duration::from_secs(2),
+    };
+    thread::scope(|scope| {
+        let writer = scope.spawn(move || sink(Box::new(stream)));
+        let supervised = follow::supervise(runner, &spec, handle, |pod| {
+            let mut child = runner.spawn_exec(&k8s::PodExec {
+                namespace: ns.to_string(),
+                pod: pod.to_string(),
+                container: args.container.clone(),
+                command: remote_cmd.clone(),
+            })?;
+            let target = format!("pod/{ns}/{pod}");
+            events::emit(&events::Event::TargetResolved {
+                target: &target,
+                node: None,
+            });
+            events::emit(&events::Event::CaptureStarted { target: &target });
+            capture::track(&child);
+            capture::watch_stderr(&mut child, None);
+            Ok(child)
+        });
+
+        let written = writer
+            .join()
+            .map_err(|_| anyhow::anyhow!("output writer panicked"))?;
+        supervised?;
+        written
+    })
+}
+
+fn plan_selector_jobs(
+    args: &Args,
+    runner: &impl k8s::Runner,
+    selector: &str,
+    base_filter: Option<String>,
+) -> Result<Vec<CaptureJob>> {
+    let ns = args.namespace.as_deref().unwrap_or("default");
+    let pods = k8s::list_pod_states(runner, ns, selector, args.container.as_deref())?;
+    let jobs: Vec<CaptureJob> = pods
+        .into_iter()
+        .filter(|p| p.ready_for_exec())
+        .map(|p| {
+            let target = Target::KubernetesExec {
+                namespace: ns.to_string(),
+                pod: p.name,
+                container: args.container.clone(),
+            };
+            CaptureJob {
+                label: target_label(&target),
+                target,
+                filter: base_filter.clone(),
+            }
+        })
+        .collect();
+    if jobs.is_empty() {
+        bail!("no running pods match selector {selector} in namespace {ns}");
     }
+    Ok(jobs)
+}
 
-    let stdout = child.stdout.take().context("failed to capture ssh stdout")?;
-    output::write_stream(stdout, &args.output)
-        .with_context(|| format!("failed to write output to {}", args.output))?;
+fn plan_service_jobs(
+    args: &Args,
+    runner: &impl k8s::Runner,
+    service: &str,
+    base_filter: Option<String>,
+) -> Result<Vec<CaptureJob>> {
+    let ns = args.namespace.as_deref().unwrap_or("default");
+    let endpoints = k8s::resolve_service_endpoints(runner, ns, service, args.port)?;
+    info!(service, namespace = ns, endpoints = endpoints.len(), "resolved service endpoints");
+    // Inside the pod the traffic uses the targetPort, not the Service port.
+    let endpoint_filter = |ep: &k8s::ServiceEndpoint| match ep.port {
+        Some(port) if Some(port) != args.port => {
+            info!(pod = %ep.pod, target_port = port, "filtering on the service's target port");
+            filter::build_filter(Some(port), args.protocol, args.filter.as_deref())
+        }
+        _ => base_filter.clone(),
+    };
 
-    let status = child.wait()?;
-    if !status.success() {
-        bail!("remote command failed with status {status}");
+    if !args.via_node {
+        return Ok(endpoints
+            .into_iter()
+            .map(|ep| {
+                let filter = endpoint_filter(&ep);
+                let target = Target::KubernetesExec {
+                    namespace: ns.to_string(),
+                    pod: ep.pod,
+                    container: args.container.clone(),
+                };
+                CaptureJob {
+                    label: target_label(&target),
+                    target,
+                    filter,
+                }
+            })
+            .collect());
     }
 
-    Ok(())
+    // On a node, narrow the capture to the pod IPs it hosts; one session per node and port.
+    let mut by_node: Vec<(String, Option<String>, Vec<String>)> = Vec::new();
+    for ep in endpoints {
+        let filter = endpoint_filter(&ep);
+        match by_node
+            .iter_mut()
+            .find(|(node, f, _)| *node == ep.node && *f == filter)
+        {
+            Some((_, _, ips)) => ips.push(ep.ip),
+            None => by_node.push((ep.node, filter, vec![ep.ip])),
+        }
+    }
+    Ok(by_node
+        .into_iter()
+        .map(|(node, filter, ips)| {
+            let hosts = filter::build_host_filter(&ips);
+            CaptureJob {
+                label: format!("node/{node}"),
+                target: Target::Ssh { host: node },
+                filter: Some(filter::and_clause(filter, &hosts)),
+            }
+        })
+        .collect())
+}
+
+/// Plans the capture of one pod from its node over SSH, narrowed to the pod's address.
+/// Parameters: `args` (&Args) CLI arguments with `--pod`.
+/// Parameters: `runner` (&impl k8s::Runner) command runner.
+/// Parameters: `base_filter` (Option<String>) filter from the port and protocol flags.
+/// Returns: Result<CaptureJob> node capture job.
+fn plan_pod_node_job(
+    args: &Args,
+    runner: &impl k8s::Runner,
+    base_filter: Option<String>,
+) -> Result<CaptureJob> {
+    let Some(pod) = &args.pod else {
+        bail!("--via-node needs --service or --pod");
+    };
+    let ns = args.namespace.as_deref().unwrap_or("default");
+    let node = k8s::resolve_pod_node(runner, ns, pod)?;
+    let ip = k8s::resolve_pod_ip(runner, ns, pod)?;
+    info!(pod = %pod, namespace = ns, %node, %ip, "capturing pod from its node");
+    let hosts = filter::build_host_filter(&[ip]);
+    Ok(CaptureJob {
+        label: format!("node/{node}"),
+        target: Target::Ssh { host: node },
+        filter: Some(filter::and_clause(base_filter, &hosts)),
+    })
+}
+
+/// Collects output name template values from the arguments.
+/// Parameters: `args` (&Args) CLI arguments.
+/// Returns: naming::NameVars values for `{target}`, `{ns}`, `{pod}`, `{node}`, `{ts}` and `{port}`.
+fn name_vars(args: &Args) -> naming::NameVars {
+    let ns = args.namespace.clone().unwrap_or_else(|| "default".to_string());
+    let target = [&args.ssh_host, &args.pod, &args.service, &args.selector]
+        .into_iter()
+        .find_map(|v| v.clone())
+        .unwrap_or_else(|| "capture".to_string());
+    let mut node = args.ssh_host.clone().unwrap_or_default();
+    let mut templates = std::iter::once(&args.output).chain(&args.tee);
+    if node.is_empty() && templates.any(|t| t.contains("{node}")) {
+        // Only pay for the lookup when a template asks for it.
+        if let Some(pod) = &args.pod {
+            let lookup = k8s::KubeRunner::select(args.kube_backend, &kube_context(args))
+                .and_then(|runner| k8s::resolve_pod_node(&runner, &ns, pod));
+            match lookup {
+                Ok(n) => node = n,
+                Err(err) => warn!("could not resolve node for output name: {err:#}"),
+            }
+        }
+    }
+    naming::NameVars {
+        target,
+        ns,
+        pod: args.pod.clone().unwrap_or_default(),
+        node,
+        port: args.port.map(|p| p.to_string()).unwrap_or_default(),
+        ts: naming::timestamp(std::time::SystemTime::now()),
+    }
+}
+
+/// Builds the local sanitization pass from `--payload` and `--anonymize-ips`.
+/// Parameters: `args` (&Args) parsed CLI arguments.
+/// Returns: Result<Option<sanitize::Sanitizer>> None when packets are kept as captured.
+fn sanitizer(args: &Args) -> Result<Option<sanitize::Sanitizer>> {
+    if args.anonymize_ips && args.embed_names {
+        bail!("--embed-names would reveal the addresses --anonymize-ips hides");
+    }
+    let anonymizer = args
+        .anonymize_ips
+        .then(|| sanitize::Anonymizer::from_key(args.anonymize_key.as_deref()))
+        .transpose()?;
+    let sanitizer = sanitize::Sanitizer::new(args.payload, anonymizer);
+    Ok(sanitizer.is_active().then_some(sanitizer))
+}
+
+/// Looks up cluster names for `--decode` and `--embed-names`; a capture without names
+/// beats no capture.
+/// Parameters: `args` (&Args) parsed CLI arguments.
+/// Returns: k8s::IpOwners mapping, empty when the cluster cannot be queried.
+fn ip_owners(args: &Args) -> k8s::IpOwners {
+    let lookup = k8s::KubeRunner::select(args.kube_backend, &kube_context(args))
+        .and_then(|runner| k8s::lookup_ip_owners(&runner, args.namespace.as_deref()));
+    match lookup {
+        Ok(owners) => {
+            info!(addresses = owners.len(), "looked up cluster names for decoding");
+            owners
+        }
+        Err(err) => {
+            warn!("could not look up pod and service names: {err:#}");
+            k8s::IpOwners::new()
+        }
+    }
+}
+
+fn kube_context(args: &Args) -> k8s::KubeContext {
+    k8s::KubeContext {
+        kubeconfig: args.kubeconfig.clone(),
+        context: args.context.clone(),
+        cluster: args.cluster.clone(),
+    }
+}
+
+pub(crate) fn target_label(target: &Target) -> String {
+    match target {
+        Target::Ssh { host } => format!("ssh/{host}"),
+        Target::KubernetesExec { namespace, pod, .. } => format!("pod/{namespace}/{pod}"),
+    }
+}
+
+fn exclude_own_session(args: &Args, host: &str, filter: Option<String>) -> Option<String> {
+    // Capturing the SSH stream that carries the capture feeds back into itself.
+    let route = ssh_route(args, host);
+    match ssh::probe_ssh_connection(
+        &k8s::SystemRunner,
+        route.user.as_deref(),
+        &route.host,
+        route.port,
+        route.jump_host.as_deref(),
+    ) {
+        Ok(conn) => {
+            let clause = filter::build_self_exclusion(&conn.client_ip, conn.server_port);
+            info!(%clause, "excluding kcap ssh session from capture");
+            Some(filter::and_clause(filter, &clause))
+        }
+        Err(err) => {
+            warn!("could not detect ssh session endpoints, capture may include itself: {err:#}");
+            filter
+        }
+    }
+}
+
+/// SSH settings for a target host name, from the node inventory or the `--ssh-*` flags.
+/// Parameters: `args` (&Args) CLI arguments carrying the flags and the inventory.
+/// Parameters: `host` (&str) `--ssh-host` value or node name.
+/// Returns: inventory::Route to connect with.
+fn ssh_route(args: &Args, host: &str) -> inventory::Route {
+    args.inventory.route(
+        host,
+        args.ssh_user.as_deref(),
+        args.ssh_port,
+        args.jump_host.as_deref(),
+    )
+}
+
+/// Looks up the InternalIP of planned node targets the inventory gives no host for.
+/// Parameters: `args` (&mut Args) CLI arguments whose inventory receives the addresses.
+/// Parameters: `runner` (&impl k8s::Runner) command runner.
+/// Parameters: `jobs` (&[CaptureJob]) planned captures.
+fn resolve_node_addresses(args: &mut Args, runner: &impl k8s::Runner, jobs: &[CaptureJob]) {
+    let missing = jobs.iter().any(|job| match &job.target {
+        Target::Ssh { host } => args.inventory.needs_address(host),
+        Target::KubernetesExec { .. } => false,
+    });
+    if !missing {
+        return;
+    }
+    match k8s::node_addresses(runner) {
+        Ok(addresses) => args.inventory.add_addresses(addresses),
+        Err(err) => warn!("could not look up node InternalIPs, connecting by node name: {err:#}"),
+    }
 }
 
 fn resolve_target(args: &Args) -> Result<Target> {
@@ -92,24 +1139,15 @@ fn resolve_target(args: &Args) -> Result<Target> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::Parser;
+
+    fn args(argv: &[&str]) -> Args {
+        Args::parse_from(std::iter::once("kcap").chain(argv.iter().copied()))
+    }
+
     #[test]
     fn resolve_target_prefers_ssh_host() {
-        let args = Args {
-            ssh_user: None,
-            ssh_host: Some("10.0.0.1".to_string()),
-            ssh_port: 22,
-            jump_host: None,
-            namespace: None,
-            pod: Some("p1".to_string()),
-            container: None,
-            port: None,
-            protocol: cli::Protocol::All,
-            iface: "any".to_string(),
-            output: "capture.pcap".to_string(),
-            format: cli::CaptureFormat::Pcap,
-            duration: None,
-            filter: None,
-        };
+        let args = args(&["--ssh-host", "10.0.0.1", "--pod", "p1"]);
         let target = resolve_target(&args).unwrap();
         match target {
             Target::Ssh { host } => assert_eq!(host, "10.0.0.1"),
@@ -119,22 +1157,7 @@ mod tests {
 
     #[test]
     fn resolve_target_from_pod() {
-        let args = Args {
-            ssh_user: None,
-            ssh_host: None,
-            ssh_port: 22,
-            jump_host: None,
-            namespace: Some("prod".to_string()),
-            pod: Some("orders".to_string()),
-            container: None,
-            port: None,
-            protocol: cli::Protocol::All,
-            iface: "any".to_string(),
-            output: "capture.pcap".to_string(),
-            format: cli::CaptureFormat::Pcap,
-            duration: None,
-            filter: None,
-        };
+        let args = args(&["-n", "prod", "--pod", "orders"]);
         let target = resolve_target(&args).unwrap();
         match target {
             Target::KubernetesExec { namespace, pod, .. } => {
@@ -147,22 +1170,7 @@ mod tests {
 
     #[test]
     fn resolve_target_pod_with_container() {
-        let args = Args {
-            ssh_user: None,
-            ssh_host: None,
-            ssh_port: 22,
-            jump_host: None,
-            namespace: Some("prod".to_string()),
-            pod: Some("orders".to_string()),
-            container: Some("api".to_string()),
-            port: None,
-            protocol: cli::Protocol::All,
-            iface: "any".to_string(),
-            output: "capture.pcap".to_string(),
-            format: cli::CaptureFormat::Pcap,
-            duration: None,
-            filter: None,
-        };
+        let args = args(&["-n", "prod", "--pod", "orders", "--container", "api"]);
 
         let target = resolve_target(&args).unwrap();
         match target {
@@ -178,4 +1186,192 @@ mod tests {
             _ => panic!("expected kubectl exec target"),
         }
     }
+
+    #[test]
+    fn missing_pod_exits_with_pod_not_found() {
+        let args = args(&["-n", "prod", "--pod", "missing", "--via-node"]);
+        // kubectl prints nothing for `get pod missing --ignore-not-found`.
+        let runner = k8s::FakeRunner::new("");
+        let Err(err) = plan_pod_node_job(&args, &runner, None) else {
+            panic!("a missing pod has no node to capture on");
+        };
+        assert_eq!(error::exit_code(&err), 32);
+    }
+
+    #[test]
+    fn service_jobs_group_endpoints_by_node() {
+        let args = args(&["-n", "prod", "--service", "payments", "--port", "8443", "--via-node"]);
+        // Answers both the Service lookup (no ports, so 8443 is a pod port) and the slices.
+        let runner = k8s::FakeRunner::new(
+            r#"{"items":[{"endpoints":[
+                {"addresses":["10.244.1.5"],"nodeName":"node-a","targetRef":{"kind":"Pod","name":"pay-1"}},
+                {"addresses":["10.244.1.6"],"nodeName":"node-a","targetRef":{"kind":"Pod","name":"pay-2"}}
+            ]}]}"#,
+        );
+        let base = Some("tcp port 8443".to_string());
+        let jobs = plan_service_jobs(&args, &runner, "payments", base).unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].label, "node/node-a");
+        assert_eq!(
+            jobs[0].filter.as_deref(),
+            Some("(tcp port 8443) and (host 10.244.1.5 or host 10.244.1.6)")
+        );
+
+        // The node has no inventory host, so kubectl supplies its InternalIP.
+        let mut args = args;
+        let table: toml::Table = "jump-host = \"bastion\"".parse().unwrap();
+        let entry = inventory::NodeEntry::parse(&table, "nodes.node-*").unwrap();
+        args.inventory.insert("node-*".to_string(), entry);
+        let nodes = k8s::FakeRunner::new(
+            r#"{"items":[
+                {"metadata":{"name":"node-a"},"status":{"addresses":[{"type":"InternalIP","address":"10.0.0.21"}]}},
+                {"metadata":{"name":"node-b"},"status":{"addresses":[{"type":"InternalIP","address":"10.0.0.22"}]}}
+            ]}"#,
+        );
+        resolve_node_addresses(&mut args, &nodes, &jobs);
+        let route = ssh_route(&args, "node-a");
+        assert_eq!(route.host, "10.0.0.21");
+        assert_eq!(route.jump_host.as_deref(), Some("bastion"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn duration_stops_the_remote_tool_so_it_reports_drops() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Prints its counters only when signalled, like tcpdump.
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tcpdump");
+        std::fs::write(
+            &tool,
+            "#!/bin/sh\n\
+             trap 'echo \"9 packets captured\" >&2; echo \"10 packets received by filter\" >&2; \
+             echo \"1 packet dropped by kernel\" >&2; exit 0' TERM\n\
+             printf pcap\n\
+             while :; do sleep 0.05; done\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut runner = k8s::FakeRunner::new("");
+        runner.exec_path = Some(dir.path().to_path_buf());
+
+        let args = args(&["-n", "prod", "--pod", "p1", "--duration", "1"]);
+        let job = CaptureJob {
+            label: "pod/prod/p1".to_string(),
+            target: resolve_target(&args).unwrap(),
+            filter: None,
+        };
+        let mut manifest = manifest::Manifest::new(false);
+        let mut captured = Vec::new();
+        let sink = |mut stream: Box<dyn Read + Send>| {
+            stream.read_to_end(&mut captured)?;
+            Ok(())
+        };
+        let tool = capture::CaptureTool::Tcpdump;
+        capture_jobs(&args, &runner, tool, vec![job], &mut manifest, None, sink).unwrap();
+
+        assert_eq!(captured, b"pcap");
+        let record = &manifest.jobs[0];
+        assert_eq!(record.exit_code, Some(0));
+        let stats = record.stats.unwrap();
+        assert_eq!(stats.dropped_by_kernel, Some(1));
+        assert_eq!(stats.received, Some(10));
+        assert!(!record.stderr.iter().any(|l| l.contains("kcap-remote-pid")));
+    }
+
+    /// Runs pod execs like `FakeRunner`, except in pod p2, which cannot be reached.
+    struct SecondPodFails {
+        inner: k8s::FakeRunner,
+        pids: Arc<Mutex<Vec<u32>>>,
+    }
+
+    impl k8s::Runner for SecondPodFails {
+        fn run_capture(&self, program: &str, args: &[&str]) -> Result<String> {
+            self.inner.run_capture(program, args)
+        }
+    }
+
+    impl k8s::Exec for SecondPodFails {
+        fn exec_factory(&self) -> k8s::ExecFactory {
+            let inner = self.inner.exec_factory();
+            let pids = self.pids.clone();
+            Box::new(move |exec| {
+                if exec.pod == "p2" {
+                    return Box::new(|| Err(anyhow::anyhow!("unable to upgrade connection")));
+                }
+                let spawn = inner(exec);
+                let pids = pids.clone();
+                Box::new(move || {
+                    let child = spawn()?;
+                    pids.lock().unwrap().push(child.id());
+                    Ok(child)
+                })
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failed_spawn_stops_the_captures_already_started() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tool = dir.path().join("tcpdump");
+        std::fs::write(&tool, "#!/bin/sh\nexec sleep 10\n").unwrap();
+        std::fs::set_permissions(&tool, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut inner = k8s::FakeRunner::new("");
+        inner.exec_path = Some(dir.path().to_path_buf());
+        let runner = SecondPodFails {
+            inner,
+            pids: Arc::default(),
+        };
+
+        let args = args(&["-n", "prod", "--selector", "app=web"]);
+        let jobs = ["p1", "p2"]
+            .into_iter()
+            .map(|pod| CaptureJob {
+                label: format!("pod/prod/{pod}"),
+                target: Target::KubernetesExec {
+                    namespace: "prod".to_string(),
+                    pod: pod.to_string(),
+                    container: None,
+                },
+                filter: None,
+            })
+            .collect();
+        let mut manifest = manifest::Manifest::new(false);
+        let tool = capture::CaptureTool::Tcpdump;
+        let err = capture_jobs(&args, &runner, tool, jobs, &mut manifest, None, |_| Ok(()))
+            .unwrap_err();
+        assert!(err.to_string().contains("unable to upgrade"), "{err:#}");
+
+        // The capture in p1 has been killed and reaped, not left running.
+        let pids = runner.pids.lock().unwrap().clone();
+        assert_eq!(pids.len(), 1);
+        let alive = std::process::Command::new("kill")
+            .args(["-0", &pids[0].to_string()])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!alive.success());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sidecars_only_describe_regular_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("live.pcap");
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let fifo = fifo.to_string_lossy().to_string();
+        let file = dir.path().join("new.pcap").to_string_lossy().to_string();
+
+        assert_eq!(sidecar_file(&file).unwrap(), Some(file.clone()));
+        assert_eq!(sidecar_file(&fifo).unwrap(), None);
+        assert_eq!(sidecar_file("/dev/null").unwrap(), None);
+        assert_eq!(sidecar_file("-").unwrap(), None);
+    }
 }
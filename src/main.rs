@@ -1,16 +1,133 @@
-﻿use clap::Parser;
+﻿use kcap::cli::{Args, Cli, Command, LogFormat};
+use kcap::extcap::ExtcapArgs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing_subscriber::filter::LevelFilter;
 use tracing_subscriber::EnvFilter;
 
 fn main() {
-    // Initialize structured logging to aid CLI troubleshooting.
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    // kcap re-executes itself as a kubectl exec stand-in for the native backend.
+    #[cfg(feature = "native-k8s")]
+    if let Some(code) = kcap::kube_native::shim_main() {
+        std::process::exit(code);
+    }
+
+    // Wireshark drives kcap through its own extcap flags; any other command line is a
+    // kcap subcommand, or a capture run when none is given.
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let result = if kcap::extcap::is_extcap_invocation(&argv) {
+        kcap::config::parse_args(&argv).and_then(|extcap: ExtcapArgs| {
+            start(&extcap.args, true);
+            kcap::extcap::run(extcap)
+        })
+    } else {
+        kcap::config::parse_cli(&argv).and_then(run)
+    };
+    // Fail fast with a readable error message and an exit code automation can act on.
+    if let Err(err) = result {
+        let message = format!("{err:#}");
+        let kind = kcap::error::find(&err);
+        let code = kcap::error::exit_code(&err);
+        kcap::events::emit(&kcap::events::Event::Error {
+            message: &message,
+            code,
+            retryable: kind.map(|k| k.retryable()),
+            hint: kind.map(|k| k.hint()),
+        });
+        if kcap::events::human_stderr() {
+            eprintln!("error: {message}");
+            if let Some(kind) = kind {
+                eprintln!("hint: {}", kind.hint());
+            }
+        } else if !kcap::events::on_stderr() {
+            tracing::error!(exit_code = code, hint = kind.map(|k| k.hint()), "{message}");
+        }
+        std::process::exit(code);
+    }
+}
+
+/// Runs a parsed kcap command line.
+/// Parameters: `cli` (Cli) subcommand or capture options, with the configuration applied.
+/// Returns: Result<()> error of the command.
+fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command {
+        None => {
+            start(&cli.args, true);
+            kcap::run(cli.args)
+        }
+        Some(Command::Serve(serve)) => {
+            start(&serve.args, true);
+            kcap::serve::run(serve)
+        }
+        // The rpcap server exits on Ctrl-C; its clients end their own captures.
+        Some(Command::Rpcap(rpcap)) => {
+            start(&rpcap.args, false);
+            kcap::rpcap::run(rpcap)
+        }
+        Some(Command::Analyze(analyze)) => {
+            init_logging(LogFormat::Text);
+            kcap::analyze::run(analyze)
+        }
+        Some(Command::Sanitize(sanitize)) => {
+            init_logging(LogFormat::Text);
+            kcap::sanitize::run(sanitize)
+        }
+        Some(Command::Config(config)) => {
+            init_logging(LogFormat::Text);
+            kcap::config::run(config)
+        }
+    }
+}
+
+/// Sets up logging and interrupt handling for a command running captures.
+/// Parameters: `args` (&Args) capture options of the command.
+/// Parameters: `interruptible` (bool) whether Ctrl-C finishes the capture cleanly.
+fn start(args: &Args, interruptible: bool) {
+    init_logging(args.log_format);
+    if interruptible {
+        finish_on_interrupt();
+    }
+}
+
+/// Initializes structured logging to aid CLI troubleshooting.
+/// Parameters: `format` (LogFormat) log format on stderr; JSON also reserves stderr for JSON.
+fn init_logging(format: LogFormat) {
+    // Warnings (ignored flags, truncated streams, ...) show up without RUST_LOG; stdout may
+    // carry the capture, so logs always go to stderr.
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::WARN.into())
+        .from_env_lossy();
+    let logs = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    let json = format == LogFormat::Json;
+    if json {
+        logs.json().init();
+    } else {
+        logs.init();
+    }
+    kcap::events::set_json_stderr(json);
+}
 
-    let args = kcap::cli::Args::parse();
-    // Fail fast with a readable error message.
-    if let Err(err) = kcap::run(args) {
-        eprintln!("error: {err:#}");
-        std::process::exit(1);
+/// Turns the first Ctrl-C into a clean stop so outputs (and compression trailers) are
+/// written completely; a second Ctrl-C exits immediately.
+fn finish_on_interrupt() {
+    let pressed = AtomicBool::new(false);
+    let handler = ctrlc::set_handler(move || {
+        if pressed.swap(true, Ordering::SeqCst) {
+            kcap::capture::abort_all();
+            std::process::exit(130);
+        }
+        if kcap::events::human_stderr() {
+            kcap::progress::eprint_line(
+                "stopping capture; finishing output (press Ctrl-C again to abort)",
+            );
+        } else {
+            tracing::info!("stopping capture; finishing output");
+        }
+        kcap::capture::stop_all();
+    });
+    if handler.is_ok() {
+        // Captures no longer see the terminal's SIGINT; kcap stops them itself.
+        kcap::capture::handle_interrupts();
     }
 }
@@ -0,0 +1,305 @@
+use crate::capture::CaptureStats;
+use crate::compress::CompressionStats;
+use crate::naming::rfc3339;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// What kcap ran on one capture target and how it ended.
+pub struct JobRecord {
+    pub target: String,
+    /// Node the capture ran on, when known.
+    pub node: Option<String>,
+    pub filter: Option<String>,
+    pub remote_command: String,
+    pub tool: String,
+    /// First line of `<tool> --version` on the target.
+    pub tool_version: Option<String>,
+    /// e.g. `exit status: 0` or `signal: 15 (SIGTERM)`; None while still running.
+    pub exit_status: Option<String>,
+    pub exit_code: Option<i32>,
+    /// Last lines the remote side wrote to stderr.
+    pub stderr: Vec<String>,
+    /// Counters parsed from `stderr`.
+    pub stats: Option<CaptureStats>,
+    /// Bytes moved by `--remote-compress`, when the stream was compressed.
+    pub compression: Option<CompressionStats>,
+}
+
+#[derive(Debug, Clone)]
+/// Provenance collected during a run, written as `<output>.kcap.json`.
+pub struct Manifest {
+    /// Look up nodes and tool versions, which costs extra remote calls.
+    pub detailed: bool,
+    pub started: SystemTime,
+    pub jobs: Vec<JobRecord>,
+}
+
+impl Manifest {
+    /// Starts recording a run.
+    /// Parameters: `detailed` (bool) whether to spend remote calls on nodes and versions.
+    /// Returns: Manifest with the start time set to now.
+    pub fn new(detailed: bool) -> Self {
+        Manifest {
+            detailed,
+            started: SystemTime::now(),
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Renders the manifest.
+    /// Parameters: `output` (&str) capture file path.
+    /// Parameters: `result` (&Result<()>) outcome of the run.
+    /// Parameters: `file` (Option<&FileSummary>) contents of the written file, if readable.
+    /// Returns: serde_json::Value manifest document.
+    pub fn to_json(&self, output: &str, result: &Result<()>, file: Option<&FileSummary>) -> Value {
+        let jobs: Vec<Value> = self
+            .jobs
+            .iter()
+            .map(|j| {
+                json!({
+                    "target": j.target,
+                    "node": j.node,
+                    "filter": j.filter,
+                    "remote_command": j.remote_command,
+                    "tool": j.tool,
+                    "tool_version": j.tool_version,
+                    "exit_status": j.exit_status,
+                    "exit_code": j.exit_code,
+                    "stderr": j.stderr,
+                    "stats": j.stats.map(|st| json!({
+                        "captured": st.captured,
+                        "received_by_filter": st.received,
+                        "dropped_by_kernel": st.dropped_by_kernel,
+                        "dropped_by_interface": st.dropped_by_interface,
+                    })),
+                    "compression": j.compression.map(|c| json!({
+                        "codec": c.codec.name(),
+                        "wire_bytes": c.wire_bytes,
+                        "capture_bytes": c.capture_bytes,
+                        "ratio": c.ratio(),
+                    })),
+                })
+            })
+            .collect();
+        json!({
+            "kcap_version": env!("CARGO_PKG_VERSION"),
+            "output": output,
+            "started": rfc3339(self.started),
+            "ended": rfc3339(SystemTime::now()),
+            "status": if result.is_ok() { "ok" } else { "failed" },
+            "error": result.as_ref().err().map(|e| format!("{e:#}")),
+            "jobs": jobs,
+            "file": file.map(|f| json!({
+                "packets": f.packets,
+                "capture_bytes": f.capture_bytes,
+                "file_bytes": f.file_bytes,
+                "sha256": f.sha256,
+            })),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Contents of a written capture file.
+pub struct FileSummary {
+    pub packets: u64,
+    /// Size of the (decompressed) pcap/pcapng data.
+    pub capture_bytes: u64,
+    /// Size on disk.
+    pub file_bytes: u64,
+    /// SHA-256 of the bytes on disk, hex encoded.
+    pub sha256: String,
+}
+
+/// Summary of a capture file built up while it is written, so the file is never read
+/// back (which would block on a FIFO with no writer left).
+#[derive(Debug, Clone, Default)]
+pub struct FileDigest(Arc<Mutex<DigestState>>);
+
+#[derive(Debug, Default)]
+struct DigestState {
+    opened: bool,
+    hasher: Sha256,
+    packets: u64,
+    capture_bytes: u64,
+    file_bytes: u64,
+}
+
+impl FileDigest {
+    /// Wraps the capture file so the bytes reaching it are counted and hashed.
+    /// Parameters: `inner` (W) the opened file, after any compression.
+    /// Returns: DigestWriter<W> writer feeding this digest.
+    pub fn writer<W: Write>(&self, inner: W) -> DigestWriter<W> {
+        self.lock().opened = true;
+        DigestWriter {
+            inner,
+            digest: self.clone(),
+        }
+    }
+
+    /// Records capture data handed to the file, before compression.
+    /// Parameters: `bytes` (usize) size of the stream header or block.
+    /// Parameters: `packet` (bool) whether the block is a packet.
+    pub fn captured(&self, bytes: usize, packet: bool) {
+        let mut state = self.lock();
+        state.capture_bytes += bytes as u64;
+        state.packets += u64::from(packet);
+    }
+
+    /// Returns: Option<FileSummary> None when the file was never opened.
+    pub fn summary(&self) -> Option<FileSummary> {
+        let state = self.lock();
+        state.opened.then(|| FileSummary {
+            packets: state.packets,
+            capture_bytes: state.capture_bytes,
+            file_bytes: state.file_bytes,
+            sha256: state
+                .hasher
+                .clone()
+                .finalize()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DigestState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Writer that feeds a `FileDigest` with everything written through it.
+pub struct DigestWriter<W> {
+    inner: W,
+    digest: FileDigest,
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let mut state = self.digest.lock();
+        state.hasher.update(&buf[..n]);
+        state.file_bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Sidecar path for a capture file.
+/// Parameters: `output` (&str) capture file path.
+/// Returns: String `<output>.kcap.json`.
+pub fn sidecar_path(output: &str) -> String {
+    format!("{output}.kcap.json")
+}
+
+/// Writes the manifest next to the capture file.
+/// Parameters: `manifest` (&Manifest) recorded run.
+/// Parameters: `output` (&str) capture file path.
+/// Parameters: `result` (&Result<()>) outcome of the run.
+/// Parameters: `file` (Option<&FileSummary>) what was written to the file, if it was opened.
+/// Returns: Result<String> path of the written sidecar.
+pub fn write_sidecar(
+    manifest: &Manifest,
+    output: &str,
+    result: &Result<()>,
+    file: Option<&FileSummary>,
+) -> Result<String> {
+    // A failed run may have left a partial or no file; the manifest is still worth keeping.
+    let doc = manifest.to_json(output, result, file);
+    let path = sidecar_path(output);
+    fs::write(&path, serde_json::to_string_pretty(&doc)? + "\n")
+        .with_context(|| format!("failed to write {path}"))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::write_recorded;
+    use crate::pcap::testdata;
+    use anyhow::anyhow;
+    use std::io::Cursor;
+
+    #[test]
+    fn digests_compressed_file_while_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cap.pcap.gz").to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"one"), (2, b"two")]);
+        let digest = FileDigest::default();
+        assert!(digest.summary().is_none());
+        write_recorded(Cursor::new(capture.clone()), &path, None, Vec::new(), Some(&digest))
+            .unwrap();
+
+        let summary = digest.summary().unwrap();
+        assert_eq!(summary.packets, 2);
+        assert_eq!(summary.capture_bytes, capture.len() as u64);
+        let on_disk = fs::read(&path).unwrap();
+        assert_eq!(summary.file_bytes, on_disk.len() as u64);
+        let sha256: String = Sha256::digest(&on_disk)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        assert_eq!(summary.sha256, sha256);
+    }
+
+    #[test]
+    fn writes_sidecar_with_jobs_and_outcome() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cap.pcap").to_string_lossy().to_string();
+        let digest = FileDigest::default();
+        let capture = testdata::pcap_stream(&[(1, b"one")]);
+        write_recorded(Cursor::new(capture), &path, None, Vec::new(), Some(&digest)).unwrap();
+
+        let mut manifest = Manifest::new(true);
+        manifest.jobs.push(JobRecord {
+            target: "ssh:10.0.0.10".into(),
+            node: Some("10.0.0.10".into()),
+            remote_command: "tcpdump -i any -U -s 0 -w -".into(),
+            tool: "tcpdump".into(),
+            exit_code: Some(0),
+            stderr: vec!["1 packet captured".into()],
+            stats: CaptureStats::parse(&["1 packet captured".into()]),
+            compression: Some(CompressionStats {
+                codec: crate::compress::Codec::Zstd,
+                wire_bytes: 100,
+                capture_bytes: 400,
+            }),
+            ..JobRecord::default()
+        });
+        let sidecar = write_sidecar(
+            &manifest,
+            &path,
+            &Err(anyhow!("remote command failed")),
+            digest.summary().as_ref(),
+        )
+        .unwrap();
+        assert_eq!(sidecar, format!("{path}.kcap.json"));
+
+        let doc: Value = serde_json::from_slice(&fs::read(&sidecar).unwrap()).unwrap();
+        assert_eq!(doc["status"], "failed");
+        assert_eq!(doc["error"], "remote command failed");
+        assert_eq!(doc["jobs"][0]["node"], "10.0.0.10");
+        assert_eq!(doc["jobs"][0]["stderr"][0], "1 packet captured");
+        assert_eq!(doc["jobs"][0]["stats"]["captured"], 1);
+        assert_eq!(doc["jobs"][0]["compression"]["codec"], "zstd");
+        assert_eq!(doc["jobs"][0]["compression"]["ratio"], 4.0);
+        assert_eq!(doc["file"]["packets"], 1);
+    }
+
+    #[test]
+    fn missing_file_is_recorded_as_null() {
+        let manifest = Manifest::new(false);
+        let doc = manifest.to_json("/nonexistent.pcap", &Ok(()), None);
+        assert_eq!(doc["status"], "ok");
+        assert!(doc["file"].is_null());
+    }
+}
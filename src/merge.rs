@@ -0,0 +1,435 @@
+use crate::events;
+use crate::pcap::{self, BlockKind, CaptureReader, StreamFormat};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use tracing::warn;
+
+enum Event {
+    Header {
+        source: usize,
+        label: String,
+        comment: Option<String>,
+        format: StreamFormat,
+        big_endian: bool,
+        raw: Vec<u8>,
+    },
+    Block {
+        source: usize,
+        kind: BlockKind,
+        raw: Vec<u8>,
+    },
+    Failed {
+        label: String,
+        error: String,
+    },
+}
+
+#[derive(Default)]
+struct SourceState {
+    accepted: bool,
+    // Local pcapng interface index -> merged interface index.
+    interfaces: Vec<u32>,
+    // Attached to the first packet of this source, e.g. to mark a capture gap.
+    comment: Option<String>,
+}
+
+/// Reader that interleaves packets from several capture streams into one stream.
+pub struct MergedStream {
+    rx: Receiver<Event>,
+    sources: HashMap<usize, SourceState>,
+    format: Option<StreamFormat>,
+    header: Vec<u8>,
+    big_endian: bool,
+    next_interface: u32,
+    pending: Vec<u8>,
+    pos: usize,
+    // Gap comments classic pcap output had no room for.
+    unrecorded_gaps: usize,
+    finished: bool,
+}
+
+#[derive(Clone)]
+/// Handle for adding capture streams to a running merge.
+pub struct MergeHandle {
+    tx: SyncSender<Event>,
+    next_source: Arc<AtomicUsize>,
+}
+
+/// Creates an empty merge; the stream ends once every handle and source is finished.
+/// Returns: (MergedStream, MergeHandle) reader side and the handle feeding it.
+pub fn merged() -> (MergedStream, MergeHandle) {
+    let (tx, rx) = mpsc::sync_channel(256);
+    let stream = MergedStream {
+        rx,
+        sources: HashMap::new(),
+        format: None,
+        header: Vec::new(),
+        big_endian: false,
+        next_interface: 0,
+        pending: Vec::new(),
+        pos: 0,
+        unrecorded_gaps: 0,
+        finished: false,
+    };
+    let handle = MergeHandle {
+        tx,
+        next_source: Arc::new(AtomicUsize::new(0)),
+    };
+    (stream, handle)
+}
+
+/// Starts reading every source on its own thread and merges them in arrival order.
+/// Parameters: `sources` (Vec<(String, R)>) labelled capture streams.
+/// Returns: MergedStream yielding a single pcap or pcapng stream.
+pub fn merge_streams<R: Read + Send + 'static>(sources: Vec<(String, R)>) -> MergedStream {
+    let (stream, handle) = merged();
+    for (label, source) in sources {
+        handle.add_source(label, source, None);
+    }
+    stream
+}
+
+impl MergeHandle {
+    /// Adds a capture stream to the merge, read on its own thread.
+    /// Parameters: `label` (String) name used in logs.
+    /// Parameters: `stream` (R) capture bytes starting with a pcap/pcapng header.
+    /// Parameters: `comment` (Option<String>) pcapng comment for the source's first packet.
+    pub fn add_source<R: Read + Send + 'static>(
+        &self,
+        label: String,
+        stream: R,
+        comment: Option<String>,
+    ) {
+        let source = self.next_source.fetch_add(1, Ordering::Relaxed);
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            let mut reader = match CaptureReader::new(stream) {
+                Ok(r) => r,
+                Err(err) => {
+                    let _ = tx.send(Event::Failed {
+                        label,
+                        error: format!("{err:#}"),
+                    });
+                    return;
+                }
+            };
+            let header = Event::Header {
+                source,
+                label: label.clone(),
+                comment,
+                format: reader.format(),
+                big_endian: reader.big_endian(),
+                raw: reader.header().to_vec(),
+            };
+            if tx.send(header).is_err() {
+                return;
+            }
+            loop {
+                match reader.next_block() {
+                    Ok(Some(block)) => {
+                        let ev = Event::Block {
+                            source,
+                            kind: block.kind,
+                            raw: block.raw,
+                        };
+                        if tx.send(ev).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(err) => {
+                        let _ = tx.send(Event::Failed {
+                            label,
+                            error: format!("{err:#}"),
+                        });
+                        return;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl MergedStream {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Header {
+                source,
+                label,
+                comment,
+                format,
+                big_endian,
+                raw,
+            } => self.handle_header(source, label, comment, format, big_endian, raw),
+            Event::Block { source, kind, raw } => self.handle_block(source, kind, raw),
+            Event::Failed { label, error } => {
+                warn!(source = %label, "capture stream ended: {error}");
+            }
+        }
+    }
+
+    fn handle_header(
+        &mut self,
+        source: usize,
+        label: String,
+        comment: Option<String>,
+        format: StreamFormat,
+        big_endian: bool,
+        raw: Vec<u8>,
+    ) {
+        if format == StreamFormat::Pcap {
+            if let Some(c) = &comment {
+                // Classic pcap has nowhere to store annotations.
+                warn!(source = %label, "{c}; not recorded in pcap output");
+                self.unrecorded_gaps += 1;
+            }
+        }
+        let Some(out_format) = self.format else {
+            // The first stream to start decides the merged header.
+            self.format = Some(format);
+            self.big_endian = big_endian;
+            self.header = raw.clone();
+            self.pending.extend_from_slice(&raw);
+            self.sources.insert(
+                source,
+                SourceState {
+                    accepted: true,
+                    comment,
+                    ..Default::default()
+                },
+            );
+            return;
+        };
+
+        let compatible = out_format == format
+            && big_endian == self.big_endian
+            && match format {
+                // Records are copied verbatim, so time units and link type must agree.
+                StreamFormat::Pcap => {
+                    raw[..4] == self.header[..4] && raw[20..24] == self.header[20..24]
+                }
+                StreamFormat::Pcapng => true,
+            };
+        if !compatible {
+            warn!(source = %label, "capture header differs from the first stream; dropping it from the merge");
+        }
+        self.sources.insert(
+            source,
+            SourceState {
+                accepted: compatible,
+                comment,
+                ..Default::default()
+            },
+        );
+    }
+
+    fn handle_block(&mut self, source: usize, kind: BlockKind, mut raw: Vec<u8>) {
+        let be = self.big_endian;
+        let Some(state) = self.sources.get_mut(&source) else {
+            return;
+        };
+        if !state.accepted {
+            return;
+        }
+        if self.format == Some(StreamFormat::Pcap) {
+            self.pending.extend_from_slice(&raw);
+            return;
+        }
+
+        match kind {
+            BlockKind::SectionHeader => {
+                // A new section restarts interface numbering for that source.
+                state.interfaces.clear();
+                return;
+            }
+            BlockKind::InterfaceDescription => {
+                state.interfaces.push(self.next_interface);
+                self.next_interface += 1;
+            }
+            BlockKind::Packet(_) | BlockKind::Other(pcap::BLOCK_INTERFACE_STATISTICS) => {
+                let block_type = pcap::read_u32(&raw, 0, be);
+                if block_type == pcap::BLOCK_ENHANCED_PACKET
+                    || block_type == pcap::BLOCK_INTERFACE_STATISTICS
+                {
+                    let local = pcap::read_u32(&raw, 8, be) as usize;
+                    let Some(&global) = state.interfaces.get(local) else {
+                        return;
+                    };
+                    pcap::write_u32(&mut raw, 8, global, be);
+                }
+                if block_type == pcap::BLOCK_ENHANCED_PACKET {
+                    if let Some(c) = state.comment.take() {
+                        raw = pcap::with_packet_comment(&raw, be, &c).unwrap_or(raw);
+                    }
+                }
+            }
+            BlockKind::Other(_) => {}
+        }
+        self.pending.extend_from_slice(&raw);
+    }
+}
+
+impl MergedStream {
+    fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        if self.unrecorded_gaps == 0 || !events::human_stderr() {
+            return;
+        }
+        // Like the capture statistics, printed even without RUST_LOG: the file alone
+        // looks like one continuous capture.
+        eprintln!(
+            "kcap: WARNING: {} capture gap(s) are not marked in the pcap output; \
+             use --format pcapng to record them",
+            self.unrecorded_gaps
+        );
+    }
+}
+
+impl Read for MergedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.pending.len() {
+            self.pending.clear();
+            self.pos = 0;
+            match self.rx.recv() {
+                Ok(event) => self.handle(event),
+                // Every handle and source thread has finished.
+                Err(_) => {
+                    self.finish();
+                    return Ok(0);
+                }
+            }
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+
+    fn read_packets(bytes: Vec<u8>) -> Vec<Vec<u8>> {
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let mut out = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            if let Some(data) = block.packet_data() {
+                out.push(data.to_vec());
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn merges_pcap_streams() {
+        let a = testdata::pcap_stream(&[(1, b"a1"), (2, b"a2")]);
+        let b = testdata::pcap_stream(&[(1, b"b1")]);
+        let mut merged = merge_streams(vec![
+            ("a".to_string(), Cursor::new(a)),
+            ("b".to_string(), Cursor::new(b)),
+        ]);
+        let mut bytes = Vec::new();
+        merged.read_to_end(&mut bytes).unwrap();
+
+        let mut packets = read_packets(bytes);
+        packets.sort();
+        assert_eq!(
+            packets,
+            vec![b"a1".to_vec(), b"a2".to_vec(), b"b1".to_vec()]
+        );
+    }
+
+    #[test]
+    fn merges_pcapng_streams_with_distinct_interfaces() {
+        let a = testdata::pcapng_stream(&[(1, b"a1")]);
+        let b = testdata::pcapng_stream(&[(2, b"b1")]);
+        let mut merged = merge_streams(vec![
+            ("a".to_string(), Cursor::new(a)),
+            ("b".to_string(), Cursor::new(b)),
+        ]);
+        let mut bytes = Vec::new();
+        merged.read_to_end(&mut bytes).unwrap();
+
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        let mut interfaces = 0;
+        let mut packet_ifaces = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            match block.kind {
+                BlockKind::InterfaceDescription => interfaces += 1,
+                BlockKind::Packet(info) => packet_ifaces.push(info.interface),
+                _ => {}
+            }
+        }
+        packet_ifaces.sort();
+        assert_eq!(interfaces, 2);
+        assert_eq!(packet_ifaces, vec![0, 1]);
+    }
+
+    #[test]
+    fn skips_streams_that_fail_to_start() {
+        let a = testdata::pcap_stream(&[(1, b"a1")]);
+        let mut merged = merge_streams(vec![
+            ("a".to_string(), Cursor::new(a)),
+            ("bad".to_string(), Cursor::new(b"nope".to_vec())),
+        ]);
+        let mut bytes = Vec::new();
+        merged.read_to_end(&mut bytes).unwrap();
+        assert_eq!(read_packets(bytes), vec![b"a1".to_vec()]);
+    }
+
+    #[test]
+    fn late_source_gets_gap_comment() {
+        let (mut merged, handle) = merged();
+        let first = testdata::pcapng_stream(&[(1, b"a1")]);
+        handle.add_source("first".to_string(), Cursor::new(first), None);
+        let mut bytes = vec![0u8; 1];
+        // Wait until the first source has produced output before adding the next.
+        merged.read_exact(&mut bytes).unwrap();
+
+        let second = testdata::pcapng_stream(&[(2, b"b1")]);
+        handle.add_source(
+            "second".to_string(),
+            Cursor::new(second),
+            Some("resumed".to_string()),
+        );
+        drop(handle);
+        merged.read_to_end(&mut bytes).unwrap();
+
+        let needle = b"resumed";
+        assert!(bytes.windows(needle.len()).any(|w| w == needle));
+        assert_eq!(read_packets(bytes).len(), 2);
+    }
+
+    #[test]
+    fn late_pcap_source_counts_an_unrecorded_gap() {
+        let (mut merged, handle) = merged();
+        let first = testdata::pcap_stream(&[(1, b"a1")]);
+        handle.add_source("first".to_string(), Cursor::new(first), None);
+        let mut bytes = vec![0u8; 1];
+        merged.read_exact(&mut bytes).unwrap();
+
+        let second = testdata::pcap_stream(&[(2, b"b1")]);
+        handle.add_source(
+            "second".to_string(),
+            Cursor::new(second),
+            Some("resumed".to_string()),
+        );
+        drop(handle);
+        merged.read_to_end(&mut bytes).unwrap();
+
+        let needle = b"resumed";
+        assert!(!bytes.windows(needle.len()).any(|w| w == needle));
+        assert_eq!(read_packets(bytes).len(), 2);
+        assert_eq!(merged.unrecorded_gaps, 1);
+        assert!(merged.finished);
+    }
+}
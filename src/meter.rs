@@ -0,0 +1,289 @@
+use crate::pcap::{Block, BlockKind, CaptureReader};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Callback run for every block of a metered stream, on the metering thread.
+pub type Observer = Box<dyn FnMut(&Block) + Send>;
+
+#[derive(Debug, Default)]
+struct Counters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+/// Live packet and byte counts of the capture stream, shared with progress reporters.
+pub struct Meter {
+    counters: Arc<Counters>,
+    started: Instant,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+/// Counts of a metered stream at one point in time.
+pub struct Snapshot {
+    pub packets: u64,
+    /// Capture bytes (pcap/pcapng, after remote decompression).
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Snapshot {
+    /// Average packet rate since metering started.
+    /// Returns: f64 packets per second.
+    pub fn packets_per_sec(&self) -> f64 {
+        per_sec(self.packets, self.elapsed)
+    }
+
+    /// Average byte rate since metering started.
+    /// Returns: f64 bytes per second.
+    pub fn bytes_per_sec(&self) -> f64 {
+        per_sec(self.bytes, self.elapsed)
+    }
+}
+
+fn per_sec(count: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        count as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter::new()
+    }
+}
+
+impl Meter {
+    /// Starts a meter with zero counts and the clock running.
+    /// Returns: Meter shared handle; clones see the same counts.
+    pub fn new() -> Self {
+        Meter {
+            counters: Arc::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Current counts.
+    /// Returns: Snapshot packets, bytes and time since `new`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            packets: self.counters.packets.load(Ordering::Relaxed),
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Counts a capture stream while passing its bytes through unchanged.
+    /// Parameters: `stream` (R) pcap/pcapng bytes.
+    /// Parameters: `observers` (Vec<Observer>) called for every parsed block.
+    /// Returns: Metered<R> reader yielding exactly the bytes of `stream`.
+    pub fn wrap<R: Read>(&self, stream: R, observers: Vec<Observer>) -> Metered<R> {
+        // Parsing happens on a copy in another thread, so a stream kcap cannot parse is
+        // still written as-is and a slow observer only delays the copy.
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(256);
+        let counters = self.counters.clone();
+        let parser = thread::spawn(move || parse(rx, &counters, observers));
+        Metered {
+            inner: stream,
+            counters: self.counters.clone(),
+            tx: Some(tx),
+            parser: Some(parser),
+        }
+    }
+}
+
+/// Reader returned by `Meter::wrap`.
+pub struct Metered<R> {
+    inner: R,
+    counters: Arc<Counters>,
+    tx: Option<SyncSender<Vec<u8>>>,
+    parser: Option<JoinHandle<()>>,
+}
+
+impl<R: Read> Read for Metered<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counters.bytes.fetch_add(n as u64, Ordering::Relaxed);
+        if n == 0 {
+            self.tx = None;
+        } else if let Some(tx) = &self.tx {
+            // The parser hangs up on data it cannot parse; keep streaming regardless.
+            if tx.send(buf[..n].to_vec()).is_err() {
+                self.tx = None;
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl<R> Drop for Metered<R> {
+    fn drop(&mut self) {
+        // Let observers see the last blocks before callers read final counts.
+        self.tx = None;
+        if let Some(parser) = self.parser.take() {
+            let _ = parser.join();
+        }
+    }
+}
+
+fn parse(rx: Receiver<Vec<u8>>, counters: &Counters, mut observers: Vec<Observer>) {
+    let chunks = Chunks {
+        rx,
+        chunk: Vec::new(),
+        pos: 0,
+    };
+    let mut capture = match CaptureReader::new(chunks) {
+        Ok(capture) => capture,
+        Err(err) => {
+            debug!("not metering capture stream: {err:#}");
+            return;
+        }
+    };
+    loop {
+        match capture.next_block() {
+            Ok(Some(block)) => {
+                if matches!(block.kind, BlockKind::Packet(_)) {
+                    counters.packets.fetch_add(1, Ordering::Relaxed);
+                }
+                for observer in &mut observers {
+                    observer(&block);
+                }
+            }
+            Ok(None) => return,
+            Err(err) => {
+                // Typically a record cut short when the capture was stopped.
+                debug!("stopped metering capture stream: {err:#}");
+                return;
+            }
+        }
+    }
+}
+
+struct Chunks {
+    rx: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.rx.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// Background thread calling a function at a fixed interval until dropped.
+pub struct Ticker {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Ticker {
+    /// Starts ticking; the first call happens one `interval` from now.
+    /// Parameters: `interval` (Duration) time between calls.
+    /// Parameters: `tick` (F) function to call.
+    /// Returns: Ticker that stops when dropped.
+    pub fn every<F>(interval: Duration, mut tick: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                tick();
+            }
+        });
+        Ticker {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for Ticker {
+    fn drop(&mut self) {
+        self.stop = None;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    #[test]
+    fn counts_without_changing_the_stream() {
+        let capture = testdata::pcapng_stream(&[(1, b"one"), (2, b"two"), (3, b"three")]);
+        let meter = Meter::new();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = seen.clone();
+        let observer: Observer = Box::new(move |block: &Block| {
+            if let Some(data) = block.packet_data() {
+                record.lock().unwrap().push(data.to_vec());
+            }
+        });
+
+        let mut out = Vec::new();
+        meter
+            .wrap(Cursor::new(capture.clone()), vec![observer])
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, capture);
+        let snap = meter.snapshot();
+        assert_eq!(snap.packets, 3);
+        assert_eq!(snap.bytes, capture.len() as u64);
+        assert_eq!(seen.lock().unwrap()[2], b"three");
+    }
+
+    #[test]
+    fn passes_unparseable_bytes_through() {
+        let meter = Meter::new();
+        let mut out = Vec::new();
+        meter
+            .wrap(Cursor::new(b"not a capture".to_vec()), Vec::new())
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, b"not a capture");
+        assert_eq!(meter.snapshot().packets, 0);
+        assert_eq!(meter.snapshot().bytes, 13);
+    }
+
+    #[test]
+    fn ticker_stops_when_dropped() {
+        let ticks = Arc::new(AtomicU64::new(0));
+        let counted = ticks.clone();
+        let ticker = Ticker::every(Duration::from_millis(5), move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+        });
+        thread::sleep(Duration::from_millis(50));
+        drop(ticker);
+        let after = ticks.load(Ordering::Relaxed);
+        assert!(after > 0);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(ticks.load(Ordering::Relaxed), after);
+    }
+}
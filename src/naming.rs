@@ -0,0 +1,294 @@
+use crate::error::KcapError;
+use crate::output::{rotated_path, SinkSpec};
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Default `--output`: a new file per run instead of overwriting the last capture.
+pub const DEFAULT_TEMPLATE: &str = "capture-{ts}.pcap";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Values substituted into output name templates.
+pub struct NameVars {
+    /// Host, pod, service or selector being captured.
+    pub target: String,
+    pub ns: String,
+    pub pod: String,
+    pub node: String,
+    pub port: String,
+    /// UTC start time, `YYYYMMDD-HHMMSS`.
+    pub ts: String,
+}
+
+/// Expands `{target}`, `{ns}`, `{pod}`, `{node}`, `{ts}`, `{port}` and `{seq}`.
+/// Parameters: `template` (&str) file name template.
+/// Parameters: `vars` (&NameVars) substituted values.
+/// Parameters: `seq` (u32) value of `{seq}`.
+/// Returns: Result<String> expanded name or an error for unknown placeholders.
+pub fn expand(template: &str, vars: &NameVars, seq: u32) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            bail!("unclosed placeholder in output template {template:?}");
+        };
+        let value = match &rest[start + 1..start + len] {
+            "target" => vars.target.clone(),
+            "ns" => vars.ns.clone(),
+            "pod" => vars.pod.clone(),
+            "node" => vars.node.clone(),
+            "ts" => vars.ts.clone(),
+            "port" => vars.port.clone(),
+            "seq" => format!("{seq:03}"),
+            other => bail!("unknown placeholder {{{other}}} in output template {template:?}"),
+        };
+        // Values come from the cluster; keep them from adding path components.
+        out.extend(value.chars().map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        }));
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Turns `--output` (or a `--tee` sink) into the concrete sink to write.
+/// Parameters: `spec` (&str) sink description whose file path may be a template.
+/// Parameters: `vars` (&NameVars) template values.
+/// Parameters: `output_dir` (Option<&str>) directory for relative file names; created if missing.
+/// Parameters: `force` (bool) allow overwriting existing files.
+/// Returns: Result<String> sink description with the final path.
+pub fn resolve_sink(
+    spec: &str,
+    vars: &NameVars,
+    output_dir: Option<&str>,
+    force: bool,
+) -> Result<String> {
+    let (prefix, template) = match SinkSpec::parse(spec)? {
+        SinkSpec::File(_) => ("", spec),
+        SinkSpec::Rotate { .. } => {
+            // rotate:SIZE:PATH, with SIZE validated by the parse above.
+            let size_end = "rotate:".len() + spec["rotate:".len()..].find(':').unwrap_or(0) + 1;
+            spec.split_at(size_end)
+        }
+        _ => return Ok(spec.to_string()),
+    };
+    let rotating = !prefix.is_empty();
+
+    let mut seq = 1;
+    loop {
+        let mut path = expand(template, vars, seq)?;
+        if let Some(dir) = output_dir.filter(|_| Path::new(&path).is_relative()) {
+            fs::create_dir_all(dir).with_context(|| format!("failed to create {dir}"))?;
+            path = Path::new(dir).join(&path).to_string_lossy().to_string();
+        }
+        // Rotation writes numbered files; the first one tells whether a run was here before.
+        let first_file = if rotating {
+            rotated_path(&path, 1)
+        } else {
+            path.clone()
+        };
+        // Only regular files hold an earlier capture; FIFOs and devices are written as is.
+        if fs::metadata(&first_file).is_ok_and(|meta| !meta.is_file()) {
+            return Ok(format!("{prefix}{path}"));
+        }
+        // Creating the file claims the name, so a concurrent run cannot pick it as well.
+        match OpenOptions::new().write(true).create_new(true).open(&first_file) {
+            Ok(_) => return Ok(format!("{prefix}{path}")),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => {
+                return Err(KcapError::OutputWrite {
+                    output: first_file,
+                    detail: err.to_string(),
+                }
+                .into())
+            }
+        }
+        if template.contains("{seq}") && seq < 999 {
+            seq += 1;
+            continue;
+        }
+        if force {
+            return Ok(format!("{prefix}{path}"));
+        }
+        return Err(KcapError::OutputWrite {
+            output: first_file,
+            detail: "file already exists; use --force to overwrite it".to_string(),
+        }
+        .into());
+    }
+}
+
+/// Gives back a name `resolve_sink` claimed when the run failed before writing to it.
+/// Parameters: `spec` (&str) sink description returned by `resolve_sink`.
+pub fn release(spec: &str) {
+    let path = match SinkSpec::parse(spec) {
+        Ok(SinkSpec::File(path)) => path,
+        Ok(SinkSpec::Rotate { path, .. }) => rotated_path(&path, 1),
+        _ => return,
+    };
+    if fs::metadata(&path).is_ok_and(|meta| meta.is_file() && meta.len() == 0) {
+        let _ = fs::remove_file(&path);
+    }
+}
+
+/// Formats a time as `YYYYMMDD-HHMMSS` in UTC.
+/// Parameters: `time` (SystemTime) instant to format.
+/// Returns: String formatted timestamp.
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+    let (y, m, d) = civil_from_days(days as i64);
+    format!(
+        "{y:04}{m:02}{d:02}-{:02}{:02}{:02}",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Formats a time as RFC 3339 in UTC, e.g. `2024-03-01T12:02:03Z`.
+/// Parameters: `time` (SystemTime) instant to format.
+/// Returns: String formatted timestamp.
+pub fn rfc3339(time: SystemTime) -> String {
+    let compact = timestamp(time);
+    let (date, clock) = compact.split_at(compact.len() - 7);
+    let (y, md) = date.split_at(date.len() - 4);
+    format!(
+        "{y}-{}-{}T{}:{}:{}Z",
+        &md[..2],
+        &md[2..],
+        &clock[1..3],
+        &clock[3..5],
+        &clock[5..]
+    )
+}
+
+// Howard Hinnant's days-to-civil conversion.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    (y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn vars() -> NameVars {
+        NameVars {
+            target: "orders-6c9f".into(),
+            ns: "prod".into(),
+            pod: "orders-6c9f".into(),
+            node: "node-1".into(),
+            port: "8080".into(),
+            ts: "20240301-120000".into(),
+        }
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let name = expand("{ns}/{pod}@{node}-{port}-{ts}-{seq}.pcap", &vars(), 7).unwrap();
+        assert_eq!(
+            name,
+            "prod/orders-6c9f@node-1-8080-20240301-120000-007.pcap"
+        );
+        assert!(expand("{nope}.pcap", &vars(), 1).is_err());
+        assert!(expand("{ts.pcap", &vars(), 1).is_err());
+    }
+
+    #[test]
+    fn sanitizes_values() {
+        let v = NameVars {
+            target: "../etc/passwd".into(),
+            ..NameVars::default()
+        };
+        assert_eq!(
+            expand("{target}.pcap", &v, 1).unwrap(),
+            ".._etc_passwd.pcap"
+        );
+    }
+
+    #[test]
+    fn formats_utc_timestamps() {
+        let t = UNIX_EPOCH + Duration::from_secs(1_709_294_400 + 123);
+        assert_eq!(timestamp(t), "20240301-120203");
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        assert_eq!(rfc3339(t), "2024-03-01T12:02:03Z");
+    }
+
+    #[test]
+    fn refuses_to_overwrite_without_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_string_lossy().to_string();
+        fs::write(dir.path().join("prod.pcap"), b"old").unwrap();
+
+        assert!(resolve_sink("{ns}.pcap", &vars(), Some(&d), false).is_err());
+        let forced = resolve_sink("{ns}.pcap", &vars(), Some(&d), true).unwrap();
+        assert_eq!(Path::new(&forced), dir.path().join("prod.pcap"));
+        assert_eq!(resolve_sink("-", &vars(), Some(&d), false).unwrap(), "-");
+    }
+
+    #[test]
+    fn seq_picks_the_next_free_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_string_lossy().to_string();
+        fs::write(dir.path().join("cap-001.pcap"), b"old").unwrap();
+        let next = resolve_sink("cap-{seq}.pcap", &vars(), Some(&d), false).unwrap();
+        assert_eq!(Path::new(&next), dir.path().join("cap-002.pcap"));
+    }
+
+    #[test]
+    fn resolved_names_are_claimed() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_string_lossy().to_string();
+        let first = resolve_sink("cap-{seq}.pcap", &vars(), Some(&d), false).unwrap();
+        let second = resolve_sink("cap-{seq}.pcap", &vars(), Some(&d), false).unwrap();
+        assert_eq!(Path::new(&first), dir.path().join("cap-001.pcap"));
+        assert_eq!(Path::new(&second), dir.path().join("cap-002.pcap"));
+
+        let only = resolve_sink("{ns}.pcap", &vars(), Some(&d), false).unwrap();
+        assert!(resolve_sink("{ns}.pcap", &vars(), Some(&d), false).is_err());
+        release(&only);
+        assert!(!Path::new(&only).exists());
+        fs::write(&first, b"pcap").unwrap();
+        release(&first);
+        assert!(Path::new(&first).exists());
+    }
+
+    #[test]
+    fn rotation_checks_the_first_numbered_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let d = dir.path().to_string_lossy().to_string();
+        let spec = resolve_sink("rotate:10M:{pod}.pcap", &vars(), Some(&d), false).unwrap();
+        let path = dir.path().join("orders-6c9f.pcap");
+        assert_eq!(spec, format!("rotate:10M:{}", path.display()));
+
+        fs::write(dir.path().join("orders-6c9f-0001.pcap"), b"old").unwrap();
+        assert!(resolve_sink("rotate:10M:{pod}.pcap", &vars(), Some(&d), false).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn devices_are_not_existing_captures() {
+        assert_eq!(
+            resolve_sink("/dev/null", &vars(), None, false).unwrap(),
+            "/dev/null"
+        );
+    }
+}
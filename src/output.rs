@@ -1,32 +1,678 @@
-﻿use anyhow::{Context, Result};
-use std::fs::File;
+﻿use crate::cli::Compression;
+use crate::manifest::FileDigest;
+use crate::pcap::{BlockKind, CaptureReader, StreamHeaders};
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::{self, File, OpenOptions};
 use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
 
-/// Writes capture bytes to stdout or a file.
+// Blocks buffered per secondary sink before it is considered stuck and detached.
+const TEE_BACKLOG: usize = 4096;
+// Collectors that stop reading must not hold kcap open forever at exit.
+const TCP_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Destination for capture bytes, parsed from `--output` or `--tee`.
+pub enum SinkSpec {
+    /// `-`
+    Stdout,
+    /// A plain file path.
+    File(String),
+    /// `fifo:PATH`, a named pipe readers can attach to and leave.
+    Fifo(String),
+    /// `tcp:HOST:PORT`, a collector receiving the raw stream.
+    Tcp(String),
+    /// `rotate:SIZE:PATH`, numbered files of at most SIZE bytes each.
+    Rotate { path: String, max_bytes: u64 },
+}
+
+impl SinkSpec {
+    /// Parses a sink description.
+    /// Parameters: `spec` (&str) e.g. `-`, `out.pcap`, `fifo:/tmp/p`, `tcp:host:9000`, `rotate:100M:out.pcap`.
+    /// Returns: Result<SinkSpec> parsed sink.
+    pub fn parse(spec: &str) -> Result<SinkSpec> {
+        if spec == "-" {
+            return Ok(SinkSpec::Stdout);
+        }
+        if let Some(path) = spec.strip_prefix("fifo:") {
+            return Ok(SinkSpec::Fifo(path.to_string()));
+        }
+        if let Some(addr) = spec.strip_prefix("tcp:") {
+            if addr
+                .rsplit_once(':')
+                .is_none_or(|(h, p)| h.is_empty() || p.parse::<u16>().is_err())
+            {
+                bail!("invalid sink {spec:?}: expected tcp:HOST:PORT");
+            }
+            return Ok(SinkSpec::Tcp(addr.to_string()));
+        }
+        if let Some(rest) = spec.strip_prefix("rotate:") {
+            let Some((size, path)) = rest.split_once(':') else {
+                bail!("invalid sink {spec:?}: expected rotate:SIZE:PATH");
+            };
+            let max_bytes = parse_size(size).with_context(|| format!("invalid sink {spec:?}"))?;
+            return Ok(SinkSpec::Rotate {
+                path: path.to_string(),
+                max_bytes,
+            });
+        }
+        Ok(SinkSpec::File(spec.to_string()))
+    }
+
+    /// Compression implied by a `.gz` or `.zst` file name.
+    /// Returns: Option<Compression> None for other names and non-file sinks.
+    pub fn inferred_compression(&self) -> Option<Compression> {
+        match self {
+            SinkSpec::File(path) | SinkSpec::Rotate { path, .. } => compression_for(path),
+            _ => None,
+        }
+    }
+
+    /// Opens the sink; FIFOs are created here and TCP collectors are connected.
+    /// Parameters: `compress` (Option<Compression>) compression applied to the written bytes.
+    /// Returns: Result<Box<dyn Sink>> ready-to-use sink.
+    pub fn open(&self, compress: Option<Compression>) -> Result<Box<dyn Sink>> {
+        Ok(match self {
+            SinkSpec::Stdout => Box::new(WriterSink::new(io::stdout(), compress)?),
+            SinkSpec::File(path) => Box::new(WriterSink::new(
+                File::create(path).with_context(|| format!("failed to create {path}"))?,
+                compress,
+            )?),
+            // Live readers of a pipe expect a plain capture.
+            SinkSpec::Fifo(_) if compress.is_some() => bail!("FIFO output cannot be compressed"),
+            SinkSpec::Fifo(path) => Box::new(FifoSink::new(path)?),
+            SinkSpec::Tcp(addr) => {
+                let conn = TcpStream::connect(addr)
+                    .with_context(|| format!("failed to connect to collector {addr}"))?;
+                conn.set_write_timeout(Some(TCP_WRITE_TIMEOUT))?;
+                Box::new(WriterSink::new(conn, compress)?)
+            }
+            SinkSpec::Rotate { path, max_bytes } => {
+                Box::new(RotatingSink::new(path, *max_bytes, compress))
+            }
+        })
+    }
+}
+
+fn compression_for(path: &str) -> Option<Compression> {
+    if path.ends_with(".gz") {
+        Some(Compression::Gzip)
+    } else if path.ends_with(".zst") {
+        Some(Compression::Zstd)
+    } else {
+        None
+    }
+}
+
+/// Opens a capture file for reading, decompressing `.gz` and `.zst` files by name.
+/// Parameters: `path` (&str) capture file.
+/// Parameters: `compress` (Option<Compression>) compression it was written with; None infers it.
+/// Returns: Result<Box<dyn Read>> plain pcap/pcapng bytes.
+pub fn open_capture(path: &str, compress: Option<Compression>) -> Result<Box<dyn Read>> {
+    let file = File::open(path).with_context(|| format!("failed to open {path}"))?;
+    let file = io::BufReader::new(file);
+    Ok(match compress.or_else(|| compression_for(path)) {
+        None => Box::new(file),
+        Some(Compression::Gzip) => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        Some(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::new(file)?),
+    })
+}
+
+/// Byte writer that optionally compresses on the fly.
+pub enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Wraps a writer.
+    /// Parameters: `inner` (W) destination of the (compressed) bytes.
+    /// Parameters: `compress` (Option<Compression>) None writes bytes unchanged.
+    /// Returns: io::Result<Encoder<W>> ready-to-use writer.
+    pub fn new(inner: W, compress: Option<Compression>) -> io::Result<Self> {
+        Ok(match compress {
+            None => Encoder::Plain(inner),
+            Some(Compression::Gzip) => Encoder::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            )),
+            Some(Compression::Zstd) => Encoder::Zstd(zstd::stream::write::Encoder::new(inner, 0)?),
+        })
+    }
+
+    /// Writes the compression trailer and flushes; the file is unreadable without it.
+    /// Returns: io::Result<()> indicating success or failure.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(e) => {
+                e.try_finish()?;
+                e.get_mut().flush()
+            }
+            Encoder::Zstd(e) => {
+                e.do_finish()?;
+                e.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(w) => w.write(buf),
+            Encoder::Gzip(e) => e.write(buf),
+            Encoder::Zstd(e) => e.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(w) => w.flush(),
+            Encoder::Gzip(e) => e.flush(),
+            Encoder::Zstd(e) => e.flush(),
+        }
+    }
+}
+
+fn parse_size(size: &str) -> Result<u64> {
+    let (digits, unit) = match size.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => size.split_at(i),
+        None => (size, ""),
+    };
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("invalid size {size:?}"))?;
+    let scale = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => bail!("invalid size unit in {size:?}"),
+    };
+    if value == 0 {
+        bail!("size must be greater than zero");
+    }
+    Ok(value * scale)
+}
+
+/// Block-aware capture destination.
+pub trait Sink: Send {
+    /// Called once with the stream headers before any block.
+    fn start(&mut self, headers: &[u8]) -> Result<()>;
+
+    /// Writes one record/block; `headers` are the stream headers preceding it.
+    fn write_block(&mut self, raw: &[u8], headers: &[u8]) -> Result<()>;
+
+    /// Flushes buffered output at the end of the stream.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Sink over any byte writer (stdout, file, TCP socket, viewer stdin).
+pub struct WriterSink<W: Write>(pub Encoder<W>);
+
+impl<W: Write> WriterSink<W> {
+    /// Wraps a writer, compressing if asked to.
+    /// Parameters: `inner` (W) destination writer.
+    /// Parameters: `compress` (Option<Compression>) compression to apply.
+    /// Returns: Result<WriterSink<W>> ready-to-use sink.
+    pub fn new(inner: W, compress: Option<Compression>) -> Result<Self> {
+        Ok(WriterSink(Encoder::new(inner, compress)?))
+    }
+}
+
+impl<W: Write + Send> Sink for WriterSink<W> {
+    fn start(&mut self, headers: &[u8]) -> Result<()> {
+        Ok(self.0.write_all(headers)?)
+    }
+
+    fn write_block(&mut self, raw: &[u8], _headers: &[u8]) -> Result<()> {
+        Ok(self.0.write_all(raw)?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        Ok(self.0.finish()?)
+    }
+}
+
+/// Secondary sink opened on its own thread, so slow connects never delay the capture.
+pub struct Secondary {
+    label: String,
+    open: Box<dyn FnOnce() -> Result<Box<dyn Sink>> + Send>,
+}
+
+impl Secondary {
+    /// Builds a secondary sink from a `--tee` description.
+    /// Parameters: `spec` (&str) sink description, as for `SinkSpec::parse`.
+    /// Returns: Result<Secondary> sink opened once the capture starts.
+    pub fn parse(spec: &str) -> Result<Secondary> {
+        let parsed = SinkSpec::parse(spec)?;
+        Ok(Secondary {
+            label: spec.to_string(),
+            open: Box::new(move || parsed.open(parsed.inferred_compression())),
+        })
+    }
+
+    /// Wraps an already open writer, e.g. a viewer's stdin.
+    pub fn writer<W: Write + Send + 'static>(label: &str, writer: W) -> Secondary {
+        Secondary {
+            label: label.to_string(),
+            open: Box::new(move || {
+                Ok(Box::new(WriterSink(Encoder::Plain(writer))) as Box<dyn Sink>)
+            }),
+        }
+    }
+}
+
+/// Writes capture bytes to stdout, a file, or any other sink.
 /// Parameters: `reader` (R) readable stream of capture bytes.
-/// Parameters: `output` (&str) path or "-" for stdout.
+/// Parameters: `output` (&str) sink description; see `SinkSpec::parse`.
 /// Returns: Result<()> indicating success or failure.
-pub fn write_stream<R: Read>(mut reader: R, output: &str) -> Result<()> {
-    // Stream capture bytes directly to stdout or a file.
-    if output == "-" {
-        // Allow piping to other tools without an intermediate file.
-        let mut stdout = io::stdout().lock();
-        io::copy(&mut reader, &mut stdout).context("failed to write to stdout")?;
-        stdout.flush().ok();
-        return Ok(());
-    }
-
-    // Create or truncate the output file for a single session.
-    let mut file = File::create(output).with_context(|| format!("failed to create {output}"))?;
-    io::copy(&mut reader, &mut file).context("failed to write to file")?;
-    file.flush().ok();
+pub fn write_stream<R: Read>(reader: R, output: &str) -> Result<()> {
+    write_tee(reader, output, None, Vec::new())
+}
+
+/// Writes capture bytes to a primary sink and copies them to secondary sinks.
+/// Parameters: `reader` (R) readable stream of capture bytes.
+/// Parameters: `output` (&str) primary sink; its errors fail the run.
+/// Parameters: `compress` (Option<Compression>) primary compression; None infers it from the name.
+/// Parameters: `tee` (Vec<Secondary>) best-effort sinks; slow or failing ones are detached.
+/// Returns: Result<()> result of the primary sink.
+pub fn write_tee<R: Read>(
+    reader: R,
+    output: &str,
+    compress: Option<Compression>,
+    tee: Vec<Secondary>,
+) -> Result<()> {
+    write_recorded(reader, output, compress, tee, None)
+}
+
+/// Writes capture bytes like `write_tee`, summarizing a file output as it is written.
+/// Parameters: `reader` (R) readable stream of capture bytes.
+/// Parameters: `output` (&str) primary sink; its errors fail the run.
+/// Parameters: `compress` (Option<Compression>) primary compression; None infers it from the name.
+/// Parameters: `tee` (Vec<Secondary>) best-effort sinks; slow or failing ones are detached.
+/// Parameters: `digest` (Option<&FileDigest>) fed with what reaches a plain file output.
+/// Returns: Result<()> result of the primary sink.
+pub fn write_recorded<R: Read>(
+    mut reader: R,
+    output: &str,
+    compress: Option<Compression>,
+    tee: Vec<Secondary>,
+    digest: Option<&FileDigest>,
+) -> Result<()> {
+    let primary = SinkSpec::parse(output)?;
+    let compress = compress.or(primary.inferred_compression());
+    let digest = digest.filter(|_| matches!(primary, SinkSpec::File(_)));
+    // Counting packets for the digest needs the block loop below.
+    if tee.is_empty() && digest.is_none() {
+        // Plain destinations take the bytes verbatim, without parsing the capture.
+        match &primary {
+            SinkSpec::Stdout => {
+                // Allow piping to other tools without an intermediate file.
+                let mut stdout = Encoder::new(io::stdout().lock(), compress)?;
+                io::copy(&mut reader, &mut stdout).context("failed to write to stdout")?;
+                stdout.finish().context("failed to write to stdout")?;
+                return Ok(());
+            }
+            SinkSpec::File(path) => {
+                // Create or truncate the output file for a single session.
+                let file =
+                    File::create(path).with_context(|| format!("failed to create {path}"))?;
+                let mut file = Encoder::new(file, compress)?;
+                io::copy(&mut reader, &mut file).context("failed to write to file")?;
+                file.finish().context("failed to write to file")?;
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+
+    let mut capture = CaptureReader::new(reader)?;
+    let mut headers = StreamHeaders::new(capture.header());
+    let mut shared: Arc<[u8]> = Arc::from(headers.bytes());
+    let mut taps: Vec<Tap> = tee
+        .into_iter()
+        .map(|s| Tap::spawn(s, shared.clone()))
+        .collect();
+
+    let mut sink: Box<dyn Sink> = match (&primary, digest) {
+        (SinkSpec::File(path), Some(digest)) => {
+            let file = File::create(path).with_context(|| format!("failed to create {path}"))?;
+            Box::new(WriterSink::new(digest.writer(file), compress)?)
+        }
+        _ => primary.open(compress)?,
+    };
+    sink.start(headers.bytes())?;
+    if let Some(digest) = digest {
+        digest.captured(headers.bytes().len(), false);
+    }
+    while let Some(block) = capture.next_block()? {
+        let raw: Arc<[u8]> = Arc::from(block.raw.as_slice());
+        for tap in &mut taps {
+            tap.send(raw.clone(), shared.clone());
+        }
+        sink.write_block(&block.raw, headers.bytes())?;
+        if let Some(digest) = digest {
+            digest.captured(block.raw.len(), matches!(block.kind, BlockKind::Packet(_)));
+        }
+        if block.kind.is_stream_metadata() {
+            headers.observe(&block);
+            shared = Arc::from(headers.bytes());
+        }
+    }
+    sink.finish()?;
+    drop(sink);
+
+    for tap in taps {
+        tap.finish();
+    }
     Ok(())
 }
 
+// One block plus the stream headers preceding it.
+type TapBlock = (Arc<[u8]>, Arc<[u8]>);
+
+/// Secondary sink fed through a bounded channel from the capture loop.
+struct Tap {
+    label: String,
+    tx: Option<SyncSender<TapBlock>>,
+    thread: JoinHandle<()>,
+    detached: bool,
+}
+
+impl Tap {
+    fn spawn(secondary: Secondary, headers: Arc<[u8]>) -> Tap {
+        let (tx, rx) = mpsc::sync_channel(TEE_BACKLOG);
+        let label = secondary.label.clone();
+        let thread = thread::spawn(move || {
+            if let Err(err) = run_tap(secondary.open, &headers, rx) {
+                warn!(sink = %secondary.label, "secondary output detached: {err:#}");
+            }
+        });
+        Tap {
+            label,
+            tx: Some(tx),
+            thread,
+            detached: false,
+        }
+    }
+
+    fn send(&mut self, raw: Arc<[u8]>, headers: Arc<[u8]>) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        match tx.try_send((raw, headers)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                // Never stall the primary output for a secondary one.
+                warn!(sink = %self.label, "secondary output fell behind; detaching it");
+                self.tx = None;
+                self.detached = true;
+            }
+            Err(TrySendError::Disconnected(_)) => self.tx = None,
+        }
+    }
+
+    fn finish(self) {
+        drop(self.tx);
+        // A stuck sink is left behind rather than holding up the exit.
+        if !self.detached {
+            let _ = self.thread.join();
+        }
+    }
+}
+
+fn run_tap(
+    open: Box<dyn FnOnce() -> Result<Box<dyn Sink>> + Send>,
+    headers: &[u8],
+    rx: Receiver<TapBlock>,
+) -> Result<()> {
+    let mut sink = open()?;
+    sink.start(headers)?;
+    for (raw, headers) in rx {
+        sink.write_block(&raw, &headers)?;
+    }
+    sink.finish()
+}
+
+/// Starts Wireshark reading a capture from stdin, falling back to tshark.
+/// Returns: Result<Child> viewer process with a piped stdin.
+pub fn spawn_viewer() -> Result<Child> {
+    // tshark keeps working in sessions without a display.
+    let candidates: [(&str, &[&str]); 2] =
+        [("wireshark", &["-k", "-i", "-"]), ("tshark", &["-i", "-"])];
+    for (program, args) in candidates {
+        match Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => {
+                info!(viewer = program, "started live viewer");
+                return Ok(child);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("failed to start {program}")),
+        }
+    }
+    bail!("--wireshark needs wireshark or tshark on PATH");
+}
+
+/// Numbered files (`name-0001.pcap`, ...) each starting with the stream headers.
+/// With compression, the size limit applies to the uncompressed capture.
+struct RotatingSink {
+    path: String,
+    max_bytes: u64,
+    compress: Option<Compression>,
+    index: u32,
+    file: Option<Encoder<File>>,
+    written: u64,
+}
+
+impl RotatingSink {
+    fn new(path: &str, max_bytes: u64, compress: Option<Compression>) -> Self {
+        RotatingSink {
+            path: path.to_string(),
+            max_bytes,
+            compress,
+            index: 0,
+            file: None,
+            written: 0,
+        }
+    }
+
+    fn rotate(&mut self, headers: &[u8]) -> Result<()> {
+        self.finish()?;
+        self.index += 1;
+        let path = rotated_path(&self.path, self.index);
+        let file = File::create(&path).with_context(|| format!("failed to create {path}"))?;
+        let mut file = Encoder::new(file, self.compress)?;
+        file.write_all(headers)?;
+        info!(file = %path, "writing capture file");
+        crate::events::emit(&crate::events::Event::Rotated { file: &path });
+        self.file = Some(file);
+        self.written = headers.len() as u64;
+        Ok(())
+    }
+}
+
+impl Sink for RotatingSink {
+    fn start(&mut self, headers: &[u8]) -> Result<()> {
+        self.rotate(headers)
+    }
+
+    fn write_block(&mut self, raw: &[u8], headers: &[u8]) -> Result<()> {
+        // Rotate only once the file holds packets, so a huge block cannot loop forever.
+        let has_packets = self.written > headers.len() as u64;
+        if has_packets && self.written + raw.len() as u64 > self.max_bytes {
+            self.rotate(headers)?;
+        }
+        let file = self.file.as_mut().context("rotating output not started")?;
+        file.write_all(raw)?;
+        self.written += raw.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(file) = &mut self.file {
+            file.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn rotated_path(path: &str, index: u32) -> String {
+    // Keep `.pcap.gz` together: `cap-0001.pcap.gz`, not `cap.pcap-0001.gz`.
+    for suffix in [".gz", ".zst"] {
+        if let Some(base) = path.strip_suffix(suffix) {
+            return format!("{}{suffix}", rotated_path(base, index));
+        }
+    }
+    let p = Path::new(path);
+    let stem = p
+        .file_stem()
+        .map_or_else(|| path.into(), |s| s.to_string_lossy());
+    let name = match p.extension() {
+        Some(ext) => format!("{stem}-{index:04}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{index:04}"),
+    };
+    match p.parent().filter(|d| !d.as_os_str().is_empty()) {
+        Some(dir) => dir.join(name).to_string_lossy().to_string(),
+        None => name,
+    }
+}
+
+/// Named pipe that keeps the capture flowing while no reader is attached.
+struct FifoSink {
+    path: String,
+    created: bool,
+    file: Option<File>,
+    opening: Option<Receiver<io::Result<File>>>,
+}
+
+impl FifoSink {
+    fn new(path: &str) -> Result<Self> {
+        let created = ensure_fifo(path)?;
+        let mut sink = FifoSink {
+            path: path.to_string(),
+            created,
+            file: None,
+            opening: None,
+        };
+        info!(fifo = path, "waiting for a reader on the FIFO");
+        sink.wait_for_reader();
+        Ok(sink)
+    }
+
+    fn wait_for_reader(&mut self) {
+        // Opening a FIFO for writing blocks until a reader shows up.
+        let (tx, rx) = mpsc::sync_channel(1);
+        let path = self.path.clone();
+        thread::spawn(move || {
+            let _ = tx.send(OpenOptions::new().write(true).open(path));
+        });
+        self.file = None;
+        self.opening = Some(rx);
+    }
+
+    fn attach(&mut self, headers: &[u8]) -> Result<()> {
+        let Some(rx) = &self.opening else {
+            return Ok(());
+        };
+        let Ok(opened) = rx.try_recv() else {
+            return Ok(());
+        };
+        self.opening = None;
+        let mut file = opened.with_context(|| format!("failed to open FIFO {}", self.path))?;
+        info!(fifo = %self.path, "FIFO reader attached");
+        match file.write_all(headers) {
+            Ok(()) => self.file = Some(file),
+            Err(err) => self.handle_error(err)?,
+        }
+        Ok(())
+    }
+
+    fn handle_error(&mut self, err: io::Error) -> Result<()> {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            return Err(err).with_context(|| format!("failed to write FIFO {}", self.path));
+        }
+        info!(fifo = %self.path, "FIFO reader went away; waiting for a new one");
+        self.wait_for_reader();
+        Ok(())
+    }
+}
+
+impl Sink for FifoSink {
+    fn start(&mut self, _headers: &[u8]) -> Result<()> {
+        // Headers are sent whenever a reader attaches.
+        Ok(())
+    }
+
+    fn write_block(&mut self, raw: &[u8], headers: &[u8]) -> Result<()> {
+        // A reader that attaches now starts with the headers, then this block.
+        self.attach(headers)?;
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+        match file.write_all(raw) {
+            Ok(()) => Ok(()),
+            Err(err) => self.handle_error(err),
+        }
+    }
+}
+
+impl Drop for FifoSink {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn ensure_fifo(path: &str) -> Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match fs::metadata(path) {
+        Ok(meta) if meta.file_type().is_fifo() => Ok(false),
+        Ok(_) => bail!("{path} exists and is not a FIFO"),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let status = Command::new("mkfifo")
+                .arg(path)
+                .status()
+                .context("failed to run mkfifo")?;
+            if !status.success() {
+                bail!("mkfifo {path} failed ({status})");
+            }
+            Ok(true)
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to inspect {path}")),
+    }
+}
+
+#[cfg(not(unix))]
+fn ensure_fifo(_path: &str) -> Result<bool> {
+    bail!("FIFO output is only supported on Unix");
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::pcap::testdata;
     use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::sync::Mutex;
 
     #[test]
     fn write_stream_to_file() {
@@ -39,4 +685,285 @@ mod tests {
         File::open(path).unwrap().read_to_string(&mut content).unwrap();
         assert_eq!(content, "test-data");
     }
+
+    #[test]
+    fn parses_sink_specs() {
+        assert_eq!(SinkSpec::parse("-").unwrap(), SinkSpec::Stdout);
+        assert_eq!(
+            SinkSpec::parse("out.pcap").unwrap(),
+            SinkSpec::File("out.pcap".to_string())
+        );
+        assert_eq!(
+            SinkSpec::parse("fifo:/tmp/p").unwrap(),
+            SinkSpec::Fifo("/tmp/p".to_string())
+        );
+        assert_eq!(
+            SinkSpec::parse("tcp:collector:9000").unwrap(),
+            SinkSpec::Tcp("collector:9000".to_string())
+        );
+        assert_eq!(
+            SinkSpec::parse("rotate:2M:/data/cap.pcap").unwrap(),
+            SinkSpec::Rotate {
+                path: "/data/cap.pcap".to_string(),
+                max_bytes: 2 << 20
+            }
+        );
+        assert!(SinkSpec::parse("tcp:collector").is_err());
+        assert!(SinkSpec::parse("rotate:0:/x.pcap").is_err());
+        assert!(SinkSpec::parse("rotate:5Q:/x.pcap").is_err());
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct BrokenViewer;
+
+    impl Write for BrokenViewer {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Writer that blocks until the test releases it.
+    struct StuckWriter(Arc<Mutex<()>>);
+
+    impl Write for StuckWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _held = self.0.lock().unwrap();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn secondary_writer_gets_a_copy_of_the_recording() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"a1"), (2, b"a2")]);
+        let viewer = SharedBuf::default();
+        let tee = vec![Secondary::writer("viewer", viewer.clone())];
+        write_tee(Cursor::new(capture.clone()), &path, None, tee).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), capture);
+        assert_eq!(*viewer.0.lock().unwrap(), capture);
+    }
+
+    #[test]
+    fn failing_secondary_does_not_stop_recording() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"a1")]);
+        let tee = vec![
+            Secondary::writer("viewer", BrokenViewer),
+            Secondary::parse("tcp:127.0.0.1:1").unwrap(),
+        ];
+        write_tee(Cursor::new(capture.clone()), &path, None, tee).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), capture);
+    }
+
+    #[test]
+    fn stuck_secondary_is_detached() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+        let packets: Vec<(u32, &[u8])> = (0..TEE_BACKLOG as u32 + 100)
+            .map(|i| (i, &b"p"[..]))
+            .collect();
+        let capture = testdata::pcap_stream(&packets);
+
+        let gate = Arc::new(Mutex::new(()));
+        let held = gate.lock().unwrap();
+        let tee = vec![Secondary::writer("stuck", StuckWriter(gate.clone()))];
+        write_tee(Cursor::new(capture.clone()), &path, None, tee).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), capture);
+        drop(held);
+    }
+
+    #[test]
+    fn tees_to_tcp_collector() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let collector = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut got = Vec::new();
+            conn.read_to_end(&mut got).unwrap();
+            got
+        });
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+        let capture = testdata::pcapng_stream(&[(1, b"b1"), (2, b"b2")]);
+        let tee = vec![Secondary::parse(&format!("tcp:{addr}")).unwrap()];
+        write_tee(Cursor::new(capture.clone()), &path, None, tee).unwrap();
+        assert_eq!(collector.join().unwrap(), capture);
+        assert_eq!(fs::read(&path).unwrap(), capture);
+    }
+
+    #[test]
+    fn rotates_files_with_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("cap.pcap").to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"one"), (2, b"two"), (3, b"six")]);
+        // Header (24) plus two records (19 each) fit; the third starts a new file.
+        let spec = format!("rotate:{}:{base}", 24 + 2 * 19);
+        write_stream(Cursor::new(capture.clone()), &spec).unwrap();
+
+        let first = fs::read(dir.path().join("cap-0001.pcap")).unwrap();
+        let second = fs::read(dir.path().join("cap-0002.pcap")).unwrap();
+        assert_eq!(first, capture[..24 + 2 * 19]);
+        assert_eq!(second, [&capture[..24], &capture[24 + 2 * 19..]].concat());
+    }
+
+    #[test]
+    fn compresses_by_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let capture = testdata::pcap_stream(&[(1, b"one"), (2, b"two")]);
+
+        let gz = dir.path().join("cap.pcap.gz").to_string_lossy().to_string();
+        write_stream(Cursor::new(capture.clone()), &gz).unwrap();
+        let mut plain = Vec::new();
+        flate2::read::GzDecoder::new(File::open(&gz).unwrap())
+            .read_to_end(&mut plain)
+            .unwrap();
+        assert_eq!(plain, capture);
+
+        let zst = dir
+            .path()
+            .join("cap.pcap.zst")
+            .to_string_lossy()
+            .to_string();
+        write_stream(Cursor::new(capture.clone()), &zst).unwrap();
+        assert_eq!(
+            zstd::decode_all(File::open(&zst).unwrap()).unwrap(),
+            capture
+        );
+    }
+
+    #[test]
+    fn explicit_compression_applies_to_tee_runs() {
+        let tmp = tempfile::NamedTempFile::new().unwrap();
+        let path = tmp.path().to_string_lossy().to_string();
+        let capture = testdata::pcapng_stream(&[(1, b"b1"), (2, b"b2")]);
+        let viewer = SharedBuf::default();
+        let tee = vec![Secondary::writer("viewer", viewer.clone())];
+        write_tee(
+            Cursor::new(capture.clone()),
+            &path,
+            Some(Compression::Zstd),
+            tee,
+        )
+        .unwrap();
+        assert_eq!(
+            zstd::decode_all(File::open(&path).unwrap()).unwrap(),
+            capture
+        );
+        assert_eq!(*viewer.0.lock().unwrap(), capture);
+    }
+
+    #[test]
+    fn rotated_compressed_files_keep_their_suffix() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("cap.pcap.gz").to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"one"), (2, b"two"), (3, b"six")]);
+        let spec = format!("rotate:{}:{base}", 24 + 2 * 19);
+        write_stream(Cursor::new(capture.clone()), &spec).unwrap();
+
+        let mut second = Vec::new();
+        flate2::read::GzDecoder::new(File::open(dir.path().join("cap-0002.pcap.gz")).unwrap())
+            .read_to_end(&mut second)
+            .unwrap();
+        assert_eq!(second, [&capture[..24], &capture[24 + 2 * 19..]].concat());
+    }
+
+    #[test]
+    fn fifo_rejects_compression() {
+        assert!(SinkSpec::Fifo("/tmp/kcap-unused".into())
+            .open(Some(Compression::Gzip))
+            .is_err());
+    }
+
+    /// Capture source fed chunk by chunk from the test.
+    struct ChanReader {
+        rx: Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    impl Read for ChanReader {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.buf.is_empty() {
+                match self.rx.recv() {
+                    Ok(chunk) => self.buf = chunk,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = out.len().min(self.buf.len());
+            out[..n].copy_from_slice(&self.buf[..n]);
+            self.buf.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fifo_replays_header_to_each_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("live.pcap").to_string_lossy().to_string();
+        let capture = testdata::pcap_stream(&[(1, b"one"), (2, b"two"), (3, b"three")]);
+        // 24-byte pcap header followed by 16-byte record headers.
+        let (header, rest) = capture.split_at(24);
+        let (one, rest) = rest.split_at(16 + 3);
+        let (two, three) = rest.split_at(16 + 3);
+
+        let (tx, rx) = mpsc::channel();
+        let target = format!("fifo:{path}");
+        let writer = thread::spawn(move || {
+            write_stream(
+                ChanReader {
+                    rx,
+                    buf: Vec::new(),
+                },
+                &target,
+            )
+        });
+        tx.send(header.to_vec()).unwrap();
+        while fs::metadata(&path).is_err() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let settle = || thread::sleep(Duration::from_millis(150));
+        let mut first = File::open(&path).unwrap();
+        settle();
+        tx.send(one.to_vec()).unwrap();
+        let mut got = vec![0u8; header.len() + one.len()];
+        first.read_exact(&mut got).unwrap();
+        assert_eq!(got, [header, one].concat());
+        drop(first);
+
+        // Written while nobody listens: the writer notices and waits for a new reader.
+        settle();
+        tx.send(two.to_vec()).unwrap();
+        settle();
+        let mut second = File::open(&path).unwrap();
+        settle();
+        tx.send(three.to_vec()).unwrap();
+        drop(tx);
+        let mut got = Vec::new();
+        second.read_to_end(&mut got).unwrap();
+        assert_eq!(got, [header, three].concat());
+
+        writer.join().unwrap().unwrap();
+        assert!(fs::metadata(&path).is_err(), "kcap-created FIFO is removed");
+    }
 }
@@ -0,0 +1,796 @@
+use anyhow::{bail, Context, Result};
+use std::io::{self, Read};
+use std::net::IpAddr;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+/// pcapng block type of the Section Header Block.
+pub const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+/// pcapng block type of the Interface Description Block.
+pub const BLOCK_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+/// pcapng block type of the obsolete Packet Block.
+pub const BLOCK_PACKET: u32 = 0x0000_0002;
+/// pcapng block type of the Simple Packet Block.
+pub const BLOCK_SIMPLE_PACKET: u32 = 0x0000_0003;
+/// pcapng block type of the Name Resolution Block.
+pub const BLOCK_NAME_RESOLUTION: u32 = 0x0000_0004;
+/// pcapng block type of the Interface Statistics Block.
+pub const BLOCK_INTERFACE_STATISTICS: u32 = 0x0000_0005;
+/// pcapng block type of the Enhanced Packet Block.
+pub const BLOCK_ENHANCED_PACKET: u32 = 0x0000_0006;
+/// pcapng block type of the Decryption Secrets Block.
+pub const BLOCK_DECRYPTION_SECRETS: u32 = 0x0000_000A;
+/// Decryption Secrets Block payload type of an NSS/SSLKEYLOGFILE TLS key log.
+pub const SECRETS_TLS_KEY_LOG: u32 = 0x544c_534b;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Container format detected from the first bytes of a capture stream.
+pub enum StreamFormat {
+    Pcap,
+    Pcapng,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Decoded metadata of a single captured packet.
+pub struct PacketInfo {
+    pub interface: u32,
+    pub linktype: u32,
+    pub ts_nanos: u64,
+    pub orig_len: u32,
+    /// Byte range of the packet data inside `Block::raw`.
+    pub data: std::ops::Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Classification of a block read from a capture stream.
+pub enum BlockKind {
+    Packet(PacketInfo),
+    SectionHeader,
+    InterfaceDescription,
+    Other(u32),
+}
+
+#[derive(Debug, Clone)]
+/// One pcap record or pcapng block, kept as raw bytes for lossless forwarding.
+pub struct Block {
+    pub kind: BlockKind,
+    pub raw: Vec<u8>,
+}
+
+impl Block {
+    /// Returns the captured bytes of a packet block, or None for other blocks.
+    /// Returns: Option<&[u8]> packet data.
+    pub fn packet_data(&self) -> Option<&[u8]> {
+        match &self.kind {
+            BlockKind::Packet(info) => self.raw.get(info.data.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl BlockKind {
+    /// Returns whether a reader joining mid-stream needs this block before any packet.
+    pub fn is_stream_metadata(&self) -> bool {
+        match self {
+            BlockKind::SectionHeader | BlockKind::InterfaceDescription => true,
+            BlockKind::Other(t) => *t != BLOCK_INTERFACE_STATISTICS,
+            BlockKind::Packet(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Header bytes a reader attaching mid-stream must see first: the pcap header, or the
+/// current pcapng section header followed by its interface and metadata blocks.
+pub struct StreamHeaders {
+    bytes: Vec<u8>,
+}
+
+impl StreamHeaders {
+    /// Starts from the stream header returned by `CaptureReader::header`.
+    pub fn new(header: &[u8]) -> Self {
+        StreamHeaders {
+            bytes: header.to_vec(),
+        }
+    }
+
+    /// Records a block read after the stream header.
+    pub fn observe(&mut self, block: &Block) {
+        match block.kind {
+            BlockKind::SectionHeader => self.bytes = block.raw.clone(),
+            ref kind if kind.is_stream_metadata() => self.bytes.extend_from_slice(&block.raw),
+            _ => {}
+        }
+    }
+
+    /// Returns the bytes to replay to a new reader.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Interface {
+    linktype: u32,
+    // Timestamp units per second, from if_tsresol.
+    units_per_sec: u64,
+}
+
+/// Incremental reader for pcap and pcapng capture streams.
+pub struct CaptureReader<R> {
+    reader: R,
+    format: StreamFormat,
+    big_endian: bool,
+    nanos: bool,
+    linktype: u32,
+    header: Vec<u8>,
+    interfaces: Vec<Interface>,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads the file header and detects the capture format.
+    /// Parameters: `reader` (R) source of capture bytes.
+    /// Returns: Result<CaptureReader<R>> ready to yield blocks.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("capture stream ended before its header")?;
+
+        let le = u32::from_le_bytes(magic);
+        let be = u32::from_be_bytes(magic);
+        if le == BLOCK_SECTION_HEADER {
+            let mut this = Self {
+                reader,
+                format: StreamFormat::Pcapng,
+                big_endian: false,
+                nanos: false,
+                linktype: 0,
+                header: Vec::new(),
+                interfaces: Vec::new(),
+            };
+            let shb = this.read_ng_block_after_type(magic)?;
+            this.header = shb;
+            return Ok(this);
+        }
+
+        let (big_endian, nanos) = match (le, be) {
+            (PCAP_MAGIC_MICROS, _) => (false, false),
+            (PCAP_MAGIC_NANOS, _) => (false, true),
+            (_, PCAP_MAGIC_MICROS) => (true, false),
+            (_, PCAP_MAGIC_NANOS) => (true, true),
+            _ => bail!("not a pcap or pcapng stream (magic {le:#010x})"),
+        };
+        let mut header = vec![0u8; 24];
+        header[..4].copy_from_slice(&magic);
+        reader
+            .read_exact(&mut header[4..])
+            .context("truncated pcap header")?;
+        let linktype = read_u32(&header, 20, big_endian);
+        Ok(Self {
+            reader,
+            format: StreamFormat::Pcap,
+            big_endian,
+            nanos,
+            linktype,
+            header,
+            interfaces: Vec::new(),
+        })
+    }
+
+    /// Returns the detected container format.
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Returns the raw pcap global header or the first pcapng Section Header Block.
+    pub fn header(&self) -> &[u8] {
+        &self.header
+    }
+
+    /// Returns whether multi-byte fields in the stream are big-endian.
+    pub fn big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    /// Returns the pcap link type, or the first interface's link type for pcapng.
+    pub fn linktype(&self) -> u32 {
+        match self.format {
+            StreamFormat::Pcap => self.linktype,
+            StreamFormat::Pcapng => self.interfaces.first().map_or(0, |i| i.linktype),
+        }
+    }
+
+    /// Reads the next record or block from the stream.
+    /// Returns: Result<Option<Block>> next block, or None at a clean end of stream.
+    pub fn next_block(&mut self) -> Result<Option<Block>> {
+        match self.format {
+            StreamFormat::Pcap => self.next_pcap_record(),
+            StreamFormat::Pcapng => self.next_ng_block(),
+        }
+    }
+
+    fn next_pcap_record(&mut self) -> Result<Option<Block>> {
+        let mut rec = vec![0u8; 16];
+        if !read_exact_or_eof(&mut self.reader, &mut rec)? {
+            return Ok(None);
+        }
+        let ts_sec = read_u32(&rec, 0, self.big_endian) as u64;
+        let ts_frac = read_u32(&rec, 4, self.big_endian) as u64;
+        let caplen = read_u32(&rec, 8, self.big_endian) as usize;
+        let orig_len = read_u32(&rec, 12, self.big_endian);
+        if caplen > MAX_BLOCK_LEN {
+            bail!("pcap record length {caplen} is implausible");
+        }
+        rec.resize(16 + caplen, 0);
+        self.reader
+            .read_exact(&mut rec[16..])
+            .context("truncated pcap record")?;
+
+        let ts_nanos = ts_sec * 1_000_000_000 + if self.nanos { ts_frac } else { ts_frac * 1_000 };
+        Ok(Some(Block {
+            kind: BlockKind::Packet(PacketInfo {
+                interface: 0,
+                linktype: self.linktype,
+                ts_nanos,
+                orig_len,
+                data: 16..16 + caplen,
+            }),
+            raw: rec,
+        }))
+    }
+
+    fn next_ng_block(&mut self) -> Result<Option<Block>> {
+        let mut ty = [0u8; 4];
+        if !read_exact_or_eof(&mut self.reader, &mut ty)? {
+            return Ok(None);
+        }
+        let raw = self.read_ng_block_after_type(ty)?;
+        let block_type = read_u32(&raw, 0, self.big_endian);
+        let kind = match block_type {
+            BLOCK_SECTION_HEADER => BlockKind::SectionHeader,
+            BLOCK_INTERFACE_DESCRIPTION => BlockKind::InterfaceDescription,
+            BLOCK_ENHANCED_PACKET | BLOCK_PACKET | BLOCK_SIMPLE_PACKET => {
+                BlockKind::Packet(self.decode_ng_packet(block_type, &raw)?)
+            }
+            other => BlockKind::Other(other),
+        };
+        Ok(Some(Block { kind, raw }))
+    }
+
+    fn read_ng_block_after_type(&mut self, ty: [u8; 4]) -> Result<Vec<u8>> {
+        let mut len_bytes = [0u8; 4];
+        self.reader
+            .read_exact(&mut len_bytes)
+            .context("truncated pcapng block")?;
+
+        let is_shb = u32::from_le_bytes(ty) == BLOCK_SECTION_HEADER;
+        if is_shb {
+            // The section header decides the byte order of everything after it.
+            let mut bom = [0u8; 4];
+            self.reader
+                .read_exact(&mut bom)
+                .context("truncated pcapng section header")?;
+            self.big_endian = match u32::from_le_bytes(bom) {
+                PCAPNG_BYTE_ORDER_MAGIC => false,
+                _ if u32::from_be_bytes(bom) == PCAPNG_BYTE_ORDER_MAGIC => true,
+                _ => bail!("invalid pcapng byte-order magic"),
+            };
+            self.interfaces.clear();
+            let total = read_u32(&len_bytes, 0, self.big_endian) as usize;
+            let mut raw = self.check_block_len(total)?;
+            raw[..4].copy_from_slice(&ty);
+            raw[4..8].copy_from_slice(&len_bytes);
+            raw[8..12].copy_from_slice(&bom);
+            self.reader
+                .read_exact(&mut raw[12..])
+                .context("truncated pcapng section header")?;
+            return Ok(raw);
+        }
+
+        let total = read_u32(&len_bytes, 0, self.big_endian) as usize;
+        let mut raw = self.check_block_len(total)?;
+        raw[..4].copy_from_slice(&ty);
+        raw[4..8].copy_from_slice(&len_bytes);
+        self.reader
+            .read_exact(&mut raw[8..])
+            .context("truncated pcapng block")?;
+
+        if read_u32(&raw, 0, self.big_endian) == BLOCK_INTERFACE_DESCRIPTION {
+            self.interfaces.push(parse_interface(&raw, self.big_endian));
+        }
+        Ok(raw)
+    }
+
+    fn check_block_len(&self, total: usize) -> Result<Vec<u8>> {
+        if !(12..=MAX_BLOCK_LEN).contains(&total) || !total.is_multiple_of(4) {
+            bail!("invalid pcapng block length {total}");
+        }
+        Ok(vec![0u8; total])
+    }
+
+    fn decode_ng_packet(&self, block_type: u32, raw: &[u8]) -> Result<PacketInfo> {
+        let be = self.big_endian;
+        let (interface, ts, caplen, orig_len, start) = match block_type {
+            BLOCK_ENHANCED_PACKET => {
+                ensure_len(raw, 32)?;
+                let ts = ((read_u32(raw, 12, be) as u64) << 32) | read_u32(raw, 16, be) as u64;
+                (
+                    read_u32(raw, 8, be),
+                    ts,
+                    read_u32(raw, 20, be),
+                    read_u32(raw, 24, be),
+                    28,
+                )
+            }
+            BLOCK_PACKET => {
+                ensure_len(raw, 32)?;
+                let ts = ((read_u32(raw, 12, be) as u64) << 32) | read_u32(raw, 16, be) as u64;
+                let iface = read_u16(raw, 8, be) as u32;
+                (iface, ts, read_u32(raw, 20, be), read_u32(raw, 24, be), 28)
+            }
+            _ => {
+                ensure_len(raw, 16)?;
+                let orig = read_u32(raw, 8, be);
+                let caplen = orig.min(raw.len() as u32 - 16);
+                (0, 0, caplen, orig, 12)
+            }
+        };
+        let end = start + caplen as usize;
+        if end > raw.len() - 4 {
+            bail!("pcapng packet data exceeds its block");
+        }
+        let iface = self.interfaces.get(interface as usize);
+        let units = iface.map_or(1_000_000, |i| i.units_per_sec);
+        Ok(PacketInfo {
+            interface,
+            linktype: iface.map_or(0, |i| i.linktype),
+            ts_nanos: scale_to_nanos(ts, units),
+            orig_len,
+            data: start..end,
+        })
+    }
+}
+
+/// Reader over a stream whose first byte was already consumed by `read_started`.
+pub type StartedReader<R> = CaptureReader<io::Chain<io::Cursor<[u8; 1]>, R>>;
+
+/// Starts reading a capture stream that may end before sending anything, as a remote
+/// capture that failed to start does.
+/// Parameters: `stream` (R) pcap or pcapng bytes; blocks until the first byte arrives.
+/// Returns: Result<Option<StartedReader<R>>> None for an empty stream, an error for bytes
+/// that are not a capture.
+pub fn read_started<R: Read>(mut stream: R) -> Result<Option<StartedReader<R>>> {
+    let mut first = [0u8; 1];
+    loop {
+        match stream.read(&mut first) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("failed to read capture stream"),
+        }
+    }
+    CaptureReader::new(io::Cursor::new(first).chain(stream)).map(Some)
+}
+
+/// Adds an `opt_comment` to a pcapng Enhanced Packet Block.
+/// Parameters: `raw` (&[u8]) complete EPB bytes.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Parameters: `comment` (&str) UTF-8 comment shown by Wireshark on the packet.
+/// Returns: Option<Vec<u8>> rewritten block, or None if `raw` is not an EPB.
+pub fn with_packet_comment(raw: &[u8], big_endian: bool, comment: &str) -> Option<Vec<u8>> {
+    if raw.len() < 32 || read_u32(raw, 0, big_endian) != BLOCK_ENHANCED_PACKET {
+        return None;
+    }
+    let caplen = read_u32(raw, 20, big_endian) as usize;
+    let opts_start = 28 + caplen.div_ceil(4) * 4;
+    let opts_end = raw.len() - 4;
+    if opts_start > opts_end {
+        return None;
+    }
+
+    // Keep existing options, dropping the end-of-options marker we re-add below.
+    let mut out = raw[..opts_start].to_vec();
+    let mut off = opts_start;
+    while off + 4 <= opts_end {
+        let code = read_u16(raw, off, big_endian);
+        let len = read_u16(raw, off + 2, big_endian) as usize;
+        let next = off + 4 + len.div_ceil(4) * 4;
+        if code == 0 || next > opts_end {
+            break;
+        }
+        out.extend_from_slice(&raw[off..next]);
+        off = next;
+    }
+    push_option(&mut out, 1, comment.as_bytes(), big_endian);
+    push_option(&mut out, 0, &[], big_endian);
+
+    let total = (out.len() + 4) as u32;
+    out.extend_from_slice(&[0; 4]);
+    write_u32(&mut out, 4, total, big_endian);
+    let end = out.len() - 4;
+    write_u32(&mut out, end, total, big_endian);
+    Some(out)
+}
+
+/// Replaces the captured bytes of a pcapng Enhanced (or obsolete) Packet Block, keeping
+/// its header fields and options.
+/// Parameters: `raw` (&[u8]) complete EPB or Packet Block bytes.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Parameters: `data` (&[u8]) new captured bytes; the original length field is kept.
+/// Returns: Option<Vec<u8>> rewritten block, or None for other blocks.
+pub fn with_packet_data(raw: &[u8], big_endian: bool, data: &[u8]) -> Option<Vec<u8>> {
+    let block_type = read_u32(raw, 0, big_endian);
+    if raw.len() < 32 || !matches!(block_type, BLOCK_ENHANCED_PACKET | BLOCK_PACKET) {
+        return None;
+    }
+    let caplen = read_u32(raw, 20, big_endian) as usize;
+    let opts_start = 28 + caplen.div_ceil(4) * 4;
+    let opts_end = raw.len() - 4;
+    if opts_start > opts_end {
+        return None;
+    }
+    let mut body = raw[8..28].to_vec();
+    write_u32(&mut body, 12, data.len() as u32, big_endian);
+    body.extend_from_slice(data);
+    body.resize(20 + data.len().div_ceil(4) * 4, 0);
+    body.extend_from_slice(&raw[opts_start..opts_end]);
+    Some(ng_block(block_type, &body, big_endian))
+}
+
+fn push_option(out: &mut Vec<u8>, code: u16, value: &[u8], big_endian: bool) {
+    let header = if big_endian {
+        [code.to_be_bytes(), (value.len() as u16).to_be_bytes()]
+    } else {
+        [code.to_le_bytes(), (value.len() as u16).to_le_bytes()]
+    };
+    out.extend_from_slice(&header.concat());
+    out.extend_from_slice(value);
+    out.resize(out.len() + value.len().div_ceil(4) * 4 - value.len(), 0);
+}
+
+/// Builds a pcapng Section Header Block of unspecified length.
+/// Parameters: `big_endian` (bool) byte order of the new section.
+/// Returns: Vec<u8> complete block.
+pub fn section_header_block(big_endian: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u32(&mut body, PCAPNG_BYTE_ORDER_MAGIC, big_endian);
+    push_u16(&mut body, 1, big_endian);
+    push_u16(&mut body, 0, big_endian);
+    body.extend_from_slice(&[0xff; 8]);
+    ng_block(BLOCK_SECTION_HEADER, &body, big_endian)
+}
+
+/// Builds a pcapng Interface Description Block with nanosecond timestamps.
+/// Parameters: `linktype` (u32) link type of the interface.
+/// Parameters: `snaplen` (u32) capture length limit, 0 for none.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Returns: Vec<u8> complete block.
+pub fn interface_description_block(linktype: u32, snaplen: u32, big_endian: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    push_u16(&mut body, linktype as u16, big_endian);
+    push_u16(&mut body, 0, big_endian);
+    push_u32(&mut body, snaplen, big_endian);
+    // if_tsresol 9: timestamps count nanoseconds.
+    push_option(&mut body, 9, &[9], big_endian);
+    push_option(&mut body, 0, &[], big_endian);
+    ng_block(BLOCK_INTERFACE_DESCRIPTION, &body, big_endian)
+}
+
+/// Builds a pcapng Enhanced Packet Block for an interface with nanosecond timestamps.
+/// Parameters: `info` (&PacketInfo) packet metadata; `interface` and `ts_nanos` are used.
+/// Parameters: `data` (&[u8]) captured bytes.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Returns: Vec<u8> complete block.
+pub fn enhanced_packet_block(info: &PacketInfo, data: &[u8], big_endian: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(20 + data.len() + 3);
+    push_u32(&mut body, info.interface, big_endian);
+    push_u32(&mut body, (info.ts_nanos >> 32) as u32, big_endian);
+    push_u32(&mut body, info.ts_nanos as u32, big_endian);
+    push_u32(&mut body, data.len() as u32, big_endian);
+    push_u32(&mut body, info.orig_len, big_endian);
+    body.extend_from_slice(data);
+    ng_block(BLOCK_ENHANCED_PACKET, &body, big_endian)
+}
+
+/// Builds a pcapng Name Resolution Block mapping addresses to host names.
+/// Parameters: `entries` (&[(IpAddr, String)]) address and the name Wireshark should show.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Returns: Vec<u8> complete block.
+pub fn name_resolution_block(entries: &[(IpAddr, String)], big_endian: bool) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (ip, name) in entries {
+        let (record, mut value) = match ip {
+            IpAddr::V4(v4) => (1, v4.octets().to_vec()),
+            IpAddr::V6(v6) => (2, v6.octets().to_vec()),
+        };
+        value.extend_from_slice(name.as_bytes());
+        value.push(0);
+        push_option(&mut body, record, &value, big_endian);
+    }
+    push_option(&mut body, 0, &[], big_endian);
+    ng_block(BLOCK_NAME_RESOLUTION, &body, big_endian)
+}
+
+/// Builds a pcapng Decryption Secrets Block.
+/// Parameters: `secrets_type` (u32) payload format, e.g. `SECRETS_TLS_KEY_LOG`.
+/// Parameters: `secrets` (&[u8]) payload, for a TLS key log complete lines.
+/// Parameters: `big_endian` (bool) byte order of the section.
+/// Returns: Vec<u8> complete block.
+pub fn decryption_secrets_block(secrets_type: u32, secrets: &[u8], big_endian: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(8 + secrets.len());
+    push_u32(&mut body, secrets_type, big_endian);
+    push_u32(&mut body, secrets.len() as u32, big_endian);
+    body.extend_from_slice(secrets);
+    ng_block(BLOCK_DECRYPTION_SECRETS, &body, big_endian)
+}
+
+fn ng_block(block_type: u32, body: &[u8], big_endian: bool) -> Vec<u8> {
+    let total = (12 + body.len().div_ceil(4) * 4) as u32;
+    let mut out = Vec::with_capacity(total as usize);
+    push_u32(&mut out, block_type, big_endian);
+    push_u32(&mut out, total, big_endian);
+    out.extend_from_slice(body);
+    out.resize(total as usize - 4, 0);
+    push_u32(&mut out, total, big_endian);
+    out
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16, big_endian: bool) {
+    let b = if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    out.extend_from_slice(&b);
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32, big_endian: bool) {
+    let b = if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    out.extend_from_slice(&b);
+}
+
+// Guards allocations against corrupt length fields.
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+fn parse_interface(raw: &[u8], be: bool) -> Interface {
+    let linktype = if raw.len() >= 12 {
+        read_u16(raw, 8, be) as u32
+    } else {
+        0
+    };
+    let mut units_per_sec = 1_000_000;
+    // Options start after type, length, linktype, reserved and snaplen.
+    let mut off = 16;
+    while off + 4 <= raw.len().saturating_sub(4) {
+        let code = read_u16(raw, off, be);
+        let len = read_u16(raw, off + 2, be) as usize;
+        if code == 0 {
+            break;
+        }
+        if code == 9 && len == 1 && off + 4 < raw.len() {
+            let v = raw[off + 4];
+            units_per_sec = if v & 0x80 == 0 {
+                10u64.saturating_pow(v as u32)
+            } else {
+                1u64 << (v & 0x7f).min(63)
+            };
+        }
+        off += 4 + len.div_ceil(4) * 4;
+    }
+    Interface {
+        linktype,
+        units_per_sec,
+    }
+}
+
+fn scale_to_nanos(ts: u64, units_per_sec: u64) -> u64 {
+    if units_per_sec == 0 {
+        return ts;
+    }
+    let secs = ts / units_per_sec;
+    let rem = ts % units_per_sec;
+    secs * 1_000_000_000 + (rem as u128 * 1_000_000_000 / units_per_sec as u128) as u64
+}
+
+fn ensure_len(raw: &[u8], min: usize) -> Result<()> {
+    if raw.len() < min {
+        bail!("pcapng block too short");
+    }
+    Ok(())
+}
+
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool> {
+    // Distinguish a clean end between blocks from a truncated block.
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => bail!("capture stream truncated mid-block"),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e).context("failed to read capture stream"),
+        }
+    }
+    Ok(true)
+}
+
+/// Reads a u32 at `off` with the given byte order.
+pub(crate) fn read_u32(buf: &[u8], off: usize, big_endian: bool) -> u32 {
+    let b = [buf[off], buf[off + 1], buf[off + 2], buf[off + 3]];
+    if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    }
+}
+
+/// Reads a u16 at `off` with the given byte order.
+pub(crate) fn read_u16(buf: &[u8], off: usize, big_endian: bool) -> u16 {
+    let b = [buf[off], buf[off + 1]];
+    if big_endian {
+        u16::from_be_bytes(b)
+    } else {
+        u16::from_le_bytes(b)
+    }
+}
+
+/// Writes a u32 at `off` with the given byte order.
+pub(crate) fn write_u32(buf: &mut [u8], off: usize, value: u32, big_endian: bool) {
+    let b = if big_endian {
+        value.to_be_bytes()
+    } else {
+        value.to_le_bytes()
+    };
+    buf[off..off + 4].copy_from_slice(&b);
+}
+
+#[cfg(test)]
+pub(crate) mod testdata {
+    /// Builds a little-endian pcap stream with LINUX_SLL link type.
+    pub fn pcap_stream(packets: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&[0u8; 8]);
+        out.extend_from_slice(&65535u32.to_le_bytes());
+        out.extend_from_slice(&113u32.to_le_bytes());
+        for (sec, data) in packets {
+            out.extend_from_slice(&sec.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    /// Builds a little-endian pcapng section with one interface.
+    pub fn pcapng_stream(packets: &[(u64, &[u8])]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&super::BLOCK_SECTION_HEADER.to_le_bytes());
+        out.extend_from_slice(&28u32.to_le_bytes());
+        out.extend_from_slice(&0x1a2b3c4du32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&u64::MAX.to_le_bytes());
+        out.extend_from_slice(&28u32.to_le_bytes());
+
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.extend_from_slice(&20u32.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&20u32.to_le_bytes());
+
+        for (micros, data) in packets {
+            let padded = data.len().div_ceil(4) * 4;
+            let total = (32 + padded) as u32;
+            out.extend_from_slice(&6u32.to_le_bytes());
+            out.extend_from_slice(&total.to_le_bytes());
+            out.extend_from_slice(&0u32.to_le_bytes());
+            out.extend_from_slice(&((micros >> 32) as u32).to_le_bytes());
+            out.extend_from_slice(&(*micros as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+            out.resize(out.len() + padded - data.len(), 0);
+            out.extend_from_slice(&total.to_le_bytes());
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_pcap_records() {
+        let bytes = testdata::pcap_stream(&[(1, b"abc"), (2, b"defg")]);
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.format(), StreamFormat::Pcap);
+        assert_eq!(reader.linktype(), 113);
+
+        let first = reader.next_block().unwrap().unwrap();
+        assert_eq!(first.packet_data().unwrap(), b"abc");
+        let second = reader.next_block().unwrap().unwrap();
+        match &second.kind {
+            BlockKind::Packet(info) => assert_eq!(info.ts_nanos, 2_000_000_000),
+            other => panic!("unexpected block {other:?}"),
+        }
+        assert!(reader.next_block().unwrap().is_none());
+    }
+
+    #[test]
+    fn reads_pcapng_blocks() {
+        let bytes = testdata::pcapng_stream(&[(1_500_000, b"hello")]);
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.format(), StreamFormat::Pcapng);
+
+        let idb = reader.next_block().unwrap().unwrap();
+        assert_eq!(idb.kind, BlockKind::InterfaceDescription);
+        assert_eq!(reader.linktype(), 1);
+
+        let epb = reader.next_block().unwrap().unwrap();
+        assert_eq!(epb.packet_data().unwrap(), b"hello");
+        match &epb.kind {
+            BlockKind::Packet(info) => assert_eq!(info.ts_nanos, 1_500_000_000),
+            other => panic!("unexpected block {other:?}"),
+        }
+        assert!(reader.next_block().unwrap().is_none());
+    }
+
+    #[test]
+    fn packet_comment_is_readable_after_rewrite() {
+        let bytes = testdata::pcapng_stream(&[(7, b"abcde")]);
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        reader.next_block().unwrap();
+        let epb = reader.next_block().unwrap().unwrap();
+
+        let commented = with_packet_comment(&epb.raw, false, "gap").unwrap();
+        assert_eq!(commented.len(), epb.raw.len() + 12);
+        let total = read_u32(&commented, 4, false) as usize;
+        assert_eq!(total, commented.len());
+        assert_eq!(&commented[40..43], b"gap");
+        assert!(with_packet_comment(reader.header(), false, "x").is_none());
+    }
+
+    #[test]
+    fn packet_data_rewrite_keeps_options() {
+        let bytes = testdata::pcapng_stream(&[(7, b"abcdefgh")]);
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        reader.next_block().unwrap();
+        let epb = reader.next_block().unwrap().unwrap();
+        let commented = with_packet_comment(&epb.raw, false, "gap").unwrap();
+
+        let cut = with_packet_data(&commented, false, b"abc").unwrap();
+        assert_eq!(cut.len(), commented.len() - 4);
+        assert_eq!(read_u32(&cut, 4, false) as usize, cut.len());
+        assert_eq!(read_u32(&cut, 20, false), 3);
+        assert_eq!(read_u32(&cut, 24, false), 8);
+        assert_eq!(&cut[28..31], b"abc");
+        assert_eq!(&cut[36..39], b"gap");
+    }
+
+    #[test]
+    fn rejects_unknown_magic() {
+        assert!(CaptureReader::new(Cursor::new(b"garbage!".to_vec())).is_err());
+    }
+
+    #[test]
+    fn truncated_record_is_an_error() {
+        let mut bytes = testdata::pcap_stream(&[(1, b"abcdef")]);
+        bytes.truncate(bytes.len() - 2);
+        let mut reader = CaptureReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.next_block().is_err());
+    }
+}
@@ -0,0 +1,372 @@
+use crate::dissect;
+use crate::meter::{Meter, Observer, Snapshot, Ticker};
+use crate::pcap::{Block, BlockKind};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time between redraws of the progress display.
+pub const REFRESH: Duration = Duration::from_secs(1);
+
+/// Rows shown per `--stats` table.
+const TOP: usize = 5;
+
+/// Lines of the display currently on screen, so other output can clear them first.
+static DRAWN: Mutex<usize> = Mutex::new(0);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Packets and wire bytes attributed to a host or port.
+pub struct Usage {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64) {
+        self.packets += 1;
+        self.bytes += bytes;
+    }
+}
+
+#[derive(Debug, Default)]
+/// Per-host and per-port totals of the packets seen so far.
+pub struct Traffic {
+    hosts: HashMap<IpAddr, Usage>,
+    ports: HashMap<(&'static str, u16), Usage>,
+}
+
+impl Traffic {
+    /// Attributes one captured packet to both its hosts and to its service port.
+    /// Parameters: `linktype` (u32) link-layer header type.
+    /// Parameters: `frame` (&[u8]) captured bytes.
+    /// Parameters: `wire_len` (u32) original packet length.
+    pub fn record(&mut self, linktype: u32, frame: &[u8], wire_len: u32) {
+        let Some(packet) = dissect::dissect(linktype, frame) else {
+            return;
+        };
+        let bytes = u64::from(wire_len);
+        self.hosts.entry(packet.src).or_default().add(bytes);
+        self.hosts.entry(packet.dst).or_default().add(bytes);
+        if let Some((src, dst)) = packet.ports() {
+            // The lower port is usually the service; the other one is ephemeral.
+            let key = (packet.protocol_name(), src.min(dst));
+            self.ports.entry(key).or_default().add(bytes);
+        }
+    }
+
+    /// Hosts that sent or received the most bytes.
+    /// Parameters: `n` (usize) number of rows.
+    /// Returns: Vec<(IpAddr, Usage)> largest first.
+    pub fn top_hosts(&self, n: usize) -> Vec<(IpAddr, Usage)> {
+        top(self.hosts.iter().map(|(ip, u)| (*ip, *u)), n)
+    }
+
+    /// Service ports that carried the most bytes.
+    /// Parameters: `n` (usize) number of rows.
+    /// Returns: Vec<(String, Usage)> `proto/port` labels, largest first.
+    pub fn top_ports(&self, n: usize) -> Vec<(String, Usage)> {
+        let ports = self
+            .ports
+            .iter()
+            .map(|((proto, port), u)| (format!("{proto}/{port}"), *u));
+        top(ports, n)
+    }
+
+    /// Observer feeding a shared `Traffic` from a metered stream.
+    /// Parameters: `traffic` (Arc<Mutex<Traffic>>) totals to update.
+    /// Returns: Observer for `Meter::wrap`.
+    pub fn observer(traffic: Arc<Mutex<Traffic>>) -> Observer {
+        Box::new(move |block: &Block| {
+            if let (BlockKind::Packet(info), Some(data)) = (&block.kind, block.packet_data()) {
+                let mut traffic = traffic.lock().unwrap_or_else(|e| e.into_inner());
+                traffic.record(info.linktype, data, info.orig_len);
+            }
+        })
+    }
+}
+
+fn top<K: Ord>(rows: impl Iterator<Item = (K, Usage)>, n: usize) -> Vec<(K, Usage)> {
+    let mut rows: Vec<(K, Usage)> = rows.collect();
+    rows.sort_by(|a, b| b.1.bytes.cmp(&a.1.bytes).then_with(|| a.0.cmp(&b.0)));
+    rows.truncate(n);
+    rows
+}
+
+/// Progress line (and `--stats` tables) redrawn in place on stderr until dropped.
+pub struct Live {
+    ticker: Option<Ticker>,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    meter: Meter,
+    duration: Option<Duration>,
+    traffic: Option<Arc<Mutex<Traffic>>>,
+    last: Option<Snapshot>,
+    rate: (f64, f64),
+}
+
+impl State {
+    fn lines(&mut self) -> Vec<String> {
+        let snap = self.meter.snapshot();
+        if let Some(last) = self.last {
+            // Rates over the last refresh show a stall right away; averages would not.
+            let secs = (snap.elapsed - last.elapsed).as_secs_f64();
+            if secs > 0.0 {
+                self.rate = (
+                    (snap.packets - last.packets) as f64 / secs,
+                    (snap.bytes - last.bytes) as f64 / secs,
+                );
+            }
+        } else {
+            self.rate = (snap.packets_per_sec(), snap.bytes_per_sec());
+        }
+        self.last = Some(snap);
+        let remaining = self.duration.map(|d| d.saturating_sub(snap.elapsed));
+        let traffic = self
+            .traffic
+            .as_ref()
+            .map(|t| t.lock().unwrap_or_else(|e| e.into_inner()));
+        render(&snap, self.rate, remaining, traffic.as_deref())
+    }
+}
+
+impl Live {
+    /// Starts redrawing every `REFRESH`.
+    /// Parameters: `meter` (&Meter) counts of the capture stream.
+    /// Parameters: `duration` (Option<u64>) `--duration` in seconds, for the time left.
+    /// Parameters: `traffic` (Option<Arc<Mutex<Traffic>>>) totals for `--stats`.
+    /// Returns: Live display; dropping it draws the final counts and moves below them.
+    pub fn start(
+        meter: &Meter,
+        duration: Option<u64>,
+        traffic: Option<Arc<Mutex<Traffic>>>,
+    ) -> Live {
+        let state = Arc::new(Mutex::new(State {
+            meter: meter.clone(),
+            duration: duration.filter(|d| *d > 0).map(Duration::from_secs),
+            traffic,
+            last: None,
+            rate: (0.0, 0.0),
+        }));
+        let ticking = state.clone();
+        let ticker = Ticker::every(REFRESH, move || {
+            let lines = ticking.lock().unwrap_or_else(|e| e.into_inner()).lines();
+            draw(&lines);
+        });
+        Live {
+            ticker: Some(ticker),
+            state,
+        }
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        self.ticker = None;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        // Final counts: averages over the whole run, and no time left to show.
+        state.last = None;
+        state.duration = None;
+        let lines = state.lines();
+        draw(&lines);
+        let mut drawn = DRAWN.lock().unwrap_or_else(|e| e.into_inner());
+        *drawn = 0;
+        eprintln!();
+    }
+}
+
+/// Prints a line to stderr above the progress display instead of through it.
+/// Parameters: `line` (&str) text without trailing newline.
+pub fn eprint_line(line: &str) {
+    let mut drawn = DRAWN.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = String::new();
+    if *drawn > 0 {
+        out.push_str(&rewind(*drawn));
+        *drawn = 0;
+    }
+    out.push_str(line);
+    out.push('\n');
+    let _ = io::stderr().write_all(out.as_bytes());
+}
+
+fn draw(lines: &[String]) {
+    let mut drawn = DRAWN.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = rewind(*drawn);
+    out.push_str(&lines.join("\n"));
+    let mut stderr = io::stderr();
+    let _ = stderr
+        .write_all(out.as_bytes())
+        .and_then(|_| stderr.flush());
+    *drawn = lines.len();
+}
+
+// Back to the start of the display and clear it; a no-op when nothing is drawn.
+fn rewind(drawn: usize) -> String {
+    match drawn {
+        0 => String::new(),
+        1 => "\r\x1b[J".to_string(),
+        n => format!("\r\x1b[{}A\x1b[J", n - 1),
+    }
+}
+
+/// Renders the progress line and, with `traffic`, the top talkers and ports.
+/// Parameters: `snap` (&Snapshot) current counts.
+/// Parameters: `rate` ((f64, f64)) current packets and bytes per second.
+/// Parameters: `remaining` (Option<Duration>) time left of `--duration`.
+/// Parameters: `traffic` (Option<&Traffic>) totals for the tables.
+/// Returns: Vec<String> lines to draw.
+pub fn render(
+    snap: &Snapshot,
+    rate: (f64, f64),
+    remaining: Option<Duration>,
+    traffic: Option<&Traffic>,
+) -> Vec<String> {
+    let mut line = format!(
+        "kcap: {} packets, {}, {:.0} pkt/s, {}/s, {} elapsed",
+        snap.packets,
+        human_bytes(snap.bytes as f64),
+        rate.0,
+        human_bytes(rate.1),
+        clock(snap.elapsed),
+    );
+    if let Some(left) = remaining {
+        line.push_str(&format!(", {} left", clock(left)));
+    }
+    let mut lines = vec![line];
+    if let Some(traffic) = traffic {
+        let row = |name: &str, u: &Usage| {
+            format!(
+                "  {name:<39} {:>10} {:>11}",
+                u.packets,
+                human_bytes(u.bytes as f64)
+            )
+        };
+        lines.push(format!(
+            "  {:<39} {:>10} {:>11}",
+            "top talkers", "packets", "bytes"
+        ));
+        for (ip, usage) in traffic.top_hosts(TOP) {
+            lines.push(row(&ip.to_string(), &usage));
+        }
+        lines.push("  top ports".to_string());
+        for (port, usage) in traffic.top_ports(TOP) {
+            lines.push(row(&port, &usage));
+        }
+    }
+    lines
+}
+
+pub(crate) fn human_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024.0 {
+        return format!("{bytes:.0} B");
+    }
+    let mut value = bytes / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn clock(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissect::testdata;
+    use crate::dissect::LINKTYPE_LINUX_SLL;
+
+    fn snap(packets: u64, bytes: u64, secs: u64) -> Snapshot {
+        Snapshot {
+            packets,
+            bytes,
+            elapsed: Duration::from_secs(secs),
+        }
+    }
+
+    #[test]
+    fn renders_progress_line() {
+        let lines = render(
+            &snap(1234, 3 * 1024 * 1024, 75),
+            (120.0, 2048.0),
+            Some(Duration::from_secs(45)),
+            None,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                "kcap: 1234 packets, 3.0 MiB, 120 pkt/s, 2.0 KiB/s, 1:15 elapsed, 0:45 left"
+                    .to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn ranks_talkers_and_service_ports() {
+        let mut traffic = Traffic::default();
+        let big = testdata::sll(&testdata::ipv4_tcp(
+            [10, 0, 0, 1],
+            [10, 0, 0, 2],
+            (40000, 443),
+            (0, 0),
+            0,
+            0,
+            &[0; 200],
+        ));
+        let small = testdata::sll(&testdata::ipv4_udp(
+            [10, 0, 0, 3],
+            [10, 0, 0, 53],
+            (5353, 53),
+            b"q",
+        ));
+        traffic.record(LINKTYPE_LINUX_SLL, &big, 256);
+        traffic.record(LINKTYPE_LINUX_SLL, &big, 256);
+        traffic.record(LINKTYPE_LINUX_SLL, &small, 45);
+
+        let hosts = traffic.top_hosts(5);
+        assert_eq!(hosts.len(), 4);
+        assert_eq!(hosts[0].0, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!(
+            hosts[0].1,
+            Usage {
+                packets: 2,
+                bytes: 512
+            }
+        );
+        let ports = traffic.top_ports(1);
+        assert_eq!(
+            ports,
+            vec![(
+                "tcp/443".to_string(),
+                Usage {
+                    packets: 2,
+                    bytes: 512
+                }
+            )]
+        );
+
+        let lines = render(&snap(3, 557, 1), (3.0, 557.0), None, Some(&traffic));
+        assert!(lines[1].contains("top talkers"));
+        assert!(lines.iter().any(|l| l.contains("udp/53")));
+    }
+
+    #[test]
+    fn formats_units_and_clock() {
+        assert_eq!(human_bytes(512.0), "512 B");
+        assert_eq!(human_bytes(1536.0), "1.5 KiB");
+        assert_eq!(clock(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(rewind(3), "\r\x1b[2A\x1b[J");
+    }
+}
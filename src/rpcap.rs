@@ -0,0 +1,873 @@
+mod bpf;
+
+use crate::capture::StopHandle;
+use crate::cli::{Args, CaptureFormat};
+use crate::k8s::Target;
+use crate::pcap::{self, BlockKind, CaptureReader};
+use anyhow::{anyhow, bail, Context, Result};
+use bpf::Program;
+use clap::Parser;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+// rpcap protocol version 0, as spoken by rpcapd and libpcap's rpcap:// client.
+// https://github.com/the-tcpdump-group/libpcap/blob/master/rpcap-protocol.h
+const RPCAP_VERSION: u8 = 0;
+
+const MSG_ERROR: u8 = 0x01;
+const MSG_FINDALLIF_REQ: u8 = 0x02;
+const MSG_OPEN_REQ: u8 = 0x03;
+const MSG_STARTCAP_REQ: u8 = 0x04;
+const MSG_UPDATEFILTER_REQ: u8 = 0x05;
+const MSG_CLOSE: u8 = 0x06;
+const MSG_PACKET: u8 = 0x07;
+const MSG_AUTH_REQ: u8 = 0x08;
+const MSG_STATS_REQ: u8 = 0x09;
+const MSG_ENDCAP_REQ: u8 = 0x0a;
+const MSG_SETSAMPLING_REQ: u8 = 0x0b;
+const REPLY: u8 = 0x80;
+
+const ERR_AUTH: u16 = 3;
+const ERR_NOREMOTEIF: u16 = 5;
+const ERR_OPEN: u16 = 6;
+const ERR_UPDATEFILTER: u16 = 7;
+const ERR_STARTCAPTURE: u16 = 12;
+const ERR_WRONGMSG: u16 = 16;
+const ERR_WRONGVER: u16 = 17;
+
+const AUTH_NULL: u16 = 0;
+const FILTER_BPF: u16 = 0;
+const STARTCAP_FLAG_DGRAM: u16 = 2;
+const STARTCAP_FLAG_SERVEROPEN: u16 = 4;
+
+// Largest control message accepted; filters are the only sizeable payloads.
+const MAX_PAYLOAD: u32 = 1 << 20;
+// tcpdump holds back the pcap header until the first packet arrives.
+const HEADER_WAIT: Duration = Duration::from_secs(3);
+const DATA_ACCEPT_WAIT: Duration = Duration::from_secs(10);
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap rpcap",
+    about = "Serve kcap targets to rpcap clients such as Wireshark"
+)]
+/// Arguments of `kcap rpcap`: shared capture options plus the exposed interfaces.
+pub struct RpcapArgs {
+    #[command(flatten)]
+    pub args: Args,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:2002",
+        help = "Address to accept rpcap clients on"
+    )]
+    pub listen: String,
+
+    #[arg(
+        long,
+        help = "Allow --listen on a non-loopback address; rpcap clients are not authenticated \
+                and can start captures with your SSH and cluster credentials"
+    )]
+    pub allow_remote: bool,
+
+    #[arg(
+        long = "target",
+        value_name = "NAME=node:HOST|pod:NS/POD[/CONTAINER]",
+        required = true,
+        help = "Remote interface to expose (repeatable)"
+    )]
+    pub targets: Vec<String>,
+}
+
+impl crate::config::CaptureCommand for RpcapArgs {
+    fn capture_args(&mut self) -> &mut Args {
+        &mut self.args
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// One rpcap interface backed by a kcap capture target.
+pub struct RpcapTarget {
+    pub name: String,
+    pub target: Target,
+}
+
+/// Parses `NAME=node:HOST` or `NAME=pod:NS/POD[/CONTAINER]`.
+/// Parameters: `spec` (&str) value of `--target`.
+/// Returns: Result<RpcapTarget> interface name and capture target.
+pub fn parse_target(spec: &str) -> Result<RpcapTarget> {
+    let Some((name, target)) = spec.split_once('=') else {
+        bail!("invalid --target {spec:?}: expected NAME=node:HOST or NAME=pod:NS/POD");
+    };
+    if name.is_empty() {
+        bail!("invalid --target {spec:?}: empty interface name");
+    }
+    let target = match target.split_once(':') {
+        Some(("node", host)) if !host.is_empty() => Target::Ssh {
+            host: host.to_string(),
+        },
+        Some(("pod", path)) => {
+            let parts: Vec<&str> = path.split('/').collect();
+            match parts.as_slice() {
+                [ns, pod] | [ns, pod, _] if !ns.is_empty() && !pod.is_empty() => {
+                    Target::KubernetesExec {
+                        namespace: ns.to_string(),
+                        pod: pod.to_string(),
+                        container: parts.get(2).map(|c| c.to_string()),
+                    }
+                }
+                _ => bail!("invalid --target {spec:?}: expected pod:NS/POD[/CONTAINER]"),
+            }
+        }
+        _ => bail!("invalid --target {spec:?}: expected NAME=node:HOST or NAME=pod:NS/POD"),
+    };
+    Ok(RpcapTarget {
+        name: name.to_string(),
+        target,
+    })
+}
+
+/// Starts captures for rpcap interfaces.
+pub trait CaptureSource: Send + Sync {
+    /// Lists (name, description) of every exposed interface.
+    fn interfaces(&self) -> Vec<(String, String)>;
+
+    /// Starts capturing on an interface; `stop` ends the capture even while no packets flow.
+    /// Parameters: `name` (&str) interface name sent by the client.
+    /// Parameters: `stop` (&StopHandle) stops the capture processes when the client ends it.
+    /// Returns: Result<Box<dyn Read + Send>> pcap byte stream.
+    fn open(&self, name: &str, stop: &StopHandle) -> Result<Box<dyn Read + Send>>;
+
+    /// Link type to report when the capture header is slow to arrive.
+    fn fallback_linktype(&self) -> u32 {
+        LINKTYPE_ETHERNET
+    }
+}
+
+/// Capture source that runs regular kcap captures over SSH or kubectl exec.
+pub struct KcapSource {
+    base: Args,
+    targets: Vec<RpcapTarget>,
+}
+
+impl KcapSource {
+    /// Creates a source from shared capture arguments and exposed targets.
+    pub fn new(base: Args, targets: Vec<RpcapTarget>) -> Self {
+        KcapSource { base, targets }
+    }
+}
+
+impl CaptureSource for KcapSource {
+    fn interfaces(&self) -> Vec<(String, String)> {
+        self.targets
+            .iter()
+            .map(|t| (t.name.clone(), crate::target_label(&t.target)))
+            .collect()
+    }
+
+    fn open(&self, name: &str, stop: &StopHandle) -> Result<Box<dyn Read + Send>> {
+        let Some(found) = self.targets.iter().find(|t| t.name == name) else {
+            bail!("unknown interface {name}");
+        };
+        let mut args = self.base.clone();
+        // Packets are re-framed record by record, which needs classic pcap.
+        args.format = CaptureFormat::Pcap;
+        args.service = None;
+        args.selector = None;
+        args.follow = false;
+        args.stop = stop.clone();
+        match &found.target {
+            Target::Ssh { host } => {
+                args.ssh_host = Some(host.clone());
+                args.pod = None;
+            }
+            Target::KubernetesExec {
+                namespace,
+                pod,
+                container,
+            } => {
+                args.ssh_host = None;
+                args.namespace = Some(namespace.clone());
+                args.pod = Some(pod.clone());
+                args.container = container.clone();
+            }
+        }
+
+        let (tx, rx) = mpsc::sync_channel::<Result<Box<dyn Read + Send>>>(1);
+        let label = name.to_string();
+        thread::spawn(move || {
+            let stream_tx = tx.clone();
+            let result = crate::run_with_sink(args, move |stream| {
+                let _ = stream_tx.send(Ok(stream));
+                Ok(())
+            });
+            // The capture normally ends when the client goes away and the pipe closes.
+            if let Err(err) = result {
+                info!(interface = %label, "capture ended: {err:#}");
+                let _ = tx.send(Err(err));
+            }
+        });
+        rx.recv()
+            .map_err(|_| anyhow!("capture for {name} exited before producing output"))?
+    }
+
+    fn fallback_linktype(&self) -> u32 {
+        if self.base.iface == "any" {
+            LINKTYPE_LINUX_SLL
+        } else {
+            LINKTYPE_ETHERNET
+        }
+    }
+}
+
+/// Runs the rpcap server until interrupted.
+/// Parameters: `rpcap` (RpcapArgs) capture defaults, listen address and targets.
+/// Returns: Result<()> indicating success or failure.
+pub fn run(rpcap: RpcapArgs) -> Result<()> {
+    let targets = rpcap
+        .targets
+        .iter()
+        .map(|t| parse_target(t))
+        .collect::<Result<Vec<_>>>()?;
+    let listener = TcpListener::bind(&rpcap.listen)
+        .with_context(|| format!("failed to listen on {}", rpcap.listen))?;
+    check_listen(listener.local_addr()?, rpcap.allow_remote)?;
+    info!(
+        addr = %listener.local_addr()?,
+        interfaces = targets.len(),
+        "rpcap server ready; open rpcap://{}/<name> in Wireshark",
+        rpcap.listen
+    );
+    serve(listener, Arc::new(KcapSource::new(rpcap.args, targets)));
+    Ok(())
+}
+
+/// Refuses to expose the unauthenticated server beyond this host unless asked to.
+/// Parameters: `addr` (SocketAddr) address the control socket is bound to.
+/// Parameters: `allow_remote` (bool) `--allow-remote`.
+/// Returns: Result<()> error for a non-loopback address without `--allow-remote`.
+fn check_listen(addr: SocketAddr, allow_remote: bool) -> Result<()> {
+    if addr.ip().is_loopback() {
+        return Ok(());
+    }
+    if !allow_remote {
+        bail!(
+            "refusing to listen on {addr}: rpcap clients are not authenticated, so anyone who \
+             can reach the port could capture with your credentials; pass --allow-remote \
+             to do it anyway"
+        );
+    }
+    warn!("rpcap server on {addr} accepts unauthenticated clients");
+    Ok(())
+}
+
+/// Accepts rpcap clients forever, one session thread per control connection.
+/// Parameters: `listener` (TcpListener) bound control socket.
+/// Parameters: `source` (Arc<dyn CaptureSource>) interfaces offered to clients.
+pub fn serve(listener: TcpListener, source: Arc<dyn CaptureSource>) {
+    for conn in listener.incoming() {
+        let Ok(conn) = conn else { continue };
+        let source = source.clone();
+        thread::spawn(move || {
+            let peer = conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            info!(%peer, "rpcap client connected");
+            let mut session = Session::new(conn, source);
+            match session.run() {
+                Ok(()) => info!(%peer, "rpcap client disconnected"),
+                Err(err) => warn!(%peer, "rpcap session failed: {err:#}"),
+            }
+            session.end_capture();
+        });
+    }
+}
+
+struct Header {
+    version: u8,
+    kind: u8,
+    plen: u32,
+}
+
+fn read_header(r: &mut impl Read) -> io::Result<Option<Header>> {
+    let mut buf = [0u8; 8];
+    match r.read_exact(&mut buf) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    Ok(Some(Header {
+        version: buf[0],
+        kind: buf[1],
+        plen: pcap::read_u32(&buf, 4, true),
+    }))
+}
+
+fn message(kind: u8, value: u16, payload: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(8 + payload.len());
+    msg.push(RPCAP_VERSION);
+    msg.push(kind);
+    msg.extend_from_slice(&value.to_be_bytes());
+    msg.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    msg.extend_from_slice(payload);
+    msg
+}
+
+/// Capture reader that may still be waiting for the stream header.
+enum ReaderSlot {
+    Ready(CaptureReader<Box<dyn Read + Send>>),
+    Pending(Receiver<Result<CaptureReader<Box<dyn Read + Send>>>>),
+}
+
+impl ReaderSlot {
+    fn into_reader(self) -> Result<CaptureReader<Box<dyn Read + Send>>> {
+        match self {
+            ReaderSlot::Ready(r) => Ok(r),
+            ReaderSlot::Pending(rx) => rx
+                .recv()
+                .map_err(|_| anyhow!("capture ended before its header"))?,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Stats {
+    received: AtomicU32,
+    captured: AtomicU32,
+}
+
+struct Running {
+    stop: Arc<AtomicBool>,
+    data: Arc<Mutex<Option<TcpStream>>>,
+    filter: Arc<Mutex<Program>>,
+    stats: Arc<Stats>,
+}
+
+struct Session {
+    conn: TcpStream,
+    source: Arc<dyn CaptureSource>,
+    opened: Option<(String, ReaderSlot)>,
+    running: Option<Running>,
+    capture: Option<StopHandle>,
+}
+
+impl Session {
+    fn new(conn: TcpStream, source: Arc<dyn CaptureSource>) -> Self {
+        Session {
+            conn,
+            source,
+            opened: None,
+            running: None,
+            capture: None,
+        }
+    }
+
+    fn send(&mut self, kind: u8, value: u16, payload: &[u8]) -> Result<()> {
+        self.conn.write_all(&message(kind, value, payload))?;
+        Ok(())
+    }
+
+    fn send_error(&mut self, code: u16, text: &str) -> Result<()> {
+        warn!("rpcap error {code}: {text}");
+        self.send(MSG_ERROR, code, text.as_bytes())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        while let Some(header) = read_header(&mut self.conn)? {
+            if header.plen > MAX_PAYLOAD {
+                bail!("rpcap message of {} bytes is too large", header.plen);
+            }
+            let mut payload = vec![0u8; header.plen as usize];
+            self.conn.read_exact(&mut payload)?;
+            if header.version != RPCAP_VERSION {
+                self.send_error(ERR_WRONGVER, "only rpcap protocol version 0 is supported")?;
+                continue;
+            }
+            debug!(kind = header.kind, len = payload.len(), "rpcap request");
+            match header.kind {
+                MSG_AUTH_REQ => self.auth(&payload)?,
+                MSG_FINDALLIF_REQ => self.find_all_interfaces()?,
+                MSG_OPEN_REQ => self.open(&payload)?,
+                MSG_STARTCAP_REQ => self.start_capture(&payload)?,
+                MSG_UPDATEFILTER_REQ => self.update_filter(&payload)?,
+                MSG_STATS_REQ => self.stats()?,
+                MSG_ENDCAP_REQ => {
+                    self.end_capture();
+                    self.send(MSG_ENDCAP_REQ | REPLY, 0, &[])?;
+                }
+                // Sampling only thins out packets, so ignoring it is harmless.
+                MSG_SETSAMPLING_REQ => self.send(MSG_SETSAMPLING_REQ | REPLY, 0, &[])?,
+                MSG_CLOSE => return Ok(()),
+                other => {
+                    self.send_error(ERR_WRONGMSG, &format!("unsupported message type {other}"))?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn auth(&mut self, payload: &[u8]) -> Result<()> {
+        if payload.len() < 8 {
+            return self.send_error(ERR_AUTH, "malformed authentication request");
+        }
+        // Only null auth, hence check_listen; the targets carry their own SSH/kube credentials.
+        if pcap::read_u16(payload, 0, true) != AUTH_NULL {
+            return self.send_error(ERR_AUTH, "only null authentication is supported");
+        }
+        self.send(MSG_AUTH_REQ | REPLY, 0, &[])
+    }
+
+    fn find_all_interfaces(&mut self) -> Result<()> {
+        let interfaces = self.source.interfaces();
+        let mut payload = Vec::new();
+        for (name, desc) in &interfaces {
+            payload.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            payload.extend_from_slice(&(desc.len() as u16).to_be_bytes());
+            // flags, naddr, padding: no addresses are reported.
+            payload.extend_from_slice(&[0u8; 8]);
+            payload.extend_from_slice(name.as_bytes());
+            payload.extend_from_slice(desc.as_bytes());
+        }
+        self.send(MSG_FINDALLIF_REQ | REPLY, interfaces.len() as u16, &payload)
+    }
+
+    fn open(&mut self, payload: &[u8]) -> Result<()> {
+        let name = String::from_utf8_lossy(payload)
+            .trim_end_matches('\0')
+            .to_string();
+        if !self.source.interfaces().iter().any(|(n, _)| *n == name) {
+            return self.send_error(ERR_NOREMOTEIF, &format!("no interface named {name:?}"));
+        }
+        self.end_capture();
+        let stop = StopHandle::default();
+        self.capture = Some(stop.clone());
+        let stream = match self.source.open(&name, &stop) {
+            Ok(s) => s,
+            Err(err) => return self.send_error(ERR_OPEN, &format!("{err:#}")),
+        };
+        info!(interface = %name, "rpcap capture opened");
+
+        let (tx, rx) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let _ = tx.send(CaptureReader::new(stream));
+        });
+        let (linktype, slot) = match rx.recv_timeout(HEADER_WAIT) {
+            Ok(Ok(reader)) => (reader.linktype(), ReaderSlot::Ready(reader)),
+            Ok(Err(err)) => return self.send_error(ERR_OPEN, &format!("{err:#}")),
+            Err(RecvTimeoutError::Timeout) => {
+                let guess = self.source.fallback_linktype();
+                debug!(interface = %name, linktype = guess, "capture header not seen yet");
+                (guess, ReaderSlot::Pending(rx))
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return self.send_error(ERR_OPEN, "capture ended before its header")
+            }
+        };
+        self.opened = Some((name, slot));
+
+        let mut reply = Vec::with_capacity(8);
+        reply.extend_from_slice(&linktype.to_be_bytes());
+        // Timezone offset, always zero.
+        reply.extend_from_slice(&0u32.to_be_bytes());
+        self.send(MSG_OPEN_REQ | REPLY, 0, &reply)
+    }
+
+    fn start_capture(&mut self, payload: &[u8]) -> Result<()> {
+        if payload.len() < 20 {
+            return self.send_error(ERR_STARTCAPTURE, "malformed start capture request");
+        }
+        let snaplen = pcap::read_u32(payload, 0, true);
+        let flags = pcap::read_u16(payload, 8, true);
+        if flags & (STARTCAP_FLAG_DGRAM | STARTCAP_FLAG_SERVEROPEN) != 0 {
+            return self.send_error(
+                ERR_STARTCAPTURE,
+                "only passive TCP data connections are supported",
+            );
+        }
+        let program = match parse_filter(&payload[12..]) {
+            Ok(p) => p,
+            Err(err) => return self.send_error(ERR_STARTCAPTURE, &format!("{err:#}")),
+        };
+        let Some((name, slot)) = self.opened.take() else {
+            return self.send_error(ERR_STARTCAPTURE, "no interface is open");
+        };
+
+        // The client connects to a fresh port on the address it already reached us on.
+        let local = self.conn.local_addr()?;
+        let data_listener = TcpListener::bind(SocketAddr::new(local.ip(), 0))?;
+        let data_port = data_listener.local_addr()?.port();
+
+        let running = Running {
+            stop: Arc::new(AtomicBool::new(false)),
+            data: Arc::new(Mutex::new(None)),
+            filter: Arc::new(Mutex::new(program)),
+            stats: Arc::new(Stats::default()),
+        };
+        let forward = Forwarder {
+            stop: running.stop.clone(),
+            data: running.data.clone(),
+            filter: running.filter.clone(),
+            stats: running.stats.clone(),
+            snaplen,
+        };
+        thread::spawn(move || {
+            if let Err(err) = forward.run(data_listener, slot) {
+                warn!(interface = %name, "rpcap data connection ended: {err:#}");
+            }
+        });
+        self.running = Some(running);
+
+        let mut reply = Vec::with_capacity(8);
+        // Buffer size hint for the client.
+        reply.extend_from_slice(&(1u32 << 20).to_be_bytes());
+        reply.extend_from_slice(&data_port.to_be_bytes());
+        reply.extend_from_slice(&[0u8; 2]);
+        self.send(MSG_STARTCAP_REQ | REPLY, 0, &reply)
+    }
+
+    fn update_filter(&mut self, payload: &[u8]) -> Result<()> {
+        let program = match parse_filter(payload) {
+            Ok(p) => p,
+            Err(err) => return self.send_error(ERR_UPDATEFILTER, &format!("{err:#}")),
+        };
+        let Some(running) = &self.running else {
+            return self.send_error(ERR_UPDATEFILTER, "no capture is running");
+        };
+        *running.filter.lock().unwrap() = program;
+        self.send(MSG_UPDATEFILTER_REQ | REPLY, 0, &[])
+    }
+
+    fn stats(&mut self) -> Result<()> {
+        let (received, captured) = self.running.as_ref().map_or((0, 0), |r| {
+            (
+                r.stats.received.load(Ordering::Relaxed),
+                r.stats.captured.load(Ordering::Relaxed),
+            )
+        });
+        let mut reply = Vec::with_capacity(16);
+        reply.extend_from_slice(&received.to_be_bytes());
+        // Interface and kernel drops happen remotely and are not visible here.
+        reply.extend_from_slice(&0u32.to_be_bytes());
+        reply.extend_from_slice(&0u32.to_be_bytes());
+        reply.extend_from_slice(&captured.to_be_bytes());
+        self.send(MSG_STATS_REQ | REPLY, 0, &reply)
+    }
+
+    fn end_capture(&mut self) {
+        // The forwarder may be blocked waiting for a packet, so stop the capture itself;
+        // its stream then ends and the forwarder returns.
+        if let Some(capture) = self.capture.take() {
+            capture.stop();
+        }
+        self.opened = None;
+        if let Some(running) = self.running.take() {
+            running.stop.store(true, Ordering::Relaxed);
+            if let Some(data) = running.data.lock().unwrap().take() {
+                let _ = data.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+fn parse_filter(raw: &[u8]) -> Result<Program> {
+    if raw.len() < 8 {
+        bail!("malformed filter");
+    }
+    let filter_type = pcap::read_u16(raw, 0, true);
+    if filter_type != FILTER_BPF {
+        bail!("unsupported filter type {filter_type}");
+    }
+    let nitems = pcap::read_u32(raw, 4, true) as usize;
+    let insns = raw
+        .get(8..8 + nitems * 8)
+        .ok_or_else(|| anyhow!("filter has fewer than {nitems} instructions"))?;
+    Program::parse(insns)
+}
+
+/// Moves packets from the capture reader to the client's data connection.
+struct Forwarder {
+    stop: Arc<AtomicBool>,
+    data: Arc<Mutex<Option<TcpStream>>>,
+    filter: Arc<Mutex<Program>>,
+    stats: Arc<Stats>,
+    snaplen: u32,
+}
+
+impl Forwarder {
+    fn run(self, listener: TcpListener, slot: ReaderSlot) -> Result<()> {
+        let mut conn = self.accept(listener)?;
+        *self.data.lock().unwrap() = Some(conn.try_clone()?);
+        let mut reader = slot.into_reader()?;
+
+        let mut npkt: u32 = 0;
+        while let Some(block) = reader.next_block()? {
+            if self.stop.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let BlockKind::Packet(info) = &block.kind else {
+                continue;
+            };
+            let Some(data) = block.packet_data() else {
+                continue;
+            };
+            self.stats.received.fetch_add(1, Ordering::Relaxed);
+            let keep = self.filter.lock().unwrap().run(data, info.orig_len);
+            if keep == 0 {
+                continue;
+            }
+            let mut caplen = data.len().min(keep as usize);
+            if self.snaplen > 0 {
+                caplen = caplen.min(self.snaplen as usize);
+            }
+            npkt = npkt.wrapping_add(1);
+
+            let mut payload = Vec::with_capacity(20 + caplen);
+            payload.extend_from_slice(&((info.ts_nanos / 1_000_000_000) as u32).to_be_bytes());
+            payload
+                .extend_from_slice(&((info.ts_nanos % 1_000_000_000 / 1_000) as u32).to_be_bytes());
+            payload.extend_from_slice(&(caplen as u32).to_be_bytes());
+            payload.extend_from_slice(&info.orig_len.to_be_bytes());
+            payload.extend_from_slice(&npkt.to_be_bytes());
+            payload.extend_from_slice(&data[..caplen]);
+            conn.write_all(&message(MSG_PACKET, 0, &payload))?;
+            self.stats.captured.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn accept(&self, listener: TcpListener) -> Result<TcpStream> {
+        listener.set_nonblocking(true)?;
+        let deadline = Instant::now() + DATA_ACCEPT_WAIT;
+        loop {
+            match listener.accept() {
+                Ok((conn, _)) => {
+                    conn.set_nonblocking(false)?;
+                    return Ok(conn);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    if self.stop.load(Ordering::Relaxed) || Instant::now() >= deadline {
+                        bail!("client never opened the data connection");
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+
+    struct FakeSource;
+
+    impl CaptureSource for FakeSource {
+        fn interfaces(&self) -> Vec<(String, String)> {
+            vec![("web".to_string(), "pod/prod/web".to_string())]
+        }
+
+        fn open(&self, _name: &str, _stop: &StopHandle) -> Result<Box<dyn Read + Send>> {
+            let capture = testdata::pcap_stream(&[(1, b"first"), (2, b"second")]);
+            Ok(Box::new(Cursor::new(capture)))
+        }
+    }
+
+    fn request(conn: &mut TcpStream, kind: u8, payload: &[u8]) -> (u8, u16, Vec<u8>) {
+        conn.write_all(&message(kind, 0, payload)).unwrap();
+        read_message(conn)
+    }
+
+    fn read_message(conn: &mut TcpStream) -> (u8, u16, Vec<u8>) {
+        let mut head = [0u8; 8];
+        conn.read_exact(&mut head).unwrap();
+        let mut payload = vec![0u8; pcap::read_u32(&head, 4, true) as usize];
+        conn.read_exact(&mut payload).unwrap();
+        (head[1], pcap::read_u16(&head, 2, true), payload)
+    }
+
+    fn start_request(insns: &[u8]) -> Vec<u8> {
+        let mut req = Vec::new();
+        req.extend_from_slice(&65535u32.to_be_bytes());
+        req.extend_from_slice(&1000u32.to_be_bytes());
+        req.extend_from_slice(&[0u8; 4]);
+        req.extend_from_slice(&FILTER_BPF.to_be_bytes());
+        req.extend_from_slice(&[0u8; 2]);
+        req.extend_from_slice(&((insns.len() / 8) as u32).to_be_bytes());
+        req.extend_from_slice(insns);
+        req
+    }
+
+    /// Source whose capture sends its header and then waits for packets that never come.
+    #[derive(Default)]
+    struct QuietSource {
+        child: Arc<Mutex<Option<std::process::Child>>>,
+    }
+
+    impl CaptureSource for QuietSource {
+        fn interfaces(&self) -> Vec<(String, String)> {
+            vec![("idle".to_string(), "pod/prod/idle".to_string())]
+        }
+
+        fn open(&self, _name: &str, stop: &StopHandle) -> Result<Box<dyn Read + Send>> {
+            let mut child = std::process::Command::new("sh")
+                .args(["-c", "cat; exec sleep 30"])
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .spawn()?;
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(&testdata::pcap_stream(&[]))?;
+            crate::capture::track(&child);
+            stop.add(&child);
+            let stdout = child.stdout.take().unwrap();
+            *self.child.lock().unwrap() = Some(child);
+            Ok(Box::new(stdout))
+        }
+    }
+
+    fn connect() -> TcpStream {
+        connect_to(Arc::new(FakeSource))
+    }
+
+    fn connect_to(source: Arc<dyn CaptureSource>) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, source));
+        let mut conn = TcpStream::connect(addr).unwrap();
+        let (kind, _, _) = request(&mut conn, MSG_AUTH_REQ, &[0u8; 8]);
+        assert_eq!(kind, MSG_AUTH_REQ | REPLY);
+        conn
+    }
+
+    #[test]
+    fn parses_targets() {
+        assert_eq!(
+            parse_target("n1=node:10.0.0.5").unwrap().target,
+            Target::Ssh {
+                host: "10.0.0.5".to_string()
+            }
+        );
+        match parse_target("web=pod:prod/web-0/api").unwrap().target {
+            Target::KubernetesExec {
+                namespace,
+                pod,
+                container,
+            } => {
+                assert_eq!((namespace.as_str(), pod.as_str()), ("prod", "web-0"));
+                assert_eq!(container.as_deref(), Some("api"));
+            }
+            other => panic!("unexpected target {other:?}"),
+        }
+        assert!(parse_target("web").is_err());
+        assert!(parse_target("web=pod:onlyname").is_err());
+        assert!(parse_target("web=vm:host").is_err());
+    }
+
+    #[test]
+    fn lists_and_streams_an_interface() {
+        let mut conn = connect();
+
+        let (kind, count, payload) = request(&mut conn, MSG_FINDALLIF_REQ, &[]);
+        assert_eq!((kind, count), (MSG_FINDALLIF_REQ | REPLY, 1));
+        assert_eq!(&payload[12..15], b"web");
+
+        let (kind, _, payload) = request(&mut conn, MSG_OPEN_REQ, b"web");
+        assert_eq!(kind, MSG_OPEN_REQ | REPLY);
+        assert_eq!(pcap::read_u32(&payload, 0, true), LINKTYPE_LINUX_SLL);
+
+        let (kind, _, payload) = request(&mut conn, MSG_STARTCAP_REQ, &start_request(&[]));
+        assert_eq!(kind, MSG_STARTCAP_REQ | REPLY);
+        let port = pcap::read_u16(&payload, 4, true);
+        let mut data = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        let mut packets = Vec::new();
+        for _ in 0..2 {
+            let (kind, _, payload) = read_message(&mut data);
+            assert_eq!(kind, MSG_PACKET);
+            let caplen = pcap::read_u32(&payload, 8, true) as usize;
+            packets.push(payload[20..20 + caplen].to_vec());
+        }
+        assert_eq!(packets, vec![b"first".to_vec(), b"second".to_vec()]);
+
+        let (kind, _, payload) = request(&mut conn, MSG_STATS_REQ, &[]);
+        assert_eq!(kind, MSG_STATS_REQ | REPLY);
+        assert_eq!(pcap::read_u32(&payload, 12, true), 2);
+
+        let (kind, _, _) = request(&mut conn, MSG_ENDCAP_REQ, &[]);
+        assert_eq!(kind, MSG_ENDCAP_REQ | REPLY);
+        conn.write_all(&message(MSG_CLOSE, 0, &[])).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn endcap_stops_a_capture_without_traffic() {
+        let source = Arc::new(QuietSource::default());
+        let mut conn = connect_to(source.clone());
+        request(&mut conn, MSG_OPEN_REQ, b"idle");
+        let (_, _, payload) = request(&mut conn, MSG_STARTCAP_REQ, &start_request(&[]));
+        let port = pcap::read_u16(&payload, 4, true);
+        let _data = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        let (kind, _, _) = request(&mut conn, MSG_ENDCAP_REQ, &[]);
+        assert_eq!(kind, MSG_ENDCAP_REQ | REPLY);
+        let mut child = source.child.lock().unwrap().take().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while child.try_wait().unwrap().is_none() {
+            assert!(Instant::now() < deadline, "capture still running after ENDCAP");
+            thread::sleep(Duration::from_millis(20));
+        }
+        crate::capture::untrack(child.id());
+    }
+
+    #[test]
+    fn applies_client_filter() {
+        let mut conn = connect();
+        request(&mut conn, MSG_OPEN_REQ, b"web");
+        // ld len; jeq #6 (len("second")) jt 0 jf 1; ret #65535; ret #0
+        let insns = [
+            0x00, 0x80, 0, 0, 0, 0, 0, 0, //
+            0x00, 0x15, 0, 1, 0, 0, 0, 6, //
+            0x00, 0x06, 0, 0, 0, 0, 0xff, 0xff, //
+            0x00, 0x06, 0, 0, 0, 0, 0, 0,
+        ];
+        let (_, _, payload) = request(&mut conn, MSG_STARTCAP_REQ, &start_request(&insns));
+        let port = pcap::read_u16(&payload, 4, true);
+        let mut data = TcpStream::connect(("127.0.0.1", port)).unwrap();
+
+        let (_, _, payload) = read_message(&mut data);
+        assert_eq!(&payload[20..], b"second");
+    }
+
+    #[test]
+    fn rejects_unknown_interface_and_password_auth() {
+        let mut conn = connect();
+        let (kind, code, _) = request(&mut conn, MSG_OPEN_REQ, b"nope");
+        assert_eq!((kind, code), (MSG_ERROR, ERR_NOREMOTEIF));
+
+        let mut auth = vec![0u8; 8];
+        auth[1] = 1;
+        let (kind, code, _) = request(&mut conn, MSG_AUTH_REQ, &auth);
+        assert_eq!((kind, code), (MSG_ERROR, ERR_AUTH));
+    }
+
+    #[test]
+    fn remote_listen_needs_opt_in() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert!(check_listen(addr("127.0.0.1:2002"), false).is_ok());
+        assert!(check_listen(addr("[::1]:2002"), false).is_ok());
+        let err = check_listen(addr("0.0.0.0:2002"), false).unwrap_err();
+        assert!(err.to_string().contains("--allow-remote"));
+        assert!(check_listen(addr("10.0.0.5:2002"), true).is_ok());
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{bail, Result};
+
+// Classic BPF interpreter for the filter programs rpcap clients send. The client
+// compiles its capture filter against the link type we report, so running the
+// program locally gives the same result as applying it on the remote host.
+
+const MEMWORDS: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// One classic BPF instruction.
+pub struct Insn {
+    pub code: u16,
+    pub jt: u8,
+    pub jf: u8,
+    pub k: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+/// Validated BPF program; an empty program accepts every packet.
+pub struct Program {
+    insns: Vec<Insn>,
+}
+
+impl Program {
+    /// Decodes big-endian `rpcap_filterbpf_insn` entries.
+    /// Parameters: `raw` (&[u8]) instruction bytes, 8 per instruction.
+    /// Returns: Result<Program> validated program.
+    pub fn parse(raw: &[u8]) -> Result<Program> {
+        if !raw.len().is_multiple_of(8) {
+            bail!("bpf program length {} is not a multiple of 8", raw.len());
+        }
+        let insns = raw
+            .chunks_exact(8)
+            .map(|c| Insn {
+                code: u16::from_be_bytes([c[0], c[1]]),
+                jt: c[2],
+                jf: c[3],
+                k: u32::from_be_bytes([c[4], c[5], c[6], c[7]]),
+            })
+            .collect();
+        Program::new(insns)
+    }
+
+    /// Validates instructions the same way the kernel and libpcap do.
+    /// Parameters: `insns` (Vec<Insn>) program to check.
+    /// Returns: Result<Program> validated program.
+    pub fn new(insns: Vec<Insn>) -> Result<Program> {
+        let len = insns.len();
+        for (pc, insn) in insns.iter().enumerate() {
+            let class = insn.code & 0x07;
+            let next = pc + 1;
+            match class {
+                BPF_JMP => {
+                    let targets: &[usize] = if insn.code & 0xf0 == BPF_JA {
+                        &[next + insn.k as usize]
+                    } else {
+                        &[next + insn.jt as usize, next + insn.jf as usize]
+                    };
+                    if targets.iter().any(|t| *t >= len) {
+                        bail!("bpf jump out of range at instruction {pc}");
+                    }
+                }
+                _ => {
+                    let uses_mem = match class {
+                        BPF_LD | BPF_LDX => insn.code & 0xe0 == BPF_MEM,
+                        BPF_ST | BPF_STX => true,
+                        _ => false,
+                    };
+                    if uses_mem && insn.k as usize >= MEMWORDS {
+                        bail!("bpf scratch memory index out of range at instruction {pc}");
+                    }
+                }
+            }
+        }
+        if let Some(last) = insns.last() {
+            if last.code & 0x07 != BPF_RET {
+                bail!("bpf program does not end with a return");
+            }
+        }
+        Ok(Program { insns })
+    }
+
+    /// Runs the program against one packet.
+    /// Parameters: `packet` (&[u8]) captured bytes.
+    /// Parameters: `wire_len` (u32) original length on the wire.
+    /// Returns: u32 number of bytes to keep; 0 drops the packet.
+    pub fn run(&self, packet: &[u8], wire_len: u32) -> u32 {
+        if self.insns.is_empty() {
+            return u32::MAX;
+        }
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0u32; MEMWORDS];
+        let mut pc = 0;
+        while let Some(insn) = self.insns.get(pc) {
+            pc += 1;
+            let k = insn.k;
+            match insn.code & 0x07 {
+                BPF_LD => {
+                    a = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => wire_len,
+                        BPF_MEM => mem[k as usize],
+                        BPF_ABS => match load(packet, insn.code, k as usize) {
+                            Some(v) => v,
+                            None => return 0,
+                        },
+                        BPF_IND => match load(packet, insn.code, x.wrapping_add(k) as usize) {
+                            Some(v) => v,
+                            None => return 0,
+                        },
+                        _ => return 0,
+                    }
+                }
+                BPF_LDX => {
+                    x = match insn.code & 0xe0 {
+                        BPF_IMM => k,
+                        BPF_LEN => wire_len,
+                        BPF_MEM => mem[k as usize],
+                        // IPv4 header length: 4 * (packet[k] & 0xf).
+                        BPF_MSH => match packet.get(k as usize) {
+                            Some(b) => u32::from(b & 0x0f) * 4,
+                            None => return 0,
+                        },
+                        _ => return 0,
+                    }
+                }
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                BPF_ALU => {
+                    let operand = if insn.code & 0x08 != 0 { x } else { k };
+                    a = match insn.code & 0xf0 {
+                        0x00 => a.wrapping_add(operand),
+                        0x10 => a.wrapping_sub(operand),
+                        0x20 => a.wrapping_mul(operand),
+                        0x30 if operand == 0 => return 0,
+                        0x30 => a / operand,
+                        0x40 => a | operand,
+                        0x50 => a & operand,
+                        0x60 => a.checked_shl(operand).unwrap_or(0),
+                        0x70 => a.checked_shr(operand).unwrap_or(0),
+                        0x80 => a.wrapping_neg(),
+                        0x90 if operand == 0 => return 0,
+                        0x90 => a % operand,
+                        0xa0 => a ^ operand,
+                        _ => return 0,
+                    }
+                }
+                BPF_JMP => {
+                    let operand = if insn.code & 0x08 != 0 { x } else { k };
+                    let taken = match insn.code & 0xf0 {
+                        BPF_JA => {
+                            pc += k as usize;
+                            continue;
+                        }
+                        0x10 => a == operand,
+                        0x20 => a > operand,
+                        0x30 => a >= operand,
+                        0x40 => a & operand != 0,
+                        _ => return 0,
+                    };
+                    pc += usize::from(if taken { insn.jt } else { insn.jf });
+                }
+                BPF_RET => {
+                    return match insn.code & 0x18 {
+                        0x00 => k,
+                        0x08 => x,
+                        _ => a,
+                    }
+                }
+                _ => {
+                    // BPF_MISC: TAX / TXA.
+                    if insn.code & 0xf8 == 0x80 {
+                        a = x;
+                    } else {
+                        x = a;
+                    }
+                }
+            }
+        }
+        0
+    }
+}
+
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_IND: u16 = 0x40;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_MSH: u16 = 0xa0;
+
+const BPF_JA: u16 = 0x00;
+
+fn load(packet: &[u8], code: u16, off: usize) -> Option<u32> {
+    let size = match code & 0x18 {
+        0x00 => 4,
+        0x08 => 2,
+        0x10 => 1,
+        _ => return None,
+    };
+    let bytes = packet.get(off..off.checked_add(size)?)?;
+    Some(bytes.iter().fold(0u32, |acc, b| (acc << 8) | u32::from(*b)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insn(code: u16, jt: u8, jf: u8, k: u32) -> Insn {
+        Insn { code, jt, jf, k }
+    }
+
+    // `ip proto 6` on Ethernet, as printed by `tcpdump -d 'ip proto 6'`.
+    fn ip_tcp() -> Program {
+        Program::new(vec![
+            insn(0x28, 0, 0, 12),
+            insn(0x15, 0, 3, 0x0800),
+            insn(0x30, 0, 0, 23),
+            insn(0x15, 0, 1, 6),
+            insn(0x06, 0, 0, 262144),
+            insn(0x06, 0, 0, 0),
+        ])
+        .unwrap()
+    }
+
+    fn ethernet_ipv4(proto: u8) -> Vec<u8> {
+        let mut frame = vec![0u8; 34];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        frame[14] = 0x45;
+        frame[23] = proto;
+        frame
+    }
+
+    #[test]
+    fn matches_protocol() {
+        let prog = ip_tcp();
+        assert_eq!(prog.run(&ethernet_ipv4(6), 34), 262144);
+        assert_eq!(prog.run(&ethernet_ipv4(17), 34), 0);
+    }
+
+    #[test]
+    fn short_packet_is_rejected() {
+        assert_eq!(ip_tcp().run(&[0u8; 10], 10), 0);
+    }
+
+    #[test]
+    fn empty_program_accepts_everything() {
+        assert_eq!(Program::default().run(b"x", 1), u32::MAX);
+    }
+
+    #[test]
+    fn parses_wire_format() {
+        let raw = [0x00, 0x06, 0, 0, 0x00, 0x00, 0xff, 0xff];
+        let prog = Program::parse(&raw).unwrap();
+        assert_eq!(prog.run(b"abc", 3), 0xffff);
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        assert!(Program::new(vec![insn(0x15, 5, 0, 1), insn(0x06, 0, 0, 0)]).is_err());
+        assert!(Program::new(vec![insn(0x02, 0, 0, 16), insn(0x06, 0, 0, 0)]).is_err());
+        assert!(Program::new(vec![insn(0x00, 0, 0, 1)]).is_err());
+        assert!(Program::parse(&[0u8; 7]).is_err());
+    }
+
+    #[test]
+    fn division_by_zero_drops() {
+        let prog = Program::new(vec![insn(0x34, 0, 0, 0), insn(0x16, 0, 0, 0)]).unwrap();
+        assert_eq!(prog.run(b"x", 1), 0);
+    }
+}
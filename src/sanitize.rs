@@ -0,0 +1,809 @@
+use crate::cli::PayloadPolicy;
+use crate::dissect::{
+    self, Transport, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6, LINKTYPE_ETHERNET,
+    LINKTYPE_IPV4, LINKTYPE_IPV6,
+};
+use crate::error::KcapError;
+use crate::output::{self, open_capture};
+use crate::pcap::{self, BlockKind, CaptureReader, StreamFormat};
+use anyhow::Result;
+use clap::Parser;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::time::SystemTime;
+use tracing::debug;
+
+/// Environment variable read when `--anonymize-key` is not given.
+pub const KEY_ENV: &str = "KCAP_ANONYMIZE_KEY";
+
+/// EtherType of Ethernet frames carried in GRE or Geneve (transparent Ethernet bridging).
+const ETHERTYPE_TEB: u16 = 0x6558;
+
+/// Tunnels inside tunnels beyond this depth are cut rather than decoded.
+const MAX_TUNNEL_DEPTH: usize = 4;
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap sanitize",
+    about = "Strip payloads and anonymize addresses in a capture file"
+)]
+/// Arguments of `kcap sanitize`.
+pub struct SanitizeArgs {
+    #[arg(help = "Capture file to read (pcap or pcapng, optionally .gz or .zst)")]
+    pub input: String,
+
+    #[arg(help = "File to write; .gz and .zst are compressed")]
+    pub output: String,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PayloadPolicy::Truncate,
+        help = "What to do with packet payloads"
+    )]
+    pub payload: PayloadPolicy,
+
+    #[arg(long, help = "Replace IP addresses with prefix-preserving pseudonyms")]
+    pub anonymize_ips: bool,
+
+    #[arg(
+        long,
+        value_name = "KEY",
+        requires = "anonymize_ips",
+        help = "Key for --anonymize-ips, so pseudonyms match across captures (or KCAP_ANONYMIZE_KEY)"
+    )]
+    pub anonymize_key: Option<String>,
+
+    #[arg(long, help = "Overwrite OUTPUT if it exists")]
+    pub force: bool,
+}
+
+/// Runs `kcap sanitize`, writing a sanitized copy of a capture file.
+/// Parameters: `args` (SanitizeArgs) parsed arguments.
+/// Returns: Result<()> error when the input cannot be read or the output written.
+pub fn run(args: SanitizeArgs) -> Result<()> {
+    if Path::new(&args.output).exists() && !args.force {
+        return Err(KcapError::OutputWrite {
+            output: args.output,
+            detail: "file already exists; use --force to overwrite it".to_string(),
+        }
+        .into());
+    }
+    let anonymizer = args
+        .anonymize_ips
+        .then(|| Anonymizer::from_key(args.anonymize_key.as_deref()))
+        .transpose()?;
+    let capture = CaptureReader::new(open_capture(&args.input, None)?)?;
+    let sanitized = Sanitized::new(capture, Sanitizer::new(args.payload, anonymizer));
+    output::write_stream(sanitized, &args.output)
+}
+
+/// Sanitizes a live capture stream.
+/// Parameters: `stream` (R) pcap or pcapng bytes; blocks until the first byte arrives.
+/// Parameters: `sanitizer` (Sanitizer) rules to apply to every packet.
+/// Returns: Result<Box<dyn Read + Send>> stream in the same format, empty when `stream` was.
+pub fn sanitize_stream<R: Read + Send + 'static>(
+    stream: R,
+    sanitizer: Sanitizer,
+) -> Result<Box<dyn Read + Send>> {
+    match pcap::read_started(stream)? {
+        Some(capture) => Ok(Box::new(Sanitized::new(capture, sanitizer))),
+        None => Ok(Box::new(io::empty())),
+    }
+}
+
+/// Prefix-preserving address pseudonyms: addresses sharing an n-bit prefix map to
+/// pseudonyms sharing an n-bit prefix (the Crypto-PAn construction over keyed SHA-256).
+pub struct Anonymizer {
+    key: [u8; 32],
+    cache: HashMap<IpAddr, IpAddr>,
+}
+
+impl Anonymizer {
+    /// Creates an anonymizer from a passphrase.
+    /// Parameters: `key` (&str) passphrase; equal passphrases give equal pseudonyms.
+    /// Returns: Anonymizer with an empty cache.
+    pub fn new(key: &str) -> Self {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&Sha256::digest(key.as_bytes()));
+        Anonymizer {
+            key: digest,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Uses `key`, else `KCAP_ANONYMIZE_KEY`, else a random key for this run only.
+    /// Parameters: `key` (Option<&str>) passphrase from the command line.
+    /// Returns: Result<Anonymizer> error when no random key can be made.
+    pub fn from_key(key: Option<&str>) -> Result<Self> {
+        if let Some(key) = key {
+            return Ok(Anonymizer::new(key));
+        }
+        if let Ok(key) = std::env::var(KEY_ENV) {
+            return Ok(Anonymizer::new(&key));
+        }
+        let mut random = [0u8; 32];
+        if File::open("/dev/urandom")
+            .and_then(|mut f| f.read_exact(&mut random))
+            .is_err()
+        {
+            // Weaker, but pseudonyms only need to be unguessable by whoever gets the file.
+            let seed = format!("{:?} {}", SystemTime::now(), std::process::id());
+            random.copy_from_slice(&Sha256::digest(seed.as_bytes()));
+        }
+        Ok(Anonymizer {
+            key: random,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Pseudonym of an address, stable for the lifetime of the key.
+    /// Parameters: `ip` (IpAddr) real address.
+    /// Returns: IpAddr pseudonym of the same family.
+    pub fn map(&mut self, ip: IpAddr) -> IpAddr {
+        if let Some(mapped) = self.cache.get(&ip) {
+            return *mapped;
+        }
+        let mapped = match ip {
+            IpAddr::V4(v4) => {
+                IpAddr::V4(Ipv4Addr::from(self.permute(u32::from(v4).into(), 32) as u32))
+            }
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(self.permute(u128::from(v6), 128))),
+        };
+        self.cache.insert(ip, mapped);
+        mapped
+    }
+
+    fn permute(&self, addr: u128, bits: u32) -> u128 {
+        let mut out = 0;
+        for i in 0..bits {
+            // Bit i is flipped by a keyed function of the i bits before it.
+            let prefix = if i == 0 { 0 } else { addr >> (bits - i) };
+            let mut hash = Sha256::new();
+            hash.update(self.key);
+            hash.update([bits as u8, i as u8]);
+            hash.update(prefix.to_be_bytes());
+            let flip = u128::from(hash.finalize()[0] >> 7);
+            let pos = bits - 1 - i;
+            out |= ((addr >> pos) & 1 ^ flip) << pos;
+        }
+        out
+    }
+}
+
+/// Rules for removing sensitive data from captured packets.
+pub struct Sanitizer {
+    payload: PayloadPolicy,
+    anonymizer: Option<Anonymizer>,
+}
+
+impl Sanitizer {
+    /// Combines a payload policy with optional address anonymization.
+    /// Parameters: `payload` (PayloadPolicy) what to do with payload bytes.
+    /// Parameters: `anonymizer` (Option<Anonymizer>) address pseudonyms, if wanted.
+    /// Returns: Sanitizer ready for `frame`.
+    pub fn new(payload: PayloadPolicy, anonymizer: Option<Anonymizer>) -> Self {
+        Sanitizer {
+            payload,
+            anonymizer,
+        }
+    }
+
+    /// Whether the sanitizer changes anything at all.
+    /// Returns: bool false for `keep` without anonymization.
+    pub fn is_active(&self) -> bool {
+        self.payload != PayloadPolicy::Keep || self.anonymizer.is_some()
+    }
+
+    /// Sanitizes one captured frame, failing closed: bytes after the last header that could
+    /// be decoded are cut. IP-in-IP, GRE, VXLAN and Geneve tunnels are sanitized down to the
+    /// encapsulated packet. Checksums are updated for rewritten bytes, and length fields keep
+    /// describing the original packet.
+    /// Parameters: `linktype` (u32) link-layer header type of the interface.
+    /// Parameters: `frame` (&[u8]) captured bytes.
+    /// Returns: Vec<u8> sanitized bytes, possibly shorter than `frame`.
+    pub fn frame(&mut self, linktype: u32, frame: &[u8]) -> Vec<u8> {
+        let mut out = frame.to_vec();
+        if !self.is_active() {
+            return out;
+        }
+        let keep = match dissect::network_layer(linktype, frame) {
+            Some((ethertype, at)) => self.network(&mut out, ethertype, at, 0),
+            None => 0,
+        };
+        out.truncate(keep);
+        out
+    }
+
+    /// Sanitizes the network layer packet at `at`.
+    /// Returns: usize how many bytes of `out` may be kept.
+    fn network(&mut self, out: &mut [u8], ethertype: u16, at: usize, depth: usize) -> usize {
+        match ethertype {
+            ETHERTYPE_IPV4 | ETHERTYPE_IPV6 if depth <= MAX_TUNNEL_DEPTH => {
+                self.ip(out, ethertype, at, depth)
+            }
+            ETHERTYPE_ARP => match &mut self.anonymizer {
+                Some(anon) => {
+                    if anonymize_arp(anon, out, at) {
+                        out.len()
+                    } else {
+                        at
+                    }
+                }
+                None => out.len(),
+            },
+            // MPLS, PPPoE and the like: nothing after the link-layer header is checked.
+            _ => at,
+        }
+    }
+
+    /// Sanitizes the IP packet at `ip`, and any packet tunnelled inside it.
+    /// Returns: usize how many bytes of `out` may be kept.
+    fn ip(&mut self, out: &mut [u8], ethertype: u16, ip: usize, depth: usize) -> usize {
+        let linktype = if ethertype == ETHERTYPE_IPV4 {
+            LINKTYPE_IPV4
+        } else {
+            LINKTYPE_IPV6
+        };
+        let original = out.get(ip..).unwrap_or_default().to_vec();
+        let Some(packet) = dissect::dissect(linktype, &original) else {
+            return ip;
+        };
+        let t = ip + packet.transport_offset;
+        let ip_end = (ip + packet.ip_len).min(out.len());
+        let v6 = packet.src.is_ipv6();
+        // Where payload starts, and the transport checksum covering the addresses.
+        let (payload, l4_sum, icmp_error) = match &packet.transport {
+            Transport::Tcp(_) => {
+                let header = usize::from(out[t + 12] >> 4) * 4;
+                (t + header, Some(t + 16), false)
+            }
+            Transport::Udp(_) => {
+                // An IPv4 UDP checksum of zero means none was computed.
+                let unset = !v6 && out.get(t + 6..t + 8) == Some(&[0, 0]);
+                (t + 8, Some(t + 6).filter(|_| !unset), false)
+            }
+            Transport::Icmp { kind, .. } => (t + 8, None, matches!(kind, 3 | 4 | 5 | 11 | 12)),
+            Transport::Icmpv6 { kind, .. } => (t + 8, Some(t + 2), (1..=4).contains(kind)),
+            Transport::Fragment { .. } | Transport::Other { .. } => (t, None, false),
+        };
+        let payload = payload.min(ip_end);
+        // Encapsulated packet, its offset, and a tunnel checksum covering it.
+        let tunnel = match &packet.transport {
+            Transport::Other { protocol: 4 } => Some((ETHERTYPE_IPV4, t, None)),
+            Transport::Other { protocol: 41 } => Some((ETHERTYPE_IPV6, t, None)),
+            Transport::Other { protocol: 47 } => Some(gre(out, t)),
+            Transport::Udp(udp) => udp_tunnel(out, payload, udp.dst_port),
+            _ => None,
+        };
+
+        let mut keep = ip_end;
+        if let Some((inner_type, inner, tunnel_sum)) = tunnel {
+            let before = out[payload..ip_end].to_vec();
+            keep = self.network(out, inner_type, inner, depth + 1).min(ip_end);
+            let after = out[payload..ip_end].to_vec();
+            for sum in l4_sum.into_iter().chain(tunnel_sum) {
+                update_checksum(out, sum, &before, &after);
+            }
+        } else {
+            let data_payload = !matches!(
+                packet.transport,
+                Transport::Icmp { .. } | Transport::Icmpv6 { .. }
+            );
+            if self.payload == PayloadPolicy::Zero && data_payload {
+                let old = out[payload..ip_end].to_vec();
+                let zeros = vec![0; old.len()];
+                if let Some(sum) = l4_sum {
+                    update_checksum(out, sum, &old, &zeros);
+                }
+                out[payload..ip_end].copy_from_slice(&zeros);
+            }
+            // A later fragment of a tunnel carries addresses that cannot be decoded.
+            let hidden_addresses = self.anonymizer.is_some()
+                && matches!(packet.transport, Transport::Fragment { protocol: 4 | 41 | 47 });
+            if self.payload == PayloadPolicy::Truncate || hidden_addresses {
+                keep = payload;
+            }
+        }
+
+        if let Some(anon) = &mut self.anonymizer {
+            let header_sum = (!v6).then_some(ip + 10);
+            anonymize_ip_header(anon, out, ip, v6, header_sum, l4_sum);
+            if icmp_error && self.payload != PayloadPolicy::Truncate {
+                anonymize_quoted(anon, out, t);
+            }
+        }
+        if matches!(packet.transport, Transport::Udp(_)) {
+            fix_zero_udp_sum(out, l4_sum);
+        }
+        keep
+    }
+}
+
+/// Parses a GRE header (RFC 2784/2890).
+/// Returns: (u16, usize, Option<usize>) EtherType and offset of the carried packet, and the
+/// offset of the GRE checksum; EtherType 0 when the header is not understood.
+fn gre(out: &[u8], at: usize) -> (u16, usize, Option<usize>) {
+    let (Some(flags), Some(ethertype)) = (dissect::be16(out, at), dissect::be16(out, at + 2))
+    else {
+        return (0, at, None);
+    };
+    // Version 0 without source routing only; PPTP's enhanced GRE is left alone.
+    if flags & 0x4007 != 0 {
+        return (0, at, None);
+    }
+    let checksum = flags & 0x8000 != 0;
+    let words = [0x8000, 0x2000, 0x1000]
+        .iter()
+        .filter(|bit| flags & **bit != 0)
+        .count();
+    let inner = at + 4 + 4 * words;
+    if ethertype == ETHERTYPE_TEB {
+        let (ethertype, inner) = ethernet(out, inner);
+        return (ethertype, inner, checksum.then_some(at + 4));
+    }
+    (ethertype, inner, checksum.then_some(at + 4))
+}
+
+/// Recognizes VXLAN and Geneve by their well-known ports.
+/// Returns: Option<(u16, usize, Option<usize>)> like `gre`; None for other UDP traffic.
+fn udp_tunnel(out: &[u8], payload: usize, port: u16) -> Option<(u16, usize, Option<usize>)> {
+    match port {
+        // VXLAN, and the port the Linux kernel (and Flannel) uses for it.
+        4789 | 8472 if out.get(payload).is_some_and(|flags| flags & 0x08 != 0) => {
+            let (ethertype, inner) = ethernet(out, payload + 8);
+            Some((ethertype, inner, None))
+        }
+        6081 => {
+            let options = usize::from(out.get(payload)? & 0x3f) * 4;
+            let inner = payload + 8 + options;
+            match dissect::be16(out, payload + 2)? {
+                ETHERTYPE_TEB => {
+                    let (ethertype, inner) = ethernet(out, inner);
+                    Some((ethertype, inner, None))
+                }
+                ethertype => Some((ethertype, inner, None)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Finds the network layer of an Ethernet frame at `at`.
+/// Returns: (u16, usize) EtherType and offset; EtherType 0 when the header is cut short.
+fn ethernet(out: &[u8], at: usize) -> (u16, usize) {
+    out.get(at..)
+        .and_then(|frame| dissect::network_layer(LINKTYPE_ETHERNET, frame))
+        .map_or((0, at), |(ethertype, offset)| (ethertype, at + offset))
+}
+
+fn anonymize_ip_header(
+    anon: &mut Anonymizer,
+    out: &mut [u8],
+    ip: usize,
+    v6: bool,
+    header_sum: Option<usize>,
+    l4_sum: Option<usize>,
+) {
+    let (len, fields) = if v6 {
+        (16, [ip + 8, ip + 24])
+    } else {
+        (4, [ip + 12, ip + 16])
+    };
+    for at in fields {
+        let Some(old) = out.get(at..at + len).map(<[u8]>::to_vec) else {
+            return;
+        };
+        let new = match anon.map(address(&old)) {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        for sum in header_sum.into_iter().chain(l4_sum) {
+            update_checksum(out, sum, &old, &new);
+        }
+        out[at..at + len].copy_from_slice(&new);
+    }
+}
+
+/// Anonymizes the packet quoted in an ICMP error, fixing the ICMP checksum.
+fn anonymize_quoted(anon: &mut Anonymizer, out: &mut [u8], icmp: usize) {
+    let inner = icmp + 8;
+    let (v6, len) = match out.get(inner).map(|b| b >> 4) {
+        Some(4) => (false, 20),
+        Some(6) => (true, 40),
+        _ => return,
+    };
+    let Some(old) = out.get(inner..inner + len).map(<[u8]>::to_vec) else {
+        return;
+    };
+    let header_sum = (!v6).then_some(inner + 10);
+    anonymize_ip_header(anon, out, inner, v6, header_sum, None);
+    let new = out[inner..inner + len].to_vec();
+    update_checksum(out, icmp + 2, &old, &new);
+}
+
+/// Anonymizes the sender and target address of an ARP packet.
+/// Returns: bool false when the packet is not IPv4 ARP or is cut short.
+fn anonymize_arp(anon: &mut Anonymizer, out: &mut [u8], arp: usize) -> bool {
+    // Only IPv4 over hardware addresses of any length; sender then target address.
+    let (Some(&[0x08, 0x00]), Some(&hlen), Some(&4)) = (
+        out.get(arp + 2..arp + 4),
+        out.get(arp + 4),
+        out.get(arp + 5),
+    ) else {
+        return false;
+    };
+    let hlen = usize::from(hlen);
+    for at in [arp + 8 + hlen, arp + 8 + 2 * hlen + 4] {
+        let Some(old) = out.get(at..at + 4) else {
+            return false;
+        };
+        if let IpAddr::V4(new) = anon.map(address(old)) {
+            out[at..at + 4].copy_from_slice(&new.octets());
+        }
+    }
+    true
+}
+
+fn address(bytes: &[u8]) -> IpAddr {
+    match <[u8; 4]>::try_from(bytes) {
+        Ok(v4) => IpAddr::from(v4),
+        Err(_) => IpAddr::from(<[u8; 16]>::try_from(bytes).unwrap_or([0; 16])),
+    }
+}
+
+/// Incrementally updates the Internet checksum at `at` for bytes changing from `old` to
+/// `new` (RFC 1624); both start at an even offset of the checksummed data.
+fn update_checksum(out: &mut [u8], at: usize, old: &[u8], new: &[u8]) {
+    let Some(field) = out.get(at..at + 2) else {
+        return;
+    };
+    let mut sum = u32::from(!u16::from_be_bytes([field[0], field[1]]));
+    for (o, n) in words(old).zip(words(new)) {
+        sum += u32::from(!o) + u32::from(n);
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    out[at..at + 2].copy_from_slice(&(!(sum as u16)).to_be_bytes());
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c.get(1).copied().unwrap_or(0)]))
+}
+
+fn fix_zero_udp_sum(out: &mut [u8], sum: Option<usize>) {
+    // A computed UDP checksum of zero is sent as all ones.
+    if let Some(field) = sum.and_then(|at| out.get_mut(at..at + 2)) {
+        if field == [0, 0] {
+            field.copy_from_slice(&[0xff, 0xff]);
+        }
+    }
+}
+
+/// Capture stream with every packet passed through a `Sanitizer`.
+pub struct Sanitized<R> {
+    capture: CaptureReader<R>,
+    sanitizer: Sanitizer,
+    pending: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> Sanitized<R> {
+    /// Wraps a capture; the stream header is passed through unchanged.
+    /// Parameters: `capture` (CaptureReader<R>) capture positioned after its header.
+    /// Parameters: `sanitizer` (Sanitizer) rules to apply to every packet.
+    /// Returns: Sanitized<R> reader of the sanitized capture bytes.
+    pub fn new(capture: CaptureReader<R>, sanitizer: Sanitizer) -> Self {
+        Sanitized {
+            pending: capture.header().to_vec(),
+            capture,
+            sanitizer,
+            pos: 0,
+            done: false,
+        }
+    }
+
+    fn refill(&mut self) {
+        self.pos = 0;
+        let mut block = match self.capture.next_block() {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                self.done = true;
+                self.pending.clear();
+                return;
+            }
+            Err(err) => {
+                // Typically a record cut short when the capture was stopped.
+                debug!("stopped sanitizing capture stream: {err:#}");
+                self.done = true;
+                self.pending.clear();
+                return;
+            }
+        };
+        if let BlockKind::Packet(info) = &block.kind {
+            let range = info.data.clone();
+            let clean = self
+                .sanitizer
+                .frame(info.linktype, &block.raw[range.clone()]);
+            let be = self.capture.big_endian();
+            if clean.len() == range.len() {
+                block.raw[range].copy_from_slice(&clean);
+            } else if self.capture.format() == StreamFormat::Pcap {
+                block.raw.truncate(range.start);
+                block.raw.extend_from_slice(&clean);
+                pcap::write_u32(&mut block.raw, 8, clean.len() as u32, be);
+            } else if let Some(raw) = pcap::with_packet_data(&block.raw, be, &clean) {
+                block.raw = raw;
+            } else {
+                // Simple Packet Blocks have no captured length; blank the cut bytes instead.
+                let mut padded = clean;
+                padded.resize(range.len(), 0);
+                block.raw[range].copy_from_slice(&padded);
+            }
+        }
+        self.pending = block.raw;
+    }
+}
+
+impl<R: Read> Read for Sanitized<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.pending.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.refill();
+        }
+        let n = buf.len().min(self.pending.len() - self.pos);
+        buf[..n].copy_from_slice(&self.pending[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dissect::testdata::{ipv4_tcp, ipv4_udp, sll};
+    use crate::dissect::{tcp_flags, LINKTYPE_LINUX_SLL};
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+
+    /// Internet checksum over `data`, zero when the stored checksum is correct.
+    fn fold(data: &[u8]) -> u16 {
+        let mut sum: u32 = words(data).map(u32::from).sum();
+        while sum > 0xffff {
+            sum = (sum & 0xffff) + (sum >> 16);
+        }
+        !(sum as u16)
+    }
+
+    /// Fills in valid IPv4 header and TCP/UDP checksums.
+    fn with_checksums(mut ip: Vec<u8>) -> Vec<u8> {
+        let header = fold(&ip[..20]);
+        ip[10..12].copy_from_slice(&header.to_be_bytes());
+        let at = if ip[9] == 6 { 36 } else { 26 };
+        let sum = l4_sum(&ip);
+        ip[at..at + 2].copy_from_slice(&sum.to_be_bytes());
+        ip
+    }
+
+    fn l4_sum(ip: &[u8]) -> u16 {
+        let mut pseudo = ip[12..20].to_vec();
+        pseudo.extend_from_slice(&[0, ip[9]]);
+        pseudo.extend_from_slice(&((ip.len() - 20) as u16).to_be_bytes());
+        pseudo.extend_from_slice(&ip[20..]);
+        if pseudo.len() % 2 == 1 {
+            pseudo.push(0);
+        }
+        fold(&pseudo)
+    }
+
+    fn tcp_packet() -> Vec<u8> {
+        with_checksums(ipv4_tcp(
+            [10, 244, 1, 5],
+            [10, 244, 2, 7],
+            (40000, 8080),
+            (1, 1),
+            tcp_flags::PSH | tcp_flags::ACK,
+            502,
+            b"card=4111111111111111",
+        ))
+    }
+
+    #[test]
+    fn truncates_after_transport_header() {
+        let frame = sll(&tcp_packet());
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Truncate, None);
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &frame);
+        assert_eq!(out.len(), 16 + 40);
+        assert_eq!(out[..], frame[..56]);
+    }
+
+    #[test]
+    fn zeroes_payload_and_keeps_checksums_valid() {
+        let frame = sll(&tcp_packet());
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Zero, None);
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &frame);
+        assert_eq!(out.len(), frame.len());
+        assert!(out[56..].iter().all(|b| *b == 0));
+        assert_eq!(l4_sum(&out[16..]), 0);
+
+        let udp = sll(&with_checksums(ipv4_udp(
+            [10, 0, 0, 1],
+            [10, 0, 0, 2],
+            (5353, 53),
+            b"secret",
+        )));
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &udp);
+        assert!(out[44..].iter().all(|b| *b == 0));
+        assert_eq!(l4_sum(&out[16..]), 0);
+    }
+
+    #[test]
+    fn anonymizes_prefix_preserving_with_valid_checksums() {
+        let mut anon = Anonymizer::new("k");
+        let a = anon.map("10.244.1.5".parse().unwrap());
+        let b = anon.map("10.244.1.9".parse().unwrap());
+        let c = anon.map("192.168.0.1".parse().unwrap());
+        let (IpAddr::V4(a), IpAddr::V4(b), IpAddr::V4(c)) = (a, b, c) else {
+            panic!("family changed");
+        };
+        let common = |x: Ipv4Addr, y: Ipv4Addr| (u32::from(x) ^ u32::from(y)).leading_zeros();
+        assert_eq!(common(a, b), 28);
+        assert_eq!(common(a, c), 0);
+        assert_ne!(a, Ipv4Addr::new(10, 244, 1, 5));
+        assert_eq!(
+            Anonymizer::new("k").map(IpAddr::V4(a)),
+            anon.map(IpAddr::V4(a))
+        );
+
+        let frame = sll(&tcp_packet());
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Keep, Some(Anonymizer::new("k")));
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &frame);
+        assert_eq!(out[28..32], a.octets());
+        assert_eq!(fold(&out[16..36]), 0);
+        assert_eq!(l4_sum(&out[16..]), 0);
+        assert_eq!(out[56..], frame[56..]);
+    }
+
+    #[test]
+    fn anonymizes_arp_and_quoted_icmp_headers() {
+        let mut arp = vec![0u8; 14];
+        arp.extend_from_slice(&0x0806u16.to_be_bytes());
+        arp.extend_from_slice(&[0, 1, 8, 0, 6, 4, 0, 1]);
+        arp.extend_from_slice(&[0xaa; 6]);
+        arp.extend_from_slice(&[10, 0, 0, 1]);
+        arp.extend_from_slice(&[0; 6]);
+        arp.extend_from_slice(&[10, 0, 0, 2]);
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Keep, Some(Anonymizer::new("k")));
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &arp);
+        assert_ne!(out[30..34], [10, 0, 0, 1]);
+        assert_ne!(out[40..44], [10, 0, 0, 2]);
+
+        // Port unreachable quoting the UDP datagram that caused it.
+        let quoted = with_checksums(ipv4_udp([10, 0, 0, 1], [10, 0, 0, 2], (5353, 53), b"q"));
+        let mut icmp = ipv4_udp([10, 0, 0, 2], [10, 0, 0, 1], (0, 0), &[0; 8]);
+        icmp.truncate(20);
+        icmp[9] = 1;
+        icmp.extend_from_slice(&[3, 3, 0, 0, 0, 0, 0, 0]);
+        icmp.extend_from_slice(&quoted);
+        let total = icmp.len() as u16;
+        icmp[2..4].copy_from_slice(&total.to_be_bytes());
+        let sum = fold(&icmp[20..]);
+        icmp[22..24].copy_from_slice(&sum.to_be_bytes());
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &sll(&icmp));
+        assert_eq!(out[16 + 40..16 + 44], out[16 + 16..16 + 20]);
+        assert_eq!(fold(&out[16 + 20..]), 0);
+        assert_eq!(fold(&out[16 + 28..16 + 48]), 0);
+    }
+
+    /// Wraps an IP packet in an outer IPv4 header of `protocol`.
+    fn encapsulate(protocol: u8, inner: &[u8]) -> Vec<u8> {
+        let mut outer = ipv4_udp([192, 168, 0, 1], [192, 168, 0, 2], (0, 0), &[]);
+        outer.truncate(20);
+        outer[9] = protocol;
+        outer.extend_from_slice(inner);
+        let total = outer.len() as u16;
+        outer[2..4].copy_from_slice(&total.to_be_bytes());
+        let header = fold(&outer[..20]);
+        outer[10..12].copy_from_slice(&header.to_be_bytes());
+        outer
+    }
+
+    #[test]
+    fn sanitizes_packets_inside_ipip_and_gre() {
+        let inner = tcp_packet();
+        let ipip = sll(&encapsulate(4, &inner));
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Zero, Some(Anonymizer::new("k")));
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &ipip);
+        assert_eq!(out.len(), ipip.len());
+        let tunnelled = &out[16 + 20..];
+        assert_ne!(tunnelled[12..16], inner[12..16]);
+        assert!(tunnelled[40..].iter().all(|b| *b == 0));
+        assert_eq!(fold(&tunnelled[..20]), 0);
+        assert_eq!(l4_sum(tunnelled), 0);
+        assert_eq!(fold(&out[16..36]), 0);
+
+        let mut gre = vec![0x80, 0, 0x08, 0, 0, 0, 0, 0];
+        gre.extend_from_slice(&inner);
+        let sum = fold(&gre);
+        gre[4..6].copy_from_slice(&sum.to_be_bytes());
+        let frame = sll(&encapsulate(47, &gre));
+        let out = Sanitizer::new(PayloadPolicy::Truncate, None).frame(LINKTYPE_LINUX_SLL, &frame);
+        assert_eq!(out[..], frame[..16 + 20 + 8 + 40]);
+        let out = Sanitizer::new(PayloadPolicy::Zero, None).frame(LINKTYPE_LINUX_SLL, &frame);
+        assert!(out[16 + 20 + 8 + 40..].iter().all(|b| *b == 0));
+        assert_eq!(fold(&out[16 + 20..]), 0);
+    }
+
+    #[test]
+    fn cuts_what_it_cannot_decode() {
+        // Unknown link type: nothing can be checked.
+        let frame = sll(&tcp_packet());
+        let mut sanitizer = Sanitizer::new(PayloadPolicy::Zero, Some(Anonymizer::new("k")));
+        assert!(sanitizer.frame(147, &frame).is_empty());
+        // MPLS: the link-layer header stays.
+        let mut mpls = frame.clone();
+        mpls[14..16].copy_from_slice(&0x8847u16.to_be_bytes());
+        assert_eq!(sanitizer.frame(LINKTYPE_LINUX_SLL, &mpls), mpls[..16]);
+        // ESP: the IP header stays, the encrypted rest is zeroed or cut.
+        let esp = sll(&encapsulate(50, b"spi-and-ciphertext"));
+        let out = sanitizer.frame(LINKTYPE_LINUX_SLL, &esp);
+        assert!(out[36..].iter().all(|b| *b == 0));
+        let out = Sanitizer::new(PayloadPolicy::Truncate, None).frame(LINKTYPE_LINUX_SLL, &esp);
+        assert_eq!(out.len(), 36);
+        // Keeping payloads without anonymizing changes nothing.
+        let out = Sanitizer::new(PayloadPolicy::Keep, None).frame(147, &frame);
+        assert_eq!(out, frame);
+    }
+
+    #[test]
+    fn sanitizes_pcap_and_pcapng_streams() {
+        // The pcap test stream is Linux cooked, the pcapng one Ethernet.
+        let cooked = sll(&tcp_packet());
+        let mut ethernet = vec![0u8; 12];
+        ethernet.extend_from_slice(&0x0800u16.to_be_bytes());
+        ethernet.extend_from_slice(&tcp_packet());
+        for (input, header, frame) in [
+            (testdata::pcap_stream(&[(1, &cooked)]), 24, &cooked),
+            (testdata::pcapng_stream(&[(1, &ethernet)]), 48, &ethernet),
+        ] {
+            let capture = CaptureReader::new(Cursor::new(input.clone())).unwrap();
+            let sanitizer = Sanitizer::new(PayloadPolicy::Truncate, None);
+            let mut out = Vec::new();
+            Sanitized::new(capture, sanitizer)
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out[..header], input[..header]);
+            let mut reader = CaptureReader::new(Cursor::new(out)).unwrap();
+            let packet = loop {
+                let block = reader.next_block().unwrap().unwrap();
+                if let BlockKind::Packet(info) = &block.kind {
+                    assert_eq!(info.orig_len as usize, frame.len());
+                    break block.packet_data().unwrap().to_vec();
+                }
+            };
+            assert_eq!(packet, frame[..frame.len() - 21]);
+        }
+    }
+
+    #[test]
+    fn existing_output_is_an_output_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("clean.pcap");
+        std::fs::write(&output, b"keep").unwrap();
+        let output = output.to_string_lossy().to_string();
+        let args = SanitizeArgs::parse_from(["kcap sanitize", "missing.pcap", &output]);
+        let err = run(args).unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 36);
+        assert_eq!(std::fs::read(&output).unwrap(), b"keep");
+    }
+}
@@ -0,0 +1,424 @@
+use crate::cli::Args;
+use crate::pcap::{BlockKind, CaptureReader, StreamFormat};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+// Blocks buffered per client before it is considered too slow and dropped.
+const CLIENT_BACKLOG: usize = 1024;
+// Idle or stalled clients must not keep `kcap serve` from exiting after the capture.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "kcap serve",
+    about = "Run a capture and stream it live to HTTP and WebSocket clients"
+)]
+/// Arguments of `kcap serve`: a regular capture plus the listen address.
+pub struct ServeArgs {
+    #[command(flatten)]
+    pub args: Args,
+
+    #[arg(
+        long,
+        default_value = "127.0.0.1:8080",
+        help = "Address to serve the live capture on"
+    )]
+    pub listen: String,
+}
+
+impl crate::config::CaptureCommand for ServeArgs {
+    fn capture_args(&mut self) -> &mut Args {
+        &mut self.args
+    }
+}
+
+/// How a published block affects what late joiners receive first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Replay {
+    /// Not replayed (packets, statistics).
+    Skip,
+    /// Appended to the replay buffer (interface descriptions and other metadata).
+    Append,
+    /// Starts a new replay buffer (file header, pcapng section header).
+    Reset,
+}
+
+#[derive(Default)]
+struct HubState {
+    format: Option<StreamFormat>,
+    replay: Vec<u8>,
+    subscribers: Vec<SyncSender<Arc<[u8]>>>,
+    closed: bool,
+}
+
+/// Fans one capture stream out to any number of subscribers.
+#[derive(Default)]
+pub struct Hub {
+    state: Mutex<HubState>,
+}
+
+impl Hub {
+    /// Registers a subscriber; it first receives the headers seen so far.
+    /// Returns: (Option<StreamFormat>, Receiver) stream format if known and the block feed.
+    pub fn subscribe(&self) -> (Option<StreamFormat>, Receiver<Arc<[u8]>>) {
+        let (tx, rx) = mpsc::sync_channel(CLIENT_BACKLOG);
+        let mut state = self.state.lock().unwrap();
+        if !state.replay.is_empty() {
+            let _ = tx.try_send(Arc::from(state.replay.as_slice()));
+        }
+        // A closed hub drops the sender right away, so the client sees end of stream.
+        if !state.closed {
+            state.subscribers.push(tx);
+        }
+        (state.format, rx)
+    }
+
+    /// Returns the number of connected subscribers.
+    pub fn subscribers(&self) -> usize {
+        self.state.lock().unwrap().subscribers.len()
+    }
+
+    /// Reads a capture stream block by block and publishes it until it ends.
+    /// Parameters: `stream` (R) pcap or pcapng capture bytes.
+    /// Returns: Result<()> indicating success or failure.
+    pub fn broadcast<R: Read>(&self, stream: R) -> Result<()> {
+        let result = self.pump(stream);
+        self.close();
+        result
+    }
+
+    fn pump<R: Read>(&self, stream: R) -> Result<()> {
+        let mut reader = CaptureReader::new(stream)?;
+        self.state.lock().unwrap().format = Some(reader.format());
+        self.publish(reader.header().to_vec(), Replay::Reset);
+        while let Some(block) = reader.next_block()? {
+            let replay = match block.kind {
+                BlockKind::SectionHeader => Replay::Reset,
+                ref kind if kind.is_stream_metadata() => Replay::Append,
+                _ => Replay::Skip,
+            };
+            self.publish(block.raw, replay);
+        }
+        Ok(())
+    }
+
+    fn publish(&self, raw: Vec<u8>, replay: Replay) {
+        let mut state = self.state.lock().unwrap();
+        match replay {
+            Replay::Skip => {}
+            Replay::Append => state.replay.extend_from_slice(&raw),
+            Replay::Reset => state.replay = raw.clone(),
+        }
+        let chunk: Arc<[u8]> = Arc::from(raw);
+        state
+            .subscribers
+            .retain(|tx| match tx.try_send(chunk.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    // Never stall the capture for one client; it can reconnect and resync.
+                    warn!("live client fell behind; disconnecting it");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.subscribers.clear();
+    }
+}
+
+/// Runs a capture and serves it live until the capture ends.
+/// Parameters: `serve` (ServeArgs) capture arguments and listen address.
+/// Returns: Result<()> indicating success or failure.
+pub fn run(serve: ServeArgs) -> Result<()> {
+    let listener = TcpListener::bind(&serve.listen)
+        .with_context(|| format!("failed to listen on {}", serve.listen))?;
+    let addr = listener.local_addr()?;
+    info!(%addr, "serving live capture; try: curl -sN http://{addr}/ | wireshark -k -i -");
+
+    let hub = Arc::new(Hub::default());
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    {
+        let hub = hub.clone();
+        let clients = clients.clone();
+        thread::spawn(move || serve_clients(listener, hub, clients));
+    }
+
+    let result = crate::run_with_sink(serve.args, |stream| hub.broadcast(stream));
+    hub.close();
+    // Let connected clients drain what was already captured.
+    let pending: Vec<JoinHandle<()>> = std::mem::take(&mut *clients.lock().unwrap());
+    for handle in pending {
+        let _ = handle.join();
+    }
+    result
+}
+
+/// Accepts clients forever, each served on its own thread.
+/// Parameters: `listener` (TcpListener) bound listening socket.
+/// Parameters: `hub` (Arc<Hub>) capture fan-out.
+/// Parameters: `clients` (Arc<Mutex<Vec<JoinHandle<()>>>>) collects client threads.
+pub fn serve_clients(
+    listener: TcpListener,
+    hub: Arc<Hub>,
+    clients: Arc<Mutex<Vec<JoinHandle<()>>>>,
+) {
+    for conn in listener.incoming() {
+        let Ok(conn) = conn else { continue };
+        let hub = hub.clone();
+        let handle = thread::spawn(move || {
+            let peer = conn.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+            match handle_client(conn, &hub, CLIENT_TIMEOUT) {
+                Ok(()) => info!(%peer, "live client finished"),
+                Err(err) => warn!(%peer, "live client failed: {err:#}"),
+            }
+        });
+        let mut clients = clients.lock().unwrap();
+        clients.retain(|h| !h.is_finished());
+        clients.push(handle);
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("malformed request line {:?}", line.trim_end());
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("connection closed inside request headers");
+        }
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            break;
+        }
+        if headers.len() >= 100 {
+            bail!("too many request headers");
+        }
+        if let Some((k, v)) = trimmed.split_once(':') {
+            headers.push((k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+    Ok(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+fn handle_client(mut conn: TcpStream, hub: &Hub, timeout: Duration) -> Result<()> {
+    conn.set_read_timeout(Some(timeout))?;
+    conn.set_write_timeout(Some(timeout))?;
+    let request = read_request(&mut BufReader::new(&mut conn))?;
+    let path = request.path.split('?').next().unwrap_or_default();
+    if request.method != "GET" {
+        return respond_error(&mut conn, "405 Method Not Allowed");
+    }
+    if path != "/" {
+        return respond_error(&mut conn, "404 Not Found");
+    }
+
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|u| u.eq_ignore_ascii_case("websocket"));
+    if upgrade {
+        let Some(key) = request.header("sec-websocket-key") else {
+            return respond_error(&mut conn, "400 Bad Request");
+        };
+        let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+        write!(
+            conn,
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+        )?;
+        let (_, rx) = hub.subscribe();
+        return stream_websocket(conn, rx);
+    }
+
+    let (format, rx) = hub.subscribe();
+    let content_type = match format {
+        Some(StreamFormat::Pcap) => "application/vnd.tcpdump.pcap",
+        Some(StreamFormat::Pcapng) => "application/x-pcapng",
+        None => "application/octet-stream",
+    };
+    write!(
+        conn,
+        "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nTransfer-Encoding: chunked\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    for chunk in rx {
+        write!(conn, "{:x}\r\n", chunk.len())?;
+        conn.write_all(&chunk)?;
+        conn.write_all(b"\r\n")?;
+    }
+    conn.write_all(b"0\r\n\r\n")?;
+    conn.flush()?;
+    Ok(())
+}
+
+fn stream_websocket(conn: TcpStream, rx: Receiver<Arc<[u8]>>) -> Result<()> {
+    let mut ws = WebSocket::from_raw_socket(conn, Role::Server, None);
+    for chunk in rx {
+        ws.send(Message::Binary(chunk.to_vec().into()))
+            .context("websocket send failed")?;
+    }
+    let _ = ws.close(None);
+    let _ = ws.flush();
+    Ok(())
+}
+
+fn respond_error(conn: &mut TcpStream, status: &str) -> Result<()> {
+    write!(
+        conn,
+        "HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pcap::testdata;
+    use std::io::Cursor;
+    use std::time::Instant;
+
+    fn start_server() -> (Arc<Hub>, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let hub = Arc::new(Hub::default());
+        let server_hub = hub.clone();
+        thread::spawn(move || serve_clients(listener, server_hub, Arc::default()));
+        (hub, addr)
+    }
+
+    fn wait_for_subscriber(hub: &Hub) {
+        for _ in 0..200 {
+            if hub.subscribers() > 0 {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("client never subscribed");
+    }
+
+    fn dechunk(mut body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let eol = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let len =
+                usize::from_str_radix(std::str::from_utf8(&body[..eol]).unwrap(), 16).unwrap();
+            if len == 0 {
+                return out;
+            }
+            out.extend_from_slice(&body[eol + 2..eol + 2 + len]);
+            body = &body[eol + 4 + len..];
+        }
+    }
+
+    #[test]
+    fn silent_client_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _idle = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (conn, _) = listener.accept().unwrap();
+
+        let started = Instant::now();
+        let hub = Hub::default();
+        assert!(handle_client(conn, &hub, Duration::from_millis(100)).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn late_joiner_gets_header_replay() {
+        let hub = Hub::default();
+        hub.publish(b"SHB".to_vec(), Replay::Reset);
+        hub.publish(b"IDB".to_vec(), Replay::Append);
+        hub.publish(b"pkt1".to_vec(), Replay::Skip);
+
+        let (_, rx) = hub.subscribe();
+        hub.publish(b"pkt2".to_vec(), Replay::Skip);
+        hub.close();
+
+        let chunks: Vec<Vec<u8>> = rx.iter().map(|c| c.to_vec()).collect();
+        assert_eq!(chunks, vec![b"SHBIDB".to_vec(), b"pkt2".to_vec()]);
+    }
+
+    #[test]
+    fn http_client_receives_chunked_stream() {
+        let (hub, addr) = start_server();
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        conn.write_all(b"GET / HTTP/1.1\r\nHost: kcap\r\n\r\n")
+            .unwrap();
+        wait_for_subscriber(&hub);
+
+        let capture = testdata::pcap_stream(&[(1, b"a1"), (2, b"a2")]);
+        hub.broadcast(Cursor::new(capture.clone())).unwrap();
+
+        let mut response = Vec::new();
+        conn.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..split]);
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains("Transfer-Encoding: chunked"));
+        assert_eq!(dechunk(&response[split + 4..]), capture);
+    }
+
+    #[test]
+    fn websocket_client_receives_binary_frames() {
+        let (hub, addr) = start_server();
+        let conn = TcpStream::connect(&addr).unwrap();
+        let (mut ws, _) = tungstenite::client(format!("ws://{addr}/"), conn).unwrap();
+        wait_for_subscriber(&hub);
+
+        let capture = testdata::pcapng_stream(&[(1, b"b1")]);
+        hub.broadcast(Cursor::new(capture.clone())).unwrap();
+
+        let mut received = Vec::new();
+        while let Ok(msg) = ws.read() {
+            if let Message::Binary(data) = msg {
+                received.extend_from_slice(&data);
+            }
+        }
+        assert_eq!(received, capture);
+    }
+
+    #[test]
+    fn unknown_path_is_not_found() {
+        let (_hub, addr) = start_server();
+        let mut conn = TcpStream::connect(&addr).unwrap();
+        conn.write_all(b"GET /nope HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404"));
+    }
+}
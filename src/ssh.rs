@@ -1,4 +1,5 @@
-﻿use anyhow::{Context, Result};
+﻿use crate::k8s::Runner;
+use anyhow::{bail, Context, Result};
 use std::process::{Child, Command, Stdio};
 
 /// Builds SSH arguments for running a remote command.
@@ -16,11 +17,27 @@ pub fn build_ssh_args(
     remote_cmd: &str,
 ) -> Vec<String> {
     // Build a non-interactive SSH invocation for remote capture.
-    let mut args = Vec::new();
-    args.push("-o".to_string());
-    args.push("BatchMode=yes".to_string());
-    args.push("-p".to_string());
-    args.push(port.to_string());
+    let mut args = build_ssh_base_args(user, host, port, jump_host);
+    args.push("--".to_string());
+    args.push("sh".to_string());
+    args.push("-c".to_string());
+    args.push(remote_cmd.to_string());
+
+    args
+}
+
+fn build_ssh_base_args(
+    user: Option<&str>,
+    host: &str,
+    port: u16,
+    jump_host: Option<&str>,
+) -> Vec<String> {
+    let mut args = vec![
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        "-p".to_string(),
+        port.to_string(),
+    ];
 
     if let Some(jump) = jump_host {
         // Support bastion jumps without changing caller logic.
@@ -33,31 +50,71 @@ pub fn build_ssh_args(
         None => host.to_string(),
     };
     args.push(target);
+    args
+}
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Endpoints of an SSH session as reported by the remote `SSH_CONNECTION`.
+pub struct SshConnection {
+    pub client_ip: String,
+    pub client_port: u16,
+    pub server_ip: String,
+    pub server_port: u16,
+}
+
+/// Asks the remote host which address and port kcap's SSH session uses.
+/// Parameters: `runner` (&impl Runner) command runner.
+/// Parameters: `user` (Option<&str>) optional SSH username.
+/// Parameters: `host` (&str) target host.
+/// Parameters: `port` (u16) SSH port.
+/// Parameters: `jump_host` (Option<&str>) optional bastion host.
+/// Returns: Result<SshConnection> parsed session endpoints or an error.
+pub fn probe_ssh_connection(
+    runner: &impl Runner,
+    user: Option<&str>,
+    host: &str,
+    port: u16,
+    jump_host: Option<&str>,
+) -> Result<SshConnection> {
+    // The remote sees the last hop (the bastion when jumping), which is exactly
+    // the peer whose packets carry the capture stream.
+    let mut args = build_ssh_base_args(user, host, port, jump_host);
     args.push("--".to_string());
-    args.push("sh".to_string());
-    args.push("-c".to_string());
-    args.push(remote_cmd.to_string());
+    args.push("echo $SSH_CONNECTION".to_string());
+    let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
+    let out = runner.run_capture("ssh", &arg_refs)?;
+    parse_ssh_connection(&out)
+}
 
-    args
+fn parse_ssh_connection(value: &str) -> Result<SshConnection> {
+    // Format: "<client_ip> <client_port> <server_ip> <server_port>".
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [client_ip, client_port, server_ip, server_port] = fields.as_slice() else {
+        bail!("unexpected SSH_CONNECTION value: {value:?}");
+    };
+    Ok(SshConnection {
+        client_ip: client_ip.to_string(),
+        client_port: client_port.parse().context("invalid SSH client port")?,
+        server_ip: server_ip.to_string(),
+        server_port: server_port.parse().context("invalid SSH server port")?,
+    })
 }
 
-/// Spawns an ssh process with piped stdout.
+/// Spawns an ssh process with piped stdout and stderr.
 /// Parameters: `args` (&[String]) argument list for ssh.
 /// Returns: Result<Child> handle to the spawned process.
 pub fn spawn_ssh(args: &[String]) -> Result<Child> {
-    // Keep stdout piped for capture bytes and inherit stderr for feedback.
-    Command::new("ssh")
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .context("failed to spawn ssh")
+    // Keep stdout piped for capture bytes; stderr is echoed and summarized by the caller.
+    let mut cmd = Command::new("ssh");
+    cmd.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+    crate::capture::shield(&mut cmd);
+    cmd.spawn().context("failed to spawn ssh")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::k8s::FakeRunner;
 
     #[test]
     fn ssh_args_basic() {
@@ -74,4 +131,24 @@ mod tests {
         assert!(args.iter().any(|a| a == "-J"));
         assert!(args.iter().any(|a| a == "jump"));
     }
+
+    #[test]
+    fn probe_ssh_connection_parses_remote_env() {
+        let runner = FakeRunner::new("10.0.0.5 51514 10.0.0.1 22");
+        let conn =
+            probe_ssh_connection(&runner, Some("root"), "10.0.0.1", 22, Some("jump")).unwrap();
+        assert_eq!(conn.client_ip, "10.0.0.5");
+        assert_eq!(conn.client_port, 51514);
+        assert_eq!(conn.server_port, 22);
+
+        let rec = runner.last_command.lock().unwrap().clone();
+        assert_eq!(rec.program, "ssh");
+        assert!(rec.args.iter().any(|a| a == "root@10.0.0.1"));
+        assert_eq!(rec.args.last().unwrap(), "echo $SSH_CONNECTION");
+    }
+
+    #[test]
+    fn parse_ssh_connection_rejects_empty() {
+        assert!(parse_ssh_connection("").is_err());
+    }
 }